@@ -0,0 +1,161 @@
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Name of the manifest file in a backup directory.
+pub const BACKUP_MANIFEST: &str = "manifest.json";
+
+/// Version of the [BackupManifest] format. Bumped whenever a field is removed or changes meaning.
+///
+/// [BackupManifest]: struct.BackupManifest.html
+pub const BACKUP_MANIFEST_VERSION: u32 = 1;
+
+/// A send stream file in a backup directory.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BackupStream {
+    /// Name of the stream file, relative to the backup directory.
+    pub file: PathBuf,
+    /// Path of the snapshot the stream was generated from.
+    pub snapshot: PathBuf,
+    /// UUID of the snapshot, which a received copy records as its received UUID.
+    pub uuid: Uuid,
+    /// Transaction id of the snapshot, which a received copy records.
+    pub ctransid: u64,
+    /// UUID of the snapshot the stream is incremental to, or none for a full stream.
+    pub parent: Option<Uuid>,
+    /// Time the snapshot was taken.
+    pub created: NaiveDateTime,
+    /// Size of the stream file in bytes.
+    pub bytes: u64,
+}
+
+impl BackupStream {
+    /// Check whether this is a full stream, which does not depend on another one.
+    pub fn is_full(&self) -> bool {
+        self.parent.is_none()
+    }
+}
+
+/// Description of a backup directory, stored in it as [BACKUP_MANIFEST].
+///
+/// Streams form chains, each starting with a full stream followed by streams incremental to the
+/// one before them. UUIDs are hyphenated strings and timestamps are ISO 8601 strings without a
+/// timezone.
+///
+/// [BACKUP_MANIFEST]: constant.BACKUP_MANIFEST.html
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BackupManifest {
+    /// Version of the manifest format, see [BACKUP_MANIFEST_VERSION].
+    ///
+    /// [BACKUP_MANIFEST_VERSION]: constant.BACKUP_MANIFEST_VERSION.html
+    pub version: u32,
+    /// Path of the backed up subvolume.
+    pub subvolume: PathBuf,
+    /// UUID of the backed up subvolume.
+    pub subvolume_uuid: Uuid,
+    /// Streams, oldest first.
+    pub streams: Vec<BackupStream>,
+}
+
+impl BackupManifest {
+    /// Create an empty manifest for a subvolume.
+    pub fn new<T: Into<PathBuf>>(subvolume: T, subvolume_uuid: Uuid) -> Self {
+        Self {
+            version: BACKUP_MANIFEST_VERSION,
+            subvolume: subvolume.into(),
+            subvolume_uuid,
+            streams: Vec::new(),
+        }
+    }
+
+    /// Read the manifest of a backup directory, if it has one.
+    pub fn read<T: AsRef<Path>>(dir: T) -> Result<Option<Self>> {
+        let file = match File::open(dir.as_ref().join(BACKUP_MANIFEST)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let manifest: Self = serde_json::from_reader(BufReader::new(file))?;
+        if manifest.version > BACKUP_MANIFEST_VERSION {
+            return Err(BtrfsUtilError::InvalidManifest(format!(
+                "unsupported version {}",
+                manifest.version
+            )));
+        }
+        manifest.check()?;
+        Ok(Some(manifest))
+    }
+
+    /// Write the manifest into a backup directory.
+    ///
+    /// The manifest is written to a temporary file first, so that it is replaced atomically.
+    pub fn write<T: AsRef<Path>>(&self, dir: T) -> Result<()> {
+        let path = dir.as_ref().join(BACKUP_MANIFEST);
+        let tmp = path.with_extension("json.tmp");
+        let mut file = File::create(&tmp)?;
+        serde_json::to_writer_pretty(&mut file, self)?;
+        file.write_all(b"\n")?;
+        file.sync_all()?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Check that every incremental stream follows the stream of its parent.
+    pub fn check(&self) -> Result<()> {
+        let mut previous: Option<&BackupStream> = None;
+        for stream in &self.streams {
+            if let Some(parent) = stream.parent {
+                if previous.map(|previous| previous.uuid) != Some(parent) {
+                    return Err(BtrfsUtilError::InvalidManifest(format!(
+                        "stream {:?} does not follow its parent {}",
+                        stream.file, parent
+                    )));
+                }
+            }
+            previous = Some(stream);
+        }
+        Ok(())
+    }
+
+    /// Split the streams into chains, each starting with a full stream, oldest first.
+    pub fn chains(&self) -> Vec<&[BackupStream]> {
+        let mut chains = Vec::new();
+        let mut start = 0;
+        for (index, stream) in self.streams.iter().enumerate().skip(1) {
+            if stream.is_full() {
+                chains.push(&self.streams[start..index]);
+                start = index;
+            }
+        }
+        if start < self.streams.len() {
+            chains.push(&self.streams[start..]);
+        }
+        chains
+    }
+
+    /// Get the streams to replay, in order, to restore the snapshot with a UUID.
+    pub fn chain_to(&self, uuid: Uuid) -> Option<&[BackupStream]> {
+        self.chains().into_iter().find_map(|chain| {
+            chain
+                .iter()
+                .position(|stream| stream.uuid == uuid)
+                .map(|index| &chain[..=index])
+        })
+    }
+
+    /// Get the latest stream.
+    pub fn latest(&self) -> Option<&BackupStream> {
+        self.streams.last()
+    }
+}
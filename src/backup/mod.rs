@@ -0,0 +1,9 @@
+//! Backups of subvolumes into directories of send streams
+
+mod manifest;
+mod restore;
+mod target;
+
+pub use manifest::*;
+pub use restore::*;
+pub use target::*;
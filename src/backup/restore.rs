@@ -0,0 +1,94 @@
+use crate::backup::BackupStream;
+use crate::backup::BackupTarget;
+use crate::send::receive;
+use crate::send::ChainSnapshot;
+use crate::send::ReceiveOptions;
+use crate::subvolume::Subvolume;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::path::PathBuf;
+
+use chrono::NaiveDateTime;
+
+/// Restore the latest backup taken at or before a point in time into a directory, returning the
+/// path of the restored snapshot.
+///
+/// The full stream of its chain and the incremental streams up to it are received in order.
+/// Streams whose snapshot was already received into the directory's filesystem beneath it are
+/// skipped, so an interrupted restore can be repeated. After each stream, the received UUID and
+/// transaction id of the new snapshot are checked against the manifest. The intermediate
+/// snapshots are kept, as parents for later incremental restores.
+///
+/// This requires elevated privileges(CAP_SYS_ADMIN).
+pub fn restore<T: AsRef<Path>>(
+    target: T,
+    backup: &BackupTarget,
+    point_in_time: NaiveDateTime,
+) -> Result<PathBuf> {
+    let target = target.as_ref();
+    let manifest = backup
+        .manifest()
+        .ok_or_else(|| BtrfsUtilError::InvalidManifest("no backups".to_owned()))?;
+    let selected = manifest
+        .streams
+        .iter()
+        .rev()
+        .find(|stream| stream.created <= point_in_time)
+        .ok_or_else(|| {
+            BtrfsUtilError::InvalidManifest(format!("no backup before {}", point_in_time))
+        })?;
+    let chain = manifest.chain_to(selected.uuid).ok_or_else(|| {
+        BtrfsUtilError::InvalidManifest(format!("no chain leads to {:?}", selected.file))
+    })?;
+
+    fs::create_dir_all(target)?;
+    let mut restored = None;
+    for stream in chain {
+        let existing = ChainSnapshot::scan(target)?.into_iter().find(|snapshot| {
+            snapshot.read_only && snapshot.send_identity() == (stream.uuid, stream.ctransid)
+        });
+        restored = Some(match existing {
+            Some(snapshot) => snapshot.path,
+            None => replay(target, backup.dir(), stream)?,
+        });
+    }
+    // Chains are never empty.
+    Ok(restored.unwrap())
+}
+
+/// Receive a stream into a directory, checking the received snapshot against the manifest.
+fn replay(target: &Path, dir: &Path, stream: &BackupStream) -> Result<PathBuf> {
+    let path = dir.join(&stream.file);
+    let file = File::open(&path)?;
+    if file.metadata()?.len() != stream.bytes {
+        return Err(BtrfsUtilError::InvalidManifest(format!(
+            "stream {:?} is not {} bytes long",
+            stream.file, stream.bytes
+        )));
+    }
+
+    let received = receive(target, BufReader::new(file), &ReceiveOptions::new())?;
+    let snapshot = match received.as_slice() {
+        [snapshot] => snapshot,
+        _ => {
+            return Err(BtrfsUtilError::InvalidManifest(format!(
+                "stream {:?} holds {} snapshots",
+                stream.file,
+                received.len()
+            )))
+        }
+    };
+    let info = Subvolume::get(snapshot.as_path())?.info()?;
+    if info.received_uuid != Some(stream.uuid) || info.stransid != Some(stream.ctransid) {
+        return Err(BtrfsUtilError::InvalidManifest(format!(
+            "stream {:?} was received as {:?}, not {}",
+            stream.file, info.received_uuid, stream.uuid
+        )));
+    }
+    Ok(snapshot.clone())
+}
@@ -0,0 +1,297 @@
+use crate::backup::BackupManifest;
+use crate::backup::BackupStream;
+use crate::send::send;
+use crate::send::SendOptions;
+use crate::subvolume::subvolume_exists;
+use crate::subvolume::DeleteOptions;
+use crate::subvolume::SnapshotOptions;
+use crate::subvolume::Subvolume;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+use std::path::PathBuf;
+
+use chrono::Local;
+use uuid::Uuid;
+
+/// Options for a backup.
+///
+/// Used with [BackupTarget::backup].
+///
+/// [BackupTarget::backup]: struct.BackupTarget.html#method.backup
+#[derive(Clone, Debug)]
+pub struct BackupOptions {
+    pub(crate) snapshot_dir: PathBuf,
+    pub(crate) max_incrementals: usize,
+    pub(crate) keep_chains: usize,
+    pub(crate) keep_snapshots: bool,
+}
+
+impl BackupOptions {
+    /// Create the default backup options, taking snapshots into a directory.
+    ///
+    /// The directory must be on the same filesystem as the backed up subvolume.
+    pub fn new<T: Into<PathBuf>>(snapshot_dir: T) -> Self {
+        Self {
+            snapshot_dir: snapshot_dir.into(),
+            max_incrementals: 30,
+            keep_chains: 2,
+            keep_snapshots: false,
+        }
+    }
+
+    /// Set the number of incremental streams after which a new full stream is started. Zero only
+    /// writes full streams.
+    pub fn max_incrementals(mut self, max_incrementals: usize) -> Self {
+        self.max_incrementals = max_incrementals;
+        self
+    }
+
+    /// Set the number of chains, each of a full stream and its incrementals, to keep. Streams of
+    /// older chains are deleted. At least one chain is always kept.
+    pub fn keep_chains(mut self, keep_chains: usize) -> Self {
+        self.keep_chains = keep_chains;
+        self
+    }
+
+    /// Keep the snapshots streams were generated from. By default, only the latest snapshot is
+    /// kept, as the parent of the next incremental stream.
+    pub fn keep_snapshots(mut self, keep_snapshots: bool) -> Self {
+        self.keep_snapshots = keep_snapshots;
+        self
+    }
+}
+
+/// A directory of full and incremental send streams of a subvolume, described by a
+/// [BackupManifest].
+///
+/// [BackupManifest]: struct.BackupManifest.html
+#[derive(Clone, Debug)]
+pub struct BackupTarget {
+    dir: PathBuf,
+    manifest: Option<BackupManifest>,
+}
+
+impl BackupTarget {
+    /// Open a backup directory, creating it if it does not exist.
+    pub fn open<T: Into<PathBuf>>(dir: T) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let manifest = BackupManifest::read(&dir)?;
+        Ok(Self { dir, manifest })
+    }
+
+    /// Get the path of the backup directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Get the manifest of the backup directory, if anything was backed up into it.
+    pub fn manifest(&self) -> Option<&BackupManifest> {
+        self.manifest.as_ref()
+    }
+
+    /// Back up a subvolume, returning the new stream.
+    ///
+    /// A read-only snapshot of the subvolume is taken, and a stream of it is written, incremental
+    /// to the latest snapshot if it still exists and the current chain is not too long. Obsolete
+    /// chains and snapshots are then pruned. A directory only holds backups of a single
+    /// subvolume. This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn backup<T: AsRef<Path>>(
+        &mut self,
+        subvolume: T,
+        options: &BackupOptions,
+    ) -> Result<BackupStream> {
+        let subvolume = subvolume.as_ref();
+        let source = Subvolume::get(subvolume)?;
+        let source_uuid = source.info()?.uuid;
+        let mut manifest = match self.manifest.take() {
+            Some(manifest) if manifest.subvolume_uuid != source_uuid => {
+                let err = BtrfsUtilError::InvalidManifest(format!(
+                    "directory holds backups of {:?}",
+                    manifest.subvolume
+                ));
+                self.manifest = Some(manifest);
+                return Err(err);
+            }
+            Some(manifest) => manifest,
+            None => BackupManifest::new(subvolume, source_uuid),
+        };
+
+        let parent = parent_snapshot(&manifest, options)?;
+        let now = Local::now().naive_local();
+        let name = format!(
+            "{}.{}",
+            subvolume
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            now.format("%Y-%m-%dT%H:%M:%S")
+        );
+        let snapshot_path = options.snapshot_dir.join(&name);
+        fs::create_dir_all(&options.snapshot_dir)?;
+        let snapshot = source.snapshot_with(
+            snapshot_path.as_path(),
+            SnapshotOptions::new().read_only(true),
+        )?;
+
+        let result = self.write_stream(&name, &snapshot, parent.as_ref());
+        let (file, bytes) = match result {
+            Ok(val) => val,
+            Err(e) => {
+                let _ = snapshot.delete_with(DeleteOptions::default());
+                self.manifest = Some(manifest);
+                return Err(e);
+            }
+        };
+        let info = snapshot.info()?;
+        let stream = BackupStream {
+            file,
+            snapshot: snapshot_path,
+            uuid: info.uuid,
+            ctransid: info.ctransid,
+            parent: parent.map(|(_, uuid)| uuid),
+            created: now,
+            bytes,
+        };
+        manifest.streams.push(stream.clone());
+        self.manifest.insert(manifest).write(&self.dir)?;
+        self.prune(options)?;
+        Ok(stream)
+    }
+
+    /// Write the stream of a snapshot into a file, returning its name and size.
+    ///
+    /// The stream is written to a temporary file first, so that the directory never holds a
+    /// truncated stream under its final name.
+    fn write_stream(
+        &self,
+        name: &str,
+        snapshot: &Subvolume,
+        parent: Option<&(Subvolume, Uuid)>,
+    ) -> Result<(PathBuf, u64)> {
+        let kind = if parent.is_some() { "incr" } else { "full" };
+        let file = PathBuf::from(format!("{}.{}.btrfs", name, kind));
+        let path = self.dir.join(&file);
+        let tmp = self.dir.join(format!("{}.{}.btrfs.part", name, kind));
+
+        let mut options = SendOptions::new();
+        if let Some((parent, _)) = parent {
+            options = options.parent(parent);
+        }
+        let written = (|| -> Result<u64> {
+            let mut writer = BufWriter::new(File::create(&tmp)?);
+            let bytes = send(snapshot, &options, &mut writer)?;
+            writer
+                .into_inner()
+                .map_err(|e| e.into_error())?
+                .sync_all()?;
+            Ok(bytes)
+        })();
+        match written {
+            Ok(bytes) => {
+                fs::rename(&tmp, &path)?;
+                Ok((file, bytes))
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&tmp);
+                Err(e)
+            }
+        }
+    }
+
+    /// Delete the streams of obsolete chains and the snapshots which are no longer needed as
+    /// parents, returning the paths of the deleted files and snapshots.
+    ///
+    /// This is done after every backup. This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn prune(&mut self, options: &BackupOptions) -> Result<Vec<PathBuf>> {
+        let manifest = match self.manifest.as_mut() {
+            Some(manifest) => manifest,
+            None => return Ok(Vec::new()),
+        };
+        let mut deleted = Vec::new();
+
+        let chains = manifest.chains();
+        let keep_chains = options.keep_chains.max(1);
+        let obsolete: usize = chains
+            .iter()
+            .take(chains.len().saturating_sub(keep_chains))
+            .map(|chain| chain.len())
+            .sum();
+        if obsolete > 0 {
+            let removed: Vec<BackupStream> = manifest.streams.drain(..obsolete).collect();
+            // The manifest is updated first, so that it never lists missing files.
+            manifest.write(&self.dir)?;
+            for stream in removed {
+                let path = self.dir.join(&stream.file);
+                match fs::remove_file(&path) {
+                    Ok(()) => deleted.push(path),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+                if !options.keep_snapshots {
+                    if let Some(path) = delete_snapshot(&stream)? {
+                        deleted.push(path);
+                    }
+                }
+            }
+        }
+
+        if !options.keep_snapshots {
+            let len = manifest.streams.len();
+            for stream in manifest.streams.iter().take(len.saturating_sub(1)) {
+                if let Some(path) = delete_snapshot(stream)? {
+                    deleted.push(path);
+                }
+            }
+        }
+        Ok(deleted)
+    }
+}
+
+/// Open the snapshot of a stream, if it still exists and is unchanged since it was sent.
+fn stream_snapshot(stream: &BackupStream) -> Result<Option<Subvolume>> {
+    if !subvolume_exists(stream.snapshot.as_path())? {
+        return Ok(None);
+    }
+    let snapshot = Subvolume::get(stream.snapshot.as_path())?;
+    let info = snapshot.info()?;
+    if info.uuid == stream.uuid && info.ctransid == stream.ctransid && info.is_read_only() {
+        Ok(Some(snapshot))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Delete the snapshot of a stream, if it still exists, returning its path.
+fn delete_snapshot(stream: &BackupStream) -> Result<Option<PathBuf>> {
+    match stream_snapshot(stream)? {
+        Some(snapshot) => {
+            snapshot.delete_with(DeleteOptions::default())?;
+            Ok(Some(stream.snapshot.clone()))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Choose the parent of the next stream: the snapshot of the latest stream, unless it is gone or
+/// the current chain is long enough.
+fn parent_snapshot(
+    manifest: &BackupManifest,
+    options: &BackupOptions,
+) -> Result<Option<(Subvolume, Uuid)>> {
+    let chain_len = manifest.chains().last().map_or(0, |chain| chain.len());
+    if chain_len == 0 || chain_len > options.max_incrementals {
+        return Ok(None);
+    }
+    let latest = match manifest.latest() {
+        Some(latest) => latest,
+        None => return Ok(None),
+    };
+    Ok(stream_snapshot(latest)?.map(|snapshot| (snapshot, latest.uuid)))
+}
@@ -0,0 +1,151 @@
+//! A runtime-agnostic pool running blocking calls for async code
+//!
+//! The async wrappers of this crate run the blocking libbtrfsutil calls on this pool, so they can
+//! be awaited from any executor, e.g. smol or async-std. With the `tokio` feature, the tokio
+//! blocking thread pool is used instead when called from within a tokio runtime.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+use std::thread;
+use std::time::Duration;
+
+/// Maximum number of threads of the pool.
+const MAX_THREADS: usize = 64;
+
+/// Time after which an idle thread of the pool exits.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct PoolState {
+    queue: VecDeque<Job>,
+    threads: usize,
+    idle: usize,
+}
+
+struct Pool {
+    state: Mutex<PoolState>,
+    available: Condvar,
+}
+
+static POOL: Pool = Pool {
+    state: Mutex::new(PoolState {
+        queue: VecDeque::new(),
+        threads: 0,
+        idle: 0,
+    }),
+    available: Condvar::new(),
+};
+
+impl Pool {
+    fn submit(&'static self, job: Job) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.queue.push_back(job);
+        if state.queue.len() > state.idle && state.threads < MAX_THREADS {
+            state.threads += 1;
+            let spawned = thread::Builder::new()
+                .name("btrfsutil-blocking".to_string())
+                .spawn(move || self.work());
+            if spawned.is_err() {
+                // The queued job is still run by one of the existing threads, if any.
+                state.threads -= 1;
+                assert!(state.threads > 0, "failed to spawn a blocking thread");
+            }
+        }
+        self.available.notify_one();
+    }
+
+    fn work(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(job) = state.queue.pop_front() {
+                drop(state);
+                job();
+                state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+                continue;
+            }
+            state.idle += 1;
+            let (guard, timeout) = self
+                .available
+                .wait_timeout(state, IDLE_TIMEOUT)
+                .unwrap_or_else(|e| e.into_inner());
+            state = guard;
+            state.idle -= 1;
+            if timeout.timed_out() && state.queue.is_empty() {
+                state.threads -= 1;
+                return;
+            }
+        }
+    }
+}
+
+struct Shared<R> {
+    result: Option<thread::Result<R>>,
+    waker: Option<Waker>,
+}
+
+/// The result of a closure running on the blocking pool, created by [unblock].
+///
+/// Dropping it does not stop the closure, whose result is then discarded.
+///
+/// [unblock]: fn.unblock.html
+pub struct Unblock<R> {
+    shared: Arc<Mutex<Shared<R>>>,
+}
+
+impl<R> Future for Unblock<R> {
+    type Output = R;
+
+    /// Poll for the result of the closure.
+    ///
+    /// # Panics
+    ///
+    /// Panics raised by the closure are resumed here.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+        let mut shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        match shared.result.take() {
+            Some(Ok(val)) => Poll::Ready(val),
+            Some(Err(payload)) => panic::resume_unwind(payload),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Run a blocking closure on the pool of this crate, resolving to its result.
+///
+/// Works with any executor, since the pool wakes the awaiting task itself.
+pub fn unblock<F, R>(f: F) -> Unblock<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let shared = Arc::new(Mutex::new(Shared {
+        result: None,
+        waker: None,
+    }));
+    let job_shared = Arc::clone(&shared);
+    POOL.submit(Box::new(move || {
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        let waker = {
+            let mut shared = job_shared.lock().unwrap_or_else(|e| e.into_inner());
+            shared.result = Some(result);
+            shared.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }));
+    Unblock { shared }
+}
@@ -0,0 +1,166 @@
+//! Boot menu entries for snapshots of the root filesystem
+
+use crate::common;
+use crate::filesystem::Filesystem;
+use crate::send::subvolume_root;
+use crate::subvolume::Subvolume;
+use crate::subvolume::SubvolumeInfo;
+use crate::subvolume::SubvolumeIterator;
+use crate::Result;
+
+use std::fs;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+
+use chrono::NaiveDateTime;
+#[cfg(feature = "json")]
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Directory of a root filesystem holding the kernels and initial ramdisks.
+pub const BOOT_DIR: &str = "boot";
+
+/// A kernel found in a snapshot.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct BootKernel {
+    /// Version of the kernel, taken from its file name, e.g. `6.1.0-13-amd64` or `linux`.
+    pub version: Option<String>,
+    /// Path of the kernel image, relative to the snapshot.
+    pub kernel: PathBuf,
+    /// Path of the matching initial ramdisk, relative to the snapshot, if there is one.
+    pub initrd: Option<PathBuf>,
+}
+
+/// A read-only snapshot of the root subvolume, with the kernels it contains.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct BootableSnapshot {
+    /// Path of the snapshot.
+    pub path: PathBuf,
+    /// Id of the snapshot, for the `subvolid` mount option.
+    pub id: u64,
+    /// UUID of the snapshot.
+    pub uuid: Uuid,
+    /// Path of the snapshot relative to the top level subvolume, for the `subvol` mount option.
+    pub subvol: PathBuf,
+    /// Time when the snapshot was created.
+    pub created: NaiveDateTime,
+    /// Kernels in the boot directory of the snapshot, sorted by file name.
+    pub kernels: Vec<BootKernel>,
+}
+
+impl BootableSnapshot {
+    /// Check whether the snapshot contains a kernel with an initial ramdisk.
+    pub fn is_bootable(&self) -> bool {
+        self.kernels.iter().any(|kernel| kernel.initrd.is_some())
+    }
+}
+
+/// List the read-only snapshots of the root subvolume of a filesystem, oldest first.
+///
+/// The root subvolume is the default subvolume of the filesystem. The snapshots beneath the
+/// subvolume containing the path the filesystem was opened with are considered, and the boot
+/// directory of each is checked for kernels and their initial ramdisks, so that boot menu
+/// entries can be generated from the result directly. Snapshots without kernels are listed as
+/// well, see [BootableSnapshot::is_bootable].
+///
+/// [BootableSnapshot::is_bootable]: struct.BootableSnapshot.html#method.is_bootable
+pub fn bootable_snapshots(fs: &Filesystem) -> Result<Vec<BootableSnapshot>> {
+    let fs_path = common::path_to_cstr(fs.path().to_path_buf())?;
+    let root_id = Subvolume::get_default(Some(fs.path()))?.id();
+    let root_uuid = SubvolumeInfo::fetch(&fs_path, root_id)?.uuid;
+
+    let top = subvolume_root(fs.path())?;
+    let mut snapshots = Vec::new();
+    for entry in SubvolumeIterator::builder_for_fd(fs.as_raw_fd())
+        .read_only()
+        .snapshots()
+        .iter_with_info()?
+    {
+        let (path, info) = entry?;
+        if info.parent_uuid != Some(root_uuid) {
+            continue;
+        }
+        let path = top.join(path);
+        snapshots.push(BootableSnapshot {
+            kernels: kernels(&path)?,
+            subvol: fs.subvolume_path(info.id)?,
+            path,
+            id: info.id,
+            uuid: info.uuid,
+            created: info.otime,
+        });
+    }
+    snapshots.sort_by_key(|snapshot| snapshot.created);
+    Ok(snapshots)
+}
+
+/// Find the kernels in the boot directory of a snapshot, with their initial ramdisks.
+fn kernels(snapshot: &Path) -> Result<Vec<BootKernel>> {
+    let boot = snapshot.join(BOOT_DIR);
+    let entries = match fs::read_dir(&boot) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        names.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+
+    let is_file = |name: &str| boot.join(name).is_file();
+    let mut kernels = Vec::new();
+    for name in &names {
+        let version = match kernel_version(name) {
+            Some(version) => version,
+            None => continue,
+        };
+        if !is_file(name) {
+            continue;
+        }
+        let initrd = initrd_names(version)
+            .into_iter()
+            .find(|initrd| is_file(initrd))
+            .map(|initrd| Path::new(BOOT_DIR).join(initrd));
+        kernels.push(BootKernel {
+            version: version.map(str::to_owned),
+            kernel: Path::new(BOOT_DIR).join(name),
+            initrd,
+        });
+    }
+    Ok(kernels)
+}
+
+/// Get the version of a kernel from its file name, `None` if it is not the name of a kernel and
+/// `Some(None)` if it is one without a version.
+fn kernel_version(name: &str) -> Option<Option<&str>> {
+    ["vmlinuz", "vmlinux"].iter().find_map(|prefix| {
+        let rest = name.strip_prefix(prefix)?;
+        if rest.is_empty() {
+            Some(None)
+        } else {
+            rest.strip_prefix('-').map(Some)
+        }
+    })
+}
+
+/// Get the file names an initial ramdisk for a kernel version may have, by distribution.
+fn initrd_names(version: Option<&str>) -> Vec<String> {
+    match version {
+        Some(version) => vec![
+            format!("initrd.img-{}", version),
+            format!("initramfs-{}.img", version),
+            format!("initrd-{}", version),
+            format!("initrd-{}.img", version),
+        ],
+        None => vec![
+            "initrd.img".to_owned(),
+            "initramfs.img".to_owned(),
+            "initrd".to_owned(),
+        ],
+    }
+}
@@ -0,0 +1,153 @@
+//! Cancellation of long-running operations
+//!
+//! A [CancelToken] is passed to an operation through its options and cancelled from another
+//! thread. Operations performed by the kernel, like balance and scrub, are cancelled with the
+//! kernel cancel request. Others stop at the next point where they can stop cleanly. Either way,
+//! the operation fails with [BtrfsUtilError::Cancelled].
+//!
+//! [CancelToken]: struct.CancelToken.html
+//! [BtrfsUtilError::Cancelled]: ../error/enum.BtrfsUtilError.html#variant.Cancelled
+
+use crate::filesystem::Filesystem;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+type Hook = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct State {
+    cancelled: bool,
+    next_hook: u64,
+    hooks: Vec<(u64, Hook)>,
+}
+
+/// A token to cancel operations with.
+///
+/// Clones share the same state, so a clone can be kept to cancel an operation given the token.
+/// Once cancelled, a token stays cancelled.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    state: Arc<Mutex<State>>,
+}
+
+impl CancelToken {
+    /// Create a token which is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the operations given this token or a clone of it.
+    ///
+    /// The kernel cancel requests of running operations are issued on the calling thread, which
+    /// may block until the kernel acknowledges them.
+    pub fn cancel(&self) {
+        let hooks = {
+            let mut state = self.lock();
+            state.cancelled = true;
+            std::mem::take(&mut state.hooks)
+        };
+        for (_, hook) in hooks {
+            hook();
+        }
+    }
+
+    /// Check whether the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.lock().cancelled
+    }
+
+    /// Fail with [BtrfsUtilError::Cancelled] if the token was cancelled.
+    ///
+    /// [BtrfsUtilError::Cancelled]: ../error/enum.BtrfsUtilError.html#variant.Cancelled
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(BtrfsUtilError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Call a hook when the token is cancelled, or right away if it already is, until the
+    /// returned guard is dropped.
+    pub(crate) fn on_cancel<F: FnOnce() + Send + 'static>(&self, hook: F) -> CancelHook<'_> {
+        let mut state = self.lock();
+        let id = state.next_hook;
+        state.next_hook += 1;
+        if state.cancelled {
+            drop(state);
+            hook();
+        } else {
+            state.hooks.push((id, Box::new(hook)));
+        }
+        CancelHook { token: self, id }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Guard of a hook registered with [CancelToken::on_cancel], which is unregistered when this is
+/// dropped.
+///
+/// [CancelToken::on_cancel]: struct.CancelToken.html#method.on_cancel
+pub(crate) struct CancelHook<'a> {
+    token: &'a CancelToken,
+    id: u64,
+}
+
+impl Drop for CancelHook<'_> {
+    fn drop(&mut self) {
+        self.token.lock().hooks.retain(|(id, _)| *id != self.id);
+    }
+}
+
+/// Check an optional token, doing nothing without one.
+pub(crate) fn check(token: Option<&CancelToken>) -> Result<()> {
+    token.map_or(Ok(()), CancelToken::check)
+}
+
+/// Issue a kernel cancel request on a filesystem when an optional token is cancelled, until the
+/// returned guard is dropped. Fails if the token already is.
+pub(crate) fn cancel_on_filesystem<'a>(
+    token: Option<&'a CancelToken>,
+    fs: &Filesystem,
+    cancel: fn(&Filesystem) -> Result<bool>,
+) -> Result<Option<CancelHook<'a>>> {
+    let token = match token {
+        Some(token) => token,
+        None => return Ok(None),
+    };
+    let fs = fs.try_clone()?;
+    let hook = token.on_cancel(move || {
+        let _ = cancel(&fs);
+    });
+    token.check()?;
+    Ok(Some(hook))
+}
+
+/// Convert the error of an operation to [BtrfsUtilError::Cancelled] if an optional token was
+/// cancelled, since the kernel fails cancelled operations with `ECANCELED`.
+///
+/// [BtrfsUtilError::Cancelled]: ../error/enum.BtrfsUtilError.html#variant.Cancelled
+pub(crate) fn map_cancelled(token: Option<&CancelToken>, e: io::Error) -> BtrfsUtilError {
+    if token.is_some_and(CancelToken::is_cancelled) {
+        BtrfsUtilError::Cancelled
+    } else {
+        e.into()
+    }
+}
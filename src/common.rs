@@ -1,7 +1,13 @@
 use crate::error::GlueError;
 use crate::Result;
 
+use std::ffi::CStr;
 use std::ffi::CString;
+use std::ffi::OsStr;
+use std::io;
+use std::os::raw::c_char;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
 use std::path::PathBuf;
 
 /// Convert an Into<PathBuf> into a CString.
@@ -35,6 +41,165 @@ pub(crate) fn optional_into_path_to_cstr<T: Into<PathBuf>>(path: Option<T>) -> R
     path_to_cstr(path)
 }
 
+/// Convert a path and an extended attribute name into CStrings.
+fn xattr_cstrs(path: &Path, name: &str) -> io::Result<(CString, CString)> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidInput, e);
+    Ok((
+        CString::new(path.as_os_str().as_bytes()).map_err(invalid)?,
+        CString::new(name).map_err(invalid)?,
+    ))
+}
+
+/// Get an extended attribute of a file, without following symlinks, or `None` if it is not set.
+pub(crate) fn get_xattr(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+    let (path, name) = xattr_cstrs(path, name)?;
+    loop {
+        let len = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+        if len < 0 {
+            let e = io::Error::last_os_error();
+            return match e.raw_os_error() {
+                Some(libc::ENODATA) => Ok(None),
+                _ => Err(e),
+            };
+        }
+        let mut value: Vec<u8> = vec![0; len as usize];
+        let len = unsafe {
+            libc::lgetxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr() as *mut libc::c_void,
+                value.len(),
+            )
+        };
+        if len >= 0 {
+            value.truncate(len as usize);
+            return Ok(Some(value));
+        }
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            // The value grew in between.
+            Some(libc::ERANGE) => continue,
+            Some(libc::ENODATA) => return Ok(None),
+            _ => return Err(e),
+        }
+    }
+}
+
+/// Set an extended attribute of a file, without following symlinks.
+pub(crate) fn set_xattr(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    let (path, name) = xattr_cstrs(path, name)?;
+    let ret = unsafe {
+        libc::lsetxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Remove an extended attribute of a file, without following symlinks, succeeding if it is not
+/// set.
+pub(crate) fn remove_xattr(path: &Path, name: &str) -> io::Result<()> {
+    let (path, name) = xattr_cstrs(path, name)?;
+    let ret = unsafe { libc::lremovexattr(path.as_ptr(), name.as_ptr()) };
+    if ret < 0 {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::ENODATA) {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Run a blocking closure on a blocking thread pool and wait for its result.
+///
+/// The tokio blocking thread pool is used when called from within a tokio runtime, the pool of
+/// [blocking] otherwise. Panics raised by the closure are resumed in the caller.
+///
+/// [blocking]: ../blocking/index.html
+#[cfg(feature = "async")]
+pub(crate) async fn spawn_blocking<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    #[cfg(feature = "tokio")]
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return join_blocking(tokio::task::spawn_blocking(f)).await;
+    }
+    crate::blocking::unblock(f).await
+}
+
+/// Wait for the result of a closure running on the tokio blocking thread pool.
+///
+/// Panics raised by the closure are resumed in the caller.
+#[cfg(feature = "tokio")]
+pub(crate) async fn join_blocking<R>(handle: tokio::task::JoinHandle<R>) -> R {
+    match handle.await {
+        Ok(val) => val,
+        Err(e) => {
+            if e.is_panic() {
+                std::panic::resume_unwind(e.into_panic())
+            } else {
+                panic!("Blocking task cancelled: {}", e)
+            }
+        }
+    }
+}
+
+/// A NUL-terminated string allocated by [libbtrfsutil].
+///
+/// The underlying buffer is owned by this struct and released with `free()` exactly once, when it
+/// is dropped. Borrowed views can be obtained without copying the string.
+///
+/// [libbtrfsutil]: https://github.com/kdave/btrfs-progs/tree/master/libbtrfsutil
+pub(crate) struct LibString(*mut c_char);
+
+impl LibString {
+    /// Take ownership of a string returned by [libbtrfsutil].
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be non-null, point to a NUL-terminated string allocated with `malloc()`
+    /// and must not be owned by anything else.
+    ///
+    /// [libbtrfsutil]: https://github.com/kdave/btrfs-progs/tree/master/libbtrfsutil
+    #[inline]
+    pub(crate) unsafe fn from_raw(ptr: *mut c_char) -> Self {
+        Self(ptr)
+    }
+
+    /// Borrow this string as a [CStr].
+    ///
+    /// [CStr]: https://doc.rust-lang.org/stable/std/ffi/struct.CStr.html
+    #[inline]
+    pub(crate) fn as_c_str(&self) -> &CStr {
+        unsafe { CStr::from_ptr(self.0) }
+    }
+
+    /// Borrow this string as a [Path]. No UTF-8 validation is performed.
+    ///
+    /// [Path]: https://doc.rust-lang.org/stable/std/path/struct.Path.html
+    #[inline]
+    pub(crate) fn as_path(&self) -> &Path {
+        Path::new(OsStr::from_bytes(self.as_c_str().to_bytes()))
+    }
+}
+
+impl Drop for LibString {
+    fn drop(&mut self) {
+        unsafe {
+            libc::free(self.0 as *mut libc::c_void);
+        }
+    }
+}
+
 /// Macro for simplifying an `if let Some(val) {} else {}` statement.
 macro_rules! if_let_some {
     ($option: ident, $val_name: ident, $some: expr, $none: expr) => {
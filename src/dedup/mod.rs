@@ -0,0 +1,16 @@
+//! Deduplication of identical data across files, like a small duperemove
+//!
+//! Files are read in fixed size blocks, which are hashed to find the blocks holding the same
+//! data. Runs of consecutive duplicate blocks are merged into ranges, which are then submitted to
+//! the kernel with [dedupe_ranges]. The kernel compares the data again before sharing the
+//! extents, so hash collisions and files modified in between cannot corrupt data.
+//!
+//! [dedupe_ranges]: ../extent/fn.dedupe_ranges.html
+
+mod options;
+mod run;
+mod scan;
+
+pub use options::*;
+pub use run::*;
+pub use scan::*;
@@ -0,0 +1,155 @@
+use crate::cancel::CancelToken;
+use crate::progress::Progress;
+use crate::rate_limit::RateLimit;
+
+use std::time::Duration;
+
+/// Default size of the blocks files are hashed in, in bytes.
+pub const DEFAULT_DEDUP_BLOCK_SIZE: u64 = 128 * 1024;
+
+/// Options of [find_duplicates] and [dedup].
+///
+/// [find_duplicates]: fn.find_duplicates.html
+/// [dedup]: fn.dedup.html
+#[derive(Clone, Debug)]
+pub struct DedupOptions {
+    pub(crate) block_size: u64,
+    pub(crate) min_size: u64,
+    pub(crate) threads: usize,
+    pub(crate) dry_run: bool,
+    pub(crate) cancel: Option<CancelToken>,
+    pub(crate) rate_limit: Option<RateLimit>,
+}
+
+impl Default for DedupOptions {
+    fn default() -> Self {
+        Self {
+            block_size: DEFAULT_DEDUP_BLOCK_SIZE,
+            min_size: DEFAULT_DEDUP_BLOCK_SIZE,
+            threads: 1,
+            dry_run: false,
+            cancel: None,
+            rate_limit: None,
+        }
+    }
+}
+
+impl DedupOptions {
+    /// Create the default options, hashing blocks of [DEFAULT_DEDUP_BLOCK_SIZE] on one thread.
+    ///
+    /// [DEFAULT_DEDUP_BLOCK_SIZE]: constant.DEFAULT_DEDUP_BLOCK_SIZE.html
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the size of the blocks files are hashed in, in bytes, which must be a multiple of the
+    /// block size of the filesystem. Smaller blocks find more duplicates, but take more memory
+    /// and create more extents.
+    pub fn block_size(mut self, block_size: u64) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Skip files smaller than a size, in bytes. Defaults to the block size.
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Set the number of threads hashing files and submitting deduplications.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Only find the duplicates, without deduplicating them.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Stop hashing or deduplicating when a token is cancelled, failing with
+    /// [BtrfsUtilError::Cancelled]. The files and sets being processed are finished first, so no
+    /// deduplication is interrupted.
+    ///
+    /// [BtrfsUtilError::Cancelled]: ../error/enum.BtrfsUtilError.html#variant.Cancelled
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Limit the rate of the I/O, e.g. in bytes per second, shared by all threads. The bytes
+    /// hashed and the bytes compared by the kernel when deduplicating both count.
+    pub fn rate_limit<L: Into<RateLimit>>(mut self, limit: L) -> Self {
+        self.rate_limit = Some(limit.into());
+        self
+    }
+}
+
+/// Phase of a deduplication.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DedupPhase {
+    /// Files are being hashed.
+    Scanning,
+    /// Duplicate ranges are being submitted to the kernel.
+    Deduplicating,
+}
+
+/// Progress of a deduplication, passed to the progress callback.
+#[derive(Clone, Debug)]
+pub struct DedupProgress {
+    /// Current phase.
+    pub phase: DedupPhase,
+    /// Number of files hashed so far.
+    pub files_scanned: u64,
+    /// Number of files to hash.
+    pub files_total: u64,
+    /// Number of bytes hashed so far.
+    pub bytes_scanned: u64,
+    /// Number of duplicate sets submitted so far.
+    pub sets_done: u64,
+    /// Number of duplicate sets found.
+    pub sets_total: u64,
+    /// Number of bytes deduplicated so far.
+    pub bytes_deduped: u64,
+    /// Time elapsed since the deduplication started.
+    pub elapsed: Duration,
+    /// I/O rate over the last few seconds, in bytes per second.
+    pub throughput: f64,
+}
+
+impl Progress for DedupProgress {
+    fn phase(&self) -> &'static str {
+        match self.phase {
+            DedupPhase::Scanning => "scanning",
+            DedupPhase::Deduplicating => "deduplicating",
+        }
+    }
+
+    /// Get the number of files hashed while scanning, or sets submitted while deduplicating.
+    fn items_done(&self) -> u64 {
+        match self.phase {
+            DedupPhase::Scanning => self.files_scanned,
+            DedupPhase::Deduplicating => self.sets_done,
+        }
+    }
+
+    fn items_total(&self) -> Option<u64> {
+        match self.phase {
+            DedupPhase::Scanning => Some(self.files_total),
+            DedupPhase::Deduplicating => Some(self.sets_total),
+        }
+    }
+
+    /// Get the number of bytes hashed while scanning, deduplicated while deduplicating.
+    fn bytes_done(&self) -> u64 {
+        match self.phase {
+            DedupPhase::Scanning => self.bytes_scanned,
+            DedupPhase::Deduplicating => self.bytes_deduped,
+        }
+    }
+
+    fn throughput(&self) -> Option<f64> {
+        Some(self.throughput)
+    }
+}
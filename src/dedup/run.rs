@@ -0,0 +1,155 @@
+use crate::cancel;
+use crate::dedup::scan::collect_files;
+use crate::dedup::scan::for_each_parallel;
+use crate::dedup::scan::new_progress;
+use crate::dedup::scan::scan;
+use crate::dedup::DedupOptions;
+use crate::dedup::DedupPhase;
+use crate::dedup::DedupProgress;
+use crate::dedup::DuplicateSet;
+use crate::extent::dedupe_ranges;
+use crate::extent::DedupeStatus;
+use crate::extent::DedupeTarget;
+use crate::rate_limit::Limiter;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Result of [dedup].
+///
+/// [dedup]: fn.dedup.html
+#[derive(Debug, Default)]
+pub struct DedupReport {
+    /// Number of files hashed.
+    pub files: u64,
+    /// Number of bytes hashed.
+    pub bytes_scanned: u64,
+    /// Number of duplicate sets found.
+    pub duplicate_sets: u64,
+    /// Number of bytes which deduplicating the sets can free, at most.
+    pub reclaimable: u64,
+    /// Number of bytes deduplicated, zero for a dry run.
+    pub bytes_deduped: u64,
+    /// Number of ranges left alone because their data differed when they were submitted, e.g.
+    /// because the file was modified after it was hashed.
+    pub differs: u64,
+    /// Ranges which could not be deduplicated, with the error.
+    pub failed: Vec<(PathBuf, BtrfsUtilError)>,
+}
+
+/// Outcome of the submission of a duplicate set.
+#[derive(Default)]
+struct SetOutcome {
+    bytes_deduped: u64,
+    differs: u64,
+    failed: Vec<(PathBuf, BtrfsUtilError)>,
+}
+
+/// Find the ranges of files holding the same data and deduplicate them, like `duperemove -dr`.
+///
+/// Files are found and hashed like [find_duplicates] does. Each duplicate set is then submitted
+/// with the first range as the source, on as many threads as configured. A failure of a range is
+/// reported without stopping the deduplication. The ranges must be writable by the caller, unless
+/// the caller has elevated privileges(CAP_SYS_ADMIN). The progress callback is called on the
+/// calling thread after each file and each set.
+///
+/// [find_duplicates]: fn.find_duplicates.html
+pub fn dedup<P, F>(paths: &[P], options: &DedupOptions, mut progress: F) -> Result<DedupReport>
+where
+    P: AsRef<Path>,
+    F: FnMut(&DedupProgress),
+{
+    let limiter = Limiter::new(options.rate_limit);
+    let files = collect_files(paths, options)?;
+    let mut state = new_progress(files.len() as u64);
+    let sets = scan(&files, options, &limiter, &mut state, &mut progress)?;
+
+    let mut report = DedupReport {
+        files: state.files_scanned,
+        bytes_scanned: state.bytes_scanned,
+        duplicate_sets: sets.len() as u64,
+        reclaimable: sets.iter().map(DuplicateSet::reclaimable).sum(),
+        ..Default::default()
+    };
+    if options.dry_run {
+        return Ok(report);
+    }
+
+    state.phase = DedupPhase::Deduplicating;
+    state.sets_total = sets.len() as u64;
+    let work = |set: &DuplicateSet| submit(set, &limiter);
+    for_each_parallel(&sets, options.threads, work, |outcome| {
+        report.bytes_deduped += outcome.bytes_deduped;
+        report.differs += outcome.differs;
+        report.failed.extend(outcome.failed);
+        state.sets_done += 1;
+        state.bytes_deduped = report.bytes_deduped;
+        state.elapsed = limiter.elapsed();
+        state.throughput = limiter.throughput();
+        progress(&state);
+        cancel::check(options.cancel.as_ref())
+    })?;
+    Ok(report)
+}
+
+/// Deduplicate the ranges of a set against its first one.
+fn submit(set: &DuplicateSet, limiter: &Limiter) -> SetOutcome {
+    let mut outcome = SetOutcome::default();
+    let (source, ranges) = match set.ranges.split_first() {
+        Some(split) => split,
+        None => return outcome,
+    };
+    let src = match File::open(&source.path) {
+        Ok(src) => src,
+        Err(e) => {
+            outcome.failed.push((source.path.clone(), e.into()));
+            return outcome;
+        }
+    };
+
+    let mut opened = Vec::new();
+    for range in ranges {
+        match open_target(&range.path) {
+            Ok(file) => opened.push((range, file)),
+            Err(e) => outcome.failed.push((range.path.clone(), e.into())),
+        }
+    }
+    let targets: Vec<DedupeTarget<'_, File>> = opened
+        .iter()
+        .map(|(range, file)| DedupeTarget::new(file, range.offset))
+        .collect();
+    // The kernel reads the source and every target to compare them.
+    limiter.acquire(set.len * (targets.len() as u64 + 1));
+    let results = match dedupe_ranges(&src, source.offset, set.len, &targets) {
+        Ok(results) => results,
+        Err(e) => {
+            outcome.failed.push((source.path.clone(), e));
+            return outcome;
+        }
+    };
+    for ((range, _), result) in opened.iter().zip(results) {
+        match result.status {
+            DedupeStatus::Same => outcome.bytes_deduped += result.bytes_deduped,
+            DedupeStatus::Differs => outcome.differs += 1,
+            DedupeStatus::Failed(errno) => outcome.failed.push((
+                range.path.clone(),
+                io::Error::from_raw_os_error(errno).into(),
+            )),
+        }
+    }
+    outcome
+}
+
+/// Open the file of a target range for writing, or for reading if that is not allowed, which is
+/// enough with elevated privileges.
+fn open_target(path: &Path) -> io::Result<File> {
+    match OpenOptions::new().write(true).open(path) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => File::open(path),
+        result => result,
+    }
+}
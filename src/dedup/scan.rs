@@ -0,0 +1,390 @@
+use crate::cancel;
+use crate::dedup::DedupOptions;
+use crate::dedup::DedupPhase;
+use crate::dedup::DedupProgress;
+use crate::rate_limit::Limiter;
+use crate::Result;
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io;
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Maximum length of a single deduplication on Btrfs, in bytes.
+const BTRFS_MAX_DEDUPE_LEN: u64 = 16 * 1024 * 1024;
+
+/// A range of a file holding duplicate data.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct DuplicateRange {
+    /// Path of the file.
+    pub path: PathBuf,
+    /// Start of the range within the file, in bytes.
+    pub offset: u64,
+}
+
+/// Ranges of files which hold the same data, as far as their hashes tell.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DuplicateSet {
+    /// Length of the ranges, in bytes.
+    pub len: u64,
+    /// The ranges, at least two, in the order their files were found.
+    pub ranges: Vec<DuplicateRange>,
+}
+
+impl DuplicateSet {
+    /// Get the number of bytes which deduplicating the ranges can free, at most.
+    pub fn reclaimable(&self) -> u64 {
+        self.len * (self.ranges.len() as u64 - 1)
+    }
+}
+
+/// A file to hash.
+#[derive(Debug)]
+pub(super) struct ScanFile {
+    path: PathBuf,
+    size: u64,
+}
+
+/// Find the ranges of files holding the same data, without deduplicating them.
+///
+/// Directories are walked recursively, without following symbolic links. Every file is only
+/// hashed once, even if it is reached through several hard links. Only full blocks are compared,
+/// the tail of a file shorter than a block is skipped. The progress callback is called on the
+/// calling thread after each file.
+pub fn find_duplicates<P, F>(
+    paths: &[P],
+    options: &DedupOptions,
+    mut progress: F,
+) -> Result<Vec<DuplicateSet>>
+where
+    P: AsRef<Path>,
+    F: FnMut(&DedupProgress),
+{
+    let files = collect_files(paths, options)?;
+    let mut state = new_progress(files.len() as u64);
+    let limiter = Limiter::new(options.rate_limit);
+    scan(&files, options, &limiter, &mut state, &mut progress)
+}
+
+/// Create the progress of a deduplication of files.
+pub(super) fn new_progress(files_total: u64) -> DedupProgress {
+    DedupProgress {
+        phase: DedupPhase::Scanning,
+        files_scanned: 0,
+        files_total,
+        bytes_scanned: 0,
+        sets_done: 0,
+        sets_total: 0,
+        bytes_deduped: 0,
+        elapsed: Duration::default(),
+        throughput: 0.0,
+    }
+}
+
+/// Collect the regular files below paths which are large enough, once per inode.
+pub(super) fn collect_files<P: AsRef<Path>>(
+    paths: &[P],
+    options: &DedupOptions,
+) -> Result<Vec<ScanFile>> {
+    if options.block_size == 0 || options.block_size % 4096 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the block size must be a multiple of 4096 bytes",
+        )
+        .into());
+    }
+    let mut files = Vec::new();
+    let mut inodes = HashSet::new();
+    for path in paths {
+        collect_path(path.as_ref(), options, &mut inodes, &mut files)?;
+    }
+    Ok(files)
+}
+
+fn collect_path(
+    path: &Path,
+    options: &DedupOptions,
+    inodes: &mut HashSet<(u64, u64)>,
+    files: &mut Vec<ScanFile>,
+) -> Result<()> {
+    cancel::check(options.cancel.as_ref())?;
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            collect_path(&entry?.path(), options, inodes, files)?;
+        }
+    } else if metadata.is_file()
+        && metadata.len() >= options.min_size.max(options.block_size)
+        && inodes.insert((metadata.dev(), metadata.ino()))
+    {
+        files.push(ScanFile {
+            path: path.to_path_buf(),
+            size: metadata.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Hash files and group their duplicate blocks into sets of ranges.
+pub(super) fn scan<F: FnMut(&DedupProgress)>(
+    files: &[ScanFile],
+    options: &DedupOptions,
+    limiter: &Limiter,
+    state: &mut DedupProgress,
+    progress: &mut F,
+) -> Result<Vec<DuplicateSet>> {
+    let mut blocks: HashMap<u64, Vec<(usize, u64)>> = HashMap::new();
+    let indices: Vec<usize> = (0..files.len()).collect();
+    for_each_parallel(
+        &indices,
+        options.threads,
+        |index| {
+            hash_file(&files[*index], options.block_size, limiter).map(|hashes| (*index, hashes))
+        },
+        |hashed| {
+            let (index, hashes) = hashed?;
+            for (hash, offset) in hashes {
+                blocks.entry(hash).or_default().push((index, offset));
+            }
+            state.files_scanned += 1;
+            state.bytes_scanned += files[index].size;
+            state.elapsed = limiter.elapsed();
+            state.throughput = limiter.throughput();
+            progress(state);
+            cancel::check(options.cancel.as_ref())
+        },
+    )?;
+
+    let mut groups: Vec<Vec<(usize, u64)>> = blocks
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .map(|mut members| {
+            members.sort_unstable();
+            members
+        })
+        .collect();
+    groups.sort_unstable();
+
+    // Merge runs of consecutive blocks duplicated at the same places, keyed by the members the
+    // next block of each run would have.
+    let mut runs: Vec<(u64, Vec<(usize, u64)>)> = Vec::new();
+    let mut next: HashMap<Vec<(usize, u64)>, usize> = HashMap::new();
+    for members in groups {
+        let shifted: Vec<(usize, u64)> = members
+            .iter()
+            .map(|(index, offset)| (*index, offset + options.block_size))
+            .collect();
+        match next.remove(&members) {
+            Some(run) if runs[run].0 + options.block_size <= BTRFS_MAX_DEDUPE_LEN => {
+                runs[run].0 += options.block_size;
+                next.insert(shifted, run);
+            }
+            _ => {
+                next.insert(shifted, runs.len());
+                runs.push((options.block_size, members));
+            }
+        }
+    }
+
+    Ok(runs
+        .into_iter()
+        .map(|(len, members)| DuplicateSet {
+            len,
+            ranges: members
+                .into_iter()
+                .map(|(index, offset)| DuplicateRange {
+                    path: files[index].path.clone(),
+                    offset,
+                })
+                .collect(),
+        })
+        .collect())
+}
+
+/// Hash the full blocks of a file, returning the hash and offset of each. A file which vanished
+/// has no blocks.
+fn hash_file(file: &ScanFile, block_size: u64, limiter: &Limiter) -> Result<Vec<(u64, u64)>> {
+    let mut reader = match File::open(&file.path) {
+        Ok(reader) => reader,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut buf = vec![0u8; block_size as usize];
+    let mut hashes = Vec::new();
+    let mut offset = 0;
+    loop {
+        match reader.read_exact(&mut buf) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        limiter.acquire(block_size);
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&buf);
+        hashes.push((hasher.finish(), offset));
+        offset += block_size;
+    }
+    Ok(hashes)
+}
+
+/// Process items on a number of threads, handing the results to a callback on the calling
+/// thread as they come. The first error returned by the callback stops the processing.
+pub(super) fn for_each_parallel<T, R, W, D>(
+    items: &[T],
+    threads: usize,
+    work: W,
+    mut done: D,
+) -> Result<()>
+where
+    T: Sync,
+    R: Send,
+    W: Fn(&T) -> R + Sync,
+    D: FnMut(R) -> Result<()>,
+{
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, items.len().max(1)) {
+            let sender = sender.clone();
+            let (next, stop, work) = (&next, &stop, &work);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let item = match items.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        Some(item) => item,
+                        None => return,
+                    };
+                    if sender.send(work(item)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(sender);
+        for result in receiver {
+            if let Err(e) = done(result) {
+                stop.store(true, Ordering::Relaxed);
+                return Err(e);
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: u64 = 4096;
+
+    /// Write a file made of blocks each filled with a byte, followed by a partial block.
+    fn write_file(dir: &Path, name: &str, blocks: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        let mut data: Vec<u8> = blocks
+            .iter()
+            .flat_map(|byte| vec![*byte; BLOCK as usize])
+            .collect();
+        data.extend_from_slice(b"tail");
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn set(blocks: u64, ranges: &[(&Path, u64)]) -> DuplicateSet {
+        DuplicateSet {
+            len: blocks * BLOCK,
+            ranges: ranges
+                .iter()
+                .map(|(path, block)| DuplicateRange {
+                    path: path.to_path_buf(),
+                    offset: block * BLOCK,
+                })
+                .collect(),
+        }
+    }
+
+    fn find(paths: &[PathBuf]) -> Vec<DuplicateSet> {
+        let options = DedupOptions::new()
+            .block_size(BLOCK)
+            .min_size(BLOCK)
+            .threads(2);
+        let mut sets = find_duplicates(paths, &options, |_| {}).unwrap();
+        sets.sort_by_key(|set| (set.ranges[0].path.clone(), set.ranges[0].offset));
+        sets
+    }
+
+    #[test]
+    fn merge_runs_of_duplicate_blocks() {
+        let dir = std::env::temp_dir().join(format!("btrfsutil-dedup-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let a = write_file(&dir, "a", &[1, 2, 3, 4]);
+        let b = write_file(&dir, "b", &[1, 2, 3, 5]);
+        let c = write_file(&dir, "c", &[9, 2, 3, 4]);
+        let d = write_file(&dir, "d", &[7, 7, 8]);
+        let sets = find(&[a.clone(), b.clone(), c.clone(), d.clone()]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            sets,
+            [
+                // The first block of a and b, which c does not share.
+                set(1, &[(&a, 0), (&b, 0)]),
+                // Blocks duplicated at the same places are merged into a single run.
+                set(2, &[(&a, 1), (&b, 1), (&c, 1)]),
+                // The run of a and c continues where b differs.
+                set(1, &[(&a, 3), (&c, 3)]),
+                // Duplicate blocks within a file.
+                set(1, &[(&d, 0), (&d, 1)]),
+            ]
+        );
+        assert_eq!(sets[1].reclaimable(), 4 * BLOCK);
+    }
+
+    #[test]
+    fn split_runs_at_the_dedupe_limit() {
+        let dir = std::env::temp_dir().join(format!("btrfsutil-dedup-max-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let blocks = (BTRFS_MAX_DEDUPE_LEN / BLOCK) as usize + 2;
+        // Blocks with distinct contents, so that only the same blocks of both files match.
+        let data: Vec<u8> = (0..blocks)
+            .flat_map(|block| {
+                let mut buf = vec![0u8; BLOCK as usize];
+                buf[..8].copy_from_slice(&(block as u64).to_le_bytes());
+                buf
+            })
+            .collect();
+        let a = dir.join("a");
+        let b = dir.join("b");
+        fs::write(&a, &data).unwrap();
+        fs::write(&b, &data).unwrap();
+        let sets = find(&[a.clone(), b.clone()]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let max_blocks = BTRFS_MAX_DEDUPE_LEN / BLOCK;
+        assert_eq!(
+            sets,
+            [
+                set(max_blocks, &[(&a, 0), (&b, 0)]),
+                set(2, &[(&a, max_blocks), (&b, max_blocks)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_unaligned_block_size() {
+        let options = DedupOptions::new().block_size(1000);
+        assert!(collect_files::<&Path>(&[], &options).is_err());
+    }
+}
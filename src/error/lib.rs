@@ -3,8 +3,6 @@ use crate::error::GlueError;
 use crate::BtrfsUtilError;
 use crate::Result;
 
-#[cfg(feature = "enable-glue-errors")]
-use std::convert::Into;
 use std::convert::TryFrom;
 use std::ffi::CStr;
 use std::os::raw::c_char;
@@ -208,10 +206,3 @@ impl TryFrom<LibErrorCode> for LibError {
         }
     }
 }
-
-#[cfg(feature = "enable-glue-errors")]
-impl Into<BtrfsUtilError> for LibError {
-    fn into(self) -> BtrfsUtilError {
-        BtrfsUtilError::Lib(self)
-    }
-}
@@ -1,33 +1,115 @@
 //! Library errors
 
-#[cfg(feature = "enable-glue-errors")]
 use thiserror::Error;
 
 #[macro_use]
 pub(crate) mod glue;
 pub(crate) mod lib;
+mod parse;
+mod shared;
 
 pub use glue::GlueError;
 pub use lib::LibError;
 pub(crate) use lib::LibErrorCode;
 
-/// Generic library error type. May be either a [LibError] or a [GlueError].
+use crate::filesystem::SwapfileRefusal;
+use crate::mount::RemountRefusal;
+use crate::qgroup::QgroupId;
+pub use parse::ParseError;
+pub use shared::SharedError;
+
+/// Generic library error type.
+///
+/// If the `enable-glue-errors` feature is not enabled, [GlueError]s will panic instead of being
+/// returned.
 ///
-/// [GlueError]: enum.LibError.html
 /// [GlueError]: enum.GlueError.html
-#[cfg(feature = "enable-glue-errors")]
 #[derive(Clone, Debug, Eq, Error, PartialEq)]
 pub enum BtrfsUtilError {
     /// Glue error
+    #[cfg(feature = "enable-glue-errors")]
     #[error("{0}")]
     Glue(GlueError),
     /// Library error
     #[error("{0}")]
     Lib(LibError),
+    /// I/O error, raised by operations which are not performed through libbtrfsutil.
+    #[error("{0}")]
+    Io(#[source] SharedError<std::io::Error>),
+    /// Parse error
+    #[error("{0}")]
+    Parse(#[from] ParseError),
+    /// A qgroup does not have enough quota left for the operation.
+    #[error("Quota of qgroup {qgroupid} exceeded: {needed} bytes needed, {available} available")]
+    QuotaExceeded {
+        /// The qgroup whose limit would be exceeded.
+        qgroupid: QgroupId,
+        /// Estimated number of bytes the operation needs.
+        needed: u64,
+        /// Number of bytes left before reaching the limit.
+        available: u64,
+    },
+    /// The operation did not complete within the allotted time.
+    #[error("Timed out")]
+    TimedOut,
+    /// The operation was cancelled through a [CancelToken].
+    ///
+    /// [CancelToken]: ../cancel/struct.CancelToken.html
+    #[error("Cancelled")]
+    Cancelled,
+    /// Source and destination of a reflink are on different filesystems.
+    #[error("Cannot reflink across filesystems")]
+    CrossDevice,
+    /// A reflink range is not aligned to the block size of the filesystem, or the files cannot
+    /// share extents, e.g. because only one of them has checksums disabled.
+    #[error("Reflink range is unaligned or the files are incompatible")]
+    Unaligned,
+    /// A send stream is malformed or uses an unsupported feature.
+    #[error("Invalid send stream: {0}")]
+    InvalidStream(String),
+    /// A backup or snapshot manifest is malformed, of an unsupported version, or inconsistent with
+    /// the files or subvolumes it describes.
+    #[error("Invalid manifest: {0}")]
+    InvalidManifest(String),
+    /// A filesystem cannot be unmounted because other mounts are nested beneath it.
+    #[error("Target is busy, with nested mounts {nested:?}")]
+    MountBusy {
+        /// Mount points beneath the target, e.g. of subvolumes.
+        nested: Vec<std::path::PathBuf>,
+    },
+    /// The kernel refused to remount a filesystem read-write.
+    #[error("Cannot remount read-write: {0}")]
+    RemountRefused(RemountRefusal),
+    /// A swapfile cannot be created at a location, because the kernel would refuse to activate it.
+    #[error("Cannot create a swapfile: {0}")]
+    SwapfileUnsupported(SwapfileRefusal),
+    /// JSON serialization error
+    #[cfg(feature = "json")]
+    #[error("{0}")]
+    Json(#[source] SharedError<serde_json::Error>),
 }
 
-#[cfg(not(feature = "enable-glue-errors"))]
-/// Generic library error type. If [GlueError]s happen, they will panic.
-///
-/// [GlueError]: enum.GlueError.html
-pub type BtrfsUtilError = LibError;
+impl From<LibError> for BtrfsUtilError {
+    fn from(err: LibError) -> Self {
+        BtrfsUtilError::Lib(err)
+    }
+}
+
+impl From<std::io::Error> for BtrfsUtilError {
+    fn from(err: std::io::Error) -> Self {
+        BtrfsUtilError::Io(err.into())
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for BtrfsUtilError {
+    fn from(err: serde_json::Error) -> Self {
+        BtrfsUtilError::Json(err.into())
+    }
+}
+
+impl PartialEq<LibError> for BtrfsUtilError {
+    fn eq(&self, other: &LibError) -> bool {
+        matches!(self, BtrfsUtilError::Lib(err) if err == other)
+    }
+}
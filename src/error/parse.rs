@@ -0,0 +1,20 @@
+use thiserror::Error;
+
+/// Error raised when parsing a value from a string fails.
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+#[error("Invalid {kind}: {input:?}")]
+pub struct ParseError {
+    /// What was being parsed.
+    pub kind: &'static str,
+    /// The string which could not be parsed.
+    pub input: String,
+}
+
+impl ParseError {
+    pub(crate) fn new<T: Into<String>>(kind: &'static str, input: T) -> Self {
+        Self {
+            kind,
+            input: input.into(),
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use crate::extent::reflink;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::ffi::CString;
+use std::fs;
+use std::fs::File;
+use std::fs::Metadata;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Options for copying a directory tree with [copy_tree_reflink].
+///
+/// [copy_tree_reflink]: fn.copy_tree_reflink.html
+#[derive(Clone, Debug)]
+pub struct CopyOptions {
+    pub(crate) reflink_only: bool,
+    pub(crate) preserve_ownership: bool,
+    pub(crate) preserve_timestamps: bool,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self {
+            reflink_only: false,
+            preserve_ownership: true,
+            preserve_timestamps: true,
+        }
+    }
+}
+
+impl CopyOptions {
+    /// Create the default copy options, which fall back to regular copies and preserve ownership
+    /// and timestamps, like `cp --reflink=auto -a`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fail instead of falling back to a regular copy when a file cannot be reflinked, like
+    /// `cp --reflink=always`.
+    pub fn reflink_only(mut self, reflink_only: bool) -> Self {
+        self.reflink_only = reflink_only;
+        self
+    }
+
+    /// Preserve the owner and group of the copied files, which requires elevated
+    /// privileges(CAP_CHOWN) for files not owned by the caller.
+    pub fn preserve_ownership(mut self, preserve_ownership: bool) -> Self {
+        self.preserve_ownership = preserve_ownership;
+        self
+    }
+
+    /// Preserve the access and modification times of the copied files.
+    pub fn preserve_timestamps(mut self, preserve_timestamps: bool) -> Self {
+        self.preserve_timestamps = preserve_timestamps;
+        self
+    }
+}
+
+/// Summary of a [copy_tree_reflink].
+///
+/// [copy_tree_reflink]: fn.copy_tree_reflink.html
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CopyReport {
+    /// Number of files which share their extents with the source.
+    pub reflinked: u64,
+    /// Number of files whose data was copied.
+    pub copied: u64,
+    /// Number of directories created.
+    pub directories: u64,
+    /// Number of symbolic links created.
+    pub symlinks: u64,
+    /// Number of special files skipped, e.g. sockets and device nodes.
+    pub skipped: u64,
+}
+
+/// Copy a directory tree, sharing the extents of the regular files with the source where
+/// possible, like `cp --reflink=auto -a`.
+///
+/// The destination directory is created if needed, existing files in it are overwritten. Files on
+/// another filesystem than the destination are copied unless [reflink_only] is set. Permissions
+/// are always preserved, ownership and timestamps according to the options. Hard links are copied
+/// as separate files.
+///
+/// [reflink_only]: struct.CopyOptions.html#method.reflink_only
+pub fn copy_tree_reflink<S: AsRef<Path>, D: AsRef<Path>>(
+    src_dir: S,
+    dst_dir: D,
+    options: &CopyOptions,
+) -> Result<CopyReport> {
+    let mut report = CopyReport::default();
+    copy_dir(src_dir.as_ref(), dst_dir.as_ref(), options, &mut report)?;
+    Ok(report)
+}
+
+fn copy_dir(src: &Path, dst: &Path, options: &CopyOptions, report: &mut CopyReport) -> Result<()> {
+    let metadata = fs::symlink_metadata(src)?;
+    match fs::create_dir(dst) {
+        Ok(()) => report.directories += 1,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+        Err(e) => return Err(e.into()),
+    }
+
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let src_path = entry.path();
+        let dst_path = dst.join(entry.file_name());
+        if file_type.is_dir() {
+            copy_dir(&src_path, &dst_path, options, report)?;
+        } else if file_type.is_file() {
+            copy_file(&src_path, &dst_path, options, report)?;
+        } else if file_type.is_symlink() {
+            if fs::symlink_metadata(&dst_path).is_ok() {
+                fs::remove_file(&dst_path)?;
+            }
+            std::os::unix::fs::symlink(fs::read_link(&src_path)?, &dst_path)?;
+            copy_metadata(&dst_path, &fs::symlink_metadata(&src_path)?, options)?;
+            report.symlinks += 1;
+        } else {
+            report.skipped += 1;
+        }
+    }
+
+    // Copying the entries updates the modification time of the directory, so set it last.
+    copy_metadata(dst, &metadata, options)
+}
+
+fn copy_file(src: &Path, dst: &Path, options: &CopyOptions, report: &mut CopyReport) -> Result<()> {
+    let mut src_file = File::open(src)?;
+    let metadata = src_file.metadata()?;
+    let mut dst_file = File::create(dst)?;
+
+    match reflink(&src_file, &dst_file) {
+        Ok(()) => report.reflinked += 1,
+        Err(e) if !options.reflink_only && can_fall_back(&e) => {
+            io::copy(&mut src_file, &mut dst_file)?;
+            report.copied += 1;
+        }
+        Err(e) => return Err(e),
+    }
+
+    copy_metadata(dst, &metadata, options)
+}
+
+/// Check whether a failed reflink can be replaced with a regular copy.
+fn can_fall_back(err: &BtrfsUtilError) -> bool {
+    match err {
+        BtrfsUtilError::CrossDevice | BtrfsUtilError::Unaligned => true,
+        BtrfsUtilError::Io(e) => matches!(
+            e.raw_os_error(),
+            Some(libc::EOPNOTSUPP) | Some(libc::ENOTTY) | Some(libc::EPERM)
+        ),
+        _ => false,
+    }
+}
+
+fn copy_metadata(dst: &Path, metadata: &Metadata, options: &CopyOptions) -> Result<()> {
+    let is_symlink = metadata.file_type().is_symlink();
+    if options.preserve_ownership {
+        std::os::unix::fs::lchown(dst, Some(metadata.uid()), Some(metadata.gid()))?;
+    }
+    // Symbolic links have no permissions of their own on Linux.
+    if !is_symlink {
+        fs::set_permissions(dst, metadata.permissions())?;
+    }
+    if options.preserve_timestamps {
+        let path = CString::new(dst.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let times = [
+            libc::timespec {
+                tv_sec: metadata.atime(),
+                tv_nsec: metadata.atime_nsec(),
+            },
+            libc::timespec {
+                tv_sec: metadata.mtime(),
+                tv_nsec: metadata.mtime_nsec(),
+            },
+        ];
+        let ret = unsafe {
+            libc::utimensat(
+                libc::AT_FDCWD,
+                path.as_ptr(),
+                times.as_ptr(),
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
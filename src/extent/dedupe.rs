@@ -0,0 +1,100 @@
+use crate::ioctl;
+use crate::Result;
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+/// A destination range of a deduplication.
+#[derive(Debug)]
+pub struct DedupeTarget<'a, T: AsRawFd> {
+    /// File holding the range, which must be open for writing unless the caller has elevated
+    /// privileges(CAP_SYS_ADMIN).
+    pub file: &'a T,
+    /// Start of the range within the file, in bytes.
+    pub offset: u64,
+}
+
+impl<'a, T: AsRawFd> DedupeTarget<'a, T> {
+    /// Create a deduplication target.
+    pub fn new(file: &'a T, offset: u64) -> Self {
+        Self { file, offset }
+    }
+}
+
+/// Outcome of a deduplication for one of its targets.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DedupeStatus {
+    /// The data was identical and the range now shares the extents of the source.
+    Same,
+    /// The data differs, the range was left untouched.
+    Differs,
+    /// The deduplication failed with this errno, e.g. `EINVAL` for a range past the end of file.
+    Failed(i32),
+}
+
+/// Result of a deduplication for one of its targets.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct DedupeResult {
+    /// Outcome of the deduplication.
+    pub status: DedupeStatus,
+    /// Number of bytes deduplicated.
+    pub bytes_deduped: u64,
+}
+
+impl DedupeResult {
+    /// Get the error of a failed deduplication.
+    pub fn error(&self) -> Option<io::Error> {
+        match self.status {
+            DedupeStatus::Failed(errno) => Some(io::Error::from_raw_os_error(errno)),
+            _ => None,
+        }
+    }
+}
+
+impl From<&ioctl::file_dedupe_range_info> for DedupeResult {
+    fn from(info: &ioctl::file_dedupe_range_info) -> Self {
+        let status = match info.status {
+            ioctl::FILE_DEDUPE_RANGE_SAME => DedupeStatus::Same,
+            ioctl::FILE_DEDUPE_RANGE_DIFFERS => DedupeStatus::Differs,
+            errno => DedupeStatus::Failed(-errno),
+        };
+        Self {
+            status,
+            bytes_deduped: info.bytes_deduped,
+        }
+    }
+}
+
+/// Share the extents of a range of a file with ranges of other files, where they hold the same
+/// data.
+///
+/// The kernel locks and compares the ranges, so this is safe to use on files being modified.
+/// Returns a result for each target, in order. A failure of a single target is reported in its
+/// result, while an error is returned if the whole request is rejected, e.g. because the source is
+/// not a regular file.
+pub fn dedupe_ranges<S: AsRawFd, T: AsRawFd>(
+    src: &S,
+    offset: u64,
+    len: u64,
+    targets: &[DedupeTarget<'_, T>],
+) -> Result<Vec<DedupeResult>> {
+    let mut results: Vec<DedupeResult> = Vec::with_capacity(targets.len());
+    let mut args: Box<ioctl::file_dedupe_range> = Box::new(unsafe { std::mem::zeroed() });
+
+    for batch in targets.chunks(ioctl::DEDUPE_BATCH) {
+        args.src_offset = offset;
+        args.src_length = len;
+        args.dest_count = batch.len() as u16;
+        for (info, target) in args.info.iter_mut().zip(batch) {
+            *info = ioctl::file_dedupe_range_info {
+                dest_fd: target.file.as_raw_fd() as i64,
+                dest_offset: target.offset,
+                ..Default::default()
+            };
+        }
+        unsafe { ioctl::ioctl(src.as_raw_fd(), ioctl::FIDEDUPERANGE, &mut *args)? };
+        results.extend(args.info[..batch.len()].iter().map(DedupeResult::from));
+    }
+
+    Ok(results)
+}
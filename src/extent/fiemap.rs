@@ -0,0 +1,102 @@
+use crate::ioctl;
+use crate::Result;
+
+use std::ops::Range;
+use std::os::unix::io::AsRawFd;
+
+bitflags! {
+    /// Extent flags reported by FIEMAP.
+    #[derive(Default)]
+    pub struct ExtentFlags: u32 {
+        /// Last extent of the file.
+        const LAST = 0x1;
+        /// Location of the data is not known yet.
+        const UNKNOWN = 0x2;
+        /// Delayed allocation, the data is not written to disk yet.
+        const DELALLOC = 0x4;
+        /// Data is encoded, e.g. compressed, so the physical length differs from the logical one.
+        const ENCODED = 0x8;
+        /// Data is encrypted.
+        const DATA_ENCRYPTED = 0x80;
+        /// Extent offsets may not be block aligned.
+        const NOT_ALIGNED = 0x100;
+        /// Data is stored inline in the metadata.
+        const DATA_INLINE = 0x200;
+        /// Data is packed with the tail of other files.
+        const DATA_TAIL = 0x400;
+        /// Space is allocated but not written, reading it returns zeroes.
+        const UNWRITTEN = 0x800;
+        /// Extent was merged from several smaller ones by the filesystem.
+        const MERGED = 0x1000;
+        /// Extent is shared with other files or snapshots, e.g. through reflinks.
+        const SHARED = 0x2000;
+    }
+}
+
+/// An extent of a file, as mapped by FIEMAP.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Extent {
+    /// Offset of the extent within the file, in bytes.
+    pub logical: u64,
+    /// Offset of the extent on disk, in bytes. On Btrfs, this is an address in the logical
+    /// address space of the filesystem, not on a device.
+    pub physical: u64,
+    /// Length of the extent, in bytes.
+    pub length: u64,
+    /// Flags of the extent.
+    pub flags: ExtentFlags,
+}
+
+impl Extent {
+    /// Check whether the extent is shared with other files or snapshots.
+    pub fn is_shared(&self) -> bool {
+        self.flags.contains(ExtentFlags::SHARED)
+    }
+
+    /// Check whether the extent is encoded, e.g. compressed.
+    pub fn is_encoded(&self) -> bool {
+        self.flags.contains(ExtentFlags::ENCODED)
+    }
+}
+
+impl From<&ioctl::fiemap_extent> for Extent {
+    fn from(extent: &ioctl::fiemap_extent) -> Self {
+        Self {
+            logical: extent.fe_logical,
+            physical: extent.fe_physical,
+            length: extent.fe_length,
+            flags: ExtentFlags::from_bits_truncate(extent.fe_flags),
+        }
+    }
+}
+
+/// Map the extents of a file overlapping a range of bytes.
+///
+/// Dirty data is flushed first, so that delayed allocations get their final location.
+pub fn extent_map<T: AsRawFd>(file: &T, range: Range<u64>) -> Result<Vec<Extent>> {
+    let mut extents: Vec<Extent> = Vec::new();
+    let mut args: Box<ioctl::fiemap> = Box::new(unsafe { std::mem::zeroed() });
+    let mut start = range.start;
+
+    while start < range.end {
+        args.fm_start = start;
+        args.fm_length = range.end - start;
+        args.fm_flags = ioctl::FIEMAP_FLAG_SYNC;
+        args.fm_mapped_extents = 0;
+        args.fm_extent_count = ioctl::FIEMAP_BATCH as u32;
+        unsafe { ioctl::ioctl(file.as_raw_fd(), ioctl::FS_IOC_FIEMAP, &mut *args)? };
+
+        let mapped = &args.fm_extents[..args.fm_mapped_extents as usize];
+        let last = match mapped.last() {
+            Some(last) => last,
+            None => break,
+        };
+        extents.extend(mapped.iter().map(Extent::from));
+        if last.fe_flags & ioctl::FIEMAP_EXTENT_LAST != 0 {
+            break;
+        }
+        start = last.fe_logical + last.fe_length;
+    }
+
+    Ok(extents)
+}
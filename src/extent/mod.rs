@@ -0,0 +1,11 @@
+//! File extents
+
+mod copy;
+mod dedupe;
+mod fiemap;
+mod reflink;
+
+pub use copy::*;
+pub use dedupe::*;
+pub use fiemap::*;
+pub use reflink::*;
@@ -0,0 +1,58 @@
+use crate::ioctl;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+/// Make a file share all extents of another file, replacing its content, like
+/// `cp --reflink=always`.
+///
+/// The destination must be open for writing. Both files must be on the same filesystem, otherwise
+/// [CrossDevice] is returned.
+///
+/// [CrossDevice]: ../error/enum.BtrfsUtilError.html#variant.CrossDevice
+pub fn reflink<S: AsRawFd, D: AsRawFd>(src: &S, dst: &D) -> Result<()> {
+    ioctl::ioctl_value(
+        dst.as_raw_fd(),
+        ioctl::FICLONE,
+        src.as_raw_fd() as libc::c_ulong,
+    )
+    .map_err(reflink_error)?;
+    Ok(())
+}
+
+/// Make a range of a file share the extents of a range of another file.
+///
+/// A zero length shares everything from the source offset to the end of the source file. Offsets
+/// and length must be aligned to the block size of the filesystem, except for a range ending at
+/// the end of the source file, otherwise [Unaligned] is returned. Both files must be on the same
+/// filesystem, otherwise [CrossDevice] is returned.
+///
+/// [Unaligned]: ../error/enum.BtrfsUtilError.html#variant.Unaligned
+/// [CrossDevice]: ../error/enum.BtrfsUtilError.html#variant.CrossDevice
+pub fn reflink_range<S: AsRawFd, D: AsRawFd>(
+    src: &S,
+    src_offset: u64,
+    len: u64,
+    dst: &D,
+    dst_offset: u64,
+) -> Result<()> {
+    let mut args = ioctl::file_clone_range {
+        src_fd: src.as_raw_fd() as i64,
+        src_offset,
+        src_length: len,
+        dest_offset: dst_offset,
+    };
+    unsafe { ioctl::ioctl(dst.as_raw_fd(), ioctl::FICLONERANGE, &mut args) }
+        .map_err(reflink_error)?;
+    Ok(())
+}
+
+fn reflink_error(err: io::Error) -> BtrfsUtilError {
+    match err.raw_os_error() {
+        Some(libc::EXDEV) => BtrfsUtilError::CrossDevice,
+        Some(libc::EINVAL) => BtrfsUtilError::Unaligned,
+        _ => err.into(),
+    }
+}
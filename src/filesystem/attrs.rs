@@ -0,0 +1,102 @@
+use crate::ioctl;
+use crate::Result;
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+/// `chattr` flag disabling compression, FS_NOCOMP_FL.
+pub(crate) const FS_NOCOMP_FL: libc::c_int = 0x0000_0400;
+/// `chattr` flag making a file immutable, FS_IMMUTABLE_FL.
+const FS_IMMUTABLE_FL: libc::c_int = 0x0000_0010;
+/// `chattr` flag disabling access time updates, FS_NOATIME_FL.
+const FS_NOATIME_FL: libc::c_int = 0x0000_0080;
+/// `chattr` flag disabling copy-on-write, FS_NOCOW_FL.
+pub(crate) const FS_NOCOW_FL: libc::c_int = 0x0080_0000;
+
+/// Attributes of a file or directory, as set by `chattr`.
+///
+/// Attributes of a directory are inherited by the files created in it.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Attrs {
+    /// Copy-on-write is disabled, e.g. for database and VM image files.
+    pub nocow: bool,
+    /// Data checksums are disabled. Btrfs ties this to [nocow](#structfield.nocow), so both must
+    /// be equal when setting attributes.
+    pub nodatasum: bool,
+    /// The file cannot be modified, deleted or renamed. Changing this requires the
+    /// CAP_LINUX_IMMUTABLE capability.
+    pub immutable: bool,
+    /// Access times are not updated.
+    pub noatime: bool,
+}
+
+/// Get the attributes of a file or directory.
+pub fn get_file_attrs<T: AsRef<Path>>(path: T) -> Result<Attrs> {
+    let file = File::open(path.as_ref())?;
+    let flags = get_flags(&file)?;
+    Ok(Attrs {
+        nocow: flags & FS_NOCOW_FL != 0,
+        nodatasum: flags & FS_NOCOW_FL != 0,
+        immutable: flags & FS_IMMUTABLE_FL != 0,
+        noatime: flags & FS_NOATIME_FL != 0,
+    })
+}
+
+/// Set the attributes of a file or directory, leaving its other `chattr` flags unchanged.
+///
+/// Copy-on-write can only be enabled or disabled on directories and empty files, since Btrfs
+/// does not rewrite existing data. Changing it on a file with data fails with an `InvalidInput`
+/// I/O error, as does asking for checksums to be disabled independently of copy-on-write.
+pub fn set_file_attrs<T: AsRef<Path>>(path: T, attrs: Attrs) -> Result<()> {
+    if attrs.nodatasum != attrs.nocow {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "data checksums are only disabled together with copy-on-write",
+        )
+        .into());
+    }
+    let file = File::open(path.as_ref())?;
+    let old = get_flags(&file)?;
+    let mut flags = old;
+    for (flag, set) in [
+        (FS_NOCOW_FL, attrs.nocow),
+        (FS_IMMUTABLE_FL, attrs.immutable),
+        (FS_NOATIME_FL, attrs.noatime),
+    ] {
+        if set {
+            flags |= flag;
+        } else {
+            flags &= !flag;
+        }
+    }
+    if flags == old {
+        return Ok(());
+    }
+    if (flags ^ old) & FS_NOCOW_FL != 0 {
+        let metadata = file.metadata()?;
+        if metadata.is_file() && metadata.len() > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "copy-on-write can only be changed on empty files",
+            )
+            .into());
+        }
+    }
+    set_flags(&file, flags)
+}
+
+/// Get the `chattr` flags of an open file.
+pub(crate) fn get_flags(file: &File) -> Result<libc::c_int> {
+    let mut flags: libc::c_int = 0;
+    unsafe { ioctl::ioctl(file.as_raw_fd(), ioctl::FS_IOC_GETFLAGS, &mut flags)? };
+    Ok(flags)
+}
+
+/// Set the `chattr` flags of an open file.
+pub(crate) fn set_flags(file: &File, flags: libc::c_int) -> Result<()> {
+    let mut flags = flags;
+    unsafe { ioctl::ioctl(file.as_raw_fd(), ioctl::FS_IOC_SETFLAGS, &mut flags)? };
+    Ok(())
+}
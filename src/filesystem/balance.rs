@@ -0,0 +1,378 @@
+use crate::cancel;
+use crate::cancel::CancelToken;
+use crate::filesystem::Filesystem;
+use crate::filesystem::RaidProfile;
+use crate::ioctl;
+use crate::progress;
+use crate::progress::Progress;
+use crate::Result;
+
+use std::ops::Range;
+use std::ops::RangeInclusive;
+use std::os::unix::io::AsRawFd;
+
+/// Filters selecting the chunks of one block group type to balance.
+///
+/// Without any filter, all chunks of the type are balanced.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BalanceFilter {
+    profiles: Vec<RaidProfile>,
+    usage: Option<RangeInclusive<u32>>,
+    devid: Option<u64>,
+    drange: Option<Range<u64>>,
+    vrange: Option<Range<u64>>,
+    limit: Option<RangeInclusive<u32>>,
+    convert: Option<RaidProfile>,
+    soft: bool,
+}
+
+impl BalanceFilter {
+    /// Create a filter selecting all chunks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only balance chunks with one of some RAID profiles.
+    pub fn profiles<I: IntoIterator<Item = RaidProfile>>(mut self, profiles: I) -> Self {
+        self.profiles = profiles.into_iter().collect();
+        self
+    }
+
+    /// Only balance chunks whose usage is within a range of percentages, e.g. `0..=50`.
+    pub fn usage(mut self, percent: RangeInclusive<u32>) -> Self {
+        self.usage = Some(percent);
+        self
+    }
+
+    /// Only balance chunks which have a stripe on a device.
+    pub fn devid(mut self, devid: u64) -> Self {
+        self.devid = Some(devid);
+        self
+    }
+
+    /// Only balance chunks overlapping a range of physical bytes on the device selected by
+    /// [devid].
+    ///
+    /// [devid]: #method.devid
+    pub fn drange(mut self, range: Range<u64>) -> Self {
+        self.drange = Some(range);
+        self
+    }
+
+    /// Only balance chunks overlapping a range of logical bytes.
+    pub fn vrange(mut self, range: Range<u64>) -> Self {
+        self.vrange = Some(range);
+        self
+    }
+
+    /// Balance a number of chunks within a range, e.g. `0..=10`.
+    pub fn limit(mut self, chunks: RangeInclusive<u32>) -> Self {
+        self.limit = Some(chunks);
+        self
+    }
+
+    /// Convert the balanced chunks to a RAID profile.
+    pub fn convert(mut self, profile: RaidProfile) -> Self {
+        self.convert = Some(profile);
+        self
+    }
+
+    /// When converting, skip the chunks which already have the target profile.
+    pub fn soft(mut self, soft: bool) -> Self {
+        self.soft = soft;
+        self
+    }
+
+    fn to_args(&self) -> ioctl::btrfs_balance_args {
+        let mut args = ioctl::btrfs_balance_args::default();
+        if !self.profiles.is_empty() {
+            args.flags |= ioctl::BTRFS_BALANCE_ARGS_PROFILES;
+            args.profiles = self
+                .profiles
+                .iter()
+                .fold(0, |flags, profile| flags | profile.flags());
+        }
+        if let Some(usage) = &self.usage {
+            args.flags |= ioctl::BTRFS_BALANCE_ARGS_USAGE_RANGE;
+            args.usage = ioctl::u32_range(*usage.start(), *usage.end());
+        }
+        if let Some(devid) = self.devid {
+            args.flags |= ioctl::BTRFS_BALANCE_ARGS_DEVID;
+            args.devid = devid;
+        }
+        if let Some(drange) = &self.drange {
+            args.flags |= ioctl::BTRFS_BALANCE_ARGS_DRANGE;
+            args.pstart = drange.start;
+            args.pend = drange.end;
+        }
+        if let Some(vrange) = &self.vrange {
+            args.flags |= ioctl::BTRFS_BALANCE_ARGS_VRANGE;
+            args.vstart = vrange.start;
+            args.vend = vrange.end;
+        }
+        if let Some(limit) = &self.limit {
+            args.flags |= ioctl::BTRFS_BALANCE_ARGS_LIMIT_RANGE;
+            args.limit = ioctl::u32_range(*limit.start(), *limit.end());
+        }
+        if let Some(convert) = self.convert {
+            args.flags |= ioctl::BTRFS_BALANCE_ARGS_CONVERT;
+            args.target = convert.flags();
+            if self.soft {
+                args.flags |= ioctl::BTRFS_BALANCE_ARGS_SOFT;
+            }
+        }
+        args
+    }
+}
+
+/// Progress of a balance, in chunks.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct BalanceProgress {
+    /// Estimated number of chunks to balance.
+    pub expected: u64,
+    /// Number of chunks considered so far.
+    pub considered: u64,
+    /// Number of chunks relocated so far.
+    pub completed: u64,
+}
+
+impl From<ioctl::btrfs_balance_progress> for BalanceProgress {
+    fn from(progress: ioctl::btrfs_balance_progress) -> Self {
+        Self {
+            expected: progress.expected,
+            considered: progress.considered,
+            completed: progress.completed,
+        }
+    }
+}
+
+impl Progress for BalanceProgress {
+    fn phase(&self) -> &'static str {
+        "balancing"
+    }
+
+    /// Get the number of chunks considered so far.
+    fn items_done(&self) -> u64 {
+        self.considered
+    }
+
+    fn items_total(&self) -> Option<u64> {
+        Some(self.expected)
+    }
+}
+
+/// Btrfs balance, which relocates chunks to spread them over the devices, reclaim partially used
+/// chunks or convert their RAID profile.
+///
+/// All operations require elevated privileges(CAP_SYS_ADMIN).
+#[derive(Clone, Copy, Debug)]
+pub struct Balance;
+
+impl Balance {
+    /// Create a builder for a balance.
+    pub fn builder() -> BalanceBuilder {
+        BalanceBuilder::default()
+    }
+
+    /// Pause the running balance of a filesystem. The call to [BalanceBuilder::start] returns.
+    ///
+    /// Returns whether a balance was running.
+    ///
+    /// [BalanceBuilder::start]: struct.BalanceBuilder.html#method.start
+    pub fn pause(fs: &Filesystem) -> Result<bool> {
+        balance_ctl(fs, ioctl::BTRFS_BALANCE_CTL_PAUSE)
+    }
+
+    /// Cancel the running or paused balance of a filesystem.
+    ///
+    /// Returns whether a balance was running or paused.
+    pub fn cancel(fs: &Filesystem) -> Result<bool> {
+        balance_ctl(fs, ioctl::BTRFS_BALANCE_CTL_CANCEL)
+    }
+
+    /// Resume the paused balance of a filesystem.
+    ///
+    /// Like [BalanceBuilder::start], this blocks until the balance finishes, is paused or is
+    /// canceled. Returns the final progress.
+    ///
+    /// [BalanceBuilder::start]: struct.BalanceBuilder.html#method.start
+    pub fn resume(fs: &Filesystem) -> Result<BalanceProgress> {
+        let mut args: Box<ioctl::btrfs_ioctl_balance_args> =
+            Box::new(unsafe { std::mem::zeroed() });
+        args.flags = ioctl::BTRFS_BALANCE_RESUME;
+        unsafe { ioctl::ioctl(fs.as_raw_fd(), ioctl::BTRFS_IOC_BALANCE_V2, &mut *args)? };
+        Ok(args.stat.into())
+    }
+
+    /// Get the status of the balance of a filesystem, or `None` if there is no running or paused
+    /// balance.
+    pub fn status(fs: &Filesystem) -> Result<Option<BalanceStatus>> {
+        let mut args: Box<ioctl::btrfs_ioctl_balance_args> =
+            Box::new(unsafe { std::mem::zeroed() });
+        match unsafe {
+            ioctl::ioctl(
+                fs.as_raw_fd(),
+                ioctl::BTRFS_IOC_BALANCE_PROGRESS,
+                &mut *args,
+            )
+        } {
+            Ok(_) => Ok(Some(BalanceStatus {
+                running: args.state & ioctl::BTRFS_BALANCE_STATE_RUNNING != 0,
+                pause_requested: args.state & ioctl::BTRFS_BALANCE_STATE_PAUSE_REQ != 0,
+                cancel_requested: args.state & ioctl::BTRFS_BALANCE_STATE_CANCEL_REQ != 0,
+                progress: args.stat.into(),
+            })),
+            Err(e) if e.raw_os_error() == Some(libc::ENOTCONN) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Status of a balance. Created by [Balance::status].
+///
+/// [Balance::status]: struct.Balance.html#method.status
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct BalanceStatus {
+    /// Whether the balance is running, as opposed to paused.
+    pub running: bool,
+    /// Whether the balance is being paused.
+    pub pause_requested: bool,
+    /// Whether the balance is being canceled.
+    pub cancel_requested: bool,
+    /// Progress of the balance.
+    pub progress: BalanceProgress,
+}
+
+impl BalanceStatus {
+    /// Get the progress in percent of the expected chunks which were considered.
+    pub fn percent(&self) -> f64 {
+        if self.progress.expected == 0 {
+            100.0
+        } else {
+            self.progress.considered as f64 * 100.0 / self.progress.expected as f64
+        }
+    }
+}
+
+fn balance_ctl(fs: &Filesystem, cmd: libc::c_ulong) -> Result<bool> {
+    match ioctl::ioctl_value(fs.as_raw_fd(), ioctl::BTRFS_IOC_BALANCE_CTL, cmd) {
+        Ok(_) => Ok(true),
+        Err(e) if e.raw_os_error() == Some(libc::ENOTCONN) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Builder for a balance. Created by [Balance::builder].
+///
+/// Without any filter, all chunks of all types are balanced.
+///
+/// [Balance::builder]: struct.Balance.html#method.builder
+#[derive(Clone, Debug, Default)]
+pub struct BalanceBuilder {
+    data: Option<BalanceFilter>,
+    metadata: Option<BalanceFilter>,
+    system: Option<BalanceFilter>,
+    force: bool,
+    cancel: Option<CancelToken>,
+}
+
+impl BalanceBuilder {
+    /// Balance the data chunks selected by a filter.
+    pub fn data(mut self, filter: BalanceFilter) -> Self {
+        self.data = Some(filter);
+        self
+    }
+
+    /// Balance the metadata chunks selected by a filter.
+    ///
+    /// Unless [system] is set as well, the system chunks are balanced with the same filter.
+    ///
+    /// [system]: #method.system
+    pub fn metadata(mut self, filter: BalanceFilter) -> Self {
+        self.metadata = Some(filter);
+        self
+    }
+
+    /// Balance the system chunks selected by a filter.
+    pub fn system(mut self, filter: BalanceFilter) -> Self {
+        self.system = Some(filter);
+        self
+    }
+
+    /// Allow reducing the redundancy of the metadata or system chunks.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Cancel the balance when a token is cancelled, failing with [BtrfsUtilError::Cancelled].
+    ///
+    /// [BtrfsUtilError::Cancelled]: ../error/enum.BtrfsUtilError.html#variant.Cancelled
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Run the balance on a filesystem.
+    ///
+    /// This blocks until the balance finishes, is paused or is canceled. Returns the final
+    /// progress, unless the balance was canceled through the [cancel_token].
+    ///
+    /// [cancel_token]: #method.cancel_token
+    pub fn start(self, fs: &Filesystem) -> Result<BalanceProgress> {
+        let mut args: Box<ioctl::btrfs_ioctl_balance_args> =
+            Box::new(unsafe { std::mem::zeroed() });
+
+        let all = self.data.is_none() && self.metadata.is_none() && self.system.is_none();
+        let system = self.system.as_ref().or(self.metadata.as_ref());
+        if all {
+            args.flags |= ioctl::BTRFS_BALANCE_DATA
+                | ioctl::BTRFS_BALANCE_METADATA
+                | ioctl::BTRFS_BALANCE_SYSTEM;
+        }
+        if let Some(filter) = &self.data {
+            args.flags |= ioctl::BTRFS_BALANCE_DATA;
+            args.data = filter.to_args();
+        }
+        if let Some(filter) = &self.metadata {
+            args.flags |= ioctl::BTRFS_BALANCE_METADATA;
+            args.meta = filter.to_args();
+        }
+        if let Some(filter) = system {
+            args.flags |= ioctl::BTRFS_BALANCE_SYSTEM;
+            args.sys = filter.to_args();
+        }
+        if self.force {
+            args.flags |= ioctl::BTRFS_BALANCE_FORCE;
+        }
+
+        let token = self.cancel.as_ref();
+        let _hook = cancel::cancel_on_filesystem(token, fs, Balance::cancel)?;
+        unsafe { ioctl::ioctl(fs.as_raw_fd(), ioctl::BTRFS_IOC_BALANCE_V2, &mut *args) }
+            .map_err(|e| cancel::map_cancelled(token, e))?;
+        Ok(args.stat.into())
+    }
+
+    /// Run the balance on a filesystem, reporting progress.
+    ///
+    /// Like [start], running the balance on another thread and calling the progress callback
+    /// with the progress queried from the kernel every second, and with the final progress.
+    ///
+    /// [start]: #method.start
+    pub fn start_with_progress<F>(self, fs: &Filesystem, mut progress: F) -> Result<BalanceProgress>
+    where
+        F: FnMut(&BalanceProgress),
+    {
+        let finished = progress::poll_while(
+            || self.start(fs),
+            || {
+                if let Ok(Some(status)) = Balance::status(fs) {
+                    progress(&status.progress);
+                }
+            },
+            progress::PROGRESS_INTERVAL,
+        )?;
+        progress(&finished);
+        Ok(finished)
+    }
+}
@@ -0,0 +1,95 @@
+use crate::common;
+use crate::error::ParseError;
+use crate::filesystem::Compression;
+use crate::Result;
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Extended attribute holding the `compression` property of a file or directory.
+pub const COMPRESSION_XATTR: &str = "btrfs.compression";
+
+/// Compression of the new data of a file or directory, set with the `compression` property.
+///
+/// Parsed from and formatted to property values like `zstd:3` or `none`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CompressionProperty {
+    /// Algorithm, or `None` to disable compression (`none`), even if the filesystem is mounted
+    /// with compression.
+    pub algorithm: Option<Compression>,
+    /// Level of the algorithm, or `None` for its default. Kernels which do not support levels in
+    /// the property use the default level.
+    pub level: Option<u32>,
+}
+
+impl fmt::Display for CompressionProperty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.algorithm, self.level) {
+            (None, _) => write!(f, "none"),
+            (Some(algorithm), None) => write!(f, "{}", algorithm),
+            (Some(algorithm), Some(level)) => write!(f, "{}:{}", algorithm, level),
+        }
+    }
+}
+
+impl FromStr for CompressionProperty {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let error = || ParseError::new("compression property", s);
+        let (algorithm, level) = match s {
+            "none" | "no" => (None, None),
+            _ => match s.split_once(':') {
+                Some((algorithm, level)) => (
+                    Some(algorithm.parse().map_err(|_| error())?),
+                    Some(level.parse().map_err(|_| error())?),
+                ),
+                None => (Some(s.parse().map_err(|_| error())?), None),
+            },
+        };
+        Ok(Self { algorithm, level })
+    }
+}
+
+impl From<Compression> for CompressionProperty {
+    fn from(algorithm: Compression) -> Self {
+        Self {
+            algorithm: Some(algorithm),
+            level: None,
+        }
+    }
+}
+
+/// Get the `compression` property of a file or directory, or `None` if it is not set and the
+/// mount options apply.
+pub fn get_compression<T: AsRef<Path>>(path: T) -> Result<Option<CompressionProperty>> {
+    match common::get_xattr(path.as_ref(), COMPRESSION_XATTR)? {
+        // The kernel may store an empty value when the property is reset.
+        Some(value) if !value.is_empty() => Ok(Some(
+            String::from_utf8_lossy(&value)
+                .trim_end_matches('\0')
+                .parse()?,
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Set the `compression` property of a file or directory, like `btrfs property set <path>
+/// compression`, or reset it with `None` so that the mount options apply.
+///
+/// The property applies to data written afterwards. Files created in a directory inherit the
+/// property of the directory. This requires write access to the file, or ownership of it.
+pub fn set_compression<T: AsRef<Path>>(
+    path: T,
+    compression: Option<CompressionProperty>,
+) -> Result<()> {
+    let path = path.as_ref();
+    match compression {
+        Some(compression) => {
+            common::set_xattr(path, COMPRESSION_XATTR, compression.to_string().as_bytes())?
+        }
+        None => common::remove_xattr(path, COMPRESSION_XATTR)?,
+    }
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use crate::error::ParseError;
+use crate::ioctl;
+use crate::rate_limit::Limiter;
+use crate::rate_limit::RateLimit;
+use crate::Result;
+
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Compression algorithms supported by Btrfs.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Compression {
+    /// zlib, slow with a good ratio.
+    Zlib,
+    /// LZO, fast with a lower ratio.
+    Lzo,
+    /// Zstandard, with a good balance of speed and ratio.
+    Zstd,
+}
+
+impl Compression {
+    /// Get the kernel's compression type number.
+    pub(crate) fn type_number(self) -> u32 {
+        match self {
+            Compression::Zlib => 1,
+            Compression::Lzo => 2,
+            Compression::Zstd => 3,
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    /// Format the algorithm like mount options and properties name it, e.g. `zstd`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::Zlib => write!(f, "zlib"),
+            Compression::Lzo => write!(f, "lzo"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "zlib" => Ok(Compression::Zlib),
+            "lzo" => Ok(Compression::Lzo),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(ParseError::new("compression algorithm", s)),
+        }
+    }
+}
+
+/// Size of the pieces a rate limited defragmentation is performed in, in bytes.
+pub const DEFRAG_RATE_LIMIT_CHUNK: u64 = 4 * 1024 * 1024;
+
+/// Options for a defragmentation.
+///
+/// Analogous to the arguments of `btrfs filesystem defragment`.
+#[derive(Clone, Debug, Default)]
+pub struct DefragOptions {
+    /// Start of the range to defragment, in bytes.
+    pub start: u64,
+    /// Length of the range to defragment, in bytes, or `None` for the whole file.
+    pub len: Option<u64>,
+    /// Only defragment extents smaller than this size, in bytes, or `None` for the kernel's
+    /// default.
+    pub extent_threshold: Option<u32>,
+    /// Compress the defragmented data with this algorithm.
+    pub compress: Option<Compression>,
+    /// Flush the defragmented data to disk before returning.
+    pub flush: bool,
+    /// Limit the rate of the defragmentation, shared by all files of a recursive one. The range
+    /// is then defragmented in pieces of [DEFRAG_RATE_LIMIT_CHUNK] bytes, each of which counts
+    /// fully against the limit.
+    ///
+    /// [DEFRAG_RATE_LIMIT_CHUNK]: constant.DEFRAG_RATE_LIMIT_CHUNK.html
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl DefragOptions {
+    /// Create the default defragmentation options, for the whole file.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl From<&DefragOptions> for ioctl::btrfs_ioctl_defrag_range_args {
+    fn from(options: &DefragOptions) -> Self {
+        let mut args = Self {
+            start: options.start,
+            len: options.len.unwrap_or(u64::MAX),
+            extent_thresh: options.extent_threshold.unwrap_or(0),
+            ..Default::default()
+        };
+        if let Some(compress) = options.compress {
+            args.flags |= ioctl::BTRFS_DEFRAG_RANGE_COMPRESS;
+            args.compress_type = compress.type_number();
+        }
+        if options.flush {
+            args.flags |= ioctl::BTRFS_DEFRAG_RANGE_START_IO;
+        }
+        args
+    }
+}
+
+/// Defragment a file on a Btrfs filesystem.
+///
+/// This requires write access to the file, or elevated privileges(CAP_SYS_ADMIN).
+pub fn defragment<T: AsRef<Path>>(path: T, options: &DefragOptions) -> Result<()> {
+    let file = File::open(path)?;
+    defragment_fd(&file, options)
+}
+
+/// Defragment an already opened file on a Btrfs filesystem.
+///
+/// This requires write access to the file, or elevated privileges(CAP_SYS_ADMIN).
+pub fn defragment_fd<T: AsRawFd>(file: &T, options: &DefragOptions) -> Result<()> {
+    defragment_limited(file.as_raw_fd(), options, &Limiter::new(options.rate_limit))
+}
+
+fn defragment_limited(fd: RawFd, options: &DefragOptions, limiter: &Limiter) -> Result<()> {
+    let mut args = ioctl::btrfs_ioctl_defrag_range_args::from(options);
+    if options.rate_limit.is_none() {
+        unsafe { ioctl::ioctl(fd, ioctl::BTRFS_IOC_DEFRAG_RANGE, &mut args)? };
+        return Ok(());
+    }
+
+    let mut stat: MaybeUninit<libc::stat> = MaybeUninit::uninit();
+    if unsafe { libc::fstat(fd, stat.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let size = unsafe { stat.assume_init() }.st_size as u64;
+    let end = options
+        .len
+        .map_or(size, |len| options.start.saturating_add(len).min(size));
+    let mut start = options.start;
+    while start < end {
+        let len = DEFRAG_RATE_LIMIT_CHUNK.min(end - start);
+        limiter.acquire(len);
+        args.start = start;
+        args.len = len;
+        unsafe { ioctl::ioctl(fd, ioctl::BTRFS_IOC_DEFRAG_RANGE, &mut args)? };
+        start += len;
+    }
+    Ok(())
+}
+
+/// Defragment all regular files below a directory on a Btrfs filesystem.
+///
+/// Like `btrfs filesystem defragment -r`, symbolic links are not followed, and nested subvolumes
+/// and other filesystems mounted below the directory are skipped, since they have their own device
+/// number. Returns the number of files defragmented.
+///
+/// This requires write access to the files, or elevated privileges(CAP_SYS_ADMIN).
+pub fn defragment_recursive<T: AsRef<Path>>(path: T, options: &DefragOptions) -> Result<u64> {
+    let path = path.as_ref();
+    let dev = fs::symlink_metadata(path)?.dev();
+    defragment_dir(path, dev, options, &Limiter::new(options.rate_limit))
+}
+
+fn defragment_dir(dir: &Path, dev: u64, options: &DefragOptions, limiter: &Limiter) -> Result<u64> {
+    let mut count = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if entry.metadata()?.dev() == dev {
+                count += defragment_dir(&entry.path(), dev, options, limiter)?;
+            }
+        } else if file_type.is_file() {
+            let file = File::open(entry.path())?;
+            defragment_limited(file.as_raw_fd(), options, limiter)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
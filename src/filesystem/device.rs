@@ -0,0 +1,308 @@
+use crate::filesystem::Filesystem;
+use crate::ioctl;
+use crate::search;
+use crate::search::SearchKey;
+use crate::Result;
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+/// A device of a mounted Btrfs filesystem.
+#[derive(Clone, Debug)]
+pub struct Device<'a> {
+    fs: &'a Filesystem,
+    /// Id of the device within the filesystem.
+    pub devid: u64,
+    /// UUID of the device.
+    pub uuid: Uuid,
+    /// Path of the device node, or `None` if the device is missing.
+    pub path: Option<PathBuf>,
+    /// Size of the device available to the filesystem, in bytes.
+    pub total_bytes: u64,
+    /// Bytes of the device allocated to block groups.
+    pub bytes_used: u64,
+}
+
+impl<'a> Device<'a> {
+    /// Get the filesystem this device belongs to.
+    pub fn filesystem(&self) -> &'a Filesystem {
+        self.fs
+    }
+
+    /// Get the minimum size this device can be shrunk to, in bytes, like
+    /// `btrfs inspect-internal min-dev-size`.
+    ///
+    /// There is no ioctl for this, so it is computed from the device extents, accounting for the
+    /// extents which must be relocated into free space before the new end of the device. This
+    /// requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn min_size(&self) -> Result<u64> {
+        let mut extents: Vec<(u64, u64)> = Vec::new();
+        for item in SearchKey::new(search::BTRFS_DEV_TREE_OBJECTID)
+            .objectids(self.devid, self.devid)
+            .types(search::BTRFS_DEV_EXTENT_KEY, search::BTRFS_DEV_EXTENT_KEY)
+            .search(self.fs.as_raw_fd())
+        {
+            let item = item?;
+            if item.item_type != search::BTRFS_DEV_EXTENT_KEY {
+                continue;
+            }
+            // Offset of the length within btrfs_dev_extent.
+            extents.push((item.offset, item.offset + item.u64_at(24)));
+        }
+        Ok(min_dev_size(&extents))
+    }
+
+    /// Get the error statistics of this device, optionally resetting them after reading.
+    ///
+    /// The counters persist across mounts. Resetting them requires elevated
+    /// privileges(CAP_SYS_ADMIN).
+    pub fn stats(&self, reset: bool) -> Result<DeviceStats> {
+        let mut args: ioctl::btrfs_ioctl_get_dev_stats = unsafe { std::mem::zeroed() };
+        args.devid = self.devid;
+        args.nr_items = ioctl::BTRFS_DEV_STAT_VALUES_MAX as u64;
+        args.flags = if reset {
+            ioctl::BTRFS_DEV_STATS_RESET
+        } else {
+            0
+        };
+        unsafe {
+            ioctl::ioctl(
+                self.fs.as_raw_fd(),
+                ioctl::BTRFS_IOC_GET_DEV_STATS,
+                &mut args,
+            )?
+        };
+
+        // Older kernels may return fewer counters, the others stay zero.
+        let values = &args.values[..(args.nr_items as usize).min(args.values.len())];
+        let value = |index: usize| values.get(index).copied().unwrap_or(0);
+        Ok(DeviceStats {
+            write_errs: value(0),
+            read_errs: value(1),
+            flush_errs: value(2),
+            corruption_errs: value(3),
+            generation_errs: value(4),
+        })
+    }
+}
+
+/// Space reserved at the start of every device, in bytes.
+const BTRFS_DEVICE_RANGE_RESERVED: u64 = 1024 * 1024;
+/// Maximum size of a system chunk, which relocations may need to allocate, in bytes.
+const BTRFS_MAX_SYSTEM_CHUNK_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Compute the minimum size of a device holding some extents, given as sorted `(start, end)`
+/// physical ranges with an exclusive end, like `btrfs inspect-internal min-dev-size`.
+fn min_dev_size(extents: &[(u64, u64)]) -> u64 {
+    let mut min_size = BTRFS_DEVICE_RANGE_RESERVED;
+    let mut holes: Vec<(u64, u64)> = Vec::new();
+    let mut last_end: Option<u64> = None;
+    for (start, end) in extents {
+        min_size += end - start;
+        if let Some(last_end) = last_end.filter(|last_end| last_end != start) {
+            holes.push((last_end, *start));
+        }
+        last_end = Some(*end);
+    }
+
+    // Extents past the minimum size must be relocated into holes before it, starting with the
+    // ones ending last. Relocation needs scratch space for the largest extent moved, since the
+    // space is released after each block group.
+    let mut scratch_space = 0;
+    for (start, end) in extents.iter().rev() {
+        if *end <= min_size {
+            break;
+        }
+        let len = end - start;
+        match holes.iter().position(|(start, end)| end - start >= len) {
+            Some(index) => {
+                holes[index].0 += len;
+                if holes[index].0 == holes[index].1 {
+                    holes.remove(index);
+                }
+                scratch_space = scratch_space.max(len);
+            }
+            None => {
+                min_size = *end;
+                break;
+            }
+        }
+    }
+
+    if scratch_space > 0 {
+        min_size += scratch_space + BTRFS_MAX_SYSTEM_CHUNK_SIZE;
+    }
+    min_size
+}
+
+/// Specifies a device to remove from a filesystem.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum DeviceSpec {
+    /// The device with a path.
+    Path(PathBuf),
+    /// The device with an id.
+    Id(u64),
+    /// The first device which is missing, on a filesystem mounted degraded.
+    Missing,
+}
+
+impl From<u64> for DeviceSpec {
+    fn from(devid: u64) -> Self {
+        DeviceSpec::Id(devid)
+    }
+}
+
+impl From<PathBuf> for DeviceSpec {
+    fn from(path: PathBuf) -> Self {
+        DeviceSpec::Path(path)
+    }
+}
+
+impl From<&Path> for DeviceSpec {
+    fn from(path: &Path) -> Self {
+        DeviceSpec::Path(path.into())
+    }
+}
+
+impl From<&str> for DeviceSpec {
+    /// Interpret a device argument like btrfs-progs does: `"missing"`, a device id, or a path.
+    fn from(spec: &str) -> Self {
+        if spec == "missing" {
+            DeviceSpec::Missing
+        } else if let Ok(devid) = spec.parse::<u64>() {
+            DeviceSpec::Id(devid)
+        } else {
+            DeviceSpec::Path(spec.into())
+        }
+    }
+}
+
+impl Filesystem {
+    /// Add a device to this filesystem.
+    ///
+    /// This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn add_device<T: AsRef<Path>>(&self, path: T) -> Result<()> {
+        let mut args: Box<ioctl::btrfs_ioctl_vol_args> = Box::new(unsafe { std::mem::zeroed() });
+        ioctl::copy_name(&mut args.name, path.as_ref().as_os_str().as_bytes())?;
+        unsafe { ioctl::ioctl(self.as_raw_fd(), ioctl::BTRFS_IOC_ADD_DEV, &mut *args)? };
+        Ok(())
+    }
+
+    /// Remove a device from this filesystem, moving its data to the other devices first.
+    ///
+    /// This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn remove_device<T: Into<DeviceSpec>>(&self, spec: T) -> Result<()> {
+        let spec: DeviceSpec = spec.into();
+        let name: &[u8] = match &spec {
+            DeviceSpec::Path(path) => path.as_os_str().as_bytes(),
+            DeviceSpec::Missing => b"missing",
+            DeviceSpec::Id(_) => b"",
+        };
+
+        let mut args: Box<ioctl::btrfs_ioctl_vol_args_v2> = Box::new(unsafe { std::mem::zeroed() });
+        match spec {
+            DeviceSpec::Id(devid) => {
+                args.flags = ioctl::BTRFS_DEVICE_SPEC_BY_ID;
+                args.set_id(devid);
+            }
+            _ => ioctl::copy_name(&mut args.name, name)?,
+        }
+        let result =
+            unsafe { ioctl::ioctl(self.as_raw_fd(), ioctl::BTRFS_IOC_RM_DEV_V2, &mut *args) };
+
+        match result {
+            // Kernels older than 4.6 only support removing devices by name.
+            Err(e) if e.raw_os_error() == Some(libc::ENOTTY) && !name.is_empty() => {
+                let mut args: Box<ioctl::btrfs_ioctl_vol_args> =
+                    Box::new(unsafe { std::mem::zeroed() });
+                ioctl::copy_name(&mut args.name, name)?;
+                unsafe { ioctl::ioctl(self.as_raw_fd(), ioctl::BTRFS_IOC_RM_DEV, &mut *args)? };
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+}
+
+/// Error statistics of a device. Created by [Device::stats].
+///
+/// [Device::stats]: struct.Device.html#method.stats
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct DeviceStats {
+    /// Failed writes to the device.
+    pub write_errs: u64,
+    /// Failed reads from the device.
+    pub read_errs: u64,
+    /// Failed flushes of the device.
+    pub flush_errs: u64,
+    /// Blocks read with a checksum error, a wrong bytenr or invalid contents.
+    pub corruption_errs: u64,
+    /// Blocks which were not written, detected by an unexpected generation.
+    pub generation_errs: u64,
+}
+
+impl DeviceStats {
+    /// Get the sum of all error counters.
+    pub fn total(&self) -> u64 {
+        self.write_errs
+            + self.read_errs
+            + self.flush_errs
+            + self.corruption_errs
+            + self.generation_errs
+    }
+
+    /// Check whether any error was recorded.
+    pub fn has_errors(&self) -> bool {
+        self.total() != 0
+    }
+}
+
+impl Filesystem {
+    /// Get the devices of this filesystem, ordered by device id.
+    ///
+    /// This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn devices(&self) -> Result<Vec<Device<'_>>> {
+        let max_id = ioctl::fs_info(self.as_raw_fd(), 0)?.max_id;
+        let mut devices: Vec<Device<'_>> = Vec::new();
+        // Device ids can have gaps, e.g. after removing a device.
+        for devid in 1..=max_id {
+            if let Some(device) = self.device(devid)? {
+                devices.push(device);
+            }
+        }
+        Ok(devices)
+    }
+
+    /// Get a device of this filesystem by id, or `None` if there is no such device.
+    ///
+    /// This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn device(&self, devid: u64) -> Result<Option<Device<'_>>> {
+        let info = match ioctl::dev_info(self.as_raw_fd(), devid)? {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+        let path_len = info
+            .path
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(info.path.len());
+        let path = Some(&info.path[..path_len])
+            .filter(|path| !path.is_empty())
+            .map(|path| PathBuf::from(OsStr::from_bytes(path)));
+
+        Ok(Some(Device {
+            fs: self,
+            devid: info.devid,
+            uuid: Uuid::from_bytes(info.uuid),
+            path,
+            total_bytes: info.total_bytes,
+            bytes_used: info.bytes_used,
+        }))
+    }
+}
@@ -0,0 +1,198 @@
+use crate::filesystem::SYSFS_BTRFS;
+use crate::ioctl;
+use crate::Result;
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+/// Offset of the primary superblock on a device.
+const BTRFS_SUPER_INFO_OFFSET: u64 = 64 * 1024;
+/// Size of the superblock fields read.
+const SUPER_READ_SIZE: usize = 0x12b + ioctl::BTRFS_LABEL_SIZE;
+/// Magic number of the superblock, "_BHRfS_M".
+const BTRFS_MAGIC: u64 = 0x4d5f_5366_5248_425f;
+/// Control device for registering devices.
+const BTRFS_CONTROL: &str = "/dev/btrfs-control";
+
+/// A device holding a Btrfs filesystem, found by [discover_filesystems].
+///
+/// [discover_filesystems]: fn.discover_filesystems.html
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DiscoveredDevice {
+    /// Path of the device node.
+    pub path: PathBuf,
+    /// Id of the device within the filesystem.
+    pub devid: u64,
+    /// UUID of the device.
+    pub uuid: Uuid,
+    /// Size of the device available to the filesystem, in bytes.
+    pub total_bytes: u64,
+    /// Generation of the superblock on this device.
+    pub generation: u64,
+}
+
+/// A Btrfs filesystem found on the block devices of the system, mounted or not.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DiscoveredFilesystem {
+    /// UUID of the filesystem.
+    pub fsid: Uuid,
+    /// Label of the filesystem, if any.
+    pub label: Option<String>,
+    /// Number of devices the filesystem is made of.
+    pub num_devices: u64,
+    /// Devices found, by increasing device id.
+    pub devices: Vec<DiscoveredDevice>,
+    /// Whether the filesystem is mounted.
+    pub mounted: bool,
+}
+
+impl DiscoveredFilesystem {
+    /// Check whether all the devices of the filesystem were found, so it can be mounted without
+    /// the `degraded` option.
+    pub fn is_complete(&self) -> bool {
+        self.devices.len() as u64 >= self.num_devices
+    }
+}
+
+/// Fields of a superblock.
+struct Superblock {
+    fsid: Uuid,
+    label: Option<String>,
+    num_devices: u64,
+    device: DiscoveredDevice,
+}
+
+/// Read the primary superblock of a device, or `None` if it does not hold Btrfs.
+fn read_super(path: &Path) -> io::Result<Option<Superblock>> {
+    let file = File::open(path)?;
+    let mut buf = vec![0u8; SUPER_READ_SIZE];
+    if let Err(e) = file.read_exact_at(&mut buf, BTRFS_SUPER_INFO_OFFSET) {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            return Ok(None);
+        }
+        return Err(e);
+    }
+    let u64_at = |offset: usize| u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap());
+    let uuid_at = |offset: usize| Uuid::from_slice(&buf[offset..offset + 16]).unwrap();
+    if u64_at(0x40) != BTRFS_MAGIC || u64_at(0x30) != BTRFS_SUPER_INFO_OFFSET {
+        return Ok(None);
+    }
+
+    let label = &buf[0x12b..];
+    let label = &label[..label.iter().position(|&b| b == 0).unwrap_or(label.len())];
+    let label = if label.is_empty() {
+        None
+    } else {
+        Some(String::from_utf8_lossy(label).into_owned())
+    };
+    let device = DiscoveredDevice {
+        path: path.to_path_buf(),
+        devid: u64_at(0xc9),
+        uuid: uuid_at(0x10b),
+        total_bytes: u64_at(0xd1),
+        generation: u64_at(0x48),
+    };
+    Ok(Some(Superblock {
+        fsid: uuid_at(0x20),
+        label,
+        num_devices: u64_at(0x88),
+        device,
+    }))
+}
+
+/// List the block devices of the system, from `/proc/partitions`.
+fn block_devices() -> io::Result<Vec<PathBuf>> {
+    Ok(fs::read_to_string("/proc/partitions")?
+        .lines()
+        .skip(2)
+        .filter_map(|line| line.split_whitespace().nth(3))
+        .map(|name| Path::new("/dev").join(name))
+        .collect())
+}
+
+/// Find the Btrfs filesystems on the block devices of the system, mounted or not, by reading
+/// the superblock of every device.
+///
+/// Devices which cannot be read are skipped, so this requires elevated privileges to find all
+/// filesystems. When a device holds an outdated copy of a superblock, e.g. after it was removed
+/// from a filesystem, the newest generation wins.
+pub fn discover_filesystems() -> Result<Vec<DiscoveredFilesystem>> {
+    let mut filesystems: BTreeMap<Uuid, DiscoveredFilesystem> = BTreeMap::new();
+    for path in block_devices()? {
+        let Superblock {
+            fsid,
+            label,
+            num_devices,
+            device,
+        } = match read_super(&path) {
+            Ok(Some(val)) => val,
+            _ => continue,
+        };
+        let fs = filesystems
+            .entry(fsid)
+            .or_insert_with(|| DiscoveredFilesystem {
+                fsid,
+                label: None,
+                num_devices: 0,
+                devices: Vec::new(),
+                mounted: Path::new(SYSFS_BTRFS)
+                    .join(fsid.to_hyphenated().to_string())
+                    .is_dir(),
+            });
+        let newest = fs
+            .devices
+            .iter()
+            .all(|known| known.generation <= device.generation);
+        if newest {
+            fs.label = label;
+            fs.num_devices = num_devices;
+        }
+        match fs
+            .devices
+            .iter_mut()
+            .find(|known| known.devid == device.devid)
+        {
+            Some(known) if known.generation < device.generation => *known = device,
+            Some(_) => (),
+            None => fs.devices.push(device),
+        }
+    }
+    let mut filesystems: Vec<DiscoveredFilesystem> = filesystems.into_values().collect();
+    for fs in &mut filesystems {
+        fs.devices.sort_by_key(|device| device.devid);
+    }
+    Ok(filesystems)
+}
+
+/// Find a Btrfs filesystem by label, e.g. before mounting it.
+///
+/// See [discover_filesystems].
+///
+/// [discover_filesystems]: fn.discover_filesystems.html
+pub fn find_filesystem_by_label(label: &str) -> Result<Option<DiscoveredFilesystem>> {
+    Ok(discover_filesystems()?
+        .into_iter()
+        .find(|fs| fs.label.as_deref() == Some(label)))
+}
+
+/// Register a device with the kernel, like `btrfs device scan`, so a filesystem spanning
+/// several devices can be mounted through any one of them.
+///
+/// This requires elevated privileges(CAP_SYS_ADMIN).
+pub fn scan_device<T: AsRef<Path>>(path: T) -> Result<()> {
+    let control = File::open(BTRFS_CONTROL)?;
+    let mut args: Box<ioctl::btrfs_ioctl_vol_args> = Box::new(unsafe { std::mem::zeroed() });
+    ioctl::copy_name(&mut args.name, path.as_ref().as_os_str().as_bytes())?;
+    unsafe { ioctl::ioctl(control.as_raw_fd(), ioctl::BTRFS_IOC_SCAN_DEV, &mut *args)? };
+    Ok(())
+}
@@ -0,0 +1,261 @@
+use crate::filesystem::Filesystem;
+use crate::Result;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Root of the Btrfs sysfs interface.
+pub(crate) const SYSFS_BTRFS: &str = "/sys/fs/btrfs";
+
+bitflags! {
+    /// Incompatible feature flags of the superblock, which older kernels cannot mount.
+    #[derive(Default)]
+    pub struct IncompatFlags: u64 {
+        /// Mixed back references, set on all current filesystems.
+        const MIXED_BACKREF = 1 << 0;
+        /// A default subvolume was set.
+        const DEFAULT_SUBVOL = 1 << 1;
+        /// Mixed data and metadata block groups.
+        const MIXED_GROUPS = 1 << 2;
+        /// LZO compression.
+        const COMPRESS_LZO = 1 << 3;
+        /// Zstandard compression.
+        const COMPRESS_ZSTD = 1 << 4;
+        /// Metadata blocks larger than a page.
+        const BIG_METADATA = 1 << 5;
+        /// Extended inode references.
+        const EXTENDED_IREF = 1 << 6;
+        /// RAID5 and RAID6 profiles.
+        const RAID56 = 1 << 7;
+        /// Smaller metadata extent items.
+        const SKINNY_METADATA = 1 << 8;
+        /// Holes are not stored as extent items.
+        const NO_HOLES = 1 << 9;
+        /// Metadata UUID differing from the filesystem UUID.
+        const METADATA_UUID = 1 << 10;
+        /// RAID1C3 and RAID1C4 profiles.
+        const RAID1C34 = 1 << 11;
+        /// Zoned mode.
+        const ZONED = 1 << 12;
+        /// Extent tree v2.
+        const EXTENT_TREE_V2 = 1 << 13;
+        /// RAID stripe tree.
+        const RAID_STRIPE_TREE = 1 << 14;
+        /// Simple quotas.
+        const SIMPLE_QUOTA = 1 << 16;
+    }
+}
+
+bitflags! {
+    /// Read-only compatible feature flags of the superblock, which older kernels can only mount
+    /// read-only.
+    #[derive(Default)]
+    pub struct CompatRoFlags: u64 {
+        /// Free space tree.
+        const FREE_SPACE_TREE = 1 << 0;
+        /// The free space tree is consistent.
+        const FREE_SPACE_TREE_VALID = 1 << 1;
+        /// fs-verity is used on some files.
+        const VERITY = 1 << 2;
+        /// Block group tree.
+        const BLOCK_GROUP_TREE = 1 << 3;
+    }
+}
+
+/// Sysfs names of the incompatible feature flags.
+const INCOMPAT_NAMES: &[(&str, IncompatFlags)] = &[
+    ("mixed_backref", IncompatFlags::MIXED_BACKREF),
+    ("default_subvol", IncompatFlags::DEFAULT_SUBVOL),
+    ("mixed_groups", IncompatFlags::MIXED_GROUPS),
+    ("compress_lzo", IncompatFlags::COMPRESS_LZO),
+    ("compress_zstd", IncompatFlags::COMPRESS_ZSTD),
+    ("big_metadata", IncompatFlags::BIG_METADATA),
+    ("extended_iref", IncompatFlags::EXTENDED_IREF),
+    ("raid56", IncompatFlags::RAID56),
+    ("skinny_metadata", IncompatFlags::SKINNY_METADATA),
+    ("no_holes", IncompatFlags::NO_HOLES),
+    ("metadata_uuid", IncompatFlags::METADATA_UUID),
+    ("raid1c34", IncompatFlags::RAID1C34),
+    ("zoned", IncompatFlags::ZONED),
+    ("extent_tree_v2", IncompatFlags::EXTENT_TREE_V2),
+    ("raid_stripe_tree", IncompatFlags::RAID_STRIPE_TREE),
+    ("simple_quota", IncompatFlags::SIMPLE_QUOTA),
+];
+
+/// Sysfs names of the read-only compatible feature flags.
+const COMPAT_RO_NAMES: &[(&str, CompatRoFlags)] = &[
+    ("free_space_tree", CompatRoFlags::FREE_SPACE_TREE),
+    ("verity", CompatRoFlags::VERITY),
+    ("block_group_tree", CompatRoFlags::BLOCK_GROUP_TREE),
+];
+
+/// Read a sysfs attribute, without the trailing newline.
+pub(crate) fn read_sysfs<T: AsRef<Path>>(path: T) -> io::Result<String> {
+    Ok(fs::read_to_string(path)?.trim_end().to_owned())
+}
+
+/// Read a numeric sysfs attribute.
+pub(crate) fn read_sysfs_u64<T: AsRef<Path>>(path: T) -> io::Result<u64> {
+    read_sysfs(path)?
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Btrfs features, as listed in sysfs.
+///
+/// Features without a field of their own, e.g. added by a newer kernel, are listed in
+/// [other](#structfield.other).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Features {
+    /// Mixed data and metadata block groups.
+    pub mixed_groups: bool,
+    /// LZO compression.
+    pub compress_lzo: bool,
+    /// Zstandard compression.
+    pub compress_zstd: bool,
+    /// Metadata blocks larger than a page.
+    pub big_metadata: bool,
+    /// Extended inode references, allowing many hard links in a directory.
+    pub extended_iref: bool,
+    /// RAID5 and RAID6 profiles.
+    pub raid56: bool,
+    /// Smaller metadata extent items.
+    pub skinny_metadata: bool,
+    /// Holes are not stored as extent items.
+    pub no_holes: bool,
+    /// Metadata UUID differing from the filesystem UUID.
+    pub metadata_uuid: bool,
+    /// Free space tree, replacing the free space cache.
+    pub free_space_tree: bool,
+    /// RAID1C3 and RAID1C4 profiles.
+    pub raid1c34: bool,
+    /// Zoned mode, for zoned block devices.
+    pub zoned: bool,
+    /// Block group tree, speeding up mounts of large filesystems.
+    pub block_group_tree: bool,
+    /// RAID stripe tree.
+    pub raid_stripe_tree: bool,
+    /// Simple quotas.
+    pub simple_quota: bool,
+    /// Other features, by sysfs name.
+    pub other: Vec<String>,
+}
+
+impl Features {
+    /// Get the features supported by the running kernel, from the module-global sysfs directory.
+    pub fn supported() -> Result<Self> {
+        Self::read_dir(Path::new(SYSFS_BTRFS).join("features"))
+    }
+
+    /// Check whether a feature is set, by sysfs name.
+    pub fn contains(&self, name: &str) -> bool {
+        match name {
+            "mixed_groups" => self.mixed_groups,
+            "compress_lzo" => self.compress_lzo,
+            "compress_zstd" => self.compress_zstd,
+            "big_metadata" => self.big_metadata,
+            "extended_iref" => self.extended_iref,
+            "raid56" => self.raid56,
+            "skinny_metadata" => self.skinny_metadata,
+            "no_holes" => self.no_holes,
+            "metadata_uuid" => self.metadata_uuid,
+            "free_space_tree" => self.free_space_tree,
+            "raid1c34" => self.raid1c34,
+            "zoned" => self.zoned,
+            "block_group_tree" => self.block_group_tree,
+            "raid_stripe_tree" => self.raid_stripe_tree,
+            "simple_quota" => self.simple_quota,
+            _ => self.other.iter().any(|other| other == name),
+        }
+    }
+
+    /// Get the incompatible feature flags matching these features.
+    pub fn incompat_flags(&self) -> IncompatFlags {
+        INCOMPAT_NAMES
+            .iter()
+            .filter(|(name, _)| self.contains(name))
+            .fold(IncompatFlags::empty(), |flags, (_, flag)| flags | *flag)
+    }
+
+    /// Get the read-only compatible feature flags matching these features.
+    ///
+    /// Sysfs does not list [FREE_SPACE_TREE_VALID], so it is assumed along with the free space
+    /// tree.
+    ///
+    /// [FREE_SPACE_TREE_VALID]: struct.CompatRoFlags.html#associatedconstant.FREE_SPACE_TREE_VALID
+    pub fn compat_ro_flags(&self) -> CompatRoFlags {
+        let mut flags = COMPAT_RO_NAMES
+            .iter()
+            .filter(|(name, _)| self.contains(name))
+            .fold(CompatRoFlags::empty(), |flags, (_, flag)| flags | *flag);
+        if flags.contains(CompatRoFlags::FREE_SPACE_TREE) {
+            flags |= CompatRoFlags::FREE_SPACE_TREE_VALID;
+        }
+        flags
+    }
+
+    /// Read the features listed as files of a sysfs directory.
+    pub(crate) fn read_dir<T: AsRef<Path>>(dir: T) -> Result<Self> {
+        let mut features = Features::default();
+        for entry in fs::read_dir(dir)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            let flag = match name.as_str() {
+                "mixed_groups" => &mut features.mixed_groups,
+                "compress_lzo" => &mut features.compress_lzo,
+                "compress_zstd" => &mut features.compress_zstd,
+                "big_metadata" => &mut features.big_metadata,
+                "extended_iref" => &mut features.extended_iref,
+                "raid56" => &mut features.raid56,
+                "skinny_metadata" => &mut features.skinny_metadata,
+                "no_holes" => &mut features.no_holes,
+                "metadata_uuid" => &mut features.metadata_uuid,
+                "free_space_tree" => &mut features.free_space_tree,
+                "raid1c34" => &mut features.raid1c34,
+                "zoned" => &mut features.zoned,
+                "block_group_tree" => &mut features.block_group_tree,
+                "raid_stripe_tree" => &mut features.raid_stripe_tree,
+                "simple_quota" => &mut features.simple_quota,
+                _ => {
+                    features.other.push(name);
+                    continue;
+                }
+            };
+            *flag = true;
+        }
+        features.other.sort();
+        Ok(features)
+    }
+}
+
+impl Filesystem {
+    /// Get the sysfs directory of this filesystem.
+    pub(crate) fn sysfs_dir(&self) -> Result<PathBuf> {
+        let fsid = self.info()?.fsid;
+        let dir = Path::new(SYSFS_BTRFS).join(fsid.to_hyphenated().to_string());
+        if !dir.is_dir() {
+            return Err(io::Error::from(io::ErrorKind::NotFound).into());
+        }
+        Ok(dir)
+    }
+
+    /// Get the features enabled on this filesystem, from sysfs.
+    ///
+    /// Compare with [Features::supported] to check which features could be enabled.
+    ///
+    /// [Features::supported]: struct.Features.html#method.supported
+    pub fn features(&self) -> Result<Features> {
+        Features::read_dir(self.sysfs_dir()?.join("features"))
+    }
+
+    /// Get the incompatible feature flags of this filesystem, from sysfs.
+    pub fn incompat_flags(&self) -> Result<IncompatFlags> {
+        Ok(self.features()?.incompat_flags())
+    }
+
+    /// Get the read-only compatible feature flags of this filesystem, from sysfs.
+    pub fn compat_ro_flags(&self) -> Result<CompatRoFlags> {
+        Ok(self.features()?.compat_ro_flags())
+    }
+}
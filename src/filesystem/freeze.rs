@@ -0,0 +1,47 @@
+use crate::filesystem::Filesystem;
+use crate::ioctl;
+use crate::Result;
+
+use std::os::unix::io::AsRawFd;
+
+impl Filesystem {
+    /// Freeze this filesystem, blocking all writes to it until it is thawed.
+    ///
+    /// The filesystem is thawed when the returned guard is dropped, including while unwinding from
+    /// a panic. This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn freeze(&self) -> Result<FreezeGuard<'_>> {
+        let mut arg: libc::c_int = 0;
+        unsafe { ioctl::ioctl(self.as_raw_fd(), ioctl::FIFREEZE, &mut arg)? };
+        Ok(FreezeGuard { fs: self })
+    }
+}
+
+/// Guard keeping a filesystem frozen. Created by [Filesystem::freeze].
+///
+/// [Filesystem::freeze]: struct.Filesystem.html#method.freeze
+#[derive(Debug)]
+pub struct FreezeGuard<'a> {
+    fs: &'a Filesystem,
+}
+
+impl FreezeGuard<'_> {
+    /// Thaw the filesystem, reporting any error instead of ignoring it like dropping the guard
+    /// does.
+    pub fn thaw(self) -> Result<()> {
+        let result = thaw(self.fs);
+        std::mem::forget(self);
+        result
+    }
+}
+
+impl Drop for FreezeGuard<'_> {
+    fn drop(&mut self) {
+        let _ = thaw(self.fs);
+    }
+}
+
+fn thaw(fs: &Filesystem) -> Result<()> {
+    let mut arg: libc::c_int = 0;
+    unsafe { ioctl::ioctl(fs.as_raw_fd(), ioctl::FITHAW, &mut arg)? };
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use crate::filesystem::features::read_sysfs;
+use crate::filesystem::DeviceStats;
+use crate::filesystem::Filesystem;
+use crate::mount::btrfs_mounts;
+use crate::Result;
+
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+/// Health of a filesystem, aggregated from its devices. Created by [Filesystem::health].
+///
+/// [Filesystem::health]: struct.Filesystem.html#method.health
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FilesystemHealth {
+    /// Ids of the devices which are missing, e.g. on a filesystem mounted degraded.
+    pub missing_devices: Vec<u64>,
+    /// Whether the filesystem is read-only.
+    pub read_only: bool,
+    /// Whether the filesystem was forced read-only by the kernel after an error, although it was
+    /// mounted read-write.
+    pub read_only_error: bool,
+    /// Sum of the error counters of all devices.
+    pub errors: DeviceStats,
+    /// Error counters of the devices which recorded errors, by device id.
+    pub device_errors: Vec<(u64, DeviceStats)>,
+}
+
+impl FilesystemHealth {
+    /// Check whether devices are missing.
+    pub fn is_degraded(&self) -> bool {
+        !self.missing_devices.is_empty()
+    }
+
+    /// Check whether nothing is wrong: no missing device, no error state and no device error.
+    pub fn is_healthy(&self) -> bool {
+        !self.is_degraded() && !self.read_only_error && !self.errors.has_errors()
+    }
+}
+
+/// Check whether a Btrfs filesystem with one of these devices is mounted read-write while its
+/// superblock is read-only, which happens when the kernel aborts a transaction.
+fn forced_read_only(devices: &[PathBuf]) -> Result<bool> {
+    let is_rw = |options: &[String]| options.iter().any(|option| option == "rw");
+    Ok(btrfs_mounts()?.iter().any(|mount| {
+        devices.contains(&mount.device)
+            && is_rw(&mount.mount_options)
+            && !is_rw(&mount.super_options)
+    }))
+}
+
+impl Filesystem {
+    /// Get the health of this filesystem in one call: missing devices, error state and device
+    /// error counters, e.g. for a monitoring agent.
+    ///
+    /// This requires elevated privileges(CAP_SYS_ADMIN) to list the devices.
+    pub fn health(&self) -> Result<FilesystemHealth> {
+        let mut health = FilesystemHealth::default();
+        let sysfs_dir = self.sysfs_dir().ok();
+        let mut paths: Vec<PathBuf> = Vec::new();
+
+        for device in self.devices()? {
+            // Sysfs knows about missing devices whose path is still recorded.
+            let missing_in_sysfs = sysfs_dir.as_ref().is_some_and(|dir| {
+                read_sysfs(dir.join(format!("devinfo/{}/missing", device.devid)))
+                    .is_ok_and(|missing| missing == "1")
+            });
+            match &device.path {
+                Some(path) if !missing_in_sysfs => paths.push(path.clone()),
+                _ => {
+                    health.missing_devices.push(device.devid);
+                    continue;
+                }
+            }
+
+            let stats = device.stats(false)?;
+            if stats.has_errors() {
+                health.errors.write_errs += stats.write_errs;
+                health.errors.read_errs += stats.read_errs;
+                health.errors.flush_errs += stats.flush_errs;
+                health.errors.corruption_errs += stats.corruption_errs;
+                health.errors.generation_errs += stats.generation_errs;
+                health.device_errors.push((device.devid, stats));
+            }
+        }
+
+        let mut stat: MaybeUninit<libc::statvfs> = MaybeUninit::uninit();
+        if unsafe { libc::fstatvfs(self.as_raw_fd(), stat.as_mut_ptr()) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let stat = unsafe { stat.assume_init() };
+        health.read_only = stat.f_flag & libc::ST_RDONLY != 0;
+        health.read_only_error = health.read_only && forced_read_only(&paths)?;
+
+        Ok(health)
+    }
+}
@@ -0,0 +1,136 @@
+use crate::error::LibError;
+use crate::filesystem::Filesystem;
+use crate::ioctl;
+use crate::sync::Transid;
+use crate::Result;
+
+use std::fmt;
+use std::os::unix::io::AsRawFd;
+
+use uuid::Uuid;
+
+/// Checksum algorithm of a Btrfs filesystem.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ChecksumType {
+    /// CRC32C, the default.
+    Crc32c,
+    /// xxHash64.
+    Xxhash,
+    /// SHA-256.
+    Sha256,
+    /// BLAKE2b-256.
+    Blake2,
+}
+
+impl ChecksumType {
+    fn from_raw(csum_type: u16) -> Option<Self> {
+        match csum_type {
+            0 => Some(ChecksumType::Crc32c),
+            1 => Some(ChecksumType::Xxhash),
+            2 => Some(ChecksumType::Sha256),
+            3 => Some(ChecksumType::Blake2),
+            _ => None,
+        }
+    }
+
+    /// Get the size of a checksum, in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            ChecksumType::Crc32c => 4,
+            ChecksumType::Xxhash => 8,
+            ChecksumType::Sha256 | ChecksumType::Blake2 => 32,
+        }
+    }
+}
+
+impl fmt::Display for ChecksumType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ChecksumType::Crc32c => "crc32c",
+            ChecksumType::Xxhash => "xxhash64",
+            ChecksumType::Sha256 => "sha256",
+            ChecksumType::Blake2 => "blake2b",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Information about a Btrfs filesystem.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct FilesystemInfo {
+    /// UUID of the filesystem.
+    pub fsid: Uuid,
+    /// UUID stored in the metadata blocks, if it differs from the filesystem UUID.
+    pub metadata_uuid: Option<Uuid>,
+    /// Number of devices in the filesystem.
+    pub num_devices: u64,
+    /// Highest device id in the filesystem.
+    pub max_id: u64,
+    /// Size of a metadata tree node, in bytes.
+    pub nodesize: u32,
+    /// Minimum unit of data allocation, in bytes.
+    pub sectorsize: u32,
+    /// Required alignment of cloned ranges, in bytes.
+    pub clone_alignment: u32,
+    /// Checksum algorithm, or `None` for an algorithm unknown to this library.
+    pub checksum_type: Option<ChecksumType>,
+    /// Current generation of the filesystem, if reported by the kernel (Linux 5.10 and newer).
+    pub generation: Option<Transid>,
+}
+
+impl Filesystem {
+    /// Get information about this filesystem.
+    pub fn info(&self) -> Result<FilesystemInfo> {
+        let info = ioctl::fs_info(
+            self.as_raw_fd(),
+            ioctl::BTRFS_FS_INFO_FLAG_CSUM_INFO
+                | ioctl::BTRFS_FS_INFO_FLAG_GENERATION
+                | ioctl::BTRFS_FS_INFO_FLAG_METADATA_UUID,
+        )
+        .map_err(|_| LibError::FsInfoFailed)?;
+
+        let fsid = Uuid::from_bytes(info.fsid);
+        // The kernel clears the flags it does not support.
+        let metadata_uuid = Some(Uuid::from_bytes(info.metadata_uuid)).filter(|uuid| {
+            info.flags & ioctl::BTRFS_FS_INFO_FLAG_METADATA_UUID != 0
+                && !uuid.is_nil()
+                && *uuid != fsid
+        });
+        // Kernels older than 5.5 do not report the checksum type, and only support CRC32C.
+        let checksum_type = if info.flags & ioctl::BTRFS_FS_INFO_FLAG_CSUM_INFO != 0 {
+            ChecksumType::from_raw(info.csum_type)
+        } else {
+            Some(ChecksumType::Crc32c)
+        };
+        let generation = Some(Transid(info.generation))
+            .filter(|_| info.flags & ioctl::BTRFS_FS_INFO_FLAG_GENERATION != 0);
+
+        Ok(FilesystemInfo {
+            fsid,
+            metadata_uuid,
+            num_devices: info.num_devices,
+            max_id: info.max_id,
+            nodesize: info.nodesize,
+            sectorsize: info.sectorsize,
+            clone_alignment: info.clone_alignment,
+            checksum_type,
+            generation,
+        })
+    }
+
+    /// Get the checksum algorithm of this filesystem, or `None` for an algorithm unknown to this
+    /// library.
+    pub fn checksum_type(&self) -> Result<Option<ChecksumType>> {
+        Ok(self.info()?.checksum_type)
+    }
+
+    /// Get the size of a metadata tree node of this filesystem, in bytes.
+    pub fn nodesize(&self) -> Result<u32> {
+        Ok(self.info()?.nodesize)
+    }
+
+    /// Get the minimum unit of data allocation of this filesystem, in bytes.
+    pub fn sectorsize(&self) -> Result<u32> {
+        Ok(self.info()?.sectorsize)
+    }
+}
@@ -0,0 +1,120 @@
+use crate::bindings;
+use crate::common;
+use crate::common::LibString;
+use crate::error::GlueError;
+use crate::error::LibError;
+use crate::error::LibErrorCode;
+use crate::filesystem::Filesystem;
+use crate::ioctl;
+use crate::Result;
+
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+use bindings::btrfs_util_subvolume_path;
+
+/// A reference from an inode to a logical address of the filesystem.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct InodeRef {
+    /// Inode number.
+    pub inode: u64,
+    /// Offset within the file where the extent holding the address is referenced, in bytes.
+    pub offset: u64,
+    /// Id of the subvolume containing the inode.
+    pub root: u64,
+}
+
+/// Paths resolved from an inode or a logical address.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ResolvedPaths {
+    /// The paths found.
+    pub paths: Vec<PathBuf>,
+    /// Whether paths were left out, because the kernel resolves at most 4 KiB of paths of an
+    /// inode at once.
+    pub truncated: bool,
+}
+
+impl Filesystem {
+    /// Find the inodes referencing a logical address, e.g. one reported by a scrub.
+    ///
+    /// This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn logical_to_inodes(&self, logical: u64) -> Result<Vec<InodeRef>> {
+        Ok(ioctl::logical_ino(self.as_raw_fd(), logical)?
+            .into_iter()
+            .map(|[inode, offset, root]| InodeRef {
+                inode,
+                offset,
+                root,
+            })
+            .collect())
+    }
+
+    /// Find the paths of an inode of a subvolume, i.e. all its hard links.
+    ///
+    /// The paths are resolved below the path this filesystem was opened with, so subvolumes which
+    /// are not reachable from it yield `None`. This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn inode_paths(&self, root: u64, inode: u64) -> Result<Option<ResolvedPaths>> {
+        let subvol_path = match self.reachable_subvolume(root)? {
+            Some(val) => val,
+            None => return Ok(None),
+        };
+        let subvol = File::open(&subvol_path)?;
+        let (paths, truncated) = ioctl::ino_paths(subvol.as_raw_fd(), inode)?;
+        Ok(Some(ResolvedPaths {
+            paths: paths
+                .iter()
+                .map(|path| subvol_path.join(OsStr::from_bytes(path)))
+                .collect(),
+            truncated,
+        }))
+    }
+
+    /// Find the paths of the files referencing a logical address, e.g. to know which files are
+    /// affected by a corruption reported by a scrub.
+    ///
+    /// Files in subvolumes which are not reachable from the path this filesystem was opened with
+    /// are skipped. The result is truncated if the paths of any of the files were.
+    /// This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn logical_to_paths(&self, logical: u64) -> Result<ResolvedPaths> {
+        let mut resolved = ResolvedPaths::default();
+        for inode_ref in self.logical_to_inodes(logical)? {
+            if let Some(inode_paths) = self.inode_paths(inode_ref.root, inode_ref.inode)? {
+                resolved.paths.extend(inode_paths.paths);
+                resolved.truncated |= inode_paths.truncated;
+            }
+        }
+        resolved.paths.sort();
+        resolved.paths.dedup();
+        Ok(resolved)
+    }
+
+    /// Get the path of a subvolume below the path this filesystem was opened with, if any.
+    fn reachable_subvolume(&self, id: u64) -> Result<Option<PathBuf>> {
+        let own_path = self.subvolume_path(0)?;
+        let path = self.subvolume_path(id)?;
+        Ok(path
+            .strip_prefix(&own_path)
+            .ok()
+            .map(|relative| self.path().join(relative)))
+    }
+
+    /// Get the path of a subvolume relative to the top level subvolume, or the one of the
+    /// subvolume containing the path this filesystem was opened with if the id is zero.
+    pub(crate) fn subvolume_path(&self, id: u64) -> Result<PathBuf> {
+        let path_cstr = common::path_to_cstr(self.path().to_path_buf())?;
+        let mut str_ptr: *mut std::os::raw::c_char = std::ptr::null_mut();
+
+        unsafe_wrapper!(errcode, {
+            errcode = btrfs_util_subvolume_path(path_cstr.as_ptr(), id, &mut str_ptr);
+        });
+
+        glue_error!(str_ptr.is_null(), GlueError::NullPointerReceived);
+
+        let path: LibString = unsafe { LibString::from_raw(str_ptr) };
+        Ok(path.as_path().to_path_buf())
+    }
+}
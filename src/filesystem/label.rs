@@ -0,0 +1,29 @@
+use crate::filesystem::Filesystem;
+use crate::ioctl;
+use crate::Result;
+
+use std::os::unix::io::AsRawFd;
+
+impl Filesystem {
+    /// Get the label of this filesystem, or `None` if it has none.
+    pub fn label(&self) -> Result<Option<String>> {
+        let mut label = [0u8; ioctl::BTRFS_LABEL_SIZE];
+        unsafe { ioctl::ioctl(self.as_raw_fd(), ioctl::BTRFS_IOC_GET_FSLABEL, &mut label)? };
+        let len = label.iter().position(|&b| b == 0).unwrap_or(label.len());
+        if len == 0 {
+            return Ok(None);
+        }
+        Ok(Some(String::from_utf8_lossy(&label[..len]).into_owned()))
+    }
+
+    /// Set the label of this filesystem, or remove it with an empty label.
+    ///
+    /// The label must be shorter than 256 bytes. This requires elevated
+    /// privileges(CAP_SYS_ADMIN).
+    pub fn set_label(&self, label: &str) -> Result<()> {
+        let mut buf = [0u8; ioctl::BTRFS_LABEL_SIZE];
+        ioctl::copy_name(&mut buf, label.as_bytes())?;
+        unsafe { ioctl::ioctl(self.as_raw_fd(), ioctl::BTRFS_IOC_SET_FSLABEL, &mut buf)? };
+        Ok(())
+    }
+}
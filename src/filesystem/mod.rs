@@ -0,0 +1,100 @@
+//! Btrfs filesystems
+
+mod attrs;
+mod balance;
+mod compression;
+mod defrag;
+mod device;
+mod discover;
+mod features;
+mod freeze;
+mod health;
+mod info;
+mod inspect;
+mod label;
+mod replace;
+mod resize;
+mod scrub;
+mod space;
+mod swapfile;
+mod trim;
+mod zoned;
+
+pub use attrs::*;
+pub use balance::*;
+pub use compression::*;
+pub use defrag::*;
+pub use device::*;
+pub use discover::*;
+pub use features::*;
+pub use freeze::*;
+pub use health::*;
+pub use info::*;
+pub use inspect::*;
+pub use replace::*;
+pub use resize::*;
+pub use scrub::*;
+pub use space::*;
+pub use swapfile::*;
+pub use zoned::*;
+
+use crate::error::LibError;
+use crate::Result;
+
+use std::fs::File;
+use std::mem::MaybeUninit;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Magic number identifying Btrfs in `statfs()` results.
+const BTRFS_SUPER_MAGIC: u64 = 0x9123_683e;
+
+/// A mounted Btrfs filesystem.
+///
+/// Internally, this holds an open file descriptor to a path on the filesystem, usually its mount
+/// point.
+#[derive(Debug)]
+pub struct Filesystem {
+    path: PathBuf,
+    file: File,
+}
+
+impl Filesystem {
+    /// Open the Btrfs filesystem containing a path.
+    pub fn open<T: Into<PathBuf>>(path: T) -> Result<Self> {
+        let path: PathBuf = path.into();
+        let file = File::open(&path)?;
+
+        let mut stat: MaybeUninit<libc::statfs> = MaybeUninit::uninit();
+        if unsafe { libc::fstatfs(file.as_raw_fd(), stat.as_mut_ptr()) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let stat = unsafe { stat.assume_init() };
+        if stat.f_type as u64 != BTRFS_SUPER_MAGIC {
+            return Err(LibError::NotBtrfs.into());
+        }
+
+        Ok(Self { path, file })
+    }
+
+    /// Create another handle to this filesystem, with a duplicated file descriptor.
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            path: self.path.clone(),
+            file: self.file.try_clone()?,
+        })
+    }
+
+    /// Get the path this filesystem was opened with.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRawFd for Filesystem {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
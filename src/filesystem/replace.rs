@@ -0,0 +1,174 @@
+use crate::filesystem::DeviceSpec;
+use crate::filesystem::Filesystem;
+use crate::ioctl;
+use crate::Result;
+
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+
+/// Options for starting a device replace.
+#[derive(Clone, Debug, Default)]
+pub struct ReplaceOptions {
+    avoid_source_reads: bool,
+}
+
+impl ReplaceOptions {
+    /// Create the default replace options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only read from the source device if no other copy of the data is available, e.g. because
+    /// the source device is failing.
+    pub fn avoid_source_reads(mut self, avoid: bool) -> Self {
+        self.avoid_source_reads = avoid;
+        self
+    }
+}
+
+/// State of a device replace.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ReplaceState {
+    /// No replace was ever started on the filesystem.
+    NeverStarted,
+    /// A replace is running.
+    Started,
+    /// The last replace finished.
+    Finished,
+    /// The last replace was canceled.
+    Canceled,
+    /// The replace was interrupted by an unmount and will continue on the next mount.
+    Suspended,
+}
+
+/// Status of a device replace. Created by [Replace::status].
+///
+/// [Replace::status]: struct.Replace.html#method.status
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplaceStatus {
+    /// State of the replace.
+    pub state: ReplaceState,
+    /// Progress of the replace, in percent.
+    pub progress: f64,
+    /// Time when the replace started.
+    pub time_started: Option<NaiveDateTime>,
+    /// Time when the replace stopped.
+    pub time_stopped: Option<NaiveDateTime>,
+    /// Number of errors writing to the target device.
+    pub num_write_errors: u64,
+    /// Number of blocks which could not be read from any device.
+    pub num_uncorrectable_read_errors: u64,
+}
+
+/// Btrfs device replace.
+///
+/// All operations require elevated privileges(CAP_SYS_ADMIN).
+#[derive(Clone, Copy, Debug)]
+pub struct Replace;
+
+impl Replace {
+    /// Replace a device of a filesystem with another device.
+    ///
+    /// This blocks until the replace finishes or is canceled, its progress can be queried from
+    /// another thread with [status]. A [DeviceSpec::Missing] source is not supported, use the id of
+    /// the missing device instead.
+    ///
+    /// [status]: #method.status
+    /// [DeviceSpec::Missing]: enum.DeviceSpec.html#variant.Missing
+    pub fn start<S: Into<DeviceSpec>, T: AsRef<Path>>(
+        fs: &Filesystem,
+        source: S,
+        target: T,
+        options: ReplaceOptions,
+    ) -> Result<()> {
+        let mut start = ioctl::btrfs_ioctl_dev_replace_start_params {
+            srcdevid: 0,
+            cont_reading_from_srcdev_mode: if options.avoid_source_reads {
+                ioctl::BTRFS_IOCTL_DEV_REPLACE_CONT_READING_FROM_SRCDEV_MODE_AVOID
+            } else {
+                ioctl::BTRFS_IOCTL_DEV_REPLACE_CONT_READING_FROM_SRCDEV_MODE_ALWAYS
+            },
+            srcdev_name: [0; ioctl::BTRFS_DEVICE_PATH_NAME_MAX + 1],
+            tgtdev_name: [0; ioctl::BTRFS_DEVICE_PATH_NAME_MAX + 1],
+        };
+        match source.into() {
+            DeviceSpec::Id(devid) => start.srcdevid = devid,
+            DeviceSpec::Path(path) => {
+                ioctl::copy_name(&mut start.srcdev_name, path.as_os_str().as_bytes())?
+            }
+            DeviceSpec::Missing => return Err(io::Error::from_raw_os_error(libc::EINVAL).into()),
+        }
+        ioctl::copy_name(
+            &mut start.tgtdev_name,
+            target.as_ref().as_os_str().as_bytes(),
+        )?;
+
+        let mut args = empty_args(ioctl::BTRFS_IOCTL_DEV_REPLACE_CMD_START);
+        args.params.start = start;
+        dev_replace(fs, &mut args)?;
+        match args.result {
+            ioctl::BTRFS_IOCTL_DEV_REPLACE_RESULT_ALREADY_STARTED => {
+                Err(io::Error::from_raw_os_error(libc::EINPROGRESS).into())
+            }
+            ioctl::BTRFS_IOCTL_DEV_REPLACE_RESULT_SCRUB_INPROGRESS => {
+                Err(io::Error::from_raw_os_error(libc::EBUSY).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Get the status of the device replace of a filesystem.
+    pub fn status(fs: &Filesystem) -> Result<ReplaceStatus> {
+        let mut args = empty_args(ioctl::BTRFS_IOCTL_DEV_REPLACE_CMD_STATUS);
+        dev_replace(fs, &mut args)?;
+        let status = unsafe { args.params.status };
+
+        let state = match status.replace_state {
+            ioctl::BTRFS_IOCTL_DEV_REPLACE_STATE_STARTED => ReplaceState::Started,
+            ioctl::BTRFS_IOCTL_DEV_REPLACE_STATE_FINISHED => ReplaceState::Finished,
+            ioctl::BTRFS_IOCTL_DEV_REPLACE_STATE_CANCELED => ReplaceState::Canceled,
+            ioctl::BTRFS_IOCTL_DEV_REPLACE_STATE_SUSPENDED => ReplaceState::Suspended,
+            _ => ReplaceState::NeverStarted,
+        };
+        let time = |secs: u64| {
+            Some(secs)
+                .filter(|secs| *secs != 0)
+                .and_then(|secs| NaiveDateTime::from_timestamp_opt(secs as i64, 0))
+        };
+
+        Ok(ReplaceStatus {
+            state,
+            progress: status.progress_1000 as f64 / 10.0,
+            time_started: time(status.time_started),
+            time_stopped: time(status.time_stopped),
+            num_write_errors: status.num_write_errors,
+            num_uncorrectable_read_errors: status.num_uncorrectable_read_errors,
+        })
+    }
+
+    /// Cancel the running device replace of a filesystem.
+    ///
+    /// Returns whether a replace was running.
+    pub fn cancel(fs: &Filesystem) -> Result<bool> {
+        let mut args = empty_args(ioctl::BTRFS_IOCTL_DEV_REPLACE_CMD_CANCEL);
+        dev_replace(fs, &mut args)?;
+        Ok(args.result != ioctl::BTRFS_IOCTL_DEV_REPLACE_RESULT_NOT_STARTED)
+    }
+}
+
+fn empty_args(cmd: u64) -> Box<ioctl::btrfs_ioctl_dev_replace_args> {
+    let mut args: Box<ioctl::btrfs_ioctl_dev_replace_args> =
+        Box::new(unsafe { std::mem::zeroed() });
+    args.cmd = cmd;
+    args.result = ioctl::BTRFS_IOCTL_DEV_REPLACE_RESULT_NO_ERROR;
+    args
+}
+
+fn dev_replace(fs: &Filesystem, args: &mut ioctl::btrfs_ioctl_dev_replace_args) -> Result<()> {
+    unsafe { ioctl::ioctl(fs.as_raw_fd(), ioctl::BTRFS_IOC_DEV_REPLACE, args)? };
+    Ok(())
+}
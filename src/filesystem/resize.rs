@@ -0,0 +1,50 @@
+use crate::filesystem::Filesystem;
+use crate::ioctl;
+use crate::Result;
+
+use std::fmt;
+use std::os::unix::io::AsRawFd;
+
+/// New size of a device, for [Filesystem::resize].
+///
+/// [Filesystem::resize]: struct.Filesystem.html#method.resize
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ResizeSpec {
+    /// Grow to the size of the underlying block device.
+    Max,
+    /// Grow by a number of bytes.
+    Grow(u64),
+    /// Shrink by a number of bytes.
+    Shrink(u64),
+    /// Resize to a number of bytes.
+    Set(u64),
+}
+
+impl fmt::Display for ResizeSpec {
+    /// Format the size like the kernel expects it, e.g. `max`, `+1048576` or `-1048576`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResizeSpec::Max => write!(f, "max"),
+            ResizeSpec::Grow(bytes) => write!(f, "+{}", bytes),
+            ResizeSpec::Shrink(bytes) => write!(f, "-{}", bytes),
+            ResizeSpec::Set(bytes) => write!(f, "{}", bytes),
+        }
+    }
+}
+
+impl Filesystem {
+    /// Resize a device of this filesystem while it is mounted.
+    ///
+    /// Without a device id, the device with id 1 is resized. Shrinking moves the data out of the
+    /// removed space first. This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn resize(&self, spec: ResizeSpec, devid: Option<u64>) -> Result<()> {
+        let spec = match devid {
+            Some(devid) => format!("{}:{}", devid, spec),
+            None => spec.to_string(),
+        };
+        let mut args: Box<ioctl::btrfs_ioctl_vol_args> = Box::new(unsafe { std::mem::zeroed() });
+        ioctl::copy_name(&mut args.name, spec.as_bytes())?;
+        unsafe { ioctl::ioctl(self.as_raw_fd(), ioctl::BTRFS_IOC_RESIZE, &mut *args)? };
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+use crate::cancel;
+use crate::cancel::CancelToken;
+use crate::filesystem::Filesystem;
+use crate::ioctl;
+use crate::progress;
+use crate::progress::Progress;
+use crate::Result;
+
+use std::ops::Range;
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::Duration;
+
+/// Options for a scrub.
+#[derive(Clone, Debug)]
+pub struct ScrubOptions {
+    readonly: bool,
+    range: Range<u64>,
+    cancel: Option<CancelToken>,
+}
+
+impl Default for ScrubOptions {
+    fn default() -> Self {
+        Self {
+            readonly: false,
+            range: 0..u64::MAX,
+            cancel: None,
+        }
+    }
+}
+
+impl ScrubOptions {
+    /// Create the default scrub options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only report errors, without repairing them.
+    pub fn readonly(mut self, readonly: bool) -> Self {
+        self.readonly = readonly;
+        self
+    }
+
+    /// Only scrub a range of physical bytes of the device, e.g. to resume an interrupted scrub
+    /// from [ScrubProgress::last_physical].
+    ///
+    /// [ScrubProgress::last_physical]: struct.ScrubProgress.html#structfield.last_physical
+    pub fn range(mut self, range: Range<u64>) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// Cancel the scrub when a token is cancelled, failing with [BtrfsUtilError::Cancelled].
+    /// The progress to resume from can still be queried with [Scrub::progress] until the call
+    /// returns.
+    ///
+    /// [BtrfsUtilError::Cancelled]: ../error/enum.BtrfsUtilError.html#variant.Cancelled
+    /// [Scrub::progress]: struct.Scrub.html#method.progress
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+}
+
+/// Progress of the scrub of a device.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ScrubProgress {
+    /// Number of data extents scrubbed.
+    pub data_extents_scrubbed: u64,
+    /// Number of tree extents scrubbed.
+    pub tree_extents_scrubbed: u64,
+    /// Number of data bytes scrubbed.
+    pub data_bytes_scrubbed: u64,
+    /// Number of tree bytes scrubbed.
+    pub tree_bytes_scrubbed: u64,
+    /// Number of read errors.
+    pub read_errors: u64,
+    /// Number of checksum mismatches.
+    pub csum_errors: u64,
+    /// Number of tree blocks whose header did not match the expected values.
+    pub verify_errors: u64,
+    /// Number of data blocks without checksum, e.g. written with nodatasum.
+    pub no_csum: u64,
+    /// Number of checksums without data.
+    pub csum_discards: u64,
+    /// Number of bad superblocks.
+    pub super_errors: u64,
+    /// Number of memory allocation failures, which make the scrub incomplete.
+    pub malloc_errors: u64,
+    /// Number of errors which could not be repaired.
+    pub uncorrectable_errors: u64,
+    /// Number of errors which were repaired.
+    pub corrected_errors: u64,
+    /// Last physical byte scrubbed, where an interrupted scrub can be resumed.
+    pub last_physical: u64,
+    /// Number of intermittent read errors, which did not happen again when checking.
+    pub unverified_errors: u64,
+}
+
+impl ScrubProgress {
+    /// Get the number of bytes scrubbed.
+    pub fn bytes_scrubbed(&self) -> u64 {
+        self.data_bytes_scrubbed + self.tree_bytes_scrubbed
+    }
+
+    /// Get the scrub rate in bytes per second, given the time elapsed since the scrub started.
+    pub fn rate(&self, elapsed: Duration) -> f64 {
+        let secs = elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.bytes_scrubbed() as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// Check whether any error was found.
+    pub fn has_errors(&self) -> bool {
+        self.read_errors
+            + self.csum_errors
+            + self.verify_errors
+            + self.super_errors
+            + self.malloc_errors
+            + self.uncorrectable_errors
+            + self.unverified_errors
+            != 0
+    }
+}
+
+impl Progress for ScrubProgress {
+    fn phase(&self) -> &'static str {
+        "scrubbing"
+    }
+
+    /// Get the number of data and tree extents scrubbed so far.
+    fn items_done(&self) -> u64 {
+        self.data_extents_scrubbed + self.tree_extents_scrubbed
+    }
+
+    fn bytes_done(&self) -> u64 {
+        self.bytes_scrubbed()
+    }
+}
+
+impl From<ioctl::btrfs_scrub_progress> for ScrubProgress {
+    fn from(progress: ioctl::btrfs_scrub_progress) -> Self {
+        Self {
+            data_extents_scrubbed: progress.data_extents_scrubbed,
+            tree_extents_scrubbed: progress.tree_extents_scrubbed,
+            data_bytes_scrubbed: progress.data_bytes_scrubbed,
+            tree_bytes_scrubbed: progress.tree_bytes_scrubbed,
+            read_errors: progress.read_errors,
+            csum_errors: progress.csum_errors,
+            verify_errors: progress.verify_errors,
+            no_csum: progress.no_csum,
+            csum_discards: progress.csum_discards,
+            super_errors: progress.super_errors,
+            malloc_errors: progress.malloc_errors,
+            uncorrectable_errors: progress.uncorrectable_errors,
+            corrected_errors: progress.corrected_errors,
+            last_physical: progress.last_physical,
+            unverified_errors: progress.unverified_errors,
+        }
+    }
+}
+
+/// Btrfs scrub, which reads all data and metadata and verifies their checksums, repairing the
+/// errors from another copy when possible.
+///
+/// All operations require elevated privileges(CAP_SYS_ADMIN).
+#[derive(Clone, Copy, Debug)]
+pub struct Scrub;
+
+impl Scrub {
+    /// Scrub a device of a filesystem.
+    ///
+    /// This blocks until the scrub finishes or is canceled, its progress can be queried from
+    /// another thread with [progress]. Returns the final progress.
+    ///
+    /// [progress]: #method.progress
+    pub fn start(fs: &Filesystem, devid: u64, options: &ScrubOptions) -> Result<ScrubProgress> {
+        let mut args = empty_args(devid);
+        args.start = options.range.start;
+        args.end = options.range.end;
+        if options.readonly {
+            args.flags = ioctl::BTRFS_SCRUB_READONLY;
+        }
+        let token = options.cancel.as_ref();
+        let _hook = cancel::cancel_on_filesystem(token, fs, Self::cancel)?;
+        unsafe { ioctl::ioctl(fs.as_raw_fd(), ioctl::BTRFS_IOC_SCRUB, &mut *args) }
+            .map_err(|e| cancel::map_cancelled(token, e))?;
+        Ok(args.progress.into())
+    }
+
+    /// Scrub a device of a filesystem, reporting progress.
+    ///
+    /// Like [start], running the scrub on another thread and calling the progress callback with
+    /// the progress queried from the kernel every second, and with the final progress.
+    ///
+    /// [start]: #method.start
+    pub fn start_with_progress<F>(
+        fs: &Filesystem,
+        devid: u64,
+        options: &ScrubOptions,
+        mut progress: F,
+    ) -> Result<ScrubProgress>
+    where
+        F: FnMut(&ScrubProgress),
+    {
+        let finished = progress::poll_while(
+            || Self::start(fs, devid, options),
+            || {
+                if let Ok(Some(current)) = Self::progress(fs, devid) {
+                    progress(&current);
+                }
+            },
+            progress::PROGRESS_INTERVAL,
+        )?;
+        progress(&finished);
+        Ok(finished)
+    }
+
+    /// Scrub all devices of a filesystem in parallel, with a thread per device.
+    ///
+    /// Returns the final progress of each device, by device id.
+    pub fn start_all(fs: &Filesystem, options: &ScrubOptions) -> Result<Vec<(u64, ScrubProgress)>> {
+        let devids: Vec<u64> = fs.devices()?.iter().map(|device| device.devid).collect();
+        thread::scope(|scope| {
+            let handles: Vec<_> = devids
+                .iter()
+                .map(|devid| scope.spawn(move || Self::start(fs, *devid, options)))
+                .collect();
+            devids
+                .iter()
+                .zip(handles)
+                .map(|(devid, handle)| match handle.join() {
+                    Ok(result) => result.map(|progress| (*devid, progress)),
+                    Err(panic) => std::panic::resume_unwind(panic),
+                })
+                .collect()
+        })
+    }
+
+    /// Cancel the running scrubs of a filesystem.
+    ///
+    /// Returns whether a scrub was running.
+    pub fn cancel(fs: &Filesystem) -> Result<bool> {
+        match unsafe {
+            ioctl::ioctl(
+                fs.as_raw_fd(),
+                ioctl::BTRFS_IOC_SCRUB_CANCEL,
+                std::ptr::null_mut::<libc::c_void>(),
+            )
+        } {
+            Ok(_) => Ok(true),
+            Err(e) if e.raw_os_error() == Some(libc::ENOTCONN) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the progress of the running scrub of a device, or `None` if it is not being scrubbed.
+    pub fn progress(fs: &Filesystem, devid: u64) -> Result<Option<ScrubProgress>> {
+        let mut args = empty_args(devid);
+        match unsafe { ioctl::ioctl(fs.as_raw_fd(), ioctl::BTRFS_IOC_SCRUB_PROGRESS, &mut *args) } {
+            Ok(_) => Ok(Some(args.progress.into())),
+            Err(e) if e.raw_os_error() == Some(libc::ENOTCONN) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn empty_args(devid: u64) -> Box<ioctl::btrfs_ioctl_scrub_args> {
+    let mut args: Box<ioctl::btrfs_ioctl_scrub_args> = Box::new(unsafe { std::mem::zeroed() });
+    args.devid = devid;
+    args
+}
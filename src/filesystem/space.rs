@@ -0,0 +1,278 @@
+use crate::filesystem::Filesystem;
+use crate::ioctl;
+use crate::Result;
+
+use std::os::unix::io::AsRawFd;
+
+const BTRFS_BLOCK_GROUP_DATA: u64 = 1 << 0;
+const BTRFS_BLOCK_GROUP_SYSTEM: u64 = 1 << 1;
+const BTRFS_BLOCK_GROUP_METADATA: u64 = 1 << 2;
+const BTRFS_BLOCK_GROUP_RAID0: u64 = 1 << 3;
+const BTRFS_BLOCK_GROUP_RAID1: u64 = 1 << 4;
+const BTRFS_BLOCK_GROUP_DUP: u64 = 1 << 5;
+const BTRFS_BLOCK_GROUP_RAID10: u64 = 1 << 6;
+const BTRFS_BLOCK_GROUP_RAID5: u64 = 1 << 7;
+const BTRFS_BLOCK_GROUP_RAID6: u64 = 1 << 8;
+const BTRFS_BLOCK_GROUP_RAID1C3: u64 = 1 << 9;
+const BTRFS_BLOCK_GROUP_RAID1C4: u64 = 1 << 10;
+const BTRFS_AVAIL_ALLOC_BIT_SINGLE: u64 = 1 << 48;
+const BTRFS_SPACE_INFO_GLOBAL_RSV: u64 = 1 << 49;
+
+/// Type of the block groups of a space.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum BlockGroupType {
+    /// File data.
+    Data,
+    /// Metadata.
+    Metadata,
+    /// File data and metadata mixed in the same block groups.
+    Mixed,
+    /// Chunk tree, describing where the block groups are on the devices.
+    System,
+    /// Metadata space reserved for critical operations.
+    GlobalReserve,
+}
+
+impl BlockGroupType {
+    /// Get the block group type from block group flags.
+    pub fn from_flags(flags: u64) -> Self {
+        if flags & BTRFS_SPACE_INFO_GLOBAL_RSV != 0 {
+            BlockGroupType::GlobalReserve
+        } else if flags & BTRFS_BLOCK_GROUP_SYSTEM != 0 {
+            BlockGroupType::System
+        } else if flags & BTRFS_BLOCK_GROUP_DATA != 0 && flags & BTRFS_BLOCK_GROUP_METADATA != 0 {
+            BlockGroupType::Mixed
+        } else if flags & BTRFS_BLOCK_GROUP_DATA != 0 {
+            BlockGroupType::Data
+        } else {
+            BlockGroupType::Metadata
+        }
+    }
+}
+
+/// RAID profile of the block groups of a space.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum RaidProfile {
+    /// One copy.
+    Single,
+    /// Two copies on the same device.
+    Dup,
+    /// Striped over all devices, without redundancy.
+    Raid0,
+    /// Two copies on different devices.
+    Raid1,
+    /// Three copies on different devices.
+    Raid1C3,
+    /// Four copies on different devices.
+    Raid1C4,
+    /// Two copies, striped over pairs of devices.
+    Raid10,
+    /// Striped with one parity stripe.
+    Raid5,
+    /// Striped with two parity stripes.
+    Raid6,
+}
+
+impl RaidProfile {
+    /// Get the RAID profile from block group flags.
+    pub fn from_flags(flags: u64) -> Self {
+        if flags & BTRFS_BLOCK_GROUP_RAID0 != 0 {
+            RaidProfile::Raid0
+        } else if flags & BTRFS_BLOCK_GROUP_RAID1 != 0 {
+            RaidProfile::Raid1
+        } else if flags & BTRFS_BLOCK_GROUP_DUP != 0 {
+            RaidProfile::Dup
+        } else if flags & BTRFS_BLOCK_GROUP_RAID10 != 0 {
+            RaidProfile::Raid10
+        } else if flags & BTRFS_BLOCK_GROUP_RAID5 != 0 {
+            RaidProfile::Raid5
+        } else if flags & BTRFS_BLOCK_GROUP_RAID6 != 0 {
+            RaidProfile::Raid6
+        } else if flags & BTRFS_BLOCK_GROUP_RAID1C3 != 0 {
+            RaidProfile::Raid1C3
+        } else if flags & BTRFS_BLOCK_GROUP_RAID1C4 != 0 {
+            RaidProfile::Raid1C4
+        } else {
+            RaidProfile::Single
+        }
+    }
+
+    /// Get the block group flags of the RAID profile.
+    ///
+    /// Single has a flag of its own, so that it can be a conversion target.
+    pub(crate) fn flags(self) -> u64 {
+        match self {
+            RaidProfile::Single => BTRFS_AVAIL_ALLOC_BIT_SINGLE,
+            RaidProfile::Dup => BTRFS_BLOCK_GROUP_DUP,
+            RaidProfile::Raid0 => BTRFS_BLOCK_GROUP_RAID0,
+            RaidProfile::Raid1 => BTRFS_BLOCK_GROUP_RAID1,
+            RaidProfile::Raid1C3 => BTRFS_BLOCK_GROUP_RAID1C3,
+            RaidProfile::Raid1C4 => BTRFS_BLOCK_GROUP_RAID1C4,
+            RaidProfile::Raid10 => BTRFS_BLOCK_GROUP_RAID10,
+            RaidProfile::Raid5 => BTRFS_BLOCK_GROUP_RAID5,
+            RaidProfile::Raid6 => BTRFS_BLOCK_GROUP_RAID6,
+        }
+    }
+
+    /// Get the number of bytes taken on the devices for each byte stored, on a filesystem with a
+    /// number of devices.
+    pub fn ratio(self, num_devices: u64) -> f64 {
+        match self {
+            RaidProfile::Single | RaidProfile::Raid0 => 1.0,
+            RaidProfile::Dup | RaidProfile::Raid1 | RaidProfile::Raid10 => 2.0,
+            RaidProfile::Raid1C3 => 3.0,
+            RaidProfile::Raid1C4 => 4.0,
+            RaidProfile::Raid5 => num_devices.max(2) as f64 / (num_devices.max(2) - 1) as f64,
+            RaidProfile::Raid6 => num_devices.max(3) as f64 / (num_devices.max(3) - 2) as f64,
+        }
+    }
+}
+
+/// Raw space information about the block groups with the same flags, as reported by the kernel.
+///
+/// Analogous to `struct btrfs_ioctl_space_info`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct SpaceInfo {
+    /// Block group flags, combining the type and RAID profile.
+    pub flags: u64,
+    /// Size of the block groups, in bytes, before RAID overhead.
+    pub total_bytes: u64,
+    /// Bytes used in the block groups, before RAID overhead.
+    pub used_bytes: u64,
+}
+
+impl SpaceInfo {
+    /// Get the type of the block groups.
+    pub fn group_type(&self) -> BlockGroupType {
+        BlockGroupType::from_flags(self.flags)
+    }
+
+    /// Get the RAID profile of the block groups.
+    pub fn profile(&self) -> RaidProfile {
+        RaidProfile::from_flags(self.flags)
+    }
+}
+
+/// Get the raw space information of a filesystem, exactly as reported by the kernel.
+///
+/// See [Filesystem::usage] for a summary accounting for RAID overhead.
+///
+/// [Filesystem::usage]: struct.Filesystem.html#method.usage
+pub fn space_infos(fs: &Filesystem) -> Result<Vec<SpaceInfo>> {
+    Ok(ioctl::space_info(fs.as_raw_fd())?
+        .into_iter()
+        .map(|info| SpaceInfo {
+            flags: info.flags,
+            total_bytes: info.total_bytes,
+            used_bytes: info.used_bytes,
+        })
+        .collect())
+}
+
+/// Space allocated to the block groups of one type and RAID profile.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SpaceUsage {
+    /// Type of the block groups.
+    pub group_type: BlockGroupType,
+    /// RAID profile of the block groups.
+    pub profile: RaidProfile,
+    /// Size of the block groups, in bytes, before RAID overhead.
+    pub allocated: u64,
+    /// Bytes used in the block groups, before RAID overhead.
+    pub used: u64,
+    /// Size of the block groups on the devices, in bytes, including RAID overhead.
+    pub raw_allocated: u64,
+}
+
+/// Space usage of a Btrfs filesystem. Created by [Filesystem::usage].
+///
+/// [Filesystem::usage]: struct.Filesystem.html#method.usage
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilesystemUsage {
+    /// Total size of the devices, in bytes.
+    pub device_size: u64,
+    /// Bytes of the devices allocated to block groups.
+    pub device_allocated: u64,
+    /// Bytes of the devices not allocated to any block group.
+    pub device_unallocated: u64,
+    /// Bytes taken on the devices for each byte of data.
+    pub data_ratio: f64,
+    /// Bytes taken on the devices for each byte of metadata.
+    pub metadata_ratio: f64,
+    /// Estimated bytes of data which can still be written, accounting for the RAID overhead of the
+    /// data profile.
+    pub free_estimated: u64,
+    /// Size and usage of the global reserve, if reported.
+    pub global_reserve: Option<(u64, u64)>,
+    /// Usage of each block group type and RAID profile.
+    pub spaces: Vec<SpaceUsage>,
+}
+
+impl Filesystem {
+    /// Get the space usage of this filesystem, broken down by block group type and RAID profile.
+    ///
+    /// Unlike `statfs()`, this accounts for the RAID profiles of the data and metadata.
+    pub fn usage(&self) -> Result<FilesystemUsage> {
+        let fd = self.as_raw_fd();
+        let fs_info = ioctl::fs_info(fd, 0)?;
+
+        let device_size: u64 = self
+            .devices()?
+            .iter()
+            .map(|device| device.total_bytes)
+            .sum();
+
+        let mut spaces: Vec<SpaceUsage> = Vec::new();
+        let mut global_reserve: Option<(u64, u64)> = None;
+        for info in space_infos(self)? {
+            let group_type = info.group_type();
+            if group_type == BlockGroupType::GlobalReserve {
+                global_reserve = Some((info.total_bytes, info.used_bytes));
+                continue;
+            }
+            let profile = info.profile();
+            spaces.push(SpaceUsage {
+                group_type,
+                profile,
+                allocated: info.total_bytes,
+                used: info.used_bytes,
+                raw_allocated: (info.total_bytes as f64 * profile.ratio(fs_info.num_devices))
+                    as u64,
+            });
+        }
+
+        let ratio_of = |group_types: &[BlockGroupType]| {
+            spaces
+                .iter()
+                .filter(|space| group_types.contains(&space.group_type))
+                .map(|space| space.profile.ratio(fs_info.num_devices))
+                .fold(0.0, f64::max)
+                .max(1.0)
+        };
+        let data_ratio = ratio_of(&[BlockGroupType::Data, BlockGroupType::Mixed]);
+        let metadata_ratio = ratio_of(&[BlockGroupType::Metadata, BlockGroupType::Mixed]);
+
+        let device_allocated: u64 = spaces.iter().map(|space| space.raw_allocated).sum();
+        let device_unallocated = device_size.saturating_sub(device_allocated);
+        let data_free: u64 = spaces
+            .iter()
+            .filter(|space| {
+                space.group_type == BlockGroupType::Data
+                    || space.group_type == BlockGroupType::Mixed
+            })
+            .map(|space| space.allocated.saturating_sub(space.used))
+            .sum();
+        let free_estimated = data_free + (device_unallocated as f64 / data_ratio) as u64;
+
+        Ok(FilesystemUsage {
+            device_size,
+            device_allocated,
+            device_unallocated,
+            data_ratio,
+            metadata_ratio,
+            free_estimated,
+            global_reserve,
+            spaces,
+        })
+    }
+}
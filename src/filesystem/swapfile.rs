@@ -0,0 +1,144 @@
+use crate::common;
+use crate::filesystem::attrs::get_flags;
+use crate::filesystem::attrs::set_flags;
+use crate::filesystem::attrs::FS_NOCOMP_FL;
+use crate::filesystem::attrs::FS_NOCOW_FL;
+use crate::filesystem::space_infos;
+use crate::filesystem::BlockGroupType;
+use crate::filesystem::Filesystem;
+use crate::filesystem::RaidProfile;
+use crate::ioctl;
+use crate::send::subvolume_root;
+use crate::subvolume::SubvolumeInfo;
+use crate::subvolume::SubvolumeIterator;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Why a swapfile cannot be created at a path.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SwapfileRefusal {
+    /// The size is zero or not a multiple of the page size, in bytes.
+    InvalidSize(u64),
+    /// The filesystem has more than one device, by count. Swapfiles must be on a single device.
+    MultipleDevices(u64),
+    /// The data of the filesystem has a profile other than single, e.g. RAID5 or RAID6, or is
+    /// being converted.
+    UnsupportedProfile(RaidProfile),
+    /// The subvolume which would contain the swapfile has snapshots. A subvolume cannot be
+    /// snapshotted while it contains an active swapfile.
+    Snapshotted(PathBuf),
+}
+
+impl fmt::Display for SwapfileRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SwapfileRefusal::InvalidSize(size) => {
+                write!(f, "size {} is not a multiple of the page size", size)
+            }
+            SwapfileRefusal::MultipleDevices(count) => {
+                write!(f, "the filesystem has {} devices", count)
+            }
+            SwapfileRefusal::UnsupportedProfile(profile) => {
+                write!(f, "data uses the {:?} profile", profile)
+            }
+            SwapfileRefusal::Snapshotted(subvolume) => {
+                write!(f, "subvolume {} has snapshots", subvolume.display())
+            }
+        }
+    }
+}
+
+/// Create a swapfile of a size, in bytes, like `btrfs filesystem mkswapfile` does.
+///
+/// The file is created empty with copy-on-write and compression disabled, then preallocated
+/// without holes. Conditions under which the kernel would refuse to activate it are checked
+/// first, failing with [SwapfileUnsupported]: the filesystem must have a single device and
+/// single data profile, and the subvolume containing the file must not have snapshots beneath
+/// it. The file is not formatted as swap space, `mkswap` must be run on it before `swapon`. If a
+/// step fails after the file was created, it is removed again.
+///
+/// [SwapfileUnsupported]: ../error/enum.BtrfsUtilError.html#variant.SwapfileUnsupported
+pub fn create_swapfile<T: AsRef<Path>>(path: T, size: u64) -> Result<()> {
+    let path = path.as_ref();
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+    if size == 0 || size % page_size != 0 {
+        return Err(BtrfsUtilError::SwapfileUnsupported(
+            SwapfileRefusal::InvalidSize(size),
+        ));
+    }
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    check_location(dir)?;
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    if let Err(e) = preallocate(&file, size) {
+        drop(file);
+        let _ = fs::remove_file(path);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Check that a swapfile in a directory can be activated.
+fn check_location(dir: &Path) -> Result<()> {
+    let fs = Filesystem::open(dir)?;
+    let num_devices = ioctl::fs_info(fs.as_raw_fd(), 0)?.num_devices;
+    if num_devices > 1 {
+        return Err(BtrfsUtilError::SwapfileUnsupported(
+            SwapfileRefusal::MultipleDevices(num_devices),
+        ));
+    }
+    for info in space_infos(&fs)? {
+        let data = matches!(
+            info.group_type(),
+            BlockGroupType::Data | BlockGroupType::Mixed
+        );
+        if data && info.profile() != RaidProfile::Single {
+            return Err(BtrfsUtilError::SwapfileUnsupported(
+                SwapfileRefusal::UnsupportedProfile(info.profile()),
+            ));
+        }
+    }
+
+    let root = subvolume_root(dir)?;
+    let uuid = SubvolumeInfo::fetch(&common::path_to_cstr(root.clone())?, 0)?.uuid;
+    for entry in SubvolumeIterator::builder_for_path(root.clone())
+        .snapshots()
+        .iter_with_info()?
+    {
+        if entry?.1.parent_uuid == Some(uuid) {
+            return Err(BtrfsUtilError::SwapfileUnsupported(
+                SwapfileRefusal::Snapshotted(root),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Disable copy-on-write and compression on an empty file, then allocate its whole size.
+fn preallocate(file: &File, size: u64) -> Result<()> {
+    set_flags(file, get_flags(file)? | FS_NOCOW_FL | FS_NOCOMP_FL)?;
+
+    let ret = unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, size as libc::off_t) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    file.sync_all()?;
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use crate::filesystem::Filesystem;
+use crate::ioctl;
+use crate::Result;
+
+use std::ops::Range;
+use std::os::unix::io::AsRawFd;
+
+impl Filesystem {
+    /// Discard the unused blocks of this filesystem within a range of its address space, like
+    /// `fstrim`.
+    ///
+    /// Free extents shorter than `min_len` bytes are skipped. Returns the number of bytes
+    /// trimmed. This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn trim(&self, range: Range<u64>, min_len: u64) -> Result<u64> {
+        let mut args = ioctl::fstrim_range {
+            start: range.start,
+            len: range.end.saturating_sub(range.start),
+            minlen: min_len,
+        };
+        unsafe { ioctl::ioctl(self.as_raw_fd(), ioctl::FITRIM, &mut args)? };
+        Ok(args.len)
+    }
+}
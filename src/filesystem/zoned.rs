@@ -0,0 +1,133 @@
+use crate::filesystem::features::read_sysfs;
+use crate::filesystem::features::read_sysfs_u64;
+use crate::filesystem::Device;
+use crate::filesystem::Filesystem;
+use crate::Result;
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Zone model of a block device.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ZoneModel {
+    /// Regular device, without zones.
+    None,
+    /// Zoned device which also accepts random writes.
+    HostAware,
+    /// Zoned device which only accepts sequential writes within a zone.
+    HostManaged,
+}
+
+/// Zone information of a device.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct DeviceZones {
+    /// Id of the device within the filesystem.
+    pub devid: u64,
+    /// Zone model of the device.
+    pub model: ZoneModel,
+    /// Size of a zone, in bytes, or zero for a regular device.
+    pub zone_size: u64,
+    /// Number of zones of the device.
+    pub nr_zones: u64,
+    /// Maximum number of zones which can be open at once, or zero for no limit.
+    pub max_open_zones: u64,
+    /// Maximum number of zones which can be active at once, or zero for no limit.
+    pub max_active_zones: u64,
+}
+
+/// Zone information of a filesystem.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ZoneInfo {
+    /// Whether the filesystem is in zoned mode.
+    pub zoned: bool,
+    /// Size of a zone, in bytes, or `None` if the filesystem is not zoned. All devices of a zoned
+    /// filesystem have the same zone size.
+    pub zone_size: Option<u64>,
+    /// Zone information of the devices which are present.
+    pub devices: Vec<DeviceZones>,
+}
+
+/// Get the sysfs queue directory of the block device holding a device node.
+fn queue_dir(path: &Path) -> io::Result<PathBuf> {
+    let rdev = fs::metadata(path)?.rdev();
+    let dir = PathBuf::from(format!(
+        "/sys/dev/block/{}:{}",
+        libc::major(rdev),
+        libc::minor(rdev)
+    ));
+    // Partitions share the queue of their disk.
+    let queue = dir.join("queue");
+    if queue.is_dir() {
+        Ok(queue)
+    } else {
+        Ok(dir.join("..").join("queue"))
+    }
+}
+
+impl Device<'_> {
+    /// Get the zone information of this device, from sysfs, or `None` if the device is missing.
+    pub fn zones(&self) -> Result<Option<DeviceZones>> {
+        let path = match &self.path {
+            Some(val) => val,
+            None => return Ok(None),
+        };
+        let queue = queue_dir(path)?;
+        let model = match read_sysfs(queue.join("zoned"))?.as_str() {
+            "host-aware" => ZoneModel::HostAware,
+            "host-managed" => ZoneModel::HostManaged,
+            _ => ZoneModel::None,
+        };
+        // Attributes missing on older kernels read as zero.
+        let value = |name: &str| read_sysfs_u64(queue.join(name)).unwrap_or(0);
+        let zone_size = if model == ZoneModel::None {
+            0
+        } else {
+            // In 512 byte sectors.
+            value("chunk_sectors") * 512
+        };
+        Ok(Some(DeviceZones {
+            devid: self.devid,
+            model,
+            zone_size,
+            nr_zones: value("nr_zones"),
+            max_open_zones: value("max_open_zones"),
+            max_active_zones: value("max_active_zones"),
+        }))
+    }
+}
+
+impl Filesystem {
+    /// Check whether this filesystem is in zoned mode, from sysfs.
+    ///
+    /// Some operations are not supported on zoned filesystems, e.g. `nodatacow` files, swap files
+    /// and RAID5/6 profiles.
+    pub fn is_zoned(&self) -> Result<bool> {
+        Ok(self.features()?.zoned)
+    }
+
+    /// Get the zone information of this filesystem and its devices, from sysfs.
+    ///
+    /// This requires elevated privileges(CAP_SYS_ADMIN) to list the devices.
+    pub fn zone_info(&self) -> Result<ZoneInfo> {
+        let zoned = self.is_zoned()?;
+        let mut devices: Vec<DeviceZones> = Vec::new();
+        for device in self.devices()? {
+            if let Some(zones) = device.zones()? {
+                devices.push(zones);
+            }
+        }
+        let zone_size = devices
+            .iter()
+            .map(|device| device.zone_size)
+            .find(|size| *size != 0)
+            .filter(|_| zoned);
+        Ok(ZoneInfo {
+            zoned,
+            zone_size,
+            devices,
+        })
+    }
+}
@@ -0,0 +1,961 @@
+//! Raw ioctl requests which are not covered by libbtrfsutil.
+
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+
+const IOC_NRSHIFT: u32 = 0;
+const IOC_TYPESHIFT: u32 = 8;
+const IOC_SIZESHIFT: u32 = 16;
+const IOC_DIRSHIFT: u32 = 30;
+
+const IOC_NONE: u32 = 0;
+const IOC_WRITE: u32 = 1;
+const IOC_READ: u32 = 2;
+
+/// Encode an ioctl request number, like the `_IOC` macro from the kernel headers.
+const fn ioc(dir: u32, ty: u32, nr: u32, size: usize) -> libc::Ioctl {
+    ((dir << IOC_DIRSHIFT)
+        | (ty << IOC_TYPESHIFT)
+        | (nr << IOC_NRSHIFT)
+        | ((size as u32) << IOC_SIZESHIFT)) as libc::Ioctl
+}
+
+/// Encode an ioctl request number without argument, like the `_IO` macro from the kernel headers.
+const fn io(ty: u32, nr: u32) -> libc::Ioctl {
+    ioc(IOC_NONE, ty, nr, 0)
+}
+
+/// Encode a read ioctl request number, like the `_IOR` macro from the kernel headers.
+const fn ior<T>(ty: u32, nr: u32) -> libc::Ioctl {
+    ioc(IOC_READ, ty, nr, mem::size_of::<T>())
+}
+
+/// Encode a write ioctl request number, like the `_IOW` macro from the kernel headers.
+const fn iow<T>(ty: u32, nr: u32) -> libc::Ioctl {
+    ioc(IOC_WRITE, ty, nr, mem::size_of::<T>())
+}
+
+/// Encode a read/write ioctl request number, like the `_IOWR` macro from the kernel headers.
+const fn iowr<T>(ty: u32, nr: u32) -> libc::Ioctl {
+    ioc(IOC_READ | IOC_WRITE, ty, nr, mem::size_of::<T>())
+}
+
+/// Freeze a filesystem.
+pub(crate) const FIFREEZE: libc::Ioctl = iowr::<libc::c_int>(b'X' as u32, 119);
+/// Thaw a frozen filesystem.
+pub(crate) const FITHAW: libc::Ioctl = iowr::<libc::c_int>(b'X' as u32, 120);
+
+/// Arguments of [FITRIM].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct fstrim_range {
+    pub start: u64,
+    pub len: u64,
+    pub minlen: u64,
+}
+
+/// Discard the unused blocks of a filesystem.
+pub(crate) const FITRIM: libc::Ioctl = iowr::<fstrim_range>(b'X' as u32, 121);
+
+/// Extent returned by [FS_IOC_FIEMAP].
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+#[allow(non_camel_case_types)]
+pub(crate) struct fiemap_extent {
+    pub fe_logical: u64,
+    pub fe_physical: u64,
+    pub fe_length: u64,
+    pub fe_reserved64: [u64; 2],
+    pub fe_flags: u32,
+    pub fe_reserved: [u32; 3],
+}
+
+/// Number of extents requested by each [FS_IOC_FIEMAP] call.
+pub(crate) const FIEMAP_BATCH: usize = 256;
+
+/// Arguments of [FS_IOC_FIEMAP], followed by room for [FIEMAP_BATCH] extents.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct fiemap {
+    pub fm_start: u64,
+    pub fm_length: u64,
+    pub fm_flags: u32,
+    pub fm_mapped_extents: u32,
+    pub fm_extent_count: u32,
+    pub fm_reserved: u32,
+    pub fm_extents: [fiemap_extent; FIEMAP_BATCH],
+}
+
+/// Sync the file before mapping its extents.
+pub(crate) const FIEMAP_FLAG_SYNC: u32 = 1;
+/// Last extent of the file.
+pub(crate) const FIEMAP_EXTENT_LAST: u32 = 1;
+
+/// Map the extents of a file. The size is the one of the header, without the extents.
+pub(crate) const FS_IOC_FIEMAP: libc::Ioctl = ioc(IOC_READ | IOC_WRITE, b'f' as u32, 11, 32);
+
+/// Destination of [FIDEDUPERANGE], with its result.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+#[allow(non_camel_case_types)]
+pub(crate) struct file_dedupe_range_info {
+    pub dest_fd: i64,
+    pub dest_offset: u64,
+    pub bytes_deduped: u64,
+    pub status: i32,
+    pub reserved: u32,
+}
+
+/// Maximum number of destinations of a [FIDEDUPERANGE] call, so that the arguments fit in a page.
+pub(crate) const DEDUPE_BATCH: usize = 127;
+
+/// Arguments of [FIDEDUPERANGE], followed by room for [DEDUPE_BATCH] destinations.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct file_dedupe_range {
+    pub src_offset: u64,
+    pub src_length: u64,
+    pub dest_count: u16,
+    pub reserved1: u16,
+    pub reserved2: u32,
+    pub info: [file_dedupe_range_info; DEDUPE_BATCH],
+}
+
+/// The range was deduplicated.
+pub(crate) const FILE_DEDUPE_RANGE_SAME: i32 = 0;
+/// The data differs, nothing was deduplicated.
+pub(crate) const FILE_DEDUPE_RANGE_DIFFERS: i32 = 1;
+
+/// Share the extents of a range of a file with ranges of other files holding the same data. The
+/// size is the one of the header, without the destinations.
+pub(crate) const FIDEDUPERANGE: libc::Ioctl = ioc(
+    IOC_READ | IOC_WRITE,
+    BTRFS_IOCTL_MAGIC,
+    54,
+    mem::size_of::<file_dedupe_range>() - mem::size_of::<[file_dedupe_range_info; DEDUPE_BATCH]>(),
+);
+
+/// Share all extents of a file with another file.
+pub(crate) const FICLONE: libc::Ioctl = iow::<libc::c_int>(BTRFS_IOCTL_MAGIC, 9);
+
+/// Arguments of [FICLONERANGE].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct file_clone_range {
+    pub src_fd: i64,
+    pub src_offset: u64,
+    pub src_length: u64,
+    pub dest_offset: u64,
+}
+
+/// Share the extents of a range of a file with another file.
+pub(crate) const FICLONERANGE: libc::Ioctl = iow::<file_clone_range>(BTRFS_IOCTL_MAGIC, 13);
+
+const BTRFS_IOCTL_MAGIC: u32 = 0x94;
+const BTRFS_FSID_SIZE: usize = 16;
+
+/// Request the checksum type and size in [btrfs_ioctl_fs_info_args].
+pub(crate) const BTRFS_FS_INFO_FLAG_CSUM_INFO: u64 = 1 << 0;
+/// Request the generation in [btrfs_ioctl_fs_info_args].
+pub(crate) const BTRFS_FS_INFO_FLAG_GENERATION: u64 = 1 << 1;
+/// Request the metadata uuid in [btrfs_ioctl_fs_info_args].
+pub(crate) const BTRFS_FS_INFO_FLAG_METADATA_UUID: u64 = 1 << 2;
+
+/// Arguments of [BTRFS_IOC_FS_INFO].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_fs_info_args {
+    pub max_id: u64,
+    pub num_devices: u64,
+    pub fsid: [u8; BTRFS_FSID_SIZE],
+    pub nodesize: u32,
+    pub sectorsize: u32,
+    pub clone_alignment: u32,
+    pub csum_type: u16,
+    pub csum_size: u16,
+    pub flags: u64,
+    pub generation: u64,
+    pub metadata_uuid: [u8; BTRFS_FSID_SIZE],
+    pub reserved: [u8; 944],
+}
+
+/// Get information about a Btrfs filesystem.
+pub(crate) const BTRFS_IOC_FS_INFO: libc::Ioctl =
+    ior::<btrfs_ioctl_fs_info_args>(BTRFS_IOCTL_MAGIC, 31);
+
+/// Get information about the Btrfs filesystem containing a file descriptor, requesting the optional
+/// fields selected by `flags`.
+pub(crate) fn fs_info(fd: RawFd, flags: u64) -> io::Result<btrfs_ioctl_fs_info_args> {
+    let mut args: btrfs_ioctl_fs_info_args = unsafe { mem::zeroed() };
+    args.flags = flags;
+    unsafe { ioctl(fd, BTRFS_IOC_FS_INFO, &mut args)? };
+    Ok(args)
+}
+
+pub(crate) const BTRFS_QUOTA_CTL_ENABLE: u64 = 1;
+pub(crate) const BTRFS_QUOTA_CTL_DISABLE: u64 = 2;
+
+/// Arguments of [BTRFS_IOC_QUOTA_CTL].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_quota_ctl_args {
+    pub cmd: u64,
+    pub status: u64,
+}
+
+/// Enable or disable quotas.
+pub(crate) const BTRFS_IOC_QUOTA_CTL: libc::Ioctl =
+    iowr::<btrfs_ioctl_quota_ctl_args>(BTRFS_IOCTL_MAGIC, 40);
+
+pub(crate) const BTRFS_QGROUP_LIMIT_MAX_RFER: u64 = 1 << 0;
+pub(crate) const BTRFS_QGROUP_LIMIT_MAX_EXCL: u64 = 1 << 1;
+
+/// Qgroup limits, as found in [btrfs_ioctl_qgroup_limit_args].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_qgroup_limit {
+    pub flags: u64,
+    pub max_rfer: u64,
+    pub max_excl: u64,
+    pub rsv_rfer: u64,
+    pub rsv_excl: u64,
+}
+
+/// Arguments of [BTRFS_IOC_QGROUP_LIMIT].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_qgroup_limit_args {
+    pub qgroupid: u64,
+    pub lim: btrfs_qgroup_limit,
+}
+
+/// Arguments of [BTRFS_IOC_QUOTA_RESCAN].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_quota_rescan_args {
+    pub flags: u64,
+    pub progress: u64,
+    pub reserved: [u64; 6],
+}
+
+/// Arguments of [BTRFS_IOC_QGROUP_ASSIGN].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_qgroup_assign_args {
+    pub assign: u64,
+    pub src: u64,
+    pub dst: u64,
+}
+
+/// Add or remove a relation between two qgroups.
+pub(crate) const BTRFS_IOC_QGROUP_ASSIGN: libc::Ioctl =
+    iow::<btrfs_ioctl_qgroup_assign_args>(BTRFS_IOCTL_MAGIC, 41);
+
+/// Arguments of [BTRFS_IOC_QGROUP_CREATE].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_qgroup_create_args {
+    pub create: u64,
+    pub qgroupid: u64,
+}
+
+/// Create or destroy a qgroup.
+pub(crate) const BTRFS_IOC_QGROUP_CREATE: libc::Ioctl =
+    iow::<btrfs_ioctl_qgroup_create_args>(BTRFS_IOCTL_MAGIC, 42);
+
+/// Set the limits of a qgroup.
+pub(crate) const BTRFS_IOC_QGROUP_LIMIT: libc::Ioctl =
+    ior::<btrfs_ioctl_qgroup_limit_args>(BTRFS_IOCTL_MAGIC, 43);
+
+/// Start a quota rescan.
+pub(crate) const BTRFS_IOC_QUOTA_RESCAN: libc::Ioctl =
+    iow::<btrfs_ioctl_quota_rescan_args>(BTRFS_IOCTL_MAGIC, 44);
+
+/// Search key of [btrfs_ioctl_search_args].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_search_key {
+    pub tree_id: u64,
+    pub min_objectid: u64,
+    pub max_objectid: u64,
+    pub min_offset: u64,
+    pub max_offset: u64,
+    pub min_transid: u64,
+    pub max_transid: u64,
+    pub min_type: u32,
+    pub max_type: u32,
+    pub nr_items: u32,
+    pub unused: u32,
+    pub unused1: u64,
+    pub unused2: u64,
+    pub unused3: u64,
+    pub unused4: u64,
+}
+
+/// Header preceding each item in the buffer of [btrfs_ioctl_search_args].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_search_header {
+    pub transid: u64,
+    pub objectid: u64,
+    pub offset: u64,
+    pub type_: u32,
+    pub len: u32,
+}
+
+pub(crate) const BTRFS_SEARCH_ARGS_BUFSIZE: usize = 4096 - mem::size_of::<btrfs_ioctl_search_key>();
+
+/// Arguments of [BTRFS_IOC_TREE_SEARCH].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_search_args {
+    pub key: btrfs_ioctl_search_key,
+    pub buf: [u8; BTRFS_SEARCH_ARGS_BUFSIZE],
+}
+
+/// Search the items of a Btrfs tree.
+pub(crate) const BTRFS_IOC_TREE_SEARCH: libc::Ioctl =
+    iowr::<btrfs_ioctl_search_args>(BTRFS_IOCTL_MAGIC, 17);
+
+/// Get the status of a quota rescan.
+pub(crate) const BTRFS_IOC_QUOTA_RESCAN_STATUS: libc::Ioctl =
+    ior::<btrfs_ioctl_quota_rescan_args>(BTRFS_IOCTL_MAGIC, 45);
+
+/// Wait for a quota rescan to finish.
+pub(crate) const BTRFS_IOC_QUOTA_RESCAN_WAIT: libc::Ioctl = io(BTRFS_IOCTL_MAGIC, 46);
+
+/// One entry of [btrfs_ioctl_space_args].
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_space_info {
+    pub flags: u64,
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+}
+
+/// Header of the arguments of [BTRFS_IOC_SPACE_INFO], followed by `space_slots` entries.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_space_args {
+    pub space_slots: u64,
+    pub total_spaces: u64,
+}
+
+/// Get the space allocated to each block group type and profile.
+pub(crate) const BTRFS_IOC_SPACE_INFO: libc::Ioctl =
+    iowr::<btrfs_ioctl_space_args>(BTRFS_IOCTL_MAGIC, 20);
+
+/// Get the space allocated to each block group type and profile of the Btrfs filesystem containing
+/// a file descriptor.
+pub(crate) fn space_info(fd: RawFd) -> io::Result<Vec<btrfs_ioctl_space_info>> {
+    // A first call with no slots returns the number of entries.
+    let mut header = btrfs_ioctl_space_args {
+        space_slots: 0,
+        total_spaces: 0,
+    };
+    unsafe { ioctl(fd, BTRFS_IOC_SPACE_INFO, &mut header)? };
+
+    let slots = header.total_spaces as usize;
+    let header_words = mem::size_of::<btrfs_ioctl_space_args>() / 8;
+    let entry_words = mem::size_of::<btrfs_ioctl_space_info>() / 8;
+    // Use u64 words so that the buffer is suitably aligned.
+    let mut buf: Vec<u64> = vec![0; header_words + slots * entry_words];
+    buf[0] = slots as u64;
+    unsafe { ioctl(fd, BTRFS_IOC_SPACE_INFO, buf.as_mut_ptr())? };
+
+    let total = (buf[1] as usize).min(slots);
+    let entries = unsafe {
+        std::slice::from_raw_parts(
+            buf[header_words..].as_ptr() as *const btrfs_ioctl_space_info,
+            total,
+        )
+    };
+    Ok(entries.to_vec())
+}
+
+pub(crate) const BTRFS_DEVICE_PATH_NAME_MAX: usize = 1024;
+
+/// Arguments of [BTRFS_IOC_DEV_INFO].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_dev_info_args {
+    pub devid: u64,
+    pub uuid: [u8; 16],
+    pub bytes_used: u64,
+    pub total_bytes: u64,
+    pub unused: [u64; 379],
+    pub path: [u8; BTRFS_DEVICE_PATH_NAME_MAX],
+}
+
+/// Get information about a device of a Btrfs filesystem.
+pub(crate) const BTRFS_IOC_DEV_INFO: libc::Ioctl =
+    iowr::<btrfs_ioctl_dev_info_args>(BTRFS_IOCTL_MAGIC, 30);
+
+/// Get information about a device of the Btrfs filesystem containing a file descriptor, or `None`
+/// if there is no device with this id.
+pub(crate) fn dev_info(
+    fd: RawFd,
+    devid: u64,
+) -> io::Result<Option<Box<btrfs_ioctl_dev_info_args>>> {
+    let mut args: Box<btrfs_ioctl_dev_info_args> = Box::new(unsafe { mem::zeroed() });
+    args.devid = devid;
+    match unsafe { ioctl(fd, BTRFS_IOC_DEV_INFO, &mut *args) } {
+        Ok(_) => Ok(Some(args)),
+        Err(e) if e.raw_os_error() == Some(libc::ENODEV) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) const BTRFS_DEV_STAT_VALUES_MAX: usize = 5;
+/// Reset the device statistics after reading them.
+pub(crate) const BTRFS_DEV_STATS_RESET: u64 = 1 << 0;
+
+/// Arguments of [BTRFS_IOC_GET_DEV_STATS].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_get_dev_stats {
+    pub devid: u64,
+    pub nr_items: u64,
+    pub flags: u64,
+    pub values: [u64; BTRFS_DEV_STAT_VALUES_MAX],
+    pub unused: [u64; 128 - 2 - BTRFS_DEV_STAT_VALUES_MAX],
+}
+
+/// Get the error statistics of a device.
+pub(crate) const BTRFS_IOC_GET_DEV_STATS: libc::Ioctl =
+    iowr::<btrfs_ioctl_get_dev_stats>(BTRFS_IOCTL_MAGIC, 52);
+
+pub(crate) const BTRFS_PATH_NAME_MAX: usize = 4087;
+pub(crate) const BTRFS_SUBVOL_NAME_MAX: usize = 4039;
+
+/// Specify a device by id instead of by name in [btrfs_ioctl_vol_args_v2].
+pub(crate) const BTRFS_DEVICE_SPEC_BY_ID: u64 = 1 << 3;
+
+/// Arguments of the volume ioctls taking a name.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_vol_args {
+    pub fd: i64,
+    pub name: [u8; BTRFS_PATH_NAME_MAX + 1],
+}
+
+/// Arguments of the volume ioctls taking a name or an id.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_vol_args_v2 {
+    pub fd: i64,
+    pub transid: u64,
+    pub flags: u64,
+    pub unused: [u64; 4],
+    /// Union of the name, the device id and the subvolume id.
+    pub name: [u8; BTRFS_SUBVOL_NAME_MAX + 1],
+}
+
+impl btrfs_ioctl_vol_args_v2 {
+    /// Store an id in place of the name.
+    pub(crate) fn set_id(&mut self, id: u64) {
+        self.name[..8].copy_from_slice(&id.to_ne_bytes());
+    }
+}
+
+/// Copy a name into a fixed size, nul terminated buffer of ioctl arguments.
+pub(crate) fn copy_name(dst: &mut [u8], name: &[u8]) -> io::Result<()> {
+    if name.len() >= dst.len() || name.contains(&0) {
+        return Err(io::Error::from_raw_os_error(libc::ENAMETOOLONG));
+    }
+    dst[..name.len()].copy_from_slice(name);
+    dst[name.len()] = 0;
+    Ok(())
+}
+
+/// Resize a device of a filesystem.
+pub(crate) const BTRFS_IOC_RESIZE: libc::Ioctl = iow::<btrfs_ioctl_vol_args>(BTRFS_IOCTL_MAGIC, 3);
+/// Register a device with the kernel, through the control device.
+pub(crate) const BTRFS_IOC_SCAN_DEV: libc::Ioctl =
+    iow::<btrfs_ioctl_vol_args>(BTRFS_IOCTL_MAGIC, 4);
+/// Add a device to a filesystem.
+pub(crate) const BTRFS_IOC_ADD_DEV: libc::Ioctl =
+    iow::<btrfs_ioctl_vol_args>(BTRFS_IOCTL_MAGIC, 10);
+/// Remove a device from a filesystem, by name.
+pub(crate) const BTRFS_IOC_RM_DEV: libc::Ioctl = iow::<btrfs_ioctl_vol_args>(BTRFS_IOCTL_MAGIC, 11);
+/// Remove a device from a filesystem, by name or id.
+pub(crate) const BTRFS_IOC_RM_DEV_V2: libc::Ioctl =
+    iow::<btrfs_ioctl_vol_args_v2>(BTRFS_IOCTL_MAGIC, 58);
+
+pub(crate) const BTRFS_IOCTL_DEV_REPLACE_CONT_READING_FROM_SRCDEV_MODE_ALWAYS: u64 = 0;
+pub(crate) const BTRFS_IOCTL_DEV_REPLACE_CONT_READING_FROM_SRCDEV_MODE_AVOID: u64 = 1;
+
+/// Start parameters of [btrfs_ioctl_dev_replace_args].
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_dev_replace_start_params {
+    pub srcdevid: u64,
+    pub cont_reading_from_srcdev_mode: u64,
+    pub srcdev_name: [u8; BTRFS_DEVICE_PATH_NAME_MAX + 1],
+    pub tgtdev_name: [u8; BTRFS_DEVICE_PATH_NAME_MAX + 1],
+}
+
+pub(crate) const BTRFS_IOCTL_DEV_REPLACE_STATE_STARTED: u64 = 1;
+pub(crate) const BTRFS_IOCTL_DEV_REPLACE_STATE_FINISHED: u64 = 2;
+pub(crate) const BTRFS_IOCTL_DEV_REPLACE_STATE_CANCELED: u64 = 3;
+pub(crate) const BTRFS_IOCTL_DEV_REPLACE_STATE_SUSPENDED: u64 = 4;
+
+/// Status parameters of [btrfs_ioctl_dev_replace_args].
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_dev_replace_status_params {
+    pub replace_state: u64,
+    pub progress_1000: u64,
+    pub time_started: u64,
+    pub time_stopped: u64,
+    pub num_write_errors: u64,
+    pub num_uncorrectable_read_errors: u64,
+}
+
+/// Parameters of [btrfs_ioctl_dev_replace_args], depending on the command.
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub(crate) union btrfs_ioctl_dev_replace_params {
+    pub start: btrfs_ioctl_dev_replace_start_params,
+    pub status: btrfs_ioctl_dev_replace_status_params,
+}
+
+pub(crate) const BTRFS_IOCTL_DEV_REPLACE_CMD_START: u64 = 0;
+pub(crate) const BTRFS_IOCTL_DEV_REPLACE_CMD_STATUS: u64 = 1;
+pub(crate) const BTRFS_IOCTL_DEV_REPLACE_CMD_CANCEL: u64 = 2;
+pub(crate) const BTRFS_IOCTL_DEV_REPLACE_RESULT_NO_ERROR: u64 = 0;
+pub(crate) const BTRFS_IOCTL_DEV_REPLACE_RESULT_NOT_STARTED: u64 = 1;
+pub(crate) const BTRFS_IOCTL_DEV_REPLACE_RESULT_ALREADY_STARTED: u64 = 2;
+pub(crate) const BTRFS_IOCTL_DEV_REPLACE_RESULT_SCRUB_INPROGRESS: u64 = 3;
+
+/// Arguments of [BTRFS_IOC_DEV_REPLACE].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_dev_replace_args {
+    pub cmd: u64,
+    pub result: u64,
+    pub params: btrfs_ioctl_dev_replace_params,
+    pub spare: [u64; 64],
+}
+
+/// Start, query or cancel a device replace.
+pub(crate) const BTRFS_IOC_DEV_REPLACE: libc::Ioctl =
+    iowr::<btrfs_ioctl_dev_replace_args>(BTRFS_IOCTL_MAGIC, 53);
+
+pub(crate) const BTRFS_BALANCE_DATA: u64 = 1 << 0;
+pub(crate) const BTRFS_BALANCE_SYSTEM: u64 = 1 << 1;
+pub(crate) const BTRFS_BALANCE_METADATA: u64 = 1 << 2;
+pub(crate) const BTRFS_BALANCE_FORCE: u64 = 1 << 3;
+pub(crate) const BTRFS_BALANCE_RESUME: u64 = 1 << 4;
+
+pub(crate) const BTRFS_BALANCE_STATE_RUNNING: u64 = 1 << 0;
+pub(crate) const BTRFS_BALANCE_STATE_PAUSE_REQ: u64 = 1 << 1;
+pub(crate) const BTRFS_BALANCE_STATE_CANCEL_REQ: u64 = 1 << 2;
+
+pub(crate) const BTRFS_BALANCE_CTL_PAUSE: libc::c_ulong = 1;
+pub(crate) const BTRFS_BALANCE_CTL_CANCEL: libc::c_ulong = 2;
+
+pub(crate) const BTRFS_BALANCE_ARGS_PROFILES: u64 = 1 << 0;
+pub(crate) const BTRFS_BALANCE_ARGS_DEVID: u64 = 1 << 2;
+pub(crate) const BTRFS_BALANCE_ARGS_DRANGE: u64 = 1 << 3;
+pub(crate) const BTRFS_BALANCE_ARGS_VRANGE: u64 = 1 << 4;
+pub(crate) const BTRFS_BALANCE_ARGS_LIMIT_RANGE: u64 = 1 << 6;
+pub(crate) const BTRFS_BALANCE_ARGS_CONVERT: u64 = 1 << 8;
+pub(crate) const BTRFS_BALANCE_ARGS_SOFT: u64 = 1 << 9;
+pub(crate) const BTRFS_BALANCE_ARGS_USAGE_RANGE: u64 = 1 << 10;
+
+/// Filters of one block group type in [btrfs_ioctl_balance_args].
+///
+/// `usage` and `limit` are unions of a u64 and a range of two u32, minimum first.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_balance_args {
+    pub profiles: u64,
+    pub usage: u64,
+    pub devid: u64,
+    pub pstart: u64,
+    pub pend: u64,
+    pub vstart: u64,
+    pub vend: u64,
+    pub target: u64,
+    pub flags: u64,
+    pub limit: u64,
+    pub stripes_min: u32,
+    pub stripes_max: u32,
+    pub unused: [u64; 6],
+}
+
+/// Store a range of two u32 in a u64 union of [btrfs_balance_args].
+pub(crate) fn u32_range(min: u32, max: u32) -> u64 {
+    let mut bytes = [0; 8];
+    bytes[..4].copy_from_slice(&min.to_ne_bytes());
+    bytes[4..].copy_from_slice(&max.to_ne_bytes());
+    u64::from_ne_bytes(bytes)
+}
+
+/// Progress of a balance, in chunks.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_balance_progress {
+    pub expected: u64,
+    pub considered: u64,
+    pub completed: u64,
+}
+
+/// Arguments of [BTRFS_IOC_BALANCE_V2].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_balance_args {
+    pub flags: u64,
+    pub state: u64,
+    pub data: btrfs_balance_args,
+    pub meta: btrfs_balance_args,
+    pub sys: btrfs_balance_args,
+    pub stat: btrfs_balance_progress,
+    pub unused: [u64; 72],
+}
+
+/// Start a balance.
+pub(crate) const BTRFS_IOC_BALANCE_V2: libc::Ioctl =
+    iowr::<btrfs_ioctl_balance_args>(BTRFS_IOCTL_MAGIC, 32);
+
+/// Pause or cancel a balance, passing the command as value.
+pub(crate) const BTRFS_IOC_BALANCE_CTL: libc::Ioctl = iow::<libc::c_int>(BTRFS_IOCTL_MAGIC, 33);
+/// Get the progress of a balance.
+pub(crate) const BTRFS_IOC_BALANCE_PROGRESS: libc::Ioctl =
+    ior::<btrfs_ioctl_balance_args>(BTRFS_IOCTL_MAGIC, 34);
+
+/// Progress of a scrub on a device.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_scrub_progress {
+    pub data_extents_scrubbed: u64,
+    pub tree_extents_scrubbed: u64,
+    pub data_bytes_scrubbed: u64,
+    pub tree_bytes_scrubbed: u64,
+    pub read_errors: u64,
+    pub csum_errors: u64,
+    pub verify_errors: u64,
+    pub no_csum: u64,
+    pub csum_discards: u64,
+    pub super_errors: u64,
+    pub malloc_errors: u64,
+    pub uncorrectable_errors: u64,
+    pub corrected_errors: u64,
+    pub last_physical: u64,
+    pub unverified_errors: u64,
+}
+
+/// Do not repair the errors found by a scrub.
+pub(crate) const BTRFS_SCRUB_READONLY: u64 = 1;
+
+/// Arguments of [BTRFS_IOC_SCRUB] and [BTRFS_IOC_SCRUB_PROGRESS].
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_scrub_args {
+    pub devid: u64,
+    pub start: u64,
+    pub end: u64,
+    pub flags: u64,
+    pub progress: btrfs_scrub_progress,
+    pub unused: [u64; (1024 - 32 - mem::size_of::<btrfs_scrub_progress>()) / 8],
+}
+
+/// Scrub a device.
+pub(crate) const BTRFS_IOC_SCRUB: libc::Ioctl =
+    iowr::<btrfs_ioctl_scrub_args>(BTRFS_IOCTL_MAGIC, 27);
+/// Cancel the running scrubs.
+pub(crate) const BTRFS_IOC_SCRUB_CANCEL: libc::Ioctl = io(BTRFS_IOCTL_MAGIC, 28);
+/// Get the progress of the scrub of a device.
+pub(crate) const BTRFS_IOC_SCRUB_PROGRESS: libc::Ioctl =
+    iowr::<btrfs_ioctl_scrub_args>(BTRFS_IOCTL_MAGIC, 29);
+
+/// Compress the defragmented range.
+pub(crate) const BTRFS_DEFRAG_RANGE_COMPRESS: u64 = 1;
+/// Start writeback of the defragmented range.
+pub(crate) const BTRFS_DEFRAG_RANGE_START_IO: u64 = 2;
+
+/// Arguments of [BTRFS_IOC_DEFRAG_RANGE].
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_defrag_range_args {
+    pub start: u64,
+    pub len: u64,
+    pub flags: u64,
+    pub extent_thresh: u32,
+    pub compress_type: u32,
+    pub unused: [u32; 4],
+}
+
+/// Defragment a range of a file.
+pub(crate) const BTRFS_IOC_DEFRAG_RANGE: libc::Ioctl =
+    iow::<btrfs_ioctl_defrag_range_args>(BTRFS_IOCTL_MAGIC, 16);
+
+/// Header of the output buffers of [BTRFS_IOC_INO_PATHS] and [BTRFS_IOC_LOGICAL_INO], followed by
+/// the values.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_data_container {
+    pub bytes_left: u32,
+    pub bytes_missing: u32,
+    pub elem_cnt: u32,
+    pub elem_missed: u32,
+}
+
+/// Arguments of [BTRFS_IOC_INO_PATHS].
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_ino_path_args {
+    pub inum: u64,
+    pub size: u64,
+    pub reserved: [u64; 4],
+    pub fspath: u64,
+}
+
+/// Arguments of [BTRFS_IOC_LOGICAL_INO] and [BTRFS_IOC_LOGICAL_INO_V2].
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_logical_ino_args {
+    pub logical: u64,
+    pub size: u64,
+    pub reserved: [u64; 3],
+    pub flags: u64,
+    pub inodes: u64,
+}
+
+/// Resolve an inode number to its paths within a subvolume.
+pub(crate) const BTRFS_IOC_INO_PATHS: libc::Ioctl =
+    iowr::<btrfs_ioctl_ino_path_args>(BTRFS_IOCTL_MAGIC, 35);
+/// Resolve a logical address to the inodes referencing it.
+pub(crate) const BTRFS_IOC_LOGICAL_INO: libc::Ioctl =
+    iowr::<btrfs_ioctl_logical_ino_args>(BTRFS_IOCTL_MAGIC, 36);
+/// Resolve a logical address to the inodes referencing it, with a larger buffer.
+pub(crate) const BTRFS_IOC_LOGICAL_INO_V2: libc::Ioctl =
+    iowr::<btrfs_ioctl_logical_ino_args>(BTRFS_IOCTL_MAGIC, 59);
+
+/// Initial size of the output buffers of [BTRFS_IOC_INO_PATHS] and [BTRFS_IOC_LOGICAL_INO], which
+/// is also the maximum size accepted by [BTRFS_IOC_LOGICAL_INO].
+const DATA_CONTAINER_SIZE: usize = 64 * 1024;
+/// Maximum size of the output buffers.
+const DATA_CONTAINER_MAX_SIZE: usize = 16 * 1024 * 1024;
+/// Maximum size of the output buffer of [BTRFS_IOC_INO_PATHS], larger sizes being capped by the
+/// kernel.
+const INO_PATHS_MAX_SIZE: usize = 4096;
+
+/// Call an ioctl filling a [btrfs_data_container], growing the buffer up to `max_size` bytes while
+/// values are missing. Returns the whole buffer, header included.
+///
+/// The closure performs the request given the size and the address of the buffer.
+fn data_container<F>(max_size: usize, mut request: F) -> io::Result<Vec<u64>>
+where
+    F: FnMut(u64, u64) -> io::Result<i32>,
+{
+    let mut size = DATA_CONTAINER_SIZE.min(max_size);
+    loop {
+        let mut buf: Vec<u64> = vec![0; size / 8];
+        request(size as u64, buf.as_mut_ptr() as u64)?;
+        let missing = data_container_header(&buf).bytes_missing as usize;
+        if missing == 0 || size >= max_size {
+            return Ok(buf);
+        }
+        size = (size + missing).min(max_size);
+    }
+}
+
+fn data_container_header(buf: &[u64]) -> &btrfs_data_container {
+    unsafe { &*(buf.as_ptr() as *const btrfs_data_container) }
+}
+
+/// Check whether values did not fit in a buffer filled by [data_container].
+fn data_container_truncated(buf: &[u64]) -> bool {
+    let header = data_container_header(buf);
+    header.bytes_missing != 0 || header.elem_missed != 0
+}
+
+/// Get the values of a buffer filled by [data_container].
+fn data_container_values(buf: &[u64]) -> &[u64] {
+    let header_len = mem::size_of::<btrfs_data_container>() / 8;
+    let count = data_container_header(buf).elem_cnt as usize;
+    &buf[header_len..header_len + count]
+}
+
+/// Resolve an inode number to its paths, relative to the subvolume of the file descriptor. Also
+/// returns whether paths were left out because they did not fit in the buffer of the kernel.
+pub(crate) fn ino_paths(fd: RawFd, inum: u64) -> io::Result<(Vec<Vec<u8>>, bool)> {
+    let buf = data_container(INO_PATHS_MAX_SIZE, |size, fspath| {
+        let mut args = btrfs_ioctl_ino_path_args {
+            inum,
+            size,
+            fspath,
+            ..Default::default()
+        };
+        unsafe { ioctl(fd, BTRFS_IOC_INO_PATHS, &mut args) }
+    })?;
+
+    // The values are offsets of NUL terminated strings, relative to the start of the values.
+    let header_len = mem::size_of::<btrfs_data_container>();
+    let bytes: &[u8] =
+        unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u8, buf.len() * 8) };
+    let strings = &bytes[header_len..];
+    let paths = data_container_values(&buf)
+        .iter()
+        .filter_map(|offset| strings.get(*offset as usize..))
+        .map(|path| {
+            let end = path.iter().position(|b| *b == 0).unwrap_or(path.len());
+            path[..end].to_vec()
+        })
+        .collect();
+    Ok((paths, data_container_truncated(&buf)))
+}
+
+/// Resolve a logical address to the `(inode, offset, root)` triples referencing it.
+pub(crate) fn logical_ino(fd: RawFd, logical: u64) -> io::Result<Vec<[u64; 3]>> {
+    let request = |request: libc::Ioctl| {
+        move |size, inodes| {
+            let mut args = btrfs_ioctl_logical_ino_args {
+                logical,
+                size,
+                inodes,
+                ..Default::default()
+            };
+            unsafe { ioctl(fd, request, &mut args) }
+        }
+    };
+    let buf = match data_container(DATA_CONTAINER_MAX_SIZE, request(BTRFS_IOC_LOGICAL_INO_V2)) {
+        Err(e) if e.raw_os_error() == Some(libc::ENOTTY) => {
+            data_container(DATA_CONTAINER_SIZE, request(BTRFS_IOC_LOGICAL_INO))?
+        }
+        result => result?,
+    };
+    Ok(data_container_values(&buf)
+        .chunks_exact(3)
+        .map(|triple| [triple[0], triple[1], triple[2]])
+        .collect())
+}
+
+/// Arguments of [BTRFS_IOC_SEND].
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_send_args {
+    pub send_fd: i64,
+    pub clone_sources_count: u64,
+    pub clone_sources: u64,
+    pub parent_root: u64,
+    pub flags: u64,
+    pub version: u32,
+    pub reserved: [u8; 28],
+}
+
+/// Timestamp in [btrfs_ioctl_received_subvol_args].
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_timespec {
+    pub sec: u64,
+    pub nsec: u32,
+}
+
+/// Arguments of [BTRFS_IOC_SET_RECEIVED_SUBVOL].
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_received_subvol_args {
+    pub uuid: [u8; 16],
+    pub stransid: u64,
+    pub rtransid: u64,
+    pub stime: btrfs_ioctl_timespec,
+    pub rtime: btrfs_ioctl_timespec,
+    pub flags: u64,
+    pub reserved: [u64; 16],
+}
+
+/// Record the subvolume a received subvolume was sent from.
+pub(crate) const BTRFS_IOC_SET_RECEIVED_SUBVOL: libc::Ioctl =
+    iowr::<btrfs_ioctl_received_subvol_args>(BTRFS_IOCTL_MAGIC, 37);
+
+/// Do not send file data, only metadata.
+pub(crate) const BTRFS_SEND_FLAG_NO_FILE_DATA: u64 = 0x1;
+/// Generate the stream version given in [btrfs_ioctl_send_args].
+pub(crate) const BTRFS_SEND_FLAG_VERSION: u64 = 0x8;
+/// Send compressed extents as they are stored, since stream version 2.
+pub(crate) const BTRFS_SEND_FLAG_COMPRESSED: u64 = 0x10;
+
+/// Generate a send stream of a read-only subvolume into a file descriptor.
+pub(crate) const BTRFS_IOC_SEND: libc::Ioctl = iow::<btrfs_ioctl_send_args>(BTRFS_IOCTL_MAGIC, 38);
+
+/// Arguments of [BTRFS_IOC_ENCODED_WRITE].
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_camel_case_types)]
+pub(crate) struct btrfs_ioctl_encoded_io_args {
+    pub iov: u64,
+    pub iovcnt: u64,
+    pub offset: i64,
+    pub flags: u64,
+    pub len: u64,
+    pub unencoded_len: u64,
+    pub unencoded_offset: u64,
+    pub compression: u32,
+    pub encryption: u32,
+    pub reserved: [u64; 8],
+}
+
+/// Write data to a file as it is stored, e.g. compressed.
+pub(crate) const BTRFS_IOC_ENCODED_WRITE: libc::Ioctl =
+    iow::<btrfs_ioctl_encoded_io_args>(BTRFS_IOCTL_MAGIC, 64);
+
+/// Size of the label of a filesystem, including the nul terminator.
+pub(crate) const BTRFS_LABEL_SIZE: usize = 256;
+
+/// Get the label of a mounted filesystem.
+pub(crate) const BTRFS_IOC_GET_FSLABEL: libc::Ioctl =
+    ior::<[u8; BTRFS_LABEL_SIZE]>(BTRFS_IOCTL_MAGIC, 49);
+/// Set the label of a mounted filesystem.
+pub(crate) const BTRFS_IOC_SET_FSLABEL: libc::Ioctl =
+    iow::<[u8; BTRFS_LABEL_SIZE]>(BTRFS_IOCTL_MAGIC, 50);
+
+/// Get the inode flags of a file, like `lsattr`.
+pub(crate) const FS_IOC_GETFLAGS: libc::Ioctl = ior::<libc::c_long>(b'f' as u32, 1);
+
+/// Set the inode flags of a file, like `chattr`.
+pub(crate) const FS_IOC_SETFLAGS: libc::Ioctl = iow::<libc::c_long>(b'f' as u32, 2);
+
+/// Perform an ioctl request, converting a failure into an I/O error.
+///
+/// # Safety
+///
+/// The argument must be valid for the request.
+pub(crate) unsafe fn ioctl<T>(fd: RawFd, request: libc::Ioctl, arg: *mut T) -> io::Result<i32> {
+    let ret = libc::ioctl(fd, request, arg);
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Perform an ioctl request taking its argument by value, converting a failure into an I/O error.
+pub(crate) fn ioctl_value(
+    fd: RawFd,
+    request: libc::Ioctl,
+    value: libc::c_ulong,
+) -> io::Result<i32> {
+    let ret = unsafe { libc::ioctl(fd, request, value) };
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
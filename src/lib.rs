@@ -14,6 +14,8 @@
 //! - Arch Linux: `# pacman -S btrfs-progs`
 //! - Ubuntu: `# apt install btrfs-progs`
 //!
+//! The minimum supported Rust version is 1.73.
+//!
 //! ## Usage
 //!
 //! Please keep in mind that many of the operations this library can perform may require elevated
@@ -62,6 +64,9 @@ pub mod bindings {
 
     /// Id of the root subvolume in a Btrfs filesystem.
     pub const BTRFS_FS_TREE_OBJECTID: u64 = 5;
+
+    /// Root item flag set on read-only subvolumes.
+    pub const BTRFS_ROOT_SUBVOL_RDONLY: u64 = 1 << 0;
 }
 
 #[macro_use]
@@ -69,10 +74,32 @@ extern crate bitflags;
 
 #[macro_use]
 pub mod error;
+#[cfg(feature = "json")]
+pub mod backup;
+#[cfg(feature = "async")]
+pub mod blocking;
+pub mod boot;
+pub mod cancel;
+pub mod dedup;
 #[macro_use]
 mod common;
+pub mod extent;
+pub mod filesystem;
+mod ioctl;
+pub mod mount;
+pub mod progress;
+pub mod properties;
 pub mod qgroup;
+pub mod rate_limit;
+pub mod rollback;
+pub mod scheduler;
+mod search;
+pub mod send;
+pub mod snapper;
 pub mod subvolume;
+pub mod sync;
+pub mod volumes;
+pub mod watch;
 
 pub use error::BtrfsUtilError;
 
@@ -0,0 +1,11 @@
+//! Btrfs mounts
+
+mod mountinfo;
+mod options;
+mod remount;
+mod unmount;
+
+pub use mountinfo::*;
+pub use options::*;
+pub use remount::*;
+pub use unmount::*;
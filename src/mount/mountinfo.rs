@@ -0,0 +1,228 @@
+use crate::error::ParseError;
+use crate::filesystem::SYSFS_BTRFS;
+use crate::mount::BtrfsMountOptions;
+use crate::Result;
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Path of the mount table of the calling process.
+pub(crate) const MOUNTINFO: &str = "/proc/self/mountinfo";
+
+/// Decode a field of the mount table, where spaces, tabs, newlines and backslashes are escaped
+/// as octal sequences.
+pub(crate) fn unescape(field: &str) -> PathBuf {
+    let bytes = field.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes.get(i + 1..i + 4).filter(|digits| {
+            bytes[i] == b'\\' && digits.iter().all(|digit| (b'0'..=b'7').contains(digit))
+        });
+        match escape {
+            Some(digits) => {
+                decoded.push(
+                    digits
+                        .iter()
+                        .fold(0u8, |acc, digit| acc.wrapping_mul(8) + (digit - b'0')),
+                );
+                i += 4;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(OsStr::from_bytes(&decoded))
+}
+
+/// List the mount points of the calling process, in mount order.
+pub(crate) fn mount_points() -> io::Result<Vec<PathBuf>> {
+    Ok(fs::read_to_string(MOUNTINFO)?
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(unescape)
+        .collect())
+}
+
+/// A mounted Btrfs filesystem or subvolume, as listed in the mount table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct MountEntry {
+    /// Unique id of the mount.
+    pub mount_id: u32,
+    /// Id of the mount this mount is attached to.
+    pub parent_id: u32,
+    /// Device number of the mounted filesystem, as returned by `stat(2)` for files on it.
+    pub dev: u64,
+    /// Device the filesystem was mounted from.
+    pub device: PathBuf,
+    /// UUID of the filesystem, if it could be resolved through sysfs.
+    pub fsid: Option<Uuid>,
+    /// Path of the mount point.
+    pub mount_point: PathBuf,
+    /// Path within the filesystem which is mounted, i.e. the path of the subvolume or of a
+    /// directory in it for bind mounts.
+    pub root: PathBuf,
+    /// Id of the mounted subvolume.
+    pub subvol_id: Option<u64>,
+    /// Path of the mounted subvolume, from the filesystem root.
+    pub subvol: Option<PathBuf>,
+    /// Options of the mount, e.g. `ro` or `noatime`.
+    pub mount_options: Vec<String>,
+    /// Options of the filesystem, shared by its mounts, e.g. `space_cache=v2`.
+    pub super_options: Vec<String>,
+}
+
+impl MountEntry {
+    /// Check whether the mount is read-only.
+    pub fn is_read_only(&self) -> bool {
+        self.mount_options.iter().any(|option| option == "ro")
+    }
+
+    /// Parse the options of the filesystem.
+    pub fn btrfs_options(&self) -> std::result::Result<BtrfsMountOptions, ParseError> {
+        self.super_options.join(",").parse()
+    }
+
+    /// Get the value of a filesystem option, e.g. `compress`.
+    pub fn super_option(&self, name: &str) -> Option<&str> {
+        self.super_options.iter().find_map(|option| {
+            let (key, value) = option.split_once('=')?;
+            if key == name {
+                Some(value)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Parse a line of the mount table, returning `None` for other filesystem types.
+    fn parse(line: &str) -> Option<Self> {
+        let (mount_part, super_part) = line.split_once(" - ")?;
+        let mount_fields: Vec<&str> = mount_part.split(' ').collect();
+        let super_fields: Vec<&str> = super_part.split(' ').collect();
+        if mount_fields.len() < 6 || super_fields.len() < 3 || super_fields[0] != "btrfs" {
+            return None;
+        }
+        let (major, minor) = mount_fields[2].split_once(':')?;
+        let options = |field: &str| -> Vec<String> { field.split(',').map(String::from).collect() };
+
+        let mut entry = Self {
+            mount_id: mount_fields[0].parse().ok()?,
+            parent_id: mount_fields[1].parse().ok()?,
+            dev: libc::makedev(major.parse().ok()?, minor.parse().ok()?),
+            device: unescape(super_fields[1]),
+            fsid: None,
+            mount_point: unescape(mount_fields[4]),
+            root: unescape(mount_fields[3]),
+            subvol_id: None,
+            subvol: None,
+            mount_options: options(mount_fields[5]),
+            super_options: options(super_fields[2]),
+        };
+        entry.subvol_id = entry
+            .super_option("subvolid")
+            .and_then(|id| id.parse().ok());
+        entry.subvol = entry.super_option("subvol").map(unescape);
+        Some(entry)
+    }
+}
+
+/// Map the names of the block devices of the Btrfs filesystems known to sysfs to their UUID.
+fn sysfs_devices() -> HashMap<OsString, Uuid> {
+    let mut devices = HashMap::new();
+    let entries = match fs::read_dir(SYSFS_BTRFS) {
+        Ok(val) => val,
+        Err(_) => return devices,
+    };
+    for entry in entries.flatten() {
+        let fsid = match entry
+            .file_name()
+            .to_str()
+            .and_then(|name| Uuid::parse_str(name).ok())
+        {
+            Some(val) => val,
+            None => continue,
+        };
+        if let Ok(members) = fs::read_dir(entry.path().join("devices")) {
+            for member in members.flatten() {
+                devices.insert(member.file_name(), fsid);
+            }
+        }
+    }
+    devices
+}
+
+/// Resolve the UUID of the filesystem on a device, e.g. `/dev/mapper/root` through `dm-0`.
+fn resolve_fsid(devices: &HashMap<OsString, Uuid>, device: &Path) -> Option<Uuid> {
+    let device = device.canonicalize().ok()?;
+    devices.get(device.file_name()?).copied()
+}
+
+/// List the Btrfs filesystems and subvolumes mounted in the mount namespace of the calling
+/// process, in mount order, from `/proc/self/mountinfo`.
+///
+/// This does not require elevated privileges.
+pub fn btrfs_mounts() -> Result<Vec<MountEntry>> {
+    let devices = sysfs_devices();
+    Ok(fs::read_to_string(MOUNTINFO)?
+        .lines()
+        .filter_map(MountEntry::parse)
+        .map(|mut entry| {
+            entry.fsid = resolve_fsid(&devices, &entry.device);
+            entry
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unescape_fields() {
+        assert_eq!(unescape("/mnt/plain"), Path::new("/mnt/plain"));
+        assert_eq!(
+            unescape(r"/mnt/with\040space\011tab\012newline\134backslash"),
+            Path::new("/mnt/with space\ttab\nnewline\\backslash")
+        );
+        // Backslashes which do not start an octal sequence are kept.
+        assert_eq!(unescape(r"/mnt/a\b\04"), Path::new(r"/mnt/a\b\04"));
+        assert_eq!(unescape(r"\303\251t\303\251"), Path::new("été"));
+    }
+
+    #[test]
+    fn parse_mount_entry() {
+        let line = r"36 25 0:32 /@home /home\040dir rw,relatime shared:1 - btrfs /dev/sda2 rw,space_cache=v2,subvolid=257,subvol=/@home";
+        let entry = MountEntry::parse(line).unwrap();
+        assert_eq!(entry.mount_id, 36);
+        assert_eq!(entry.parent_id, 25);
+        assert_eq!(entry.dev, libc::makedev(0, 32));
+        assert_eq!(entry.device, Path::new("/dev/sda2"));
+        assert_eq!(entry.mount_point, Path::new("/home dir"));
+        assert_eq!(entry.root, Path::new("/@home"));
+        assert_eq!(entry.subvol_id, Some(257));
+        assert_eq!(entry.subvol.as_deref(), Some(Path::new("/@home")));
+        assert!(!entry.is_read_only());
+        assert_eq!(entry.super_option("space_cache"), Some("v2"));
+        assert_eq!(
+            entry.btrfs_options().unwrap().space_cache,
+            Some(crate::mount::SpaceCache::V2)
+        );
+
+        let ext4 = "22 1 8:1 / / rw,relatime - ext4 /dev/sda1 rw";
+        assert_eq!(MountEntry::parse(ext4), None);
+    }
+}
@@ -0,0 +1,277 @@
+use crate::error::ParseError;
+use crate::filesystem::Compression;
+
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Compression of new data, set with the `compress` and `compress-force` mount options.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MountCompression {
+    /// Algorithm, or `None` to disable compression (`compress=no`).
+    pub algorithm: Option<Compression>,
+    /// Level of the algorithm, or `None` for its default.
+    pub level: Option<u32>,
+    /// Compress data even if it does not compress well (`compress-force`).
+    pub force: bool,
+}
+
+impl MountCompression {
+    fn parse(value: Option<&str>, force: bool, option: &str) -> Result<Self, ParseError> {
+        let error = || ParseError::new("compress mount option", option);
+        let (algorithm, level) = match value {
+            // A bare `compress` selects zlib.
+            None => (Some(Compression::Zlib), None),
+            Some("no") => (None, None),
+            Some(value) => match value.split_once(':') {
+                Some((algorithm, level)) => (
+                    Some(algorithm.parse().map_err(|_| error())?),
+                    Some(level.parse().map_err(|_| error())?),
+                ),
+                None => (Some(value.parse().map_err(|_| error())?), None),
+            },
+        };
+        Ok(Self {
+            algorithm,
+            level,
+            force,
+        })
+    }
+}
+
+impl fmt::Display for MountCompression {
+    /// Format the compression as a mount option, e.g. `compress-force=zstd:3`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = if self.force {
+            "compress-force"
+        } else {
+            "compress"
+        };
+        match (self.algorithm, self.level) {
+            (None, _) => write!(f, "{}=no", name),
+            (Some(algorithm), None) => write!(f, "{}={}", name, algorithm),
+            (Some(algorithm), Some(level)) => write!(f, "{}={}:{}", name, algorithm, level),
+        }
+    }
+}
+
+/// Free space tracking, set with the `space_cache` and `nospace_cache` mount options.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SpaceCache {
+    /// Free space cache stored in inodes (`space_cache=v1`), deprecated.
+    V1,
+    /// Free space tree (`space_cache=v2`).
+    V2,
+    /// No cache (`nospace_cache`).
+    Off,
+}
+
+/// Discarding of freed extents, set with the `discard` and `nodiscard` mount options.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DiscardMode {
+    /// Discard synchronously when extents are freed (`discard=sync`).
+    Sync,
+    /// Discard asynchronously in the background (`discard=async`).
+    Async,
+    /// Do not discard (`nodiscard`).
+    Off,
+}
+
+/// Btrfs mount options, parsed from and formatted to an option string like
+/// `rw,compress=zstd:3,space_cache=v2,subvolid=256,subvol=/home`.
+///
+/// Options without a field of their own, including generic mount options like `noatime`, are
+/// kept as they are in [other](#structfield.other), so an option string can be modified and
+/// formatted back without losing any of them. Options left to `None` are not formatted.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BtrfsMountOptions {
+    /// Whether the filesystem is mounted read-only (`ro`) or read-write (`rw`).
+    pub read_only: Option<bool>,
+    /// Compression of new data.
+    pub compress: Option<MountCompression>,
+    /// Free space tracking.
+    pub space_cache: Option<SpaceCache>,
+    /// Discarding of freed extents.
+    pub discard: Option<DiscardMode>,
+    /// Interval between transaction commits, in seconds (`commit`).
+    pub commit: Option<u32>,
+    /// Whether small random writes are defragmented automatically (`autodefrag`,
+    /// `noautodefrag`).
+    pub autodefrag: Option<bool>,
+    /// Id of the subvolume to mount (`subvolid`).
+    pub subvolid: Option<u64>,
+    /// Path of the subvolume to mount, from the filesystem root (`subvol`).
+    pub subvol: Option<PathBuf>,
+    /// Other options, in order.
+    pub other: Vec<String>,
+}
+
+impl FromStr for BtrfsMountOptions {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut options = Self::default();
+        for option in s.split(',').filter(|option| !option.is_empty()) {
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (option, None),
+            };
+            let error = || ParseError::new("mount option", option);
+            match (name, value) {
+                ("ro", None) => options.read_only = Some(true),
+                ("rw", None) => options.read_only = Some(false),
+                ("compress", _) => {
+                    options.compress = Some(MountCompression::parse(value, false, option)?)
+                }
+                ("compress-force", _) => {
+                    options.compress = Some(MountCompression::parse(value, true, option)?)
+                }
+                ("space_cache", None) | ("space_cache", Some("v1")) => {
+                    options.space_cache = Some(SpaceCache::V1)
+                }
+                ("space_cache", Some("v2")) => options.space_cache = Some(SpaceCache::V2),
+                ("space_cache", Some(_)) => return Err(error()),
+                ("nospace_cache", None) => options.space_cache = Some(SpaceCache::Off),
+                // A bare `discard` selects synchronous discards.
+                ("discard", None) | ("discard", Some("sync")) => {
+                    options.discard = Some(DiscardMode::Sync)
+                }
+                ("discard", Some("async")) => options.discard = Some(DiscardMode::Async),
+                ("discard", Some(_)) => return Err(error()),
+                ("nodiscard", None) => options.discard = Some(DiscardMode::Off),
+                ("commit", Some(value)) => {
+                    options.commit = Some(value.parse().map_err(|_| error())?)
+                }
+                ("autodefrag", None) => options.autodefrag = Some(true),
+                ("noautodefrag", None) => options.autodefrag = Some(false),
+                ("subvolid", Some(value)) => {
+                    options.subvolid = Some(value.parse().map_err(|_| error())?)
+                }
+                ("subvol", Some(value)) => options.subvol = Some(PathBuf::from(value)),
+                _ => options.other.push(option.to_owned()),
+            }
+        }
+        Ok(options)
+    }
+}
+
+impl fmt::Display for BtrfsMountOptions {
+    /// Format the options as a comma-separated option string, as passed to `mount -o`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut options: Vec<String> = Vec::new();
+        if let Some(read_only) = self.read_only {
+            options.push(if read_only { "ro" } else { "rw" }.to_owned());
+        }
+        if let Some(compress) = self.compress {
+            options.push(compress.to_string());
+        }
+        if let Some(space_cache) = self.space_cache {
+            options.push(
+                match space_cache {
+                    SpaceCache::V1 => "space_cache=v1",
+                    SpaceCache::V2 => "space_cache=v2",
+                    SpaceCache::Off => "nospace_cache",
+                }
+                .to_owned(),
+            );
+        }
+        if let Some(discard) = self.discard {
+            options.push(
+                match discard {
+                    DiscardMode::Sync => "discard=sync",
+                    DiscardMode::Async => "discard=async",
+                    DiscardMode::Off => "nodiscard",
+                }
+                .to_owned(),
+            );
+        }
+        if let Some(commit) = self.commit {
+            options.push(format!("commit={}", commit));
+        }
+        if let Some(autodefrag) = self.autodefrag {
+            options.push(
+                if autodefrag {
+                    "autodefrag"
+                } else {
+                    "noautodefrag"
+                }
+                .to_owned(),
+            );
+        }
+        options.extend(self.other.iter().cloned());
+        if let Some(subvolid) = self.subvolid {
+            options.push(format!("subvolid={}", subvolid));
+        }
+        if let Some(subvol) = &self.subvol {
+            options.push(format!("subvol={}", subvol.display()));
+        }
+        write!(f, "{}", options.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_options() {
+        let options: BtrfsMountOptions =
+            "rw,noatime,compress=zstd:3,ssd,space_cache=v2,subvolid=256,subvol=/home"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            options,
+            BtrfsMountOptions {
+                read_only: Some(false),
+                compress: Some(MountCompression {
+                    algorithm: Some(Compression::Zstd),
+                    level: Some(3),
+                    force: false,
+                }),
+                space_cache: Some(SpaceCache::V2),
+                subvolid: Some(256),
+                subvol: Some(PathBuf::from("/home")),
+                other: vec!["noatime".to_owned(), "ssd".to_owned()],
+                ..Default::default()
+            }
+        );
+
+        let options: BtrfsMountOptions = "compress,discard,nospace_cache".parse().unwrap();
+        assert_eq!(
+            options.compress.and_then(|compress| compress.algorithm),
+            Some(Compression::Zlib)
+        );
+        assert_eq!(options.discard, Some(DiscardMode::Sync));
+        assert_eq!(options.space_cache, Some(SpaceCache::Off));
+
+        for invalid in &[
+            "compress=brotli",
+            "commit=soon",
+            "space_cache=v3",
+            "discard=now",
+        ] {
+            assert!(invalid.parse::<BtrfsMountOptions>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn format_options_round_trip() {
+        for formatted in &[
+            "",
+            "ro,compress-force=lzo,space_cache=v1,discard=async,commit=120,autodefrag",
+            "rw,compress=no,nospace_cache,nodiscard,noautodefrag,noatime,ssd,subvolid=5",
+            "compress=zstd:15,user_subvol_rm_allowed,subvol=/@snapshots/1 snapshot",
+        ] {
+            let options: BtrfsMountOptions = formatted.parse().unwrap();
+            assert_eq!(options.to_string(), *formatted);
+            assert_eq!(
+                options.to_string().parse::<BtrfsMountOptions>(),
+                Ok(options)
+            );
+        }
+
+        // Options without a field of their own are kept, before the subvolume.
+        let options: BtrfsMountOptions = "subvol=/home,noatime,rw".parse().unwrap();
+        assert_eq!(options.to_string(), "rw,noatime,subvol=/home");
+    }
+}
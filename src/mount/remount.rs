@@ -0,0 +1,121 @@
+use crate::filesystem::Filesystem;
+use crate::mount::BtrfsMountOptions;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::fmt;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// `statvfs` flag of mounts with `relatime`, which libc does not define on all targets.
+const ST_RELATIME: libc::c_ulong = 4096;
+
+/// Per-mount `statvfs` flags and the mount flags they are kept with on a remount.
+const MOUNT_FLAGS: [(libc::c_ulong, libc::c_ulong); 6] = [
+    (libc::ST_NOSUID, libc::MS_NOSUID),
+    (libc::ST_NODEV, libc::MS_NODEV),
+    (libc::ST_NOEXEC, libc::MS_NOEXEC),
+    (libc::ST_NOATIME, libc::MS_NOATIME),
+    (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+    (ST_RELATIME, libc::MS_RELATIME),
+];
+
+/// Why a filesystem cannot be remounted read-write.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RemountRefusal {
+    /// The kernel forced the filesystem read-only after an error, e.g. an aborted transaction.
+    /// It must be unmounted and checked first.
+    FilesystemError,
+    /// Devices of the filesystem are missing, by id.
+    MissingDevices(Vec<u64>),
+}
+
+impl fmt::Display for RemountRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemountRefusal::FilesystemError => write!(f, "the filesystem was forced read-only"),
+            RemountRefusal::MissingDevices(devids) => write!(f, "devices {:?} are missing", devids),
+        }
+    }
+}
+
+/// Find out why the kernel refused to remount a filesystem read-write.
+fn refusal(target: &Path) -> Option<RemountRefusal> {
+    let health = Filesystem::open(target).ok()?.health().ok()?;
+    if health.read_only_error {
+        Some(RemountRefusal::FilesystemError)
+    } else if !health.missing_devices.is_empty() {
+        Some(RemountRefusal::MissingDevices(health.missing_devices))
+    } else {
+        None
+    }
+}
+
+/// Get the mount flags of a mount point which a remount would clear if they were not passed
+/// again, e.g. `nosuid`.
+fn current_flags(target: &CStr) -> Result<libc::c_ulong> {
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(target.as_ptr(), &mut stat) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let mut flags = MOUNT_FLAGS
+        .iter()
+        .filter(|(st_flag, _)| stat.f_flag & st_flag != 0)
+        .fold(0, |acc, (_, ms_flag)| acc | ms_flag);
+    // Without an atime flag, the kernel would switch the mount to relatime.
+    if stat.f_flag & (libc::ST_NOATIME | ST_RELATIME) == 0 {
+        flags |= libc::MS_STRICTATIME;
+    }
+    Ok(flags)
+}
+
+/// Remount the filesystem mounted at a path read-only or read-write, like
+/// `mount -o remount,ro`, e.g. around risky maintenance.
+///
+/// Other options, e.g. [compress], are changed at the same time; their `read_only` field is
+/// ignored. The flags of the mount point, e.g. `nosuid` or `noatime`, are kept. If the kernel
+/// refuses to make the filesystem writable because of errors or missing devices, a
+/// [RemountRefused] error tells why. This requires elevated privileges(CAP_SYS_ADMIN).
+///
+/// [compress]: struct.BtrfsMountOptions.html#structfield.compress
+/// [RemountRefused]: ../error/enum.BtrfsUtilError.html#variant.RemountRefused
+pub fn remount<T: AsRef<Path>>(
+    target: T,
+    read_only: bool,
+    extra_opts: &BtrfsMountOptions,
+) -> Result<()> {
+    let target = target.as_ref();
+    let mut options = extra_opts.clone();
+    options.read_only = None;
+
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidInput, e);
+    let target_cstr = CString::new(target.as_os_str().as_bytes()).map_err(invalid)?;
+    let data = CString::new(options.to_string()).map_err(invalid)?;
+    let mut flags = libc::MS_REMOUNT | current_flags(&target_cstr)?;
+    if read_only {
+        flags |= libc::MS_RDONLY;
+    }
+
+    let ret = unsafe {
+        libc::mount(
+            std::ptr::null(),
+            target_cstr.as_ptr(),
+            std::ptr::null(),
+            flags,
+            data.as_ptr() as *const libc::c_void,
+        )
+    };
+    if ret < 0 {
+        let e = io::Error::last_os_error();
+        if !read_only {
+            if let Some(reason) = refusal(target) {
+                return Err(BtrfsUtilError::RemountRefused(reason));
+            }
+        }
+        return Err(e.into());
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use crate::filesystem::Filesystem;
+use crate::mount::mountinfo::mount_points;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Release the backing file of a loop device.
+const LOOP_CLR_FD: libc::Ioctl = 0x4c01;
+
+/// Options for an unmount.
+///
+/// Used with [unmount].
+///
+/// [unmount]: fn.unmount.html
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct UnmountFlags {
+    /// Detach the mount from the hierarchy now and clean it up once it is no longer busy, like
+    /// `umount --lazy`.
+    pub lazy: bool,
+    /// Abort pending requests before unmounting, like `umount --force`. Btrfs does not implement
+    /// forced unmounts, so this only has an effect on filesystems mounted over it.
+    pub force: bool,
+    /// Release the loop devices backing the filesystem once it is unmounted, like
+    /// `umount --detach-loop`. Loop devices still in use, e.g. by another mount of the same
+    /// filesystem, are released once they are closed.
+    pub detach: bool,
+}
+
+impl UnmountFlags {
+    fn bits(&self) -> libc::c_int {
+        let mut flags = libc::UMOUNT_NOFOLLOW;
+        if self.lazy {
+            flags |= libc::MNT_DETACH;
+        }
+        if self.force {
+            flags |= libc::MNT_FORCE;
+        }
+        flags
+    }
+}
+
+/// Get the paths of the loop devices backing the Btrfs filesystem mounted at a path.
+fn loop_devices(target: &Path) -> Result<Vec<PathBuf>> {
+    let fs = Filesystem::open(target)?;
+    Ok(fs
+        .devices()?
+        .into_iter()
+        .filter_map(|device| device.path)
+        .filter(|path| path.as_os_str().as_bytes().starts_with(b"/dev/loop"))
+        .collect())
+}
+
+/// Unmount the filesystem mounted at a path, wrapping `umount2(2)`.
+///
+/// If the filesystem is busy because other filesystems or subvolumes are mounted beneath the
+/// path, a [MountBusy] error lists them. The filesystem must be Btrfs to detach loop devices.
+/// This requires elevated privileges(CAP_SYS_ADMIN).
+///
+/// [MountBusy]: ../error/enum.BtrfsUtilError.html#variant.MountBusy
+pub fn unmount<T: AsRef<Path>>(target: T, flags: UnmountFlags) -> Result<()> {
+    let target = target.as_ref();
+    // The filesystem must be closed again before it is unmounted.
+    let loops = if flags.detach {
+        loop_devices(target)?
+    } else {
+        Vec::new()
+    };
+
+    let target_cstr = CString::new(target.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if unsafe { libc::umount2(target_cstr.as_ptr(), flags.bits()) } < 0 {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() == Some(libc::EBUSY) {
+            let target = target.canonicalize()?;
+            let nested: Vec<PathBuf> = mount_points()?
+                .into_iter()
+                .filter(|mount_point| mount_point != &target && mount_point.starts_with(&target))
+                .collect();
+            if !nested.is_empty() {
+                return Err(BtrfsUtilError::MountBusy { nested });
+            }
+        }
+        return Err(e.into());
+    }
+
+    for path in loops {
+        let device = File::open(path)?;
+        if unsafe { libc::ioctl(device.as_raw_fd(), LOOP_CLR_FD, 0) } < 0 {
+            let e = io::Error::last_os_error();
+            // Another user released the device first.
+            if e.raw_os_error() != Some(libc::ENXIO) {
+                return Err(e.into());
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+//! Progress of long-running operations
+//!
+//! Each operation reports its progress through its own type, e.g. [TransferProgress] for send and
+//! receive. All of them implement [Progress], so a single progress bar can render any of them.
+//!
+//! [TransferProgress]: ../send/struct.TransferProgress.html
+//! [Progress]: trait.Progress.html
+
+use std::panic;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::Duration;
+
+/// Interval at which the progress of operations performed by the kernel is queried.
+pub(crate) const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Common view of the progress of an operation.
+///
+/// Items are the units the operation counts, e.g. stream commands, chunks or subvolumes. Totals
+/// are `None` when they are not known in advance.
+pub trait Progress {
+    /// Get the name of the current phase, e.g. `"scanning"`.
+    fn phase(&self) -> &'static str;
+
+    /// Get the number of items processed so far.
+    fn items_done(&self) -> u64;
+
+    /// Get the number of items to process, if known.
+    fn items_total(&self) -> Option<u64> {
+        None
+    }
+
+    /// Get the number of bytes processed so far.
+    fn bytes_done(&self) -> u64 {
+        0
+    }
+
+    /// Get the number of bytes to process, if known.
+    fn bytes_total(&self) -> Option<u64> {
+        None
+    }
+
+    /// Get the path being processed, if any.
+    fn current_path(&self) -> Option<&Path> {
+        None
+    }
+
+    /// Get the rate over the last few seconds, in bytes per second, if measured.
+    fn throughput(&self) -> Option<f64> {
+        None
+    }
+
+    /// Get the completed fraction of the current phase, between 0 and 1, from the bytes if their
+    /// total is known, from the items otherwise. `None` if neither total is known.
+    fn fraction(&self) -> Option<f64> {
+        let (done, total) = match (self.bytes_total(), self.items_total()) {
+            (Some(total), _) => (self.bytes_done(), total),
+            (None, Some(total)) => (self.items_done(), total),
+            (None, None) => return None,
+        };
+        if total == 0 {
+            Some(1.0)
+        } else {
+            Some((done as f64 / total as f64).min(1.0))
+        }
+    }
+}
+
+/// Run a blocking operation on another thread, calling a poll callback at an interval until it
+/// returns. Panics raised by the operation are resumed in the caller.
+pub(crate) fn poll_while<R, O, P>(operation: O, mut poll: P, interval: Duration) -> R
+where
+    R: Send,
+    O: FnOnce() -> R + Send,
+    P: FnMut(),
+{
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel::<()>();
+        let handle = scope.spawn(move || {
+            let result = operation();
+            drop(sender);
+            result
+        });
+        while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+            poll();
+        }
+        match handle.join() {
+            Ok(val) => val,
+            Err(panic) => panic::resume_unwind(panic),
+        }
+    })
+}
@@ -0,0 +1,234 @@
+//! Btrfs properties, like `btrfs property`
+//!
+//! Properties are attached to objects of a kind: the read-only flag to subvolumes, the label to
+//! filesystems and the compression to inodes. A path can be several objects at once, e.g. the
+//! mount point of a filesystem is also the root of a subvolume and an inode.
+
+use crate::bindings;
+use crate::common;
+use crate::error::LibError;
+use crate::error::LibErrorCode;
+use crate::error::ParseError;
+use crate::filesystem;
+use crate::filesystem::CompressionProperty;
+use crate::filesystem::Filesystem;
+use crate::mount;
+use crate::subvolume::Subvolume;
+use crate::Result;
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use bindings::btrfs_util_get_subvolume_read_only;
+use bindings::btrfs_util_set_subvolume_read_only;
+
+/// Kind of object a property is attached to.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ObjectKind {
+    /// A file or directory.
+    Inode,
+    /// The root of a subvolume.
+    Subvolume,
+    /// The mount point of a filesystem.
+    Filesystem,
+}
+
+/// A Btrfs property.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Property {
+    /// Whether a subvolume is read-only (`ro`).
+    ReadOnly,
+    /// Label of a filesystem (`label`).
+    Label,
+    /// Compression of the new data of a file or directory (`compression`).
+    Compression,
+}
+
+impl Property {
+    /// All properties, in the order `btrfs property list` shows them.
+    pub const ALL: [Property; 3] = [Property::ReadOnly, Property::Label, Property::Compression];
+
+    /// Get the kind of object the property is attached to.
+    pub fn object_kind(self) -> ObjectKind {
+        match self {
+            Property::ReadOnly => ObjectKind::Subvolume,
+            Property::Label => ObjectKind::Filesystem,
+            Property::Compression => ObjectKind::Inode,
+        }
+    }
+}
+
+impl fmt::Display for Property {
+    /// Format the property like `btrfs property` names it, e.g. `ro`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Property::ReadOnly => write!(f, "ro"),
+            Property::Label => write!(f, "label"),
+            Property::Compression => write!(f, "compression"),
+        }
+    }
+}
+
+impl FromStr for Property {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "ro" => Ok(Property::ReadOnly),
+            "label" => Ok(Property::Label),
+            "compression" => Ok(Property::Compression),
+            _ => Err(ParseError::new("property", s)),
+        }
+    }
+}
+
+/// Value of a property.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PropertyValue {
+    /// Whether a subvolume is read-only.
+    ReadOnly(bool),
+    /// Label of a filesystem, or `None` if it has none.
+    Label(Option<String>),
+    /// Compression of a file or directory, or `None` if the mount options apply.
+    Compression(Option<CompressionProperty>),
+}
+
+impl PropertyValue {
+    /// Get the property this is a value of.
+    pub fn property(&self) -> Property {
+        match self {
+            PropertyValue::ReadOnly(_) => Property::ReadOnly,
+            PropertyValue::Label(_) => Property::Label,
+            PropertyValue::Compression(_) => Property::Compression,
+        }
+    }
+}
+
+impl fmt::Display for PropertyValue {
+    /// Format the value like `btrfs property get` does, without the name, e.g. `true`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PropertyValue::ReadOnly(ro) => write!(f, "{}", ro),
+            PropertyValue::Label(label) => write!(f, "{}", label.as_deref().unwrap_or("")),
+            PropertyValue::Compression(Some(compression)) => write!(f, "{}", compression),
+            PropertyValue::Compression(None) => Ok(()),
+        }
+    }
+}
+
+/// Detect the kinds of object a path on a Btrfs filesystem is.
+pub fn object_kinds<T: AsRef<Path>>(path: T) -> Result<Vec<ObjectKind>> {
+    let path = path.as_ref();
+    Filesystem::open(path)?;
+    let mut kinds = vec![ObjectKind::Inode];
+    match Subvolume::is_subvolume(path) {
+        Ok(()) => kinds.push(ObjectKind::Subvolume),
+        Err(e) if e == LibError::NotSubvolume => {}
+        Err(e) => return Err(e),
+    }
+    // Subvolumes have device numbers of their own, so only the mount table tells mount points.
+    if fs::metadata(path)?.is_dir() {
+        let path = fs::canonicalize(path)?;
+        if mount::btrfs_mounts()?
+            .iter()
+            .any(|mount| mount.mount_point == path)
+        {
+            kinds.push(ObjectKind::Filesystem);
+        }
+    }
+    Ok(kinds)
+}
+
+/// List the properties which apply to a path, like `btrfs property list`.
+pub fn list_properties<T: AsRef<Path>>(path: T) -> Result<Vec<Property>> {
+    let kinds = object_kinds(path)?;
+    Ok(Property::ALL
+        .iter()
+        .copied()
+        .filter(|property| kinds.contains(&property.object_kind()))
+        .collect())
+}
+
+/// Get a property of a path, like `btrfs property get`.
+///
+/// Fails with an `InvalidInput` I/O error if the property does not apply to the path.
+pub fn get_property<T: AsRef<Path>>(path: T, property: Property) -> Result<PropertyValue> {
+    let path = path.as_ref();
+    check_applies(path, property)?;
+    Ok(match property {
+        Property::ReadOnly => PropertyValue::ReadOnly(get_read_only(path)?),
+        Property::Label => PropertyValue::Label(get_label(path)?),
+        Property::Compression => PropertyValue::Compression(filesystem::get_compression(path)?),
+    })
+}
+
+/// Set a property of a path, like `btrfs property set`.
+///
+/// Fails with an `InvalidInput` I/O error if the property does not apply to the path.
+pub fn set_property<T: AsRef<Path>>(path: T, value: &PropertyValue) -> Result<()> {
+    let path = path.as_ref();
+    check_applies(path, value.property())?;
+    match value {
+        PropertyValue::ReadOnly(ro) => set_read_only(path, *ro),
+        PropertyValue::Label(label) => set_label(path, label.as_deref().unwrap_or("")),
+        PropertyValue::Compression(compression) => filesystem::set_compression(path, *compression),
+    }
+}
+
+/// Check that a property applies to a path.
+fn check_applies(path: &Path, property: Property) -> Result<()> {
+    if !object_kinds(path)?.contains(&property.object_kind()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "property {} does not apply to {}, which is not a {:?}",
+                property,
+                path.display(),
+                property.object_kind()
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Check whether the subvolume at a path is read-only.
+pub fn get_read_only<T: AsRef<Path>>(path: T) -> Result<bool> {
+    let path_cstr = common::path_to_cstr(path.as_ref().to_path_buf())?;
+    let mut ro: bool = false;
+
+    unsafe_wrapper!(errcode, {
+        errcode = btrfs_util_get_subvolume_read_only(path_cstr.as_ptr(), &mut ro);
+    });
+
+    Ok(ro)
+}
+
+/// Set whether the subvolume at a path is read-only.
+///
+/// This requires elevated privileges(CAP_SYS_ADMIN).
+pub fn set_read_only<T: AsRef<Path>>(path: T, ro: bool) -> Result<()> {
+    let path_cstr = common::path_to_cstr(path.as_ref().to_path_buf())?;
+
+    unsafe_wrapper!(errcode, {
+        errcode = btrfs_util_set_subvolume_read_only(path_cstr.as_ptr(), ro);
+    });
+
+    Ok(())
+}
+
+/// Get the label of the filesystem containing a path, or `None` if it has none.
+pub fn get_label<T: AsRef<Path>>(path: T) -> Result<Option<String>> {
+    Filesystem::open(path.as_ref())?.label()
+}
+
+/// Set the label of the filesystem containing a path, or remove it with an empty label.
+///
+/// This requires elevated privileges(CAP_SYS_ADMIN).
+pub fn set_label<T: AsRef<Path>>(path: T, label: &str) -> Result<()> {
+    Filesystem::open(path.as_ref())?.set_label(label)
+}
@@ -0,0 +1,162 @@
+use crate::error::ParseError;
+use crate::filesystem::Filesystem;
+use crate::ioctl;
+use crate::qgroup::Quota;
+use crate::search;
+use crate::search::SearchKey;
+use crate::subvolume::Subvolume;
+use crate::Result;
+
+use std::fmt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
+use std::str::FromStr;
+
+/// Number of bits of a raw qgroup id holding the id within its level.
+const QGROUP_LEVEL_SHIFT: u32 = 48;
+
+/// A Btrfs qgroup id.
+///
+/// Level 0 qgroups belong to a subvolume and share its id. Higher level qgroups group other
+/// qgroups together. Displayed and parsed as `level/id`, like btrfs-progs does.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct QgroupId {
+    /// Level of the qgroup in the hierarchy.
+    pub level: u16,
+    /// Id of the qgroup within its level.
+    pub id: u64,
+}
+
+impl QgroupId {
+    /// Create a qgroup id.
+    pub fn new(level: u16, id: u64) -> Self {
+        Self { level, id }
+    }
+
+    /// Get the level 0 qgroup id of a subvolume.
+    pub fn for_subvolume(subvolume: &Subvolume) -> Self {
+        Self::new(0, subvolume.id())
+    }
+}
+
+impl From<u64> for QgroupId {
+    fn from(raw: u64) -> Self {
+        Self {
+            level: (raw >> QGROUP_LEVEL_SHIFT) as u16,
+            id: raw & ((1 << QGROUP_LEVEL_SHIFT) - 1),
+        }
+    }
+}
+
+impl From<QgroupId> for u64 {
+    fn from(qgroupid: QgroupId) -> Self {
+        ((qgroupid.level as u64) << QGROUP_LEVEL_SHIFT) | qgroupid.id
+    }
+}
+
+impl fmt::Display for QgroupId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.level, self.id)
+    }
+}
+
+impl FromStr for QgroupId {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let error = || ParseError::new("qgroup id", s);
+        let (level, id) = s.split_once('/').ok_or_else(error)?;
+        let level: u16 = level.parse().map_err(|_| error())?;
+        let id: u64 = id.parse().map_err(|_| error())?;
+        if id >> QGROUP_LEVEL_SHIFT != 0 {
+            return Err(error());
+        }
+        Ok(Self { level, id })
+    }
+}
+
+/// Btrfs qgroup management.
+///
+/// All operations require quotas to be enabled on the filesystem, see [Quota::enable], and
+/// elevated privileges(CAP_SYS_ADMIN).
+///
+/// [Quota::enable]: struct.Quota.html#method.enable
+#[derive(Clone, Copy, Debug)]
+pub struct Qgroup;
+
+impl Qgroup {
+    /// Create a qgroup.
+    pub fn create(fs: &Filesystem, qgroupid: QgroupId) -> Result<()> {
+        qgroup_create(fs, qgroupid, true)
+    }
+
+    /// Destroy a qgroup.
+    pub fn destroy(fs: &Filesystem, qgroupid: QgroupId) -> Result<()> {
+        qgroup_create(fs, qgroupid, false)
+    }
+
+    /// Make a qgroup a member of a higher level qgroup.
+    ///
+    /// If the accounting becomes inconsistent, a quota rescan is started. Returns whether a rescan
+    /// was started.
+    pub fn assign(fs: &Filesystem, child: QgroupId, parent: QgroupId) -> Result<bool> {
+        qgroup_assign(fs, child, parent, true)
+    }
+
+    /// Remove a qgroup from a higher level qgroup.
+    ///
+    /// If the accounting becomes inconsistent, a quota rescan is started. Returns whether a rescan
+    /// was started.
+    pub fn remove_assign(fs: &Filesystem, child: QgroupId, parent: QgroupId) -> Result<bool> {
+        qgroup_assign(fs, child, parent, false)
+    }
+}
+
+fn qgroup_create(fs: &Filesystem, qgroupid: QgroupId, create: bool) -> Result<()> {
+    let mut args = ioctl::btrfs_ioctl_qgroup_create_args {
+        create: create as u64,
+        qgroupid: qgroupid.into(),
+    };
+    unsafe { ioctl::ioctl(fs.as_raw_fd(), ioctl::BTRFS_IOC_QGROUP_CREATE, &mut args)? };
+    Ok(())
+}
+
+fn qgroup_assign(fs: &Filesystem, child: QgroupId, parent: QgroupId, assign: bool) -> Result<bool> {
+    let mut args = ioctl::btrfs_ioctl_qgroup_assign_args {
+        assign: assign as u64,
+        src: child.into(),
+        dst: parent.into(),
+    };
+    // A positive return value means the qgroups became inconsistent.
+    let ret = unsafe { ioctl::ioctl(fs.as_raw_fd(), ioctl::BTRFS_IOC_QGROUP_ASSIGN, &mut args)? };
+    if ret > 0 {
+        Quota::rescan(fs)?;
+    }
+    Ok(ret > 0)
+}
+
+/// Get the qgroups a qgroup is a member of, through a file descriptor on the filesystem.
+///
+/// If quotas are disabled, there are none.
+pub(crate) fn parent_qgroups(fd: RawFd, qgroupid: QgroupId) -> Result<Vec<QgroupId>> {
+    let raw: u64 = qgroupid.into();
+    // Relations are stored in both directions, the parent is the higher qgroup id.
+    let items = SearchKey::new(search::BTRFS_QUOTA_TREE_OBJECTID)
+        .objectids(raw, raw)
+        .types(
+            search::BTRFS_QGROUP_RELATION_KEY,
+            search::BTRFS_QGROUP_RELATION_KEY,
+        )
+        .offsets(raw + 1, u64::MAX)
+        .search(fd);
+
+    let mut parents: Vec<QgroupId> = Vec::new();
+    for item in items {
+        match item {
+            Ok(item) => parents.push(item.offset.into()),
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(parents)
+}
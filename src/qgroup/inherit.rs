@@ -1,5 +1,3 @@
-//! Btrfs quota groups
-
 use crate::bindings;
 use crate::error::*;
 use crate::Result;
@@ -14,10 +12,10 @@ use bindings::btrfs_util_qgroup_inherit_get_groups;
 
 /// Qgroup inheritance specifier.
 ///
-/// Wrapper around [btrfs_util_qgroup_inherit].
+/// Wrapper around [btrfs_util_qgroup_inherit], which is destroyed when this is dropped.
 ///
 /// [btrfs_util_qgroup_inherit]: ../bindings/struct.btrfs_util_qgroup_inherit.html
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct QgroupInherit(*mut btrfs_util_qgroup_inherit);
 
 impl QgroupInherit {
@@ -34,6 +32,15 @@ impl QgroupInherit {
         Ok(Self(qgroup_ptr))
     }
 
+    /// Create a quota group inheritance specifier containing some qgroups.
+    pub fn from_groups<I: IntoIterator<Item = u64>>(qgroup_ids: I) -> Result<Self> {
+        let mut inherit = Self::create()?;
+        for qgroup_id in qgroup_ids {
+            inherit.add(qgroup_id)?;
+        }
+        Ok(inherit)
+    }
+
     /// Add inheritance from a qgroup to a qgroup inheritance specifier.
     pub fn add(&mut self, qgroup_id: u64) -> Result<()> {
         let qgroup_ptr_initial: *mut btrfs_util_qgroup_inherit = self.into();
@@ -79,6 +86,23 @@ impl QgroupInherit {
     }
 }
 
+impl Clone for QgroupInherit {
+    /// Create a separate specifier containing the same qgroups.
+    ///
+    /// # Panics
+    ///
+    /// Panics if libbtrfsutil fails to allocate the new specifier.
+    fn clone(&self) -> Self {
+        self.get_groups()
+            .and_then(Self::from_groups)
+            .expect("failed to clone a qgroup inheritance specifier")
+    }
+}
+
+// The specifier is a heap allocation owned exclusively by this struct, which libbtrfsutil does not
+// tie to a thread, so it can be moved to another thread, e.g. with the options of a blocking call.
+unsafe impl Send for QgroupInherit {}
+
 impl Drop for QgroupInherit {
     fn drop(&mut self) {
         unsafe {
@@ -87,12 +111,6 @@ impl Drop for QgroupInherit {
     }
 }
 
-impl Into<*mut btrfs_util_qgroup_inherit> for QgroupInherit {
-    fn into(self) -> *mut btrfs_util_qgroup_inherit {
-        self.0
-    }
-}
-
 impl Into<*mut btrfs_util_qgroup_inherit> for &mut QgroupInherit {
     fn into(self) -> *mut btrfs_util_qgroup_inherit {
         self.0
@@ -0,0 +1,184 @@
+use crate::error::ParseError;
+use crate::filesystem::Filesystem;
+use crate::ioctl;
+use crate::qgroup::Qgroup;
+use crate::qgroup::QgroupId;
+use crate::qgroup::QgroupUsage;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
+
+/// Size limits of a qgroup, in bytes.
+///
+/// `None` means unlimited.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Limit {
+    /// Limit of the space referenced by the qgroup.
+    pub referenced: Option<u64>,
+    /// Limit of the space used exclusively by the qgroup.
+    pub exclusive: Option<u64>,
+}
+
+impl Qgroup {
+    /// Set the size limits of a qgroup, replacing any previous limits.
+    pub fn set_limit(fs: &Filesystem, qgroupid: QgroupId, limit: Limit) -> Result<()> {
+        let mut args = ioctl::btrfs_ioctl_qgroup_limit_args {
+            qgroupid: qgroupid.into(),
+            lim: ioctl::btrfs_qgroup_limit {
+                flags: ioctl::BTRFS_QGROUP_LIMIT_MAX_RFER | ioctl::BTRFS_QGROUP_LIMIT_MAX_EXCL,
+                // The kernel uses the maximum value for "no limit".
+                max_rfer: limit.referenced.unwrap_or(u64::MAX),
+                max_excl: limit.exclusive.unwrap_or(u64::MAX),
+                rsv_rfer: 0,
+                rsv_excl: 0,
+            },
+        };
+        unsafe { ioctl::ioctl(fs.as_raw_fd(), ioctl::BTRFS_IOC_QGROUP_LIMIT, &mut args)? };
+        Ok(())
+    }
+}
+
+/// Check that creating a subvolume or snapshot in some qgroups stays within their limits, through a
+/// file descriptor on the filesystem.
+///
+/// The growth is estimated as one tree node of metadata, which is what a new subvolume root takes.
+/// Qgroups which do not exist are skipped, creating the subvolume will report them.
+pub(crate) fn check_quota(fd: RawFd, qgroupids: &[QgroupId]) -> Result<()> {
+    if qgroupids.is_empty() {
+        return Ok(());
+    }
+    let needed = u64::from(ioctl::fs_info(fd, 0)?.nodesize);
+
+    for qgroupid in qgroupids {
+        let usage: QgroupUsage = match Qgroup::usage_fd(fd, *qgroupid) {
+            Ok(usage) => usage,
+            Err(BtrfsUtilError::Io(e)) if e.raw_os_error() == Some(libc::ENOENT) => continue,
+            Err(e) => return Err(e),
+        };
+        check_usage(&usage, needed)?;
+    }
+    Ok(())
+}
+
+/// Check that a qgroup has a number of bytes left within both of its limits.
+fn check_usage(usage: &QgroupUsage, needed: u64) -> Result<()> {
+    let limited = [
+        (usage.limits.referenced, usage.referenced),
+        (usage.limits.exclusive, usage.exclusive),
+    ];
+    for (limit, used) in limited.iter() {
+        if let Some(limit) = limit {
+            let available = limit.saturating_sub(*used);
+            if available < needed {
+                return Err(BtrfsUtilError::QuotaExceeded {
+                    qgroupid: usage.qgroupid,
+                    needed,
+                    available,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parse a byte size like btrfs-progs does, e.g. `"512"`, `"100M"` or `"1.5GiB"`.
+///
+/// Units are binary multiples: `K`, `M`, `G`, `T`, `P` and `E`, case insensitive, optionally
+/// followed by `iB` or `B`.
+pub fn parse_size(s: &str) -> std::result::Result<u64, ParseError> {
+    let error = || ParseError::new("size", s);
+    let trimmed = s.trim();
+    let number_end = trimmed
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(number_end);
+
+    let unit = unit.trim_start().to_ascii_lowercase();
+    let unit = unit
+        .strip_suffix("ib")
+        .or_else(|| unit.strip_suffix('b'))
+        .unwrap_or(&unit);
+    let shift: u32 = match unit {
+        "" => 0,
+        "k" => 10,
+        "m" => 20,
+        "g" => 30,
+        "t" => 40,
+        "p" => 50,
+        "e" => 60,
+        _ => return Err(error()),
+    };
+
+    if let Ok(value) = number.parse::<u64>() {
+        return value.checked_mul(1 << shift).ok_or_else(error);
+    }
+    let value: f64 = number.parse().map_err(|_| error())?;
+    let bytes = value * (1u64 << shift) as f64;
+    if !bytes.is_finite() || bytes >= u64::MAX as f64 {
+        return Err(error());
+    }
+    Ok(bytes as u64)
+}
+
+/// Parse a qgroup limit, where `"none"` means unlimited and anything else is parsed with
+/// [parse_size].
+///
+/// [parse_size]: fn.parse_size.html
+pub fn parse_limit(s: &str) -> std::result::Result<Option<u64>, ParseError> {
+    if s.trim().eq_ignore_ascii_case("none") {
+        Ok(None)
+    } else {
+        parse_size(s).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(referenced: u64, exclusive: u64, limits: Limit) -> QgroupUsage {
+        QgroupUsage {
+            qgroupid: 257.into(),
+            referenced,
+            exclusive,
+            limits,
+        }
+    }
+
+    #[test]
+    fn unlimited_qgroup() {
+        assert!(check_usage(&usage(u64::MAX, u64::MAX, Limit::default()), 16384).is_ok());
+    }
+
+    #[test]
+    fn referenced_limit() {
+        let limits = Limit {
+            referenced: Some(1 << 20),
+            exclusive: None,
+        };
+        assert!(check_usage(&usage((1 << 20) - 16384, 0, limits), 16384).is_ok());
+        match check_usage(&usage((1 << 20) - 4096, 0, limits), 16384) {
+            Err(BtrfsUtilError::QuotaExceeded {
+                qgroupid,
+                needed,
+                available,
+            }) => {
+                assert_eq!(u64::from(qgroupid), 257);
+                assert_eq!((needed, available), (16384, 4096));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn exclusive_limit() {
+        let limits = Limit {
+            referenced: None,
+            exclusive: Some(8192),
+        };
+        assert!(check_usage(&usage(1 << 30, 0, limits), 8192).is_ok());
+        assert!(check_usage(&usage(0, 8192, limits), 1).is_err());
+    }
+}
@@ -0,0 +1,17 @@
+//! Btrfs quota groups
+
+mod group;
+mod inherit;
+mod limit;
+mod quota;
+mod report;
+mod tree;
+mod usage;
+
+pub use group::*;
+pub use inherit::*;
+pub use limit::*;
+pub use quota::*;
+pub use report::*;
+pub use tree::*;
+pub use usage::*;
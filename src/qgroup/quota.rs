@@ -0,0 +1,120 @@
+use crate::filesystem::Filesystem;
+use crate::ioctl;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::Duration;
+
+/// Btrfs quota control.
+///
+/// Quotas have to be enabled on a filesystem before qgroups account for the space used by its
+/// subvolumes.
+#[derive(Clone, Copy, Debug)]
+pub struct Quota;
+
+impl Quota {
+    /// Enable quotas on a filesystem. This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn enable(fs: &Filesystem) -> Result<()> {
+        quota_ctl(fs, ioctl::BTRFS_QUOTA_CTL_ENABLE)
+    }
+
+    /// Disable quotas on a filesystem, removing all of its qgroups. This requires elevated
+    /// privileges(CAP_SYS_ADMIN).
+    pub fn disable(fs: &Filesystem) -> Result<()> {
+        quota_ctl(fs, ioctl::BTRFS_QUOTA_CTL_DISABLE)
+    }
+
+    /// Start a quota rescan, which recomputes the space accounted to all qgroups.
+    ///
+    /// A rescan which is already running is not an error.
+    pub fn rescan(fs: &Filesystem) -> Result<()> {
+        let mut args = empty_rescan_args();
+        match unsafe { ioctl::ioctl(fs.as_raw_fd(), ioctl::BTRFS_IOC_QUOTA_RESCAN, &mut args) } {
+            Ok(_) => Ok(()),
+            Err(e) if e.raw_os_error() == Some(libc::EINPROGRESS) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the status of the quota rescan.
+    pub fn rescan_status(fs: &Filesystem) -> Result<RescanStatus> {
+        let mut args = empty_rescan_args();
+        unsafe {
+            ioctl::ioctl(
+                fs.as_raw_fd(),
+                ioctl::BTRFS_IOC_QUOTA_RESCAN_STATUS,
+                &mut args,
+            )?
+        };
+        Ok(RescanStatus {
+            running: args.flags != 0,
+            progress: args.progress,
+        })
+    }
+
+    /// Wait for the quota rescan to finish, if one is running.
+    ///
+    /// With a timeout, the wait happens on a helper thread and [BtrfsUtilError::TimedOut] is
+    /// returned if it expires first.
+    ///
+    /// [BtrfsUtilError::TimedOut]: ../error/enum.BtrfsUtilError.html#variant.TimedOut
+    pub fn rescan_wait(fs: &Filesystem, timeout: Option<Duration>) -> Result<()> {
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return rescan_wait(fs),
+        };
+
+        let fs = fs.try_clone()?;
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let _ = sender.send(rescan_wait(&fs));
+        });
+
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(BtrfsUtilError::TimedOut),
+            Err(RecvTimeoutError::Disconnected) => match handle.join() {
+                Err(panic) => std::panic::resume_unwind(panic),
+                Ok(()) => unreachable!("the helper thread exited without a result"),
+            },
+        }
+    }
+}
+
+/// Status of a quota rescan.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct RescanStatus {
+    /// Whether a rescan is running.
+    pub running: bool,
+    /// Object id up to which the running rescan has progressed.
+    pub progress: u64,
+}
+
+fn quota_ctl(fs: &Filesystem, cmd: u64) -> Result<()> {
+    let mut args = ioctl::btrfs_ioctl_quota_ctl_args { cmd, status: 0 };
+    unsafe { ioctl::ioctl(fs.as_raw_fd(), ioctl::BTRFS_IOC_QUOTA_CTL, &mut args)? };
+    Ok(())
+}
+
+fn empty_rescan_args() -> ioctl::btrfs_ioctl_quota_rescan_args {
+    ioctl::btrfs_ioctl_quota_rescan_args {
+        flags: 0,
+        progress: 0,
+        reserved: [0; 6],
+    }
+}
+
+fn rescan_wait(fs: &Filesystem) -> Result<()> {
+    unsafe {
+        ioctl::ioctl(
+            fs.as_raw_fd(),
+            ioctl::BTRFS_IOC_QUOTA_RESCAN_WAIT,
+            std::ptr::null_mut::<libc::c_void>(),
+        )?
+    };
+    Ok(())
+}
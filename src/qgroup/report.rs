@@ -0,0 +1,72 @@
+use crate::filesystem::Filesystem;
+use crate::qgroup::Qgroup;
+use crate::qgroup::QgroupId;
+use crate::qgroup::QgroupUsage;
+use crate::subvolume::SubvolumeIterator;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::collections::HashMap;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+/// Disk usage of a subvolume, from its level 0 qgroup.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SubvolumeUsage {
+    /// Path of the subvolume, relative to the subvolume the filesystem was opened at.
+    pub path: PathBuf,
+    /// Id of the subvolume.
+    pub id: u64,
+    /// Space referenced by the subvolume, or `None` if it is not accounted.
+    pub referenced: Option<u64>,
+    /// Space used exclusively by the subvolume, which would be freed by deleting it, or `None` if
+    /// it is not accounted.
+    pub exclusive: Option<u64>,
+}
+
+/// Disk usage of the subvolumes of a filesystem. Created by [usage_report].
+///
+/// [usage_report]: fn.usage_report.html
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UsageReport {
+    /// Whether quotas are enabled. If not, no subvolume has sizes.
+    pub quotas_enabled: bool,
+    /// Usage of each subvolume, in iteration order.
+    pub subvolumes: Vec<SubvolumeUsage>,
+}
+
+/// Report the disk usage of each subvolume beneath the subvolume a filesystem was opened at.
+///
+/// Sizes come from the level 0 qgroups of the subvolumes. If quotas are disabled, the subvolumes
+/// are still listed, without sizes. This requires elevated privileges(CAP_SYS_ADMIN).
+pub fn usage_report(fs: &Filesystem) -> Result<UsageReport> {
+    let (quotas_enabled, qgroups): (bool, HashMap<QgroupId, QgroupUsage>) = match Qgroup::list(fs) {
+        Ok(list) => (
+            true,
+            list.into_iter()
+                .map(|usage| (usage.qgroupid, usage))
+                .collect(),
+        ),
+        Err(BtrfsUtilError::Io(e)) if e.raw_os_error() == Some(libc::ENOENT) => {
+            (false, HashMap::new())
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut subvolumes: Vec<SubvolumeUsage> = Vec::new();
+    for entry in SubvolumeIterator::builder_for_fd(fs.as_raw_fd()).iter_with_info()? {
+        let (path, info) = entry?;
+        let usage = qgroups.get(&QgroupId::new(0, info.id));
+        subvolumes.push(SubvolumeUsage {
+            path,
+            id: info.id,
+            referenced: usage.map(|usage| usage.referenced),
+            exclusive: usage.map(|usage| usage.exclusive),
+        });
+    }
+
+    Ok(UsageReport {
+        quotas_enabled,
+        subvolumes,
+    })
+}
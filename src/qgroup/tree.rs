@@ -0,0 +1,106 @@
+use crate::filesystem::Filesystem;
+use crate::qgroup::Qgroup;
+use crate::qgroup::QgroupId;
+use crate::search;
+use crate::search::SearchKey;
+use crate::Result;
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::os::unix::io::AsRawFd;
+
+/// The hierarchy of the qgroups of a filesystem. Created by [Qgroup::tree].
+///
+/// [Qgroup::tree]: struct.Qgroup.html#method.tree
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct QgroupTree {
+    parents: BTreeMap<QgroupId, BTreeSet<QgroupId>>,
+    children: BTreeMap<QgroupId, BTreeSet<QgroupId>>,
+}
+
+impl Qgroup {
+    /// Get the hierarchy of the qgroups of a filesystem.
+    pub fn tree(fs: &Filesystem) -> Result<QgroupTree> {
+        let items = SearchKey::new(search::BTRFS_QUOTA_TREE_OBJECTID)
+            .types(
+                search::BTRFS_QGROUP_INFO_KEY,
+                search::BTRFS_QGROUP_RELATION_KEY,
+            )
+            .search(fs.as_raw_fd());
+
+        let mut tree = QgroupTree::default();
+        for item in items {
+            let item = item?;
+            match item.item_type {
+                search::BTRFS_QGROUP_INFO_KEY => tree.insert(item.offset.into()),
+                // Relations are stored in both directions, keep the one from child to parent.
+                search::BTRFS_QGROUP_RELATION_KEY if item.objectid < item.offset => {
+                    tree.relate(item.objectid.into(), item.offset.into())
+                }
+                _ => {}
+            }
+        }
+        Ok(tree)
+    }
+}
+
+impl QgroupTree {
+    fn insert(&mut self, qgroupid: QgroupId) {
+        self.parents.entry(qgroupid).or_default();
+        self.children.entry(qgroupid).or_default();
+    }
+
+    fn relate(&mut self, child: QgroupId, parent: QgroupId) {
+        self.insert(child);
+        self.insert(parent);
+        self.parents.entry(child).or_default().insert(parent);
+        self.children.entry(parent).or_default().insert(child);
+    }
+
+    /// Get the number of qgroups.
+    pub fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    /// Check whether there are no qgroups.
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+
+    /// Check whether a qgroup exists.
+    pub fn contains(&self, qgroupid: QgroupId) -> bool {
+        self.parents.contains_key(&qgroupid)
+    }
+
+    /// Iterate over all qgroups, ordered by qgroup id.
+    pub fn qgroups(&self) -> impl Iterator<Item = QgroupId> + '_ {
+        self.parents.keys().copied()
+    }
+
+    /// Iterate over the qgroups which are not a member of any other qgroup.
+    pub fn roots(&self) -> impl Iterator<Item = QgroupId> + '_ {
+        self.parents
+            .iter()
+            .filter(|(_, parents)| parents.is_empty())
+            .map(|(qgroupid, _)| *qgroupid)
+    }
+
+    /// Iterate over the qgroups a qgroup is a direct member of.
+    pub fn parents(&self, qgroupid: QgroupId) -> impl Iterator<Item = QgroupId> + '_ {
+        self.parents.get(&qgroupid).into_iter().flatten().copied()
+    }
+
+    /// Iterate over the direct members of a qgroup.
+    pub fn children(&self, qgroupid: QgroupId) -> impl Iterator<Item = QgroupId> + '_ {
+        self.children.get(&qgroupid).into_iter().flatten().copied()
+    }
+
+    /// Iterate over the relations which break the hierarchy, where the parent is not at a higher
+    /// level than the child, as `(child, parent)` pairs.
+    pub fn invalid_relations(&self) -> impl Iterator<Item = (QgroupId, QgroupId)> + '_ {
+        self.parents
+            .iter()
+            .flat_map(|(child, parents)| parents.iter().map(move |parent| (*child, *parent)))
+            .filter(|(child, parent)| parent.level <= child.level)
+    }
+}
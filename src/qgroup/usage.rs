@@ -0,0 +1,271 @@
+use crate::filesystem::Filesystem;
+use crate::qgroup::Limit;
+use crate::qgroup::Qgroup;
+use crate::qgroup::QgroupId;
+use crate::search;
+use crate::search::SearchItem;
+use crate::search::SearchKey;
+use crate::subvolume::Subvolume;
+use crate::Result;
+
+use std::collections::BTreeMap;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
+
+use crate::ioctl::BTRFS_QGROUP_LIMIT_MAX_EXCL;
+use crate::ioctl::BTRFS_QGROUP_LIMIT_MAX_RFER;
+
+const BTRFS_QGROUP_STATUS_FLAG_RESCAN: u64 = 1 << 1;
+const BTRFS_QGROUP_STATUS_FLAG_INCONSISTENT: u64 = 1 << 2;
+
+/// Space accounted to a qgroup, in bytes.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct QgroupUsage {
+    /// Id of the qgroup.
+    pub qgroupid: QgroupId,
+    /// Space referenced by the qgroup.
+    pub referenced: u64,
+    /// Space used exclusively by the qgroup, which would be freed by deleting it.
+    pub exclusive: u64,
+    /// Size limits of the qgroup.
+    pub limits: Limit,
+}
+
+impl Qgroup {
+    /// Get the space accounted to a qgroup.
+    pub fn usage(fs: &Filesystem, qgroupid: QgroupId) -> Result<QgroupUsage> {
+        Self::usage_fd(fs.as_raw_fd(), qgroupid)
+    }
+
+    /// Get the space accounted to a qgroup, through a file descriptor on the filesystem.
+    pub(crate) fn usage_fd(fd: RawFd, qgroupid: QgroupId) -> Result<QgroupUsage> {
+        let raw: u64 = qgroupid.into();
+        search_usage(fd, raw, raw)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT).into())
+    }
+
+    /// Get the space accounted to all qgroups of a filesystem, ordered by qgroup id.
+    pub fn list(fs: &Filesystem) -> Result<Vec<QgroupUsage>> {
+        search_usage(fs.as_raw_fd(), 0, u64::MAX)
+    }
+}
+
+/// Space used exclusively by a subvolume. Created by [Subvolume::exclusive_size].
+///
+/// [Subvolume::exclusive_size]: ../subvolume/struct.Subvolume.html#method.exclusive_size
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ExclusiveSize {
+    /// Space which would be freed by deleting the subvolume, in bytes.
+    pub bytes: u64,
+    /// Whether the accounting may be out of date, because a quota rescan is running or the
+    /// qgroups are inconsistent.
+    pub stale: bool,
+}
+
+impl Subvolume {
+    /// Get the space which would be freed by deleting this subvolume, from its level 0 qgroup.
+    ///
+    /// This requires quotas to be enabled and elevated privileges(CAP_SYS_ADMIN).
+    pub fn exclusive_size(&self) -> Result<ExclusiveSize> {
+        let file = self.open()?;
+        let fd = file.as_raw_fd();
+        let usage = Qgroup::usage_fd(fd, QgroupId::for_subvolume(self))?;
+        let status = SearchKey::new(search::BTRFS_QUOTA_TREE_OBJECTID)
+            .objectids(0, 0)
+            .types(
+                search::BTRFS_QGROUP_STATUS_KEY,
+                search::BTRFS_QGROUP_STATUS_KEY,
+            )
+            .search(fd)
+            .next()
+            .transpose()?;
+        Ok(ExclusiveSize {
+            bytes: usage.exclusive,
+            stale: is_stale(status.as_ref()),
+        })
+    }
+}
+
+/// Check whether the qgroup status item tells that the accounting may be out of date.
+fn is_stale(status: Option<&SearchItem>) -> bool {
+    // The flags follow the version and generation of the status item.
+    let flags = status.map_or(0, |item| item.u64_at(16));
+    flags & (BTRFS_QGROUP_STATUS_FLAG_RESCAN | BTRFS_QGROUP_STATUS_FLAG_INCONSISTENT) != 0
+}
+
+fn search_usage(fd: RawFd, min: u64, max: u64) -> Result<Vec<QgroupUsage>> {
+    // The search compares whole keys, so a range spanning both item types would also return the
+    // items of other qgroups in between. Search each type on its own instead.
+    let mut items = Vec::new();
+    for item_type in [
+        search::BTRFS_QGROUP_INFO_KEY,
+        search::BTRFS_QGROUP_LIMIT_KEY,
+    ] {
+        let found = SearchKey::new(search::BTRFS_QUOTA_TREE_OBJECTID)
+            .objectids(0, 0)
+            .types(item_type, item_type)
+            .offsets(min, max)
+            .search(fd);
+        for item in found {
+            items.push(item?);
+        }
+    }
+    Ok(collect_usage(items, min, max))
+}
+
+/// Gather the info and limit items of the qgroups with an id within a range, ordered by qgroup id.
+fn collect_usage<I: IntoIterator<Item = SearchItem>>(
+    items: I,
+    min: u64,
+    max: u64,
+) -> Vec<QgroupUsage> {
+    let mut usages: BTreeMap<u64, QgroupUsage> = BTreeMap::new();
+    for item in items {
+        if item.objectid != 0 || item.offset < min || item.offset > max {
+            continue;
+        }
+        let usage = || QgroupUsage {
+            qgroupid: item.offset.into(),
+            ..Default::default()
+        };
+        match item.item_type {
+            search::BTRFS_QGROUP_INFO_KEY => {
+                let usage = usages.entry(item.offset).or_insert_with(usage);
+                usage.referenced = item.u64_at(8);
+                usage.exclusive = item.u64_at(24);
+            }
+            search::BTRFS_QGROUP_LIMIT_KEY => {
+                let usage = usages.entry(item.offset).or_insert_with(usage);
+                usage.limits = Limit {
+                    referenced: limit_value(&item, BTRFS_QGROUP_LIMIT_MAX_RFER, 8),
+                    exclusive: limit_value(&item, BTRFS_QGROUP_LIMIT_MAX_EXCL, 16),
+                };
+            }
+            _ => {}
+        }
+    }
+    usages.into_values().collect()
+}
+
+/// Read a limit from a limit item, if its flag is set.
+fn limit_value(item: &SearchItem, flag: u64, offset: usize) -> Option<u64> {
+    let value = item.u64_at(offset);
+    if item.u64_at(0) & flag != 0 && value != u64::MAX {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::decode_items;
+    use crate::search::tests::encode_item;
+
+    fn info(referenced: u64, exclusive: u64) -> Vec<u8> {
+        [1u64, referenced, referenced, exclusive, exclusive]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    fn limit(flags: u64, max_rfer: u64, max_excl: u64) -> Vec<u8> {
+        [flags, max_rfer, max_excl, 0, 0]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    /// Items a search of (0, INFO, 257)..(0, LIMIT, 257) returns on a filesystem with qgroups
+    /// 0/256, 0/257 and 0/258.
+    fn whole_key_range() -> Vec<SearchItem> {
+        let mut buf = Vec::new();
+        encode_item(
+            &mut buf,
+            0,
+            search::BTRFS_QGROUP_INFO_KEY,
+            257,
+            &info(4096, 1024),
+        );
+        encode_item(
+            &mut buf,
+            0,
+            search::BTRFS_QGROUP_INFO_KEY,
+            258,
+            &info(8192, 2048),
+        );
+        encode_item(
+            &mut buf,
+            0,
+            search::BTRFS_QGROUP_LIMIT_KEY,
+            256,
+            &limit(0, 0, 0),
+        );
+        encode_item(
+            &mut buf,
+            0,
+            search::BTRFS_QGROUP_LIMIT_KEY,
+            257,
+            &limit(BTRFS_QGROUP_LIMIT_MAX_RFER, 1 << 20, u64::MAX),
+        );
+        decode_items(&buf, 4)
+    }
+
+    #[test]
+    fn usage_of_one_qgroup() {
+        let usages = collect_usage(whole_key_range(), 257, 257);
+        assert_eq!(
+            usages,
+            vec![QgroupUsage {
+                qgroupid: 257.into(),
+                referenced: 4096,
+                exclusive: 1024,
+                limits: Limit {
+                    referenced: Some(1 << 20),
+                    exclusive: None,
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn exclusive_size_of_subvolume() {
+        // The level 0 qgroup of subvolume 257, among its neighbours.
+        let usage = collect_usage(whole_key_range(), 257, 257);
+        assert_eq!(usage.first().map(|usage| usage.exclusive), Some(1024));
+        assert!(collect_usage(whole_key_range(), 259, 259).is_empty());
+    }
+
+    #[test]
+    fn stale_status() {
+        let status = |flags: u64| {
+            let mut buf = Vec::new();
+            let data: Vec<u8> = [1u64, 10, flags, 0]
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect();
+            encode_item(&mut buf, 0, search::BTRFS_QGROUP_STATUS_KEY, 0, &data);
+            decode_items(&buf, 1).pop()
+        };
+        assert!(!is_stale(None));
+        assert!(!is_stale(status(1).as_ref()));
+        assert!(is_stale(
+            status(1 | BTRFS_QGROUP_STATUS_FLAG_RESCAN).as_ref()
+        ));
+        assert!(is_stale(
+            status(BTRFS_QGROUP_STATUS_FLAG_INCONSISTENT).as_ref()
+        ));
+    }
+
+    #[test]
+    fn usage_of_all_qgroups() {
+        let usages = collect_usage(whole_key_range(), 0, u64::MAX);
+        let ids: Vec<u64> = usages.iter().map(|usage| usage.qgroupid.into()).collect();
+        assert_eq!(ids, vec![256, 257, 258]);
+        assert_eq!(usages[2].exclusive, 2048);
+    }
+}
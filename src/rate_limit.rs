@@ -0,0 +1,154 @@
+//! I/O rate limiting of maintenance operations
+//!
+//! A [RateLimit] is passed to an operation through its options, so that it does not starve the
+//! foreground workload of I/O bandwidth. The limit is enforced with a token bucket: the operation
+//! can go as fast as it likes until the bucket is empty, then it waits for the bucket to refill at
+//! the configured rate.
+//!
+//! [RateLimit]: struct.RateLimit.html
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Time over which the current throughput of an operation is measured.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(5);
+
+/// A limit of the I/O rate of an operation, in bytes per second.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct RateLimit {
+    bytes_per_sec: u64,
+    burst: u64,
+}
+
+impl RateLimit {
+    /// Create a limit of a rate, in bytes per second, allowing bursts of up to a second at that
+    /// rate.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            burst: bytes_per_sec,
+        }
+    }
+
+    /// Set the size of the bucket, in bytes, which is the most an operation can transfer at once
+    /// after being idle.
+    pub fn burst(mut self, bytes: u64) -> Self {
+        self.burst = bytes;
+        self
+    }
+
+    /// Get the rate, in bytes per second.
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    /// Get the size of the bucket, in bytes.
+    pub fn burst_size(&self) -> u64 {
+        self.burst
+    }
+}
+
+impl From<u64> for RateLimit {
+    fn from(bytes_per_sec: u64) -> Self {
+        Self::new(bytes_per_sec)
+    }
+}
+
+struct LimiterState {
+    /// Bytes left in the bucket, negative while the operation is in debt.
+    tokens: f64,
+    refilled: Instant,
+    /// Bytes transferred within the throughput window, with the time they were transferred.
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl LimiterState {
+    /// Drop the samples which fell out of the throughput window.
+    fn prune(&mut self, now: Instant) {
+        while self
+            .samples
+            .front()
+            .is_some_and(|(time, _)| now.duration_since(*time) > THROUGHPUT_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+    }
+}
+
+/// Token bucket enforcing an optional rate limit, measuring the throughput as well.
+///
+/// Can be shared between the threads of an operation, which are then limited together.
+pub(crate) struct Limiter {
+    limit: Option<RateLimit>,
+    start: Instant,
+    state: Mutex<LimiterState>,
+}
+
+impl Limiter {
+    /// Start limiting an operation to a rate, if any. The bucket starts full.
+    pub(crate) fn new(limit: Option<RateLimit>) -> Self {
+        let start = Instant::now();
+        Self {
+            limit,
+            start,
+            state: Mutex::new(LimiterState {
+                tokens: limit.map_or(0.0, |limit| limit.burst as f64),
+                refilled: start,
+                samples: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Get the time elapsed since the operation started.
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Take bytes about to be transferred, or just transferred, out of the bucket, sleeping until
+    /// the bucket has refilled enough if it is short of them.
+    pub(crate) fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut state = self.lock();
+            let now = Instant::now();
+            state.prune(now);
+            state.samples.push_back((now, bytes));
+            match self.limit {
+                Some(limit) if limit.bytes_per_sec > 0 => {
+                    let rate = limit.bytes_per_sec as f64;
+                    let refill = now.duration_since(state.refilled).as_secs_f64() * rate;
+                    state.tokens = (state.tokens + refill).min(limit.burst as f64) - bytes as f64;
+                    state.refilled = now;
+                    if state.tokens < 0.0 {
+                        Some(Duration::from_secs_f64(-state.tokens / rate))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            }
+        };
+        if let Some(wait) = wait {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Get the throughput over the last few seconds, in bytes per second.
+    pub(crate) fn throughput(&self) -> f64 {
+        let mut state = self.lock();
+        state.prune(Instant::now());
+        let secs = THROUGHPUT_WINDOW.min(self.start.elapsed()).as_secs_f64();
+        if secs > 0.0 {
+            state.samples.iter().map(|(_, bytes)| *bytes).sum::<u64>() as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LimiterState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,122 @@
+//! Rollbacks of the root filesystem to a snapshot
+//!
+//! A rollback makes a snapshot the default subvolume, which is mounted as the root filesystem on
+//! the next boot unless another subvolume is requested explicitly. The running system is left
+//! untouched until then.
+
+use crate::subvolume::DeleteOptions;
+use crate::subvolume::SnapshotOptions;
+use crate::subvolume::Subvolume;
+use crate::Result;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use chrono::Local;
+
+/// Something which must be done for a rollback to take effect.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RollbackAction {
+    /// Reboot, to mount the new default subvolume as the root filesystem.
+    Reboot,
+    /// Remove the subvolume from the root mount options on the kernel command line, which
+    /// override the default subvolume.
+    UpdateKernelCommandLine {
+        /// The overriding root mount options.
+        rootflags: String,
+    },
+}
+
+/// Result of a [rollback].
+///
+/// [rollback]: fn.rollback.html
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RollbackReport {
+    /// Read-only snapshot of the previous default subvolume, to undo the rollback.
+    pub backup: PathBuf,
+    /// Writable clone of the snapshot, which is the new default subvolume.
+    pub clone: PathBuf,
+    /// Id of the previous default subvolume.
+    pub previous_default: u64,
+    /// Id of the new default subvolume.
+    pub new_default: u64,
+    /// What must be done for the rollback to take effect.
+    pub actions: Vec<RollbackAction>,
+}
+
+/// Roll the root filesystem back to a read-only snapshot.
+///
+/// The steps are ordered so that a failure leaves the default subvolume unchanged:
+///
+/// 1. a read-only backup snapshot of the current default subvolume is taken,
+/// 2. a writable clone of the snapshot is taken,
+/// 3. the clone is made the default subvolume.
+///
+/// Both new snapshots are created in a directory, named after the current time. If a step fails,
+/// the snapshots taken before it are deleted again. This requires elevated
+/// privileges(CAP_SYS_ADMIN).
+pub fn rollback<P: AsRef<Path>, Q: AsRef<Path>>(
+    snapshot: P,
+    snapshot_dir: Q,
+) -> Result<RollbackReport> {
+    let snapshot = Subvolume::get(snapshot.as_ref())?;
+    if !snapshot.is_ro()? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "rollbacks are only done to read-only snapshots",
+        )
+        .into());
+    }
+    let previous = Subvolume::get_default(Some(snapshot_dir.as_ref()))?;
+
+    let time = Local::now().naive_local().format("%Y-%m-%dT%H:%M:%S");
+    let dir = snapshot_dir.as_ref();
+    fs::create_dir_all(dir)?;
+    let backup_path = dir.join(format!("pre-rollback.{}", time));
+    let clone_path = dir.join(format!("rollback.{}", time));
+
+    let backup = previous.snapshot_with(
+        backup_path.as_path(),
+        SnapshotOptions::new().read_only(true),
+    )?;
+    let clone = match snapshot.snapshot_with(clone_path.as_path(), SnapshotOptions::new()) {
+        Ok(clone) => clone,
+        Err(e) => {
+            let _ = backup.delete_with(DeleteOptions::default());
+            return Err(e);
+        }
+    };
+    if let Err(e) = clone.set_default() {
+        let _ = clone.delete_with(DeleteOptions::default());
+        let _ = backup.delete_with(DeleteOptions::default());
+        return Err(e);
+    }
+
+    let mut actions = vec![RollbackAction::Reboot];
+    if let Some(rootflags) = subvolume_rootflags()? {
+        actions.push(RollbackAction::UpdateKernelCommandLine { rootflags });
+    }
+    Ok(RollbackReport {
+        backup: backup_path,
+        clone: clone_path,
+        previous_default: previous.id(),
+        new_default: clone.id(),
+        actions,
+    })
+}
+
+/// Get the root mount options of the kernel command line if they select a subvolume.
+fn subvolume_rootflags() -> Result<Option<String>> {
+    let cmdline = fs::read_to_string("/proc/cmdline")?;
+    Ok(cmdline
+        .split_whitespace()
+        .filter_map(|arg| arg.strip_prefix("rootflags="))
+        .find(|flags| {
+            flags
+                .split(',')
+                .any(|flag| flag.starts_with("subvol=") || flag.starts_with("subvolid="))
+        })
+        .map(str::to_owned))
+}
@@ -0,0 +1,9 @@
+//! Timeline snapshots
+
+mod retention;
+mod schedule;
+mod timeline;
+
+pub use retention::*;
+pub use schedule::*;
+pub use timeline::*;
@@ -0,0 +1,297 @@
+use crate::scheduler::Frequency;
+use crate::scheduler::TimelineSnapshot;
+use crate::subvolume::DeleteOptions;
+use crate::subvolume::Subvolume;
+use crate::Result;
+
+use std::cmp::Reverse;
+use std::path::Path;
+use std::path::PathBuf;
+
+use chrono::NaiveDateTime;
+#[cfg(feature = "json")]
+use serde::{Deserialize, Serialize};
+
+/// A snapshot with the time it was taken, as considered by a [RetentionPolicy].
+///
+/// [RetentionPolicy]: struct.RetentionPolicy.html
+pub trait DatedSnapshot {
+    /// Get the path of the snapshot.
+    fn path(&self) -> &Path;
+
+    /// Get the time the snapshot was taken.
+    fn timestamp(&self) -> NaiveDateTime;
+}
+
+impl DatedSnapshot for TimelineSnapshot {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn timestamp(&self) -> NaiveDateTime {
+        self.time
+    }
+}
+
+impl DatedSnapshot for (PathBuf, NaiveDateTime) {
+    fn path(&self) -> &Path {
+        &self.0
+    }
+
+    fn timestamp(&self) -> NaiveDateTime {
+        self.1
+    }
+}
+
+/// Numbers of snapshots to keep.
+///
+/// Besides the most recent snapshots, a policy keeps the most recent snapshot of each of the
+/// latest hours, days, ISO weeks, months and years which have one. A snapshot kept for one rule
+/// also counts for the others, so e.g. the latest snapshot is usually kept for all of them.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct RetentionPolicy {
+    /// Number of most recent snapshots to keep.
+    pub keep_last: usize,
+    /// Number of hours to keep a snapshot for.
+    pub hourly: usize,
+    /// Number of days to keep a snapshot for.
+    pub daily: usize,
+    /// Number of weeks to keep a snapshot for.
+    pub weekly: usize,
+    /// Number of months to keep a snapshot for.
+    pub monthly: usize,
+    /// Number of years to keep a snapshot for.
+    pub yearly: usize,
+}
+
+impl RetentionPolicy {
+    /// Check whether the policy keeps no snapshot at all.
+    pub fn is_empty(&self) -> bool {
+        self.keep_last == 0 && self.periods().iter().all(|(_, count)| *count == 0)
+    }
+
+    /// Get the number of periods to keep a snapshot for, for every frequency.
+    fn periods(&self) -> [(Frequency, usize); 5] {
+        [
+            (Frequency::Hourly, self.hourly),
+            (Frequency::Daily, self.daily),
+            (Frequency::Weekly, self.weekly),
+            (Frequency::Monthly, self.monthly),
+            (Frequency::Yearly, self.yearly),
+        ]
+    }
+}
+
+/// Why a [RetentionPolicy] keeps a snapshot.
+///
+/// [RetentionPolicy]: struct.RetentionPolicy.html
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeepReason {
+    /// The snapshot is among the most recent ones.
+    Last,
+    /// The snapshot is the most recent one of its period.
+    Period(Frequency),
+    /// The policy is empty, which keeps every snapshot rather than none.
+    EmptyPolicy,
+}
+
+/// A snapshot kept by a [RetentionPolicy].
+///
+/// [RetentionPolicy]: struct.RetentionPolicy.html
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeptSnapshot<T> {
+    /// The snapshot.
+    pub snapshot: T,
+    /// Why the snapshot is kept.
+    pub reasons: Vec<KeepReason>,
+}
+
+/// The snapshots a [RetentionPolicy] keeps and deletes, each newest first.
+///
+/// Returned by [apply].
+///
+/// [RetentionPolicy]: struct.RetentionPolicy.html
+/// [apply]: fn.apply.html
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PruneDecision<T> {
+    /// Snapshots to keep.
+    pub keep: Vec<KeptSnapshot<T>>,
+    /// Snapshots to delete.
+    pub delete: Vec<T>,
+}
+
+/// Select the snapshots to keep and to delete according to a retention policy.
+///
+/// Snapshots taken at the same time are ordered as given. Since deleting every snapshot is never
+/// what is intended, an [empty] policy keeps all snapshots.
+///
+/// [empty]: struct.RetentionPolicy.html#method.is_empty
+pub fn apply<T: DatedSnapshot>(policy: &RetentionPolicy, snapshots: Vec<T>) -> PruneDecision<T> {
+    let mut snapshots: Vec<(T, Vec<KeepReason>)> = snapshots
+        .into_iter()
+        .map(|snapshot| (snapshot, Vec::new()))
+        .collect();
+    // Stable, so that ties keep their order.
+    snapshots.sort_by_key(|(snapshot, _)| Reverse(snapshot.timestamp()));
+
+    if policy.is_empty() {
+        for (_, reasons) in snapshots.iter_mut() {
+            reasons.push(KeepReason::EmptyPolicy);
+        }
+    }
+
+    for (_, reasons) in snapshots.iter_mut().take(policy.keep_last) {
+        reasons.push(KeepReason::Last);
+    }
+
+    for (frequency, count) in policy.periods().iter().copied() {
+        let mut last_period = None;
+        let mut kept = 0;
+        for (snapshot, reasons) in snapshots.iter_mut() {
+            if kept == count {
+                break;
+            }
+            let period = frequency.period(snapshot.timestamp());
+            if last_period != Some(period) {
+                last_period = Some(period);
+                reasons.push(KeepReason::Period(frequency));
+                kept += 1;
+            }
+        }
+    }
+
+    let mut decision = PruneDecision {
+        keep: Vec::new(),
+        delete: Vec::new(),
+    };
+    for (snapshot, reasons) in snapshots {
+        if reasons.is_empty() {
+            decision.delete.push(snapshot);
+        } else {
+            decision.keep.push(KeptSnapshot { snapshot, reasons });
+        }
+    }
+    decision
+}
+
+/// Delete the snapshots a retention policy selected for deletion, returning their paths.
+///
+/// Deletion stops at the first failure. This requires elevated privileges(CAP_SYS_ADMIN).
+pub fn prune<T: DatedSnapshot>(decision: &PruneDecision<T>) -> Result<Vec<PathBuf>> {
+    let mut deleted = Vec::new();
+    for snapshot in &decision.delete {
+        let path = snapshot.path();
+        Subvolume::get(path)?.delete_with(DeleteOptions::default())?;
+        deleted.push(path.to_path_buf());
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    fn snapshot(name: &str, day: u32, hour: u32) -> (PathBuf, NaiveDateTime) {
+        let time = NaiveDate::from_ymd_opt(2024, 3, day)
+            .and_then(|date| date.and_hms_opt(hour, 0, 0))
+            .unwrap();
+        (PathBuf::from(name), time)
+    }
+
+    fn names<T: DatedSnapshot>(snapshots: &[T]) -> Vec<&Path> {
+        snapshots.iter().map(DatedSnapshot::path).collect()
+    }
+
+    fn kept(decision: &PruneDecision<(PathBuf, NaiveDateTime)>) -> Vec<(&Path, &[KeepReason])> {
+        decision
+            .keep
+            .iter()
+            .map(|kept| (kept.snapshot.path(), kept.reasons.as_slice()))
+            .collect()
+    }
+
+    fn snapshots() -> Vec<(PathBuf, NaiveDateTime)> {
+        vec![
+            snapshot("a", 1, 10),
+            snapshot("b", 1, 12),
+            snapshot("c", 2, 8),
+            snapshot("d", 3, 9),
+            snapshot("e", 3, 18),
+        ]
+    }
+
+    #[test]
+    fn empty_policy_keeps_everything() {
+        let decision = apply(&RetentionPolicy::default(), snapshots());
+        assert!(decision.delete.is_empty());
+        assert_eq!(decision.keep.len(), 5);
+        assert!(decision
+            .keep
+            .iter()
+            .all(|kept| kept.reasons == [KeepReason::EmptyPolicy]));
+    }
+
+    #[test]
+    fn keep_last() {
+        let policy = RetentionPolicy {
+            keep_last: 2,
+            ..Default::default()
+        };
+        let decision = apply(&policy, snapshots());
+        assert_eq!(
+            kept(&decision),
+            [
+                (Path::new("e"), &[KeepReason::Last][..]),
+                (Path::new("d"), &[KeepReason::Last][..]),
+            ]
+        );
+        assert_eq!(names(&decision.delete), ["c", "b", "a"].map(Path::new));
+    }
+
+    #[test]
+    fn keep_latest_of_each_period() {
+        let policy = RetentionPolicy {
+            keep_last: 1,
+            daily: 2,
+            ..Default::default()
+        };
+        let daily = KeepReason::Period(Frequency::Daily);
+        let decision = apply(&policy, snapshots());
+        assert_eq!(
+            kept(&decision),
+            [
+                (Path::new("e"), &[KeepReason::Last, daily][..]),
+                (Path::new("c"), &[daily][..]),
+            ]
+        );
+        assert_eq!(names(&decision.delete), ["d", "b", "a"].map(Path::new));
+
+        // Periods without snapshots do not count.
+        let policy = RetentionPolicy {
+            monthly: 3,
+            ..Default::default()
+        };
+        let decision = apply(&policy, snapshots());
+        assert_eq!(
+            kept(&decision),
+            [(
+                Path::new("e"),
+                &[KeepReason::Period(Frequency::Monthly)][..]
+            )]
+        );
+    }
+
+    #[test]
+    fn ties_keep_their_order() {
+        let policy = RetentionPolicy {
+            keep_last: 1,
+            ..Default::default()
+        };
+        let decision = apply(&policy, vec![snapshot("x", 1, 0), snapshot("y", 1, 0)]);
+        assert_eq!(kept(&decision), [(Path::new("x"), &[KeepReason::Last][..])]);
+        assert_eq!(names(&decision.delete), [Path::new("y")]);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::error::ParseError;
+use crate::Result;
+
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chrono::format::Item;
+use chrono::format::StrftimeItems;
+use chrono::Datelike;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::Timelike;
+#[cfg(feature = "json")]
+use serde::{Deserialize, Serialize};
+
+/// Template used to name timeline snapshots unless configured otherwise.
+pub const DEFAULT_TEMPLATE: &str = "{name}.{frequency}.%Y-%m-%dT%H:%M:%S";
+
+/// How often a timeline snapshot is taken.
+///
+/// A snapshot is due once per calendar period: hour, day, ISO week, month or year.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub enum Frequency {
+    /// Once per hour.
+    Hourly,
+    /// Once per day.
+    Daily,
+    /// Once per ISO week.
+    Weekly,
+    /// Once per month.
+    Monthly,
+    /// Once per year.
+    Yearly,
+}
+
+impl Frequency {
+    /// All frequencies, from the most to the least frequent.
+    pub const ALL: [Frequency; 5] = [
+        Frequency::Hourly,
+        Frequency::Daily,
+        Frequency::Weekly,
+        Frequency::Monthly,
+        Frequency::Yearly,
+    ];
+
+    /// Get the calendar period a point in time falls in.
+    ///
+    /// Two points in time are in the same period if their periods are equal.
+    pub fn period(self, time: NaiveDateTime) -> (i32, u32, u32) {
+        match self {
+            Frequency::Hourly => (time.year(), time.ordinal(), time.hour()),
+            Frequency::Daily => (time.year(), time.ordinal(), 0),
+            Frequency::Weekly => {
+                let week = time.iso_week();
+                (week.year(), week.week(), 0)
+            }
+            Frequency::Monthly => (time.year(), time.month(), 0),
+            Frequency::Yearly => (time.year(), 0, 0),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Frequency::Hourly => "hourly",
+            Frequency::Daily => "daily",
+            Frequency::Weekly => "weekly",
+            Frequency::Monthly => "monthly",
+            Frequency::Yearly => "yearly",
+        }
+    }
+}
+
+impl fmt::Display for Frequency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Frequency {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Frequency::ALL
+            .iter()
+            .copied()
+            .find(|frequency| frequency.as_str() == s)
+            .ok_or_else(|| ParseError::new("snapshot frequency", s))
+    }
+}
+
+/// Timeline snapshots of one subvolume.
+///
+/// Snapshots are named after a template, in which `{name}` is replaced by the file name of the
+/// subvolume, `{frequency}` by the [Frequency] the snapshot was taken for and `strftime`
+/// sequences such as `%Y` by the time the snapshot was taken. The template is also used to
+/// recognize existing snapshots, so it must contain `{frequency}` and the date, and the time if
+/// snapshots are taken hourly.
+///
+/// Used with [SnapshotScheduler].
+///
+/// [Frequency]: enum.Frequency.html
+/// [SnapshotScheduler]: struct.SnapshotScheduler.html
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct ScheduleConfig {
+    pub(crate) subvolume: PathBuf,
+    pub(crate) snapshot_dir: PathBuf,
+    pub(crate) frequencies: Vec<Frequency>,
+    pub(crate) template: String,
+}
+
+impl ScheduleConfig {
+    /// Create a schedule taking hourly and daily snapshots of a subvolume into a directory.
+    pub fn new<S: Into<PathBuf>, D: Into<PathBuf>>(subvolume: S, snapshot_dir: D) -> Self {
+        Self {
+            subvolume: subvolume.into(),
+            snapshot_dir: snapshot_dir.into(),
+            frequencies: vec![Frequency::Hourly, Frequency::Daily],
+            template: DEFAULT_TEMPLATE.to_owned(),
+        }
+    }
+
+    /// Set the frequencies snapshots are taken at.
+    pub fn frequencies(mut self, frequencies: &[Frequency]) -> Self {
+        self.frequencies = frequencies.to_vec();
+        self
+    }
+
+    /// Set the template snapshots are named after.
+    pub fn template<T: Into<String>>(mut self, template: T) -> Self {
+        self.template = template.into();
+        self
+    }
+
+    /// Get the path of the subvolume.
+    pub fn subvolume(&self) -> &Path {
+        &self.subvolume
+    }
+
+    /// Get the directory snapshots are created in.
+    pub fn snapshot_dir(&self) -> &Path {
+        &self.snapshot_dir
+    }
+
+    /// Get the template for a frequency, as a `strftime` format.
+    fn format(&self, frequency: Frequency) -> Result<String> {
+        let name = self
+            .subvolume
+            .file_name()
+            .map(|name| name.to_string_lossy().replace('%', "%%"))
+            .unwrap_or_default();
+        let format = self
+            .template
+            .replace("{name}", &name)
+            .replace("{frequency}", frequency.as_str());
+        if !self.template.contains("{frequency}")
+            || StrftimeItems::new(&format).any(|item| item == Item::Error)
+        {
+            return Err(ParseError::new("snapshot name template", self.template.as_str()).into());
+        }
+        Ok(format)
+    }
+
+    /// Get the name of the snapshot taken for a frequency at a point in time.
+    pub fn snapshot_name(&self, frequency: Frequency, time: NaiveDateTime) -> Result<String> {
+        Ok(time.format(&self.format(frequency)?).to_string())
+    }
+
+    /// Recognize the name of a snapshot taken by this schedule, getting the frequency and time it
+    /// was taken for.
+    pub fn parse_snapshot_name(&self, name: &str) -> Result<Option<(Frequency, NaiveDateTime)>> {
+        for frequency in Frequency::ALL.iter().copied() {
+            let format = self.format(frequency)?;
+            if let Ok(time) = NaiveDateTime::parse_from_str(name, &format) {
+                return Ok(Some((frequency, time)));
+            }
+            if let Some(time) = NaiveDate::parse_from_str(name, &format)
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+            {
+                return Ok(Some((frequency, time)));
+            }
+        }
+        Ok(None)
+    }
+}
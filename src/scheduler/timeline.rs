@@ -0,0 +1,135 @@
+use crate::scheduler::Frequency;
+use crate::scheduler::ScheduleConfig;
+use crate::subvolume::subvolume_exists;
+use crate::subvolume::SnapshotOptions;
+use crate::subvolume::Subvolume;
+use crate::Result;
+
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::Local;
+use chrono::NaiveDateTime;
+
+/// A timeline snapshot found in the snapshot directory of a schedule.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TimelineSnapshot {
+    /// Path of the snapshot.
+    pub path: PathBuf,
+    /// Frequency the snapshot was taken for.
+    pub frequency: Frequency,
+    /// Time the snapshot was taken, as recorded in its name.
+    pub time: NaiveDateTime,
+}
+
+/// A timeline snapshot which is due.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DueSnapshot {
+    /// Path of the subvolume to snapshot.
+    pub subvolume: PathBuf,
+    /// Path of the snapshot to create.
+    pub path: PathBuf,
+    /// Frequency the snapshot is taken for.
+    pub frequency: Frequency,
+}
+
+/// Scheduler of timeline snapshots.
+///
+/// The scheduler keeps no state of its own: on every tick, it recognizes the snapshots already
+/// taken by their names and takes a read-only snapshot for every frequency whose current period
+/// has none yet. It is meant to be driven by a timer or a cron job, and catches up after missed
+/// ticks with a single snapshot per frequency.
+#[derive(Clone, Debug, Default)]
+pub struct SnapshotScheduler {
+    schedules: Vec<ScheduleConfig>,
+}
+
+impl SnapshotScheduler {
+    /// Create a scheduler without schedules.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a schedule.
+    pub fn add(&mut self, schedule: ScheduleConfig) -> &mut Self {
+        self.schedules.push(schedule);
+        self
+    }
+
+    /// Get the schedules.
+    pub fn schedules(&self) -> &[ScheduleConfig] {
+        &self.schedules
+    }
+
+    /// List the timeline snapshots of a schedule, oldest first.
+    pub fn snapshots(schedule: &ScheduleConfig) -> Result<Vec<TimelineSnapshot>> {
+        let mut snapshots = Vec::new();
+        if !schedule.snapshot_dir.exists() {
+            return Ok(snapshots);
+        }
+        for entry in fs::read_dir(&schedule.snapshot_dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let (frequency, time) = match schedule.parse_snapshot_name(&name.to_string_lossy())? {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+            if subvolume_exists(entry.path())? {
+                snapshots.push(TimelineSnapshot {
+                    path: entry.path(),
+                    frequency,
+                    time,
+                });
+            }
+        }
+        snapshots.sort_by(|a, b| a.time.cmp(&b.time).then(a.frequency.cmp(&b.frequency)));
+        Ok(snapshots)
+    }
+
+    /// Compute the snapshots due at a point in time.
+    pub fn due(&self, now: NaiveDateTime) -> Result<Vec<DueSnapshot>> {
+        let mut due = Vec::new();
+        for schedule in &self.schedules {
+            let snapshots = Self::snapshots(schedule)?;
+            for frequency in schedule.frequencies.iter().copied() {
+                let taken = snapshots.iter().any(|snapshot| {
+                    snapshot.frequency == frequency
+                        && frequency.period(snapshot.time) == frequency.period(now)
+                });
+                if !taken {
+                    due.push(DueSnapshot {
+                        subvolume: schedule.subvolume.clone(),
+                        path: schedule
+                            .snapshot_dir
+                            .join(schedule.snapshot_name(frequency, now)?),
+                        frequency,
+                    });
+                }
+            }
+        }
+        Ok(due)
+    }
+
+    /// Create the snapshots due at a point in time, returning their paths.
+    ///
+    /// Snapshots are created read-only. This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn tick(&self, now: NaiveDateTime) -> Result<Vec<PathBuf>> {
+        let mut created = Vec::new();
+        for due in self.due(now)? {
+            if let Some(parent) = due.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            Subvolume::get(due.subvolume.as_path())?
+                .snapshot_with(due.path.as_path(), SnapshotOptions::new().read_only(true))?;
+            created.push(due.path);
+        }
+        Ok(created)
+    }
+
+    /// Create the snapshots due at the current local time, returning their paths.
+    ///
+    /// This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn tick_now(&self) -> Result<Vec<PathBuf>> {
+        self.tick(Local::now().naive_local())
+    }
+}
@@ -0,0 +1,283 @@
+//! Btrfs tree search, for reading on-disk items which are not exposed by libbtrfsutil.
+//!
+//! This requires elevated privileges(CAP_SYS_ADMIN).
+
+use crate::ioctl;
+
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+
+/// Tree holding the device extents.
+pub(crate) const BTRFS_DEV_TREE_OBJECTID: u64 = 4;
+/// Tree holding the qgroup items.
+pub(crate) const BTRFS_QUOTA_TREE_OBJECTID: u64 = 8;
+/// Tree mapping subvolume UUIDs to subvolume ids.
+pub(crate) const BTRFS_UUID_TREE_OBJECTID: u64 = 9;
+
+/// Inode item, keyed by inode number.
+pub(crate) const BTRFS_INODE_ITEM_KEY: u32 = 1;
+/// Directory entry item, keyed by directory inode number and index.
+pub(crate) const BTRFS_DIR_INDEX_KEY: u32 = 96;
+/// Item describing an extent of a file, keyed by inode number and file offset.
+pub(crate) const BTRFS_EXTENT_DATA_KEY: u32 = 108;
+
+/// Device extent item, keyed by device id and physical offset.
+pub(crate) const BTRFS_DEV_EXTENT_KEY: u32 = 204;
+
+pub(crate) const BTRFS_QGROUP_STATUS_KEY: u32 = 240;
+pub(crate) const BTRFS_QGROUP_INFO_KEY: u32 = 242;
+pub(crate) const BTRFS_QGROUP_LIMIT_KEY: u32 = 244;
+pub(crate) const BTRFS_QGROUP_RELATION_KEY: u32 = 246;
+
+/// UUID tree item listing the subvolumes with a UUID, keyed by the two halves of the UUID.
+pub(crate) const BTRFS_UUID_KEY_SUBVOL: u32 = 251;
+
+/// Range of item keys to search for in a tree.
+#[derive(Clone, Debug)]
+pub(crate) struct SearchKey {
+    tree_id: u64,
+    min_objectid: u64,
+    max_objectid: u64,
+    min_type: u32,
+    max_type: u32,
+    min_offset: u64,
+    max_offset: u64,
+    min_transid: u64,
+    max_transid: u64,
+}
+
+impl SearchKey {
+    /// Search all items of a tree.
+    pub(crate) fn new(tree_id: u64) -> Self {
+        Self {
+            tree_id,
+            min_objectid: 0,
+            max_objectid: u64::MAX,
+            min_type: 0,
+            max_type: u32::MAX,
+            min_offset: 0,
+            max_offset: u64::MAX,
+            min_transid: 0,
+            max_transid: u64::MAX,
+        }
+    }
+
+    /// Restrict the search to a range of object ids.
+    pub(crate) fn objectids(mut self, min: u64, max: u64) -> Self {
+        self.min_objectid = min;
+        self.max_objectid = max;
+        self
+    }
+
+    /// Restrict the search to a range of item types.
+    pub(crate) fn types(mut self, min: u32, max: u32) -> Self {
+        self.min_type = min;
+        self.max_type = max;
+        self
+    }
+
+    /// Restrict the search to a range of offsets.
+    pub(crate) fn offsets(mut self, min: u64, max: u64) -> Self {
+        self.min_offset = min;
+        self.max_offset = max;
+        self
+    }
+
+    /// Restrict the search to tree blocks written within a range of transactions.
+    pub(crate) fn transids(mut self, min: u64, max: u64) -> Self {
+        self.min_transid = min;
+        self.max_transid = max;
+        self
+    }
+
+    /// Search the tree through a file descriptor on the filesystem.
+    pub(crate) fn search(self, fd: RawFd) -> TreeSearch {
+        TreeSearch {
+            fd,
+            key: Some(self),
+            items: VecDeque::new(),
+        }
+    }
+}
+
+/// An item found by a tree search.
+#[derive(Clone, Debug)]
+pub(crate) struct SearchItem {
+    pub objectid: u64,
+    pub item_type: u32,
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+impl SearchItem {
+    /// Read a byte at an offset of the item data, or zero past its end.
+    pub(crate) fn u8_at(&self, offset: usize) -> u8 {
+        self.data.get(offset).copied().unwrap_or(0)
+    }
+
+    /// Read a little-endian u32 at a byte offset of the item data, or zero past its end.
+    pub(crate) fn u32_at(&self, offset: usize) -> u32 {
+        self.data
+            .get(offset..offset + 4)
+            .map_or(0, |bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Read a little-endian u64 at a byte offset of the item data, or zero past its end.
+    pub(crate) fn u64_at(&self, offset: usize) -> u64 {
+        self.data
+            .get(offset..offset + 8)
+            .map_or(0, |bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+/// Iterator over the items found by a tree search, fetching them in batches.
+pub(crate) struct TreeSearch {
+    fd: RawFd,
+    /// The remaining range, or `None` once the search is exhausted.
+    key: Option<SearchKey>,
+    items: VecDeque<SearchItem>,
+}
+
+impl TreeSearch {
+    fn fetch(&mut self) -> io::Result<()> {
+        let key = match self.key.as_mut() {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+
+        let mut args: Box<ioctl::btrfs_ioctl_search_args> = Box::new(unsafe { mem::zeroed() });
+        args.key.tree_id = key.tree_id;
+        args.key.min_objectid = key.min_objectid;
+        args.key.max_objectid = key.max_objectid;
+        args.key.min_type = key.min_type;
+        args.key.max_type = key.max_type;
+        args.key.min_offset = key.min_offset;
+        args.key.max_offset = key.max_offset;
+        args.key.min_transid = key.min_transid;
+        args.key.max_transid = key.max_transid;
+        args.key.nr_items = u32::MAX;
+
+        unsafe { ioctl::ioctl(self.fd, ioctl::BTRFS_IOC_TREE_SEARCH, &mut *args)? };
+        self.items
+            .extend(decode_items(&args.buf[..], args.key.nr_items as usize));
+
+        // Continue right after the last item found.
+        match self.items.back() {
+            None => self.key = None,
+            Some(last) => {
+                key.min_objectid = last.objectid;
+                key.min_type = last.item_type;
+                key.min_offset = last.offset;
+                if key.min_offset < u64::MAX {
+                    key.min_offset += 1;
+                } else if key.min_type < u8::MAX as u32 {
+                    key.min_type += 1;
+                    key.min_offset = 0;
+                } else if key.min_objectid < u64::MAX {
+                    key.min_objectid += 1;
+                    key.min_type = 0;
+                    key.min_offset = 0;
+                } else {
+                    self.key = None;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decode the items of a search result buffer, each a search header followed by the item data.
+///
+/// Decoding stops at the first item which does not fit in the buffer.
+pub(crate) fn decode_items(buf: &[u8], nr_items: usize) -> Vec<SearchItem> {
+    let header_size = mem::size_of::<ioctl::btrfs_ioctl_search_header>();
+    let mut items = Vec::with_capacity(nr_items);
+    let mut pos = 0;
+    for _ in 0..nr_items {
+        if buf.len() < pos + header_size {
+            break;
+        }
+        let header: ioctl::btrfs_ioctl_search_header =
+            unsafe { std::ptr::read_unaligned(buf[pos..].as_ptr() as *const _) };
+        pos += header_size;
+        let data = match buf.get(pos..pos + header.len as usize) {
+            Some(data) => data.to_vec(),
+            None => break,
+        };
+        pos += header.len as usize;
+        items.push(SearchItem {
+            objectid: header.objectid,
+            item_type: header.type_,
+            offset: header.offset,
+            data,
+        });
+    }
+    items
+}
+
+impl Iterator for TreeSearch {
+    type Item = io::Result<SearchItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.items.is_empty() {
+            if let Err(e) = self.fetch() {
+                self.key = None;
+                return Some(Err(e));
+            }
+        }
+        self.items.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Encode an item like the kernel does in a search result buffer.
+    pub(crate) fn encode_item(
+        buf: &mut Vec<u8>,
+        objectid: u64,
+        item_type: u32,
+        offset: u64,
+        data: &[u8],
+    ) {
+        buf.extend_from_slice(&1u64.to_le_bytes());
+        buf.extend_from_slice(&objectid.to_le_bytes());
+        buf.extend_from_slice(&offset.to_le_bytes());
+        buf.extend_from_slice(&item_type.to_le_bytes());
+        buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        buf.extend_from_slice(data);
+    }
+
+    #[test]
+    fn decode_search_buffer() {
+        let mut buf = Vec::new();
+        encode_item(&mut buf, 0, BTRFS_QGROUP_INFO_KEY, 5, &[1, 2, 3]);
+        encode_item(&mut buf, 7, BTRFS_QGROUP_LIMIT_KEY, 9, &[]);
+        buf.resize(buf.len() + 64, 0);
+
+        let items = decode_items(&buf, 2);
+        assert_eq!(items.len(), 2);
+        assert_eq!(
+            (items[0].objectid, items[0].item_type, items[0].offset),
+            (0, BTRFS_QGROUP_INFO_KEY, 5)
+        );
+        assert_eq!(items[0].data, vec![1, 2, 3]);
+        assert_eq!(
+            (items[1].objectid, items[1].item_type, items[1].offset),
+            (7, BTRFS_QGROUP_LIMIT_KEY, 9)
+        );
+        assert!(items[1].data.is_empty());
+    }
+
+    #[test]
+    fn decode_truncated_search_buffer() {
+        let mut buf = Vec::new();
+        encode_item(&mut buf, 0, BTRFS_QGROUP_INFO_KEY, 5, &[0; 16]);
+        buf.truncate(buf.len() - 1);
+        assert!(decode_items(&buf, 1).is_empty());
+    }
+}
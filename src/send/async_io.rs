@@ -0,0 +1,159 @@
+use crate::common;
+use crate::send::receive::receive;
+use crate::send::receive::ReceiveOptions;
+use crate::send::sender::send;
+use crate::send::sender::SendOptions;
+use crate::subvolume::Subvolume;
+use crate::Result;
+
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::PathBuf;
+
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+/// Number of chunks buffered between the blocking side of a transfer and the async one.
+const CHANNEL_BUFFER: usize = 16;
+/// Size of the chunks read from an async reader.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Writer passing chunks to the async side of a send.
+struct ChannelWriter(mpsc::Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(buf.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reader taking chunks from the async side of a receive.
+struct ChannelReader {
+    receiver: mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// Generate a send stream of a read-only subvolume into an async writer, like [send].
+///
+/// The send runs on the tokio blocking thread pool and the stream is written as it is produced,
+/// e.g. into a network connection. Dropping the future stops the send. Returns the number of
+/// bytes written.
+///
+/// Must be called from within a tokio runtime. This requires elevated
+/// privileges(CAP_SYS_ADMIN).
+///
+/// [send]: fn.send.html
+pub async fn send_async<W: AsyncWrite + Unpin>(
+    subvol: &Subvolume,
+    options: &SendOptions<'_>,
+    mut writer: W,
+) -> Result<u64> {
+    let subvol = subvol.clone();
+    let parent: Option<Subvolume> = options.parent.cloned();
+    let clone_sources: Vec<Subvolume> = options.clone_sources.iter().copied().cloned().collect();
+    let options = options.with_subvolumes(None, Vec::new());
+    let (sender, mut receiver) = mpsc::channel(CHANNEL_BUFFER);
+
+    let handle = tokio::task::spawn_blocking(move || {
+        let options = options.with_subvolumes(parent.as_ref(), clone_sources.iter().collect());
+        send(&subvol, &options, ChannelWriter(sender))
+    });
+
+    let mut total: u64 = 0;
+    let mut written: io::Result<()> = Ok(());
+    while let Some(chunk) = receiver.recv().await {
+        written = writer.write_all(&chunk).await;
+        if written.is_err() {
+            break;
+        }
+        total += chunk.len() as u64;
+    }
+    // Closing the channel stops the send if the write failed.
+    drop(receiver);
+
+    let sent = common::join_blocking(handle).await;
+    written?;
+    sent?;
+    writer.flush().await?;
+    Ok(total)
+}
+
+/// Receive send streams from an async reader into a directory, like [receive].
+///
+/// The stream is read as it arrives, e.g. from a network connection, and applied on the tokio
+/// blocking thread pool. Returns the paths of the received subvolumes.
+///
+/// Must be called from within a tokio runtime. This requires elevated
+/// privileges(CAP_SYS_ADMIN).
+///
+/// [receive]: fn.receive.html
+pub async fn receive_async<P: Into<PathBuf>, R: AsyncRead + Unpin>(
+    dest_dir: P,
+    mut reader: R,
+    options: &ReceiveOptions,
+) -> Result<Vec<PathBuf>> {
+    let dest_dir: PathBuf = dest_dir.into();
+    let options = options.clone();
+    let (sender, receiver) = mpsc::channel(CHANNEL_BUFFER);
+
+    let handle = tokio::task::spawn_blocking(move || {
+        let reader = ChannelReader {
+            receiver,
+            chunk: Vec::new(),
+            pos: 0,
+        };
+        receive(dest_dir, reader, &options)
+    });
+
+    loop {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let item = match reader.read(&mut chunk).await {
+            Ok(0) => break,
+            Ok(len) => {
+                chunk.truncate(len);
+                Ok(chunk)
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => Err(e),
+        };
+        let failed = item.is_err();
+        // The receive stopped early, its result tells why.
+        if sender.send(item).await.is_err() || failed {
+            break;
+        }
+    }
+    // Closing the channel ends the stream.
+    drop(sender);
+
+    common::join_blocking(handle).await
+}
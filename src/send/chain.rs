@@ -0,0 +1,219 @@
+use crate::send::receive::subvolume_root;
+use crate::subvolume::SubvolumeInfo;
+use crate::subvolume::SubvolumeIterator;
+use crate::Result;
+
+use std::path::Path;
+use std::path::PathBuf;
+
+#[cfg(feature = "json")]
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A snapshot known to a [SendChain], on the source or the target side.
+///
+/// [SendChain]: struct.SendChain.html
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct ChainSnapshot {
+    /// Path of the snapshot.
+    pub path: PathBuf,
+    /// UUID of the snapshot.
+    pub uuid: Uuid,
+    /// UUID of the subvolume this snapshot was taken of.
+    pub parent_uuid: Option<Uuid>,
+    /// UUID of the subvolume this snapshot was received from.
+    pub received_uuid: Option<Uuid>,
+    /// Transaction id of the last change of the snapshot.
+    pub ctransid: u64,
+    /// Transaction id of the subvolume this snapshot was received from.
+    pub stransid: Option<u64>,
+    /// Whether the snapshot is read-only.
+    pub read_only: bool,
+}
+
+impl ChainSnapshot {
+    /// Describe a snapshot from its information.
+    pub fn new<T: Into<PathBuf>>(path: T, info: &SubvolumeInfo) -> Self {
+        Self {
+            path: path.into(),
+            uuid: info.uuid,
+            parent_uuid: info.parent_uuid,
+            received_uuid: info.received_uuid,
+            ctransid: info.ctransid,
+            stransid: info.stransid,
+            read_only: info.is_read_only(),
+        }
+    }
+
+    /// List the snapshots beneath a directory.
+    ///
+    /// On Linux 4.18 and newer, this does not require elevated privileges.
+    pub fn scan<T: AsRef<Path>>(dir: T) -> Result<Vec<Self>> {
+        let dir = dir.as_ref().canonicalize()?;
+        let root = subvolume_root(&dir)?;
+        let mut snapshots = Vec::new();
+        for entry in SubvolumeIterator::builder_for_path(dir.clone()).iter_with_info()? {
+            let (path, info) = entry?;
+            let path = root.join(path);
+            if path.starts_with(&dir) {
+                snapshots.push(Self::new(path, &info));
+            }
+        }
+        Ok(snapshots)
+    }
+
+    /// Get the UUID and transaction id a stream of this snapshot is sent with, which a received
+    /// copy records.
+    ///
+    /// A snapshot which was itself received is sent with the identity of its origin, so chains
+    /// can be relayed.
+    pub fn send_identity(&self) -> (Uuid, u64) {
+        match (self.received_uuid, self.stransid) {
+            (Some(uuid), Some(stransid)) => (uuid, stransid),
+            _ => (self.uuid, self.ctransid),
+        }
+    }
+}
+
+/// A problem in a chain of incremental sends.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ChainProblem {
+    /// No snapshot exists on both sides, so the next send must be a full one.
+    NoCommonSnapshot,
+    /// A source snapshot with a received copy is not read-only, so it cannot be a parent.
+    SourceWritable {
+        /// Path of the source snapshot.
+        source: PathBuf,
+    },
+    /// A source snapshot changed since it was sent, so it no longer matches its received copy.
+    SourceChanged {
+        /// Path of the source snapshot.
+        source: PathBuf,
+        /// Path of the received copy.
+        target: PathBuf,
+    },
+    /// A received snapshot was made writable, so it may no longer match its source.
+    TargetWritable {
+        /// Path of the received snapshot.
+        target: PathBuf,
+    },
+}
+
+/// Tracker of the snapshots on the source and target sides of incremental sends.
+///
+/// Snapshots are matched by the UUID and transaction id a target snapshot was received from,
+/// which lets the chain pick the parent of the next incremental send and detect chains broken by
+/// changed snapshots. The target side may be scanned locally or described by records obtained
+/// from a remote host.
+#[derive(Clone, Debug, Default)]
+pub struct SendChain {
+    source: Vec<ChainSnapshot>,
+    target: Vec<ChainSnapshot>,
+}
+
+impl SendChain {
+    /// Create an empty chain.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a chain from the snapshots beneath a source and a target directory.
+    pub fn scan<P: AsRef<Path>, Q: AsRef<Path>>(source_dir: P, target_dir: Q) -> Result<Self> {
+        Ok(Self {
+            source: ChainSnapshot::scan(source_dir)?,
+            target: ChainSnapshot::scan(target_dir)?,
+        })
+    }
+
+    /// Record a snapshot on the source side.
+    pub fn add_source(&mut self, snapshot: ChainSnapshot) {
+        self.source.push(snapshot);
+    }
+
+    /// Record a snapshot on the target side.
+    pub fn add_target(&mut self, snapshot: ChainSnapshot) {
+        self.target.push(snapshot);
+    }
+
+    /// Get the snapshots on the source side.
+    pub fn source(&self) -> &[ChainSnapshot] {
+        &self.source
+    }
+
+    /// Get the snapshots on the target side.
+    pub fn target(&self) -> &[ChainSnapshot] {
+        &self.target
+    }
+
+    /// Get the received copy of a source snapshot on the target side, if it is intact.
+    pub fn received(&self, source: &ChainSnapshot) -> Option<&ChainSnapshot> {
+        let (uuid, transid) = source.send_identity();
+        self.target.iter().find(|target| {
+            target.read_only
+                && target.received_uuid == Some(uuid)
+                && target.stransid == Some(transid)
+        })
+    }
+
+    /// Get the source snapshots which have an intact received copy, i.e. which can be the parent
+    /// of an incremental send.
+    pub fn common(&self) -> Vec<&ChainSnapshot> {
+        self.source
+            .iter()
+            .filter(|source| source.read_only && self.received(source).is_some())
+            .collect()
+    }
+
+    /// Pick the parent for an incremental send of a snapshot, or `None` if a full send is needed.
+    ///
+    /// The newest common snapshot not newer than the snapshot is picked, preferring snapshots of
+    /// the same subvolume.
+    pub fn best_parent(&self, snapshot: &ChainSnapshot) -> Option<&ChainSnapshot> {
+        self.common()
+            .into_iter()
+            .filter(|candidate| {
+                candidate.uuid != snapshot.uuid && candidate.ctransid <= snapshot.ctransid
+            })
+            .max_by_key(|candidate| {
+                let related = snapshot.parent_uuid.is_some()
+                    && (candidate.parent_uuid == snapshot.parent_uuid
+                        || Some(candidate.uuid) == snapshot.parent_uuid);
+                (related, candidate.ctransid)
+            })
+    }
+
+    /// Check the chain for problems.
+    pub fn check(&self) -> Vec<ChainProblem> {
+        let mut problems = Vec::new();
+        for source in &self.source {
+            let (uuid, transid) = source.send_identity();
+            for target in &self.target {
+                if target.received_uuid != Some(uuid) {
+                    continue;
+                }
+                if target.stransid != Some(transid) {
+                    problems.push(ChainProblem::SourceChanged {
+                        source: source.path.clone(),
+                        target: target.path.clone(),
+                    });
+                } else if !source.read_only {
+                    problems.push(ChainProblem::SourceWritable {
+                        source: source.path.clone(),
+                    });
+                }
+            }
+        }
+        for target in &self.target {
+            if target.received_uuid.is_some() && !target.read_only {
+                problems.push(ChainProblem::TargetWritable {
+                    target: target.path.clone(),
+                });
+            }
+        }
+        if self.common().is_empty() {
+            problems.push(ChainProblem::NoCommonSnapshot);
+        }
+        problems
+    }
+}
@@ -0,0 +1,21 @@
+//! Btrfs send streams
+
+#[cfg(feature = "tokio")]
+mod async_io;
+mod chain;
+mod progress;
+mod receive;
+mod sender;
+mod stream;
+mod transport;
+mod verify;
+
+#[cfg(feature = "tokio")]
+pub use async_io::*;
+pub use chain::*;
+pub use progress::*;
+pub use receive::*;
+pub use sender::*;
+pub use stream::*;
+pub use transport::*;
+pub use verify::*;
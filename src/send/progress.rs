@@ -0,0 +1,93 @@
+use crate::progress::Progress;
+use crate::rate_limit::Limiter;
+use crate::rate_limit::RateLimit;
+use crate::send::receive::ReceiveCheckpoint;
+
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Progress of a send or receive, passed to the progress callback.
+///
+/// Used with [send_with_progress] and [receive_with_progress].
+///
+/// [send_with_progress]: fn.send_with_progress.html
+/// [receive_with_progress]: fn.receive_with_progress.html
+#[derive(Clone, Debug, Default)]
+pub struct TransferProgress {
+    /// Size of the stream transferred so far, in bytes.
+    pub bytes: u64,
+    /// Number of commands of the stream sent or applied so far.
+    pub commands: u64,
+    /// Path of the last command, relative to its subvolume.
+    pub path: Option<PathBuf>,
+    /// Time elapsed since the transfer started.
+    pub elapsed: Duration,
+    /// Transfer rate over the last few seconds, in bytes per second.
+    pub throughput: f64,
+    /// For a receive, the point to resume from if it is interrupted after this command.
+    pub checkpoint: Option<ReceiveCheckpoint>,
+}
+
+impl TransferProgress {
+    /// Get the average transfer rate since the transfer started, in bytes per second.
+    pub fn rate(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.bytes as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Progress for TransferProgress {
+    fn phase(&self) -> &'static str {
+        "transferring"
+    }
+
+    /// Get the number of commands transferred so far.
+    fn items_done(&self) -> u64 {
+        self.commands
+    }
+
+    fn bytes_done(&self) -> u64 {
+        self.bytes
+    }
+
+    fn current_path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn throughput(&self) -> Option<f64> {
+        Some(self.throughput)
+    }
+}
+
+/// Reader counting and rate limiting the bytes read through it.
+pub(crate) struct ThrottledReader<R> {
+    inner: R,
+    pub(crate) limiter: Limiter,
+    pub(crate) bytes: u64,
+}
+
+impl<R> ThrottledReader<R> {
+    pub(crate) fn new(inner: R, limit: Option<RateLimit>) -> Self {
+        Self {
+            inner,
+            limiter: Limiter::new(limit),
+            bytes: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.bytes += len as u64;
+        self.limiter.acquire(len as u64);
+        Ok(len)
+    }
+}
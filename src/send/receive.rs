@@ -0,0 +1,776 @@
+use crate::bindings;
+use crate::cancel;
+use crate::cancel::CancelToken;
+use crate::common;
+use crate::error::LibError;
+use crate::error::LibErrorCode;
+use crate::extent;
+use crate::ioctl;
+use crate::rate_limit::RateLimit;
+use crate::send::progress::ThrottledReader;
+use crate::send::progress::TransferProgress;
+use crate::send::stream::invalid;
+use crate::send::stream::Command;
+use crate::send::stream::SendStreamReader;
+use crate::subvolume::Subvolume;
+use crate::subvolume::SubvolumeIterator;
+use crate::Result;
+
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::ops::ControlFlow;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use bindings::btrfs_util_create_snapshot;
+use bindings::btrfs_util_set_subvolume_read_only;
+
+use chrono::NaiveDateTime;
+#[cfg(feature = "json")]
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Inode number of the root directory of a subvolume.
+const BTRFS_FIRST_FREE_OBJECTID: u64 = 256;
+
+/// Btrfs inode flags, as sent in streams, and the matching `chattr` flags.
+const INODE_FLAGS: [(u64, libc::c_int); 9] = [
+    (1 << 1, 0x0080_0000),  // NODATACOW, FS_NOCOW_FL
+    (1 << 3, 0x0000_0400),  // NOCOMPRESS, FS_NOCOMP_FL
+    (1 << 5, 0x0000_0008),  // SYNC, FS_SYNC_FL
+    (1 << 6, 0x0000_0010),  // IMMUTABLE, FS_IMMUTABLE_FL
+    (1 << 7, 0x0000_0020),  // APPEND, FS_APPEND_FL
+    (1 << 8, 0x0000_0040),  // NODUMP, FS_NODUMP_FL
+    (1 << 9, 0x0000_0080),  // NOATIME, FS_NOATIME_FL
+    (1 << 10, 0x0001_0000), // DIRSYNC, FS_DIRSYNC_FL
+    (1 << 11, 0x0000_0004), // COMPRESS, FS_COMPR_FL
+];
+/// `chattr` flags which prevent further changes: FS_IMMUTABLE_FL and FS_APPEND_FL.
+const DEFERRED_FLAGS: libc::c_int = 0x0000_0030;
+
+/// Options for a receive.
+///
+/// Used with [receive].
+///
+/// [receive]: fn.receive.html
+#[derive(Clone, Debug, Default)]
+pub struct ReceiveOptions {
+    pub(crate) skip_checksums: bool,
+    pub(crate) rate_limit: Option<RateLimit>,
+    pub(crate) resume: Option<ReceiveCheckpoint>,
+    pub(crate) cancel: Option<CancelToken>,
+}
+
+impl ReceiveOptions {
+    /// Create the default receive options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Do not verify the checksums of the stream commands, e.g. for a stream which is known to
+    /// be intact.
+    pub fn skip_checksums(mut self, skip_checksums: bool) -> Self {
+        self.skip_checksums = skip_checksums;
+        self
+    }
+
+    /// Limit the rate the stream is read at, e.g. in bytes per second.
+    pub fn rate_limit<L: Into<RateLimit>>(mut self, limit: L) -> Self {
+        self.rate_limit = Some(limit.into());
+        self
+    }
+
+    /// Resume an interrupted receive from a checkpoint.
+    ///
+    /// The stream must be restarted with [SendOptions::resume_from] at the offset of the
+    /// checkpoint, so it starts with the same subvolume command, which is checked against the
+    /// checkpoint, followed by the commands which were not applied yet.
+    ///
+    /// [SendOptions::resume_from]: struct.SendOptions.html#method.resume_from
+    pub fn resume(mut self, checkpoint: ReceiveCheckpoint) -> Self {
+        self.resume = Some(checkpoint);
+        self
+    }
+
+    /// Stop the receive between two commands when a token is cancelled, failing with
+    /// [BtrfsUtilError::Cancelled]. The subvolume being received is left writable, and can be
+    /// resumed from the last checkpoint reported.
+    ///
+    /// [BtrfsUtilError::Cancelled]: ../error/enum.BtrfsUtilError.html#variant.Cancelled
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+}
+
+/// Point an interrupted receive can be resumed from.
+///
+/// Reported by [receive_with_progress] after each command applied to a subvolume, and used with
+/// [ReceiveOptions::resume].
+///
+/// [receive_with_progress]: fn.receive_with_progress.html
+/// [ReceiveOptions::resume]: struct.ReceiveOptions.html#method.resume
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct ReceiveCheckpoint {
+    /// Offset in the stream after the last command applied, in bytes.
+    pub offset: u64,
+    /// Number of commands applied.
+    pub commands: u64,
+    /// Path of the subvolume being received.
+    pub subvolume: PathBuf,
+    /// UUID of the sent subvolume.
+    pub uuid: Uuid,
+    /// Transaction id of the last change of the sent subvolume.
+    pub ctransid: u64,
+}
+
+/// The subvolume being received.
+struct Current {
+    path: PathBuf,
+    /// Root directory of the subvolume, which the paths of the stream are resolved beneath.
+    root: File,
+    uuid: Uuid,
+    ctransid: u64,
+}
+
+impl Current {
+    fn new(path: PathBuf, uuid: Uuid, ctransid: u64) -> Result<Self> {
+        let root = File::open(&path)?;
+        Ok(Self {
+            path,
+            root,
+            uuid,
+            ctransid,
+        })
+    }
+}
+
+/// A subvolume the parent and clone sources of incremental streams can be found in.
+struct Source {
+    path: PathBuf,
+    uuid: Uuid,
+    received_uuid: Option<Uuid>,
+    stransid: Option<u64>,
+}
+
+/// A file of the subvolume being received, as the directory containing it and its name.
+///
+/// The directory is opened without following symlinks, so a stream cannot escape the subvolume
+/// through the symlinks it created.
+struct Entry {
+    dir: File,
+    name: CString,
+}
+
+impl Entry {
+    /// Find a path of the stream beneath the root directory of a subvolume, refusing paths which
+    /// escape it or go through symlinks. The empty path is the root directory itself.
+    fn beneath(root: &File, path: &Path) -> Result<Self> {
+        let unsafe_path = || invalid(format!("unsafe path {:?}", path));
+        let mut names = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => {
+                    names.push(CString::new(name.as_bytes()).map_err(|_| unsafe_path())?)
+                }
+                _ => return Err(unsafe_path()),
+            }
+        }
+        let name = match names.pop() {
+            Some(val) => val,
+            None => CString::new(".").unwrap(),
+        };
+        let mut dir = root.try_clone()?;
+        for dir_name in names {
+            dir = openat(&dir, &dir_name, libc::O_PATH | libc::O_DIRECTORY)?;
+        }
+        Ok(Self { dir, name })
+    }
+
+    fn dir(&self) -> libc::c_int {
+        self.dir.as_raw_fd()
+    }
+
+    /// Open the file, failing if it is a symlink.
+    fn open(&self, flags: libc::c_int) -> Result<File> {
+        openat(&self.dir, &self.name, flags)
+    }
+
+    /// Get a path to the file which does not go through symlinks, for calls without an `*at`
+    /// variant. The file itself is not followed by calls which do not follow symlinks.
+    fn proc_path(&self) -> CString {
+        let mut path = format!("/proc/self/fd/{}/", self.dir()).into_bytes();
+        path.extend_from_slice(self.name.as_bytes());
+        CString::new(path).unwrap()
+    }
+}
+
+/// Open a file in a directory, without following symlinks.
+fn openat(dir: &File, name: &CStr, flags: libc::c_int) -> Result<File> {
+    let fd = unsafe {
+        libc::openat(
+            dir.as_raw_fd(),
+            name.as_ptr(),
+            flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            0o600 as libc::c_uint,
+        )
+    };
+    check_errno(fd)?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// State of a receive, applying the commands of a stream to a destination directory.
+struct Receiver<'a> {
+    dest: &'a Path,
+    current: Option<Current>,
+    /// Subvolumes beneath the subvolume containing the destination directory, listed the first
+    /// time a source is looked up.
+    sources: Option<Vec<Source>>,
+    /// The file written last, kept open since writes usually come in sequence.
+    file: Option<(PathBuf, File)>,
+    /// Flags which would prevent further changes, set when the subvolume is finished.
+    deferred_flags: Vec<(PathBuf, libc::c_int)>,
+    received: Vec<PathBuf>,
+}
+
+fn cstr(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e).into())
+}
+
+/// Set the `chattr` flags of a regular file or directory. Other files are not opened, since
+/// opening a device or a FIFO has side effects.
+fn set_flags(entry: &Entry, flags: libc::c_int) -> Result<()> {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    check_errno(unsafe {
+        libc::fstatat(
+            entry.dir(),
+            entry.name.as_ptr(),
+            &mut stat,
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    })?;
+    let format = stat.st_mode & libc::S_IFMT;
+    if format != libc::S_IFREG && format != libc::S_IFDIR {
+        return Err(invalid("cannot set the flags of a special file"));
+    }
+    let file = entry.open(libc::O_RDONLY | libc::O_NONBLOCK)?;
+    let mut flags = flags;
+    unsafe { ioctl::ioctl(file.as_raw_fd(), ioctl::FS_IOC_SETFLAGS, &mut flags)? };
+    Ok(())
+}
+
+fn check_errno(ret: libc::c_int) -> Result<()> {
+    if ret < 0 {
+        Err(io::Error::last_os_error().into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Get the root directory of the subvolume containing a path.
+pub(crate) fn subvolume_root(path: &Path) -> Result<PathBuf> {
+    let mut path = fs::canonicalize(path)?;
+    while fs::metadata(&path)?.ino() != BTRFS_FIRST_FREE_OBJECTID {
+        if !path.pop() {
+            return Err(LibError::NotSubvolume.into());
+        }
+    }
+    Ok(path)
+}
+
+impl<'a> Receiver<'a> {
+    /// Continue receiving the subvolume of a checkpoint, given the first command of the
+    /// restarted stream.
+    fn resume(&mut self, checkpoint: &ReceiveCheckpoint, command: Option<Command>) -> Result<()> {
+        let (uuid, ctransid) = match command {
+            Some(Command::Subvol { uuid, ctransid, .. })
+            | Some(Command::Snapshot { uuid, ctransid, .. }) => (uuid, ctransid),
+            _ => return Err(invalid("resumed stream does not start with a subvolume")),
+        };
+        if uuid != checkpoint.uuid || ctransid != checkpoint.ctransid {
+            return Err(invalid(format!(
+                "resumed stream is of subvolume {} at transaction {}, not {} at transaction {}",
+                uuid, ctransid, checkpoint.uuid, checkpoint.ctransid
+            )));
+        }
+        if Subvolume::get(checkpoint.subvolume.clone())?.is_ro()? {
+            return Err(invalid(format!(
+                "subvolume {:?} was already received",
+                checkpoint.subvolume
+            )));
+        }
+        self.current = Some(Current::new(checkpoint.subvolume.clone(), uuid, ctransid)?);
+        Ok(())
+    }
+
+    fn current(&self) -> Result<&Current> {
+        self.current
+            .as_ref()
+            .ok_or_else(|| invalid("command outside of a subvolume"))
+    }
+
+    /// Find a path of the stream beneath the subvolume being received.
+    fn entry(&self, path: &Path) -> Result<Entry> {
+        Entry::beneath(&self.current()?.root, path)
+    }
+
+    /// Resolve the path of a new subvolume, relative to the destination directory.
+    fn resolve_subvolume(&self, path: &Path) -> Result<PathBuf> {
+        let mut components = path.components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) => Ok(self.dest.join(name)),
+            _ => Err(invalid(format!("unsafe subvolume path {:?}", path))),
+        }
+    }
+
+    /// Find a subvolume by the UUID it was sent from or its own UUID, beneath the subvolume
+    /// containing the destination directory.
+    fn find_subvolume(&mut self, uuid: Uuid, ctransid: u64) -> Result<PathBuf> {
+        if let Some(current) = &self.current {
+            if current.uuid == uuid {
+                return Ok(current.path.clone());
+            }
+        }
+        if self.sources.is_none() {
+            let root = subvolume_root(self.dest)?;
+            let mut sources = Vec::new();
+            SubvolumeIterator::builder_for_path(self.dest).for_each(|path, info| {
+                sources.push(Source {
+                    path: root.join(path),
+                    uuid: info.uuid,
+                    received_uuid: info.received_uuid,
+                    stransid: info.stransid,
+                });
+                ControlFlow::<()>::Continue(())
+            })?;
+            self.sources = Some(sources);
+        }
+        let sources = self.sources.as_ref().unwrap();
+        sources
+            .iter()
+            .find(|source| source.received_uuid == Some(uuid) && source.stransid == Some(ctransid))
+            .or_else(|| sources.iter().find(|source| source.uuid == uuid))
+            .map(|source| source.path.clone())
+            .ok_or_else(|| invalid(format!("cannot find subvolume {}", uuid)))
+    }
+
+    /// Open a file for writing, reusing the last one if it is the same.
+    fn open(&mut self, path: &Path) -> Result<&File> {
+        if self.file.as_ref().map(|(open, _)| open.as_path()) != Some(path) {
+            let file = self.entry(path)?.open(libc::O_WRONLY)?;
+            self.file = Some((path.to_path_buf(), file));
+        }
+        Ok(&self.file.as_ref().unwrap().1)
+    }
+
+    /// Record where the current subvolume was received from and make it read-only.
+    fn finish(&mut self) -> Result<()> {
+        self.file = None;
+        let current = match self.current.take() {
+            Some(val) => val,
+            None => return Ok(()),
+        };
+        for (path, flags) in self.deferred_flags.drain(..) {
+            set_flags(&Entry::beneath(&current.root, &path)?, flags)?;
+        }
+        let subvol = &current.root;
+        let mut args = ioctl::btrfs_ioctl_received_subvol_args {
+            uuid: *current.uuid.as_bytes(),
+            stransid: current.ctransid,
+            ..Default::default()
+        };
+        unsafe {
+            ioctl::ioctl(
+                subvol.as_raw_fd(),
+                ioctl::BTRFS_IOC_SET_RECEIVED_SUBVOL,
+                &mut args,
+            )?
+        };
+
+        let path_cstr = common::path_to_cstr(current.path.clone())?;
+        unsafe_wrapper!(errcode, {
+            errcode = btrfs_util_set_subvolume_read_only(path_cstr.as_ptr(), true);
+        });
+
+        if let Some(sources) = &mut self.sources {
+            sources.push(Source {
+                path: current.path.clone(),
+                uuid: Subvolume::get(current.path.clone())?.info()?.uuid,
+                received_uuid: Some(current.uuid),
+                stransid: Some(current.ctransid),
+            });
+        }
+        self.received.push(current.path);
+        Ok(())
+    }
+
+    fn apply(&mut self, command: Command) -> Result<()> {
+        match command {
+            Command::Subvol {
+                path,
+                uuid,
+                ctransid,
+            } => {
+                self.finish()?;
+                let path = self.resolve_subvolume(&path)?;
+                Subvolume::create(path.clone(), None)?;
+                self.current = Some(Current::new(path, uuid, ctransid)?);
+            }
+            Command::Snapshot {
+                path,
+                uuid,
+                ctransid,
+                clone_uuid,
+                clone_ctransid,
+            } => {
+                self.finish()?;
+                let path = self.resolve_subvolume(&path)?;
+                let parent = self.find_subvolume(clone_uuid, clone_ctransid)?;
+                let parent_cstr = common::path_to_cstr(parent)?;
+                let path_cstr = common::path_to_cstr(path.clone())?;
+                unsafe_wrapper!(errcode, {
+                    errcode = btrfs_util_create_snapshot(
+                        parent_cstr.as_ptr(),
+                        path_cstr.as_ptr(),
+                        0,
+                        std::ptr::null_mut(),
+                        std::ptr::null_mut(),
+                    );
+                });
+                self.current = Some(Current::new(path, uuid, ctransid)?);
+            }
+            Command::Mkfile { path, .. } => {
+                self.entry(&path)?
+                    .open(libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL)?;
+            }
+            Command::Mkdir { path, .. } => {
+                let entry = self.entry(&path)?;
+                check_errno(unsafe { libc::mkdirat(entry.dir(), entry.name.as_ptr(), 0o700) })?;
+            }
+            Command::Mknod {
+                path, mode, rdev, ..
+            } => {
+                let entry = self.entry(&path)?;
+                check_errno(unsafe {
+                    libc::mknodat(entry.dir(), entry.name.as_ptr(), mode, rdev)
+                })?;
+            }
+            Command::Mkfifo { path, .. } => {
+                let entry = self.entry(&path)?;
+                check_errno(unsafe { libc::mkfifoat(entry.dir(), entry.name.as_ptr(), 0o600) })?;
+            }
+            Command::Mksock { path, .. } => {
+                let entry = self.entry(&path)?;
+                check_errno(unsafe {
+                    libc::mknodat(entry.dir(), entry.name.as_ptr(), libc::S_IFSOCK | 0o600, 0)
+                })?;
+            }
+            Command::Symlink { path, target, .. } => {
+                let entry = self.entry(&path)?;
+                let target = cstr(&target)?;
+                check_errno(unsafe {
+                    libc::symlinkat(target.as_ptr(), entry.dir(), entry.name.as_ptr())
+                })?;
+            }
+            Command::Rename { from, to } => {
+                if self.file.as_ref().map(|(open, _)| open) == Some(&from) {
+                    self.file = None;
+                }
+                let (from, to) = (self.entry(&from)?, self.entry(&to)?);
+                check_errno(unsafe {
+                    libc::renameat(from.dir(), from.name.as_ptr(), to.dir(), to.name.as_ptr())
+                })?;
+            }
+            Command::Link { path, target } => {
+                let (path, target) = (self.entry(&path)?, self.entry(&target)?);
+                check_errno(unsafe {
+                    libc::linkat(
+                        target.dir(),
+                        target.name.as_ptr(),
+                        path.dir(),
+                        path.name.as_ptr(),
+                        0,
+                    )
+                })?;
+            }
+            Command::Unlink { path } => {
+                if self.file.as_ref().map(|(open, _)| open) == Some(&path) {
+                    self.file = None;
+                }
+                let entry = self.entry(&path)?;
+                check_errno(unsafe { libc::unlinkat(entry.dir(), entry.name.as_ptr(), 0) })?;
+            }
+            Command::Rmdir { path } => {
+                let entry = self.entry(&path)?;
+                check_errno(unsafe {
+                    libc::unlinkat(entry.dir(), entry.name.as_ptr(), libc::AT_REMOVEDIR)
+                })?;
+            }
+            Command::SetXattr { path, name, data } => {
+                let path = self.entry(&path)?.proc_path();
+                let name = CString::new(name).map_err(|_| invalid("bad xattr name"))?;
+                check_errno(unsafe {
+                    libc::lsetxattr(
+                        path.as_ptr(),
+                        name.as_ptr(),
+                        data.as_ptr() as *const libc::c_void,
+                        data.len(),
+                        0,
+                    )
+                })?;
+            }
+            Command::RemoveXattr { path, name } => {
+                let path = self.entry(&path)?.proc_path();
+                let name = CString::new(name).map_err(|_| invalid("bad xattr name"))?;
+                check_errno(unsafe { libc::lremovexattr(path.as_ptr(), name.as_ptr()) })?;
+            }
+            Command::Write { path, offset, data } => {
+                self.open(&path)?.write_all_at(&data, offset)?;
+            }
+            Command::Clone {
+                path,
+                offset,
+                len,
+                clone_uuid,
+                clone_ctransid,
+                clone_path,
+                clone_offset,
+            } => {
+                let source_root = File::open(self.find_subvolume(clone_uuid, clone_ctransid)?)?;
+                let source = Entry::beneath(&source_root, &clone_path)?.open(libc::O_RDONLY)?;
+                let dest = self.open(&path)?;
+                extent::reflink_range(&source, clone_offset, len, dest, offset)?;
+            }
+            Command::Truncate { path, size } => self.open(&path)?.set_len(size)?,
+            Command::Chmod { path, mode } => {
+                let entry = self.entry(&path)?;
+                check_errno(unsafe {
+                    libc::fchmodat(
+                        entry.dir(),
+                        entry.name.as_ptr(),
+                        mode as libc::mode_t,
+                        libc::AT_SYMLINK_NOFOLLOW,
+                    )
+                })?;
+            }
+            Command::Chown { path, uid, gid } => {
+                let entry = self.entry(&path)?;
+                check_errno(unsafe {
+                    libc::fchownat(
+                        entry.dir(),
+                        entry.name.as_ptr(),
+                        uid,
+                        gid,
+                        libc::AT_SYMLINK_NOFOLLOW,
+                    )
+                })?;
+            }
+            Command::Utimes {
+                path, atime, mtime, ..
+            } => {
+                let entry = self.entry(&path)?;
+                let timespec = |time: NaiveDateTime| libc::timespec {
+                    tv_sec: time.timestamp() as libc::time_t,
+                    tv_nsec: time.timestamp_subsec_nanos() as libc::c_long,
+                };
+                let times = [timespec(atime), timespec(mtime)];
+                check_errno(unsafe {
+                    libc::utimensat(
+                        entry.dir(),
+                        entry.name.as_ptr(),
+                        times.as_ptr(),
+                        libc::AT_SYMLINK_NOFOLLOW,
+                    )
+                })?;
+            }
+            Command::UpdateExtent { .. } => {
+                return Err(invalid("stream without file data cannot be received"));
+            }
+            Command::Fallocate {
+                path,
+                mode,
+                offset,
+                len,
+            } => {
+                let file = self.open(&path)?;
+                check_errno(unsafe {
+                    libc::fallocate(
+                        file.as_raw_fd(),
+                        mode as libc::c_int,
+                        offset as libc::off_t,
+                        len as libc::off_t,
+                    )
+                })?;
+            }
+            Command::SetFlags { path, flags } => {
+                let entry = self.entry(&path)?;
+                let flags = INODE_FLAGS
+                    .iter()
+                    .filter(|(inode_flag, _)| flags & inode_flag != 0)
+                    .fold(0, |acc, (_, fs_flag)| acc | fs_flag);
+                let deferred = flags & DEFERRED_FLAGS;
+                set_flags(&entry, flags & !deferred)?;
+                if deferred != 0 {
+                    self.deferred_flags.push((path, flags));
+                }
+            }
+            Command::EncodedWrite {
+                path,
+                offset,
+                unencoded_file_len,
+                unencoded_len,
+                unencoded_offset,
+                compression,
+                encryption,
+                data,
+            } => {
+                let file = self.open(&path)?;
+                let iov = libc::iovec {
+                    iov_base: data.as_ptr() as *mut libc::c_void,
+                    iov_len: data.len(),
+                };
+                let mut args = ioctl::btrfs_ioctl_encoded_io_args {
+                    iov: &iov as *const libc::iovec as u64,
+                    iovcnt: 1,
+                    offset: offset as i64,
+                    len: unencoded_file_len,
+                    unencoded_len,
+                    unencoded_offset,
+                    compression,
+                    encryption,
+                    ..Default::default()
+                };
+                unsafe {
+                    ioctl::ioctl(file.as_raw_fd(), ioctl::BTRFS_IOC_ENCODED_WRITE, &mut args)?
+                };
+            }
+            Command::End => self.finish()?,
+        }
+        Ok(())
+    }
+}
+
+/// Receive send streams from a reader into a directory, like `btrfs receive`.
+///
+/// The received subvolumes are created in the destination directory, marked with the UUID they
+/// were sent from and made read-only. For incremental streams, the parent and clone sources are
+/// looked up beneath the subvolume containing the destination directory. Returns the paths of the
+/// received subvolumes.
+///
+/// Compressed data of version 2 streams is written as it is, which requires Linux 5.18 or later.
+/// This requires elevated privileges(CAP_SYS_ADMIN).
+pub fn receive<P: AsRef<Path>, R: Read>(
+    dest_dir: P,
+    reader: R,
+    options: &ReceiveOptions,
+) -> Result<Vec<PathBuf>> {
+    receive_with_progress(dest_dir, reader, options, |_| {})
+}
+
+/// Receive send streams from a reader into a directory, reporting progress.
+///
+/// Like [receive], calling the progress callback after each command is applied. While a
+/// subvolume is being received, the progress holds a [checkpoint] to resume from if the receive
+/// is interrupted.
+///
+/// [receive]: fn.receive.html
+/// [checkpoint]: struct.TransferProgress.html#structfield.checkpoint
+pub fn receive_with_progress<P, R, F>(
+    dest_dir: P,
+    reader: R,
+    options: &ReceiveOptions,
+    mut progress: F,
+) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+    R: Read,
+    F: FnMut(&TransferProgress),
+{
+    let reader = ThrottledReader::new(reader, options.rate_limit);
+    let mut stream = SendStreamReader::new(reader).skip_checksums(options.skip_checksums);
+    let mut commands: u64 = 0;
+    // Offset in the original stream of the start of the input.
+    let mut base: u64 = 0;
+    let mut receiver = Receiver {
+        dest: dest_dir.as_ref(),
+        current: None,
+        sources: None,
+        file: None,
+        deferred_flags: Vec::new(),
+        received: Vec::new(),
+    };
+    if let Some(checkpoint) = &options.resume {
+        receiver.resume(checkpoint, stream.read_command()?)?;
+        commands = checkpoint.commands;
+        base = checkpoint.offset.saturating_sub(stream.get_ref().bytes);
+    }
+    while let Some(command) = stream.read_command()? {
+        cancel::check(options.cancel.as_ref())?;
+        let path = command.path().map(Path::to_path_buf);
+        receiver.apply(command)?;
+        commands += 1;
+        let reader = stream.get_ref();
+        let checkpoint = receiver.current.as_ref().map(|current| ReceiveCheckpoint {
+            offset: base + reader.bytes,
+            commands,
+            subvolume: current.path.clone(),
+            uuid: current.uuid,
+            ctransid: current.ctransid,
+        });
+        progress(&TransferProgress {
+            bytes: reader.bytes,
+            commands,
+            path,
+            elapsed: reader.limiter.elapsed(),
+            throughput: reader.limiter.throughput(),
+            checkpoint,
+        });
+    }
+    // Streams sent without an end command are finished at the end of the input.
+    receiver.finish()?;
+    Ok(receiver.received)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_beneath_root() {
+        let dir = std::env::temp_dir().join(format!("btrfsutil-receive-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        std::os::unix::fs::symlink("/", dir.join("link")).unwrap();
+        let root = File::open(&dir).unwrap();
+
+        assert!(Entry::beneath(&root, Path::new("sub/file")).is_ok());
+        assert_eq!(
+            Entry::beneath(&root, Path::new(""))
+                .unwrap()
+                .name
+                .as_bytes(),
+            b"."
+        );
+        for path in &["../file", "/etc/passwd", "sub/../../file", "./file"] {
+            assert!(Entry::beneath(&root, Path::new(path)).is_err(), "{}", path);
+        }
+        // Symlinks created by the stream are neither gone through nor opened.
+        assert!(Entry::beneath(&root, Path::new("link/etc/passwd")).is_err());
+        let link = Entry::beneath(&root, Path::new("link")).unwrap();
+        assert!(link.open(libc::O_RDONLY).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,307 @@
+use crate::cancel;
+use crate::cancel::CancelToken;
+use crate::filesystem::read_sysfs_u64;
+use crate::filesystem::SYSFS_BTRFS;
+use crate::ioctl;
+use crate::rate_limit::Limiter;
+use crate::rate_limit::RateLimit;
+use crate::send::progress::TransferProgress;
+use crate::send::stream::ResumeFilter;
+use crate::send::stream::StreamTracker;
+use crate::subvolume::Subvolume;
+use crate::Result;
+
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::thread;
+
+/// Options for a send.
+///
+/// Used with [send].
+///
+/// [send]: fn.send.html
+#[derive(Clone, Debug, Default)]
+pub struct SendOptions<'a> {
+    pub(crate) parent: Option<&'a Subvolume>,
+    pub(crate) clone_sources: Vec<&'a Subvolume>,
+    pub(crate) no_data: bool,
+    pub(crate) protocol: Option<u32>,
+    pub(crate) compressed_data: bool,
+    pub(crate) rate_limit: Option<RateLimit>,
+    pub(crate) resume_offset: Option<u64>,
+    pub(crate) cancel: Option<CancelToken>,
+}
+
+impl<'a> SendOptions<'a> {
+    /// Create the default send options, for a full stream of the subvolume.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Generate an incremental stream against a parent snapshot, which the receiving side must
+    /// already have, like `btrfs send -p`. The parent is also used as a clone source.
+    pub fn parent(mut self, parent: &'a Subvolume) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+
+    /// Add a snapshot whose extents the stream may reference instead of sending their data, which
+    /// the receiving side must already have, like `btrfs send -c`.
+    pub fn clone_source(mut self, clone_source: &'a Subvolume) -> Self {
+        self.clone_sources.push(clone_source);
+        self
+    }
+
+    /// Only send metadata, without file data, like `btrfs send --no-data`. The stream can be
+    /// inspected but not received.
+    pub fn no_data(mut self, no_data: bool) -> Self {
+        self.no_data = no_data;
+        self
+    }
+
+    /// Generate a stream of the given protocol version, like `btrfs send --proto`. Version 2
+    /// adds compressed data, fallocate and inode flags. The version is lowered to the highest one
+    /// the running kernel supports.
+    pub fn protocol(mut self, version: u32) -> Self {
+        self.protocol = Some(version);
+        self
+    }
+
+    /// Send compressed extents without decompressing them, like `btrfs send --compressed-data`.
+    /// Only effective with [protocol](#method.protocol) version 2 or later.
+    pub fn compressed_data(mut self, compressed_data: bool) -> Self {
+        self.compressed_data = compressed_data;
+        self
+    }
+
+    /// Limit the rate of the stream, e.g. in bytes per second.
+    pub fn rate_limit<L: Into<RateLimit>>(mut self, limit: L) -> Self {
+        self.rate_limit = Some(limit.into());
+        self
+    }
+
+    /// Restart an interrupted stream at an offset, as recorded in the [ReceiveCheckpoint] of the
+    /// receiving side.
+    ///
+    /// Only the stream header and first command are sent before the offset, for the receiving
+    /// side to check that the stream continues the same subvolume. The subvolume, parent and
+    /// options must be the same as for the interrupted stream.
+    ///
+    /// [ReceiveCheckpoint]: struct.ReceiveCheckpoint.html
+    pub fn resume_from(mut self, offset: u64) -> Self {
+        self.resume_offset = Some(offset);
+        self
+    }
+
+    /// Stop the send when a token is cancelled, failing with [BtrfsUtilError::Cancelled]. The
+    /// stream written so far is truncated at an arbitrary point.
+    ///
+    /// [BtrfsUtilError::Cancelled]: ../error/enum.BtrfsUtilError.html#variant.Cancelled
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Copy the options, borrowing the parent and clone sources from elsewhere, e.g. clones owned
+    /// by another thread.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn with_subvolumes<'b>(
+        &self,
+        parent: Option<&'b Subvolume>,
+        clone_sources: Vec<&'b Subvolume>,
+    ) -> SendOptions<'b> {
+        SendOptions {
+            parent,
+            clone_sources,
+            no_data: self.no_data,
+            protocol: self.protocol,
+            compressed_data: self.compressed_data,
+            rate_limit: self.rate_limit,
+            resume_offset: self.resume_offset,
+            cancel: self.cancel.clone(),
+        }
+    }
+
+    /// Get the protocol version to request from the kernel, if any.
+    pub(crate) fn negotiate_protocol(&self) -> Result<Option<u32>> {
+        match self.protocol {
+            Some(version) => Ok(Some(version.min(send_protocol_version()?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the flags of the send ioctl, for a negotiated protocol version.
+    pub(crate) fn flags(&self, protocol: Option<u32>) -> u64 {
+        let mut flags = 0;
+        if self.no_data {
+            flags |= ioctl::BTRFS_SEND_FLAG_NO_FILE_DATA;
+        }
+        // Kernels without versioned streams reject the flag, and send version 1 without it.
+        if protocol.is_some_and(|version| version > 1) {
+            flags |= ioctl::BTRFS_SEND_FLAG_VERSION;
+        }
+        if self.compressed_data && protocol.is_some_and(|version| version >= 2) {
+            flags |= ioctl::BTRFS_SEND_FLAG_COMPRESSED;
+        }
+        flags
+    }
+
+    /// Get the ids of the clone sources, including the parent.
+    pub(crate) fn clone_source_ids(&self) -> Vec<u64> {
+        let mut ids: Vec<u64> = self
+            .clone_sources
+            .iter()
+            .map(|subvol| subvol.id())
+            .collect();
+        if let Some(parent) = self.parent {
+            ids.push(parent.id());
+        }
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+}
+
+/// Get the highest send stream protocol version supported by the running kernel.
+///
+/// Kernels which do not report it only support version 1.
+pub fn send_protocol_version() -> Result<u32> {
+    let path = Path::new(SYSFS_BTRFS).join("features/send_stream_version");
+    match read_sysfs_u64(path) {
+        Ok(version) => Ok(version as u32),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(1),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Create a pipe, returning its read and write ends.
+pub(crate) fn pipe() -> io::Result<(File, File)> {
+    let mut fds: [RawFd; 2] = [-1; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+
+/// Generate a send stream of a read-only subvolume into a writer, like `btrfs send`.
+///
+/// The parent and clone sources of the options must be read-only snapshots on the same
+/// filesystem.
+///
+/// The kernel writes the stream into a pipe, which is copied into the writer as it is produced.
+/// Returns the number of bytes written. This requires elevated privileges(CAP_SYS_ADMIN).
+pub fn send<W: Write>(subvol: &Subvolume, options: &SendOptions, writer: W) -> Result<u64> {
+    send_with_progress(subvol, options, writer, |_| {})
+}
+
+/// Generate a send stream of a read-only subvolume into a writer, reporting progress.
+///
+/// Like [send], calling the progress callback as the stream is copied into the writer.
+///
+/// [send]: fn.send.html
+pub fn send_with_progress<W, F>(
+    subvol: &Subvolume,
+    options: &SendOptions,
+    mut writer: W,
+    mut progress: F,
+) -> Result<u64>
+where
+    W: Write,
+    F: FnMut(&TransferProgress),
+{
+    let clone_sources = options.clone_source_ids();
+    let parent_root = options.parent.map_or(0, |parent| parent.id());
+    let protocol = options.negotiate_protocol()?;
+    let flags = options.flags(protocol);
+    let file = subvol.open()?;
+    let fd = file.as_raw_fd();
+    let (mut reader, pipe_writer) = pipe()?;
+
+    thread::scope(|scope| {
+        // The write end is moved into the thread and closed once the ioctl returns, ending the
+        // stream.
+        let sender = scope.spawn(move || {
+            let mut args = ioctl::btrfs_ioctl_send_args {
+                send_fd: pipe_writer.as_raw_fd() as i64,
+                clone_sources_count: clone_sources.len() as u64,
+                clone_sources: clone_sources.as_ptr() as u64,
+                parent_root,
+                flags,
+                version: protocol.unwrap_or(0),
+                ..Default::default()
+            };
+            unsafe { ioctl::ioctl(fd, ioctl::BTRFS_IOC_SEND, &mut args) }
+        });
+
+        let mut buf: Vec<u8> = vec![0; 64 * 1024];
+        let mut total: u64 = 0;
+        let mut tracker = StreamTracker::new();
+        let mut filter = ResumeFilter::new(options.resume_offset.unwrap_or(0));
+        let limiter = Limiter::new(options.rate_limit);
+        let copied: Result<()> = loop {
+            if let Err(e) = cancel::check(options.cancel.as_ref()) {
+                break Err(e);
+            }
+            match reader.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(len) => {
+                    match filter.write_to(&buf[..len], &mut writer) {
+                        Ok(written) => total += written,
+                        Err(e) => break Err(e.into()),
+                    }
+                    tracker.feed(&buf[..len]);
+                    limiter.acquire(len as u64);
+                    progress(&TransferProgress {
+                        bytes: total,
+                        commands: tracker.commands,
+                        path: tracker.path.clone(),
+                        elapsed: limiter.elapsed(),
+                        throughput: limiter.throughput(),
+                        checkpoint: None,
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => break Err(e.into()),
+            }
+        };
+        // Closing the read end makes the kernel fail with EPIPE if the copy stopped early.
+        drop(reader);
+
+        let sent = match sender.join() {
+            Ok(val) => val,
+            Err(panic) => std::panic::resume_unwind(panic),
+        };
+        copied?;
+        sent?;
+        writer.flush()?;
+        Ok(total)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_for_protocol_versions() {
+        let options = SendOptions::default();
+        assert_eq!(options.flags(None), 0);
+        assert_eq!(options.flags(Some(1)), 0);
+        assert_eq!(options.flags(Some(2)), ioctl::BTRFS_SEND_FLAG_VERSION);
+
+        let options = SendOptions::default().no_data(true).compressed_data(true);
+        assert_eq!(options.flags(Some(1)), ioctl::BTRFS_SEND_FLAG_NO_FILE_DATA);
+        assert_eq!(
+            options.flags(Some(2)),
+            ioctl::BTRFS_SEND_FLAG_NO_FILE_DATA
+                | ioctl::BTRFS_SEND_FLAG_VERSION
+                | ioctl::BTRFS_SEND_FLAG_COMPRESSED
+        );
+    }
+}
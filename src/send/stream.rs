@@ -0,0 +1,1108 @@
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::ffi::OsStr;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+
+/// Magic string starting a send stream.
+const BTRFS_SEND_STREAM_MAGIC: &[u8; 13] = b"btrfs-stream\0";
+/// Highest send stream version understood.
+pub(crate) const BTRFS_SEND_STREAM_VERSION: u32 = 2;
+
+/// Size of the header of a command: length, command type and checksum.
+const CMD_HEADER_SIZE: usize = 10;
+/// Size of the send buffer of the kernel for version 1 streams, which bounds the commands.
+const BTRFS_SEND_BUF_SIZE_V1: usize = 64 * 1024;
+/// Size of the send buffer of the kernel for version 2 streams, which holds encoded writes of up
+/// to 128 KiB of compressed data.
+const BTRFS_SEND_BUF_SIZE_V2: usize = 16 * 1024 + 128 * 1024;
+
+const BTRFS_SEND_C_SUBVOL: u16 = 1;
+const BTRFS_SEND_C_SNAPSHOT: u16 = 2;
+const BTRFS_SEND_C_MKFILE: u16 = 3;
+const BTRFS_SEND_C_MKDIR: u16 = 4;
+const BTRFS_SEND_C_MKNOD: u16 = 5;
+const BTRFS_SEND_C_MKFIFO: u16 = 6;
+const BTRFS_SEND_C_MKSOCK: u16 = 7;
+const BTRFS_SEND_C_SYMLINK: u16 = 8;
+const BTRFS_SEND_C_RENAME: u16 = 9;
+const BTRFS_SEND_C_LINK: u16 = 10;
+const BTRFS_SEND_C_UNLINK: u16 = 11;
+const BTRFS_SEND_C_RMDIR: u16 = 12;
+const BTRFS_SEND_C_SET_XATTR: u16 = 13;
+const BTRFS_SEND_C_REMOVE_XATTR: u16 = 14;
+const BTRFS_SEND_C_WRITE: u16 = 15;
+const BTRFS_SEND_C_CLONE: u16 = 16;
+const BTRFS_SEND_C_TRUNCATE: u16 = 17;
+const BTRFS_SEND_C_CHMOD: u16 = 18;
+const BTRFS_SEND_C_CHOWN: u16 = 19;
+const BTRFS_SEND_C_UTIMES: u16 = 20;
+const BTRFS_SEND_C_END: u16 = 21;
+const BTRFS_SEND_C_UPDATE_EXTENT: u16 = 22;
+const BTRFS_SEND_C_FALLOCATE: u16 = 23;
+const BTRFS_SEND_C_FILEATTR: u16 = 24;
+const BTRFS_SEND_C_ENCODED_WRITE: u16 = 25;
+
+const BTRFS_SEND_A_UUID: u16 = 1;
+const BTRFS_SEND_A_CTRANSID: u16 = 2;
+const BTRFS_SEND_A_INO: u16 = 3;
+const BTRFS_SEND_A_SIZE: u16 = 4;
+const BTRFS_SEND_A_MODE: u16 = 5;
+const BTRFS_SEND_A_UID: u16 = 6;
+const BTRFS_SEND_A_GID: u16 = 7;
+const BTRFS_SEND_A_RDEV: u16 = 8;
+const BTRFS_SEND_A_CTIME: u16 = 9;
+const BTRFS_SEND_A_MTIME: u16 = 10;
+const BTRFS_SEND_A_ATIME: u16 = 11;
+const BTRFS_SEND_A_XATTR_NAME: u16 = 13;
+const BTRFS_SEND_A_XATTR_DATA: u16 = 14;
+const BTRFS_SEND_A_PATH: u16 = 15;
+const BTRFS_SEND_A_PATH_TO: u16 = 16;
+const BTRFS_SEND_A_PATH_LINK: u16 = 17;
+const BTRFS_SEND_A_FILE_OFFSET: u16 = 18;
+const BTRFS_SEND_A_DATA: u16 = 19;
+const BTRFS_SEND_A_CLONE_UUID: u16 = 20;
+const BTRFS_SEND_A_CLONE_CTRANSID: u16 = 21;
+const BTRFS_SEND_A_CLONE_PATH: u16 = 22;
+const BTRFS_SEND_A_CLONE_OFFSET: u16 = 23;
+const BTRFS_SEND_A_CLONE_LEN: u16 = 24;
+const BTRFS_SEND_A_FALLOCATE_MODE: u16 = 25;
+const BTRFS_SEND_A_FILEATTR: u16 = 26;
+const BTRFS_SEND_A_UNENCODED_FILE_LEN: u16 = 27;
+const BTRFS_SEND_A_UNENCODED_LEN: u16 = 28;
+const BTRFS_SEND_A_UNENCODED_OFFSET: u16 = 29;
+const BTRFS_SEND_A_COMPRESSION: u16 = 30;
+const BTRFS_SEND_A_ENCRYPTION: u16 = 31;
+
+/// Build the CRC32C lookup table at compile time.
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+/// Compute the CRC32C of send stream commands, which is seeded with zero and not inverted.
+pub(crate) fn crc32c(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc = CRC32C_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+pub(crate) fn invalid<T: Into<String>>(message: T) -> BtrfsUtilError {
+    BtrfsUtilError::InvalidStream(message.into())
+}
+
+/// A command of a send stream.
+///
+/// Paths are relative to the subvolume being received.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    /// Start of a full stream, creating a new subvolume.
+    Subvol {
+        /// Path of the subvolume, relative to the destination directory.
+        path: PathBuf,
+        /// UUID of the sent subvolume.
+        uuid: Uuid,
+        /// Transaction id of the last change of the sent subvolume.
+        ctransid: u64,
+    },
+    /// Start of an incremental stream, creating a snapshot of a parent subvolume.
+    Snapshot {
+        /// Path of the snapshot, relative to the destination directory.
+        path: PathBuf,
+        /// UUID of the sent subvolume.
+        uuid: Uuid,
+        /// Transaction id of the last change of the sent subvolume.
+        ctransid: u64,
+        /// UUID of the parent subvolume.
+        clone_uuid: Uuid,
+        /// Transaction id of the last change of the parent subvolume.
+        clone_ctransid: u64,
+    },
+    /// Create a regular file.
+    Mkfile {
+        /// Path of the file.
+        path: PathBuf,
+        /// Inode number of the file on the sending side.
+        ino: u64,
+    },
+    /// Create a directory.
+    Mkdir {
+        /// Path of the directory.
+        path: PathBuf,
+        /// Inode number of the directory on the sending side.
+        ino: u64,
+    },
+    /// Create a device node.
+    Mknod {
+        /// Path of the node.
+        path: PathBuf,
+        /// Inode number of the node on the sending side.
+        ino: u64,
+        /// Mode, including the file type.
+        mode: u32,
+        /// Device number.
+        rdev: u64,
+    },
+    /// Create a named pipe.
+    Mkfifo {
+        /// Path of the pipe.
+        path: PathBuf,
+        /// Inode number of the pipe on the sending side.
+        ino: u64,
+    },
+    /// Create a Unix socket.
+    Mksock {
+        /// Path of the socket.
+        path: PathBuf,
+        /// Inode number of the socket on the sending side.
+        ino: u64,
+    },
+    /// Create a symbolic link.
+    Symlink {
+        /// Path of the link.
+        path: PathBuf,
+        /// Inode number of the link on the sending side.
+        ino: u64,
+        /// Target of the link.
+        target: PathBuf,
+    },
+    /// Rename a file or directory.
+    Rename {
+        /// Current path.
+        from: PathBuf,
+        /// New path.
+        to: PathBuf,
+    },
+    /// Create a hard link.
+    Link {
+        /// Path of the new link.
+        path: PathBuf,
+        /// Path of the existing file.
+        target: PathBuf,
+    },
+    /// Remove a file.
+    Unlink {
+        /// Path of the file.
+        path: PathBuf,
+    },
+    /// Remove an empty directory.
+    Rmdir {
+        /// Path of the directory.
+        path: PathBuf,
+    },
+    /// Set an extended attribute.
+    SetXattr {
+        /// Path of the file.
+        path: PathBuf,
+        /// Name of the attribute.
+        name: Vec<u8>,
+        /// Value of the attribute.
+        data: Vec<u8>,
+    },
+    /// Remove an extended attribute.
+    RemoveXattr {
+        /// Path of the file.
+        path: PathBuf,
+        /// Name of the attribute.
+        name: Vec<u8>,
+    },
+    /// Write data to a file.
+    Write {
+        /// Path of the file.
+        path: PathBuf,
+        /// Offset within the file, in bytes.
+        offset: u64,
+        /// Data to write.
+        data: Vec<u8>,
+    },
+    /// Share a range of another file, or of the same file.
+    Clone {
+        /// Path of the destination file.
+        path: PathBuf,
+        /// Offset within the destination file, in bytes.
+        offset: u64,
+        /// Length of the range, in bytes.
+        len: u64,
+        /// UUID of the subvolume holding the source file.
+        clone_uuid: Uuid,
+        /// Transaction id of the last change of the subvolume holding the source file.
+        clone_ctransid: u64,
+        /// Path of the source file, relative to its subvolume.
+        clone_path: PathBuf,
+        /// Offset within the source file, in bytes.
+        clone_offset: u64,
+    },
+    /// Set the size of a file.
+    Truncate {
+        /// Path of the file.
+        path: PathBuf,
+        /// New size, in bytes.
+        size: u64,
+    },
+    /// Set the permissions of a file.
+    Chmod {
+        /// Path of the file.
+        path: PathBuf,
+        /// New mode.
+        mode: u32,
+    },
+    /// Set the owner of a file.
+    Chown {
+        /// Path of the file.
+        path: PathBuf,
+        /// New user id.
+        uid: u32,
+        /// New group id.
+        gid: u32,
+    },
+    /// Set the timestamps of a file.
+    Utimes {
+        /// Path of the file.
+        path: PathBuf,
+        /// Access time.
+        atime: NaiveDateTime,
+        /// Modification time.
+        mtime: NaiveDateTime,
+        /// Change time, which cannot be set on the receiving side.
+        ctime: NaiveDateTime,
+    },
+    /// A range of a file changed, in a stream without file data.
+    UpdateExtent {
+        /// Path of the file.
+        path: PathBuf,
+        /// Offset within the file, in bytes.
+        offset: u64,
+        /// Length of the range, in bytes.
+        len: u64,
+    },
+    /// Preallocate or punch a hole in a range of a file, since version 2.
+    Fallocate {
+        /// Path of the file.
+        path: PathBuf,
+        /// Mode of `fallocate(2)`.
+        mode: u32,
+        /// Offset within the file, in bytes.
+        offset: u64,
+        /// Length of the range, in bytes.
+        len: u64,
+    },
+    /// Set the Btrfs inode flags of a file, since version 2.
+    SetFlags {
+        /// Path of the file.
+        path: PathBuf,
+        /// Btrfs inode flags, e.g. `BTRFS_INODE_NODATACOW`.
+        flags: u64,
+    },
+    /// Write data to a file as it is stored, e.g. compressed, since version 2.
+    ///
+    /// Matches the arguments of `BTRFS_IOC_ENCODED_WRITE`.
+    EncodedWrite {
+        /// Path of the file.
+        path: PathBuf,
+        /// Offset within the file, in bytes.
+        offset: u64,
+        /// Length of the data in the file, in bytes.
+        unencoded_file_len: u64,
+        /// Length of the data once decoded, in bytes.
+        unencoded_len: u64,
+        /// Offset of the data in the file within the decoded data, in bytes.
+        unencoded_offset: u64,
+        /// Compression of the data, one of `BTRFS_ENCODED_IO_COMPRESSION_*`.
+        compression: u32,
+        /// Encryption of the data, currently always zero.
+        encryption: u32,
+        /// Encoded data.
+        data: Vec<u8>,
+    },
+    /// End of the stream.
+    End,
+}
+
+/// Attributes of a command, by type.
+struct Attributes {
+    cmd: u16,
+    values: HashMap<u16, Vec<u8>>,
+}
+
+impl Attributes {
+    fn parse(cmd: u16, mut payload: &[u8], version: u32) -> Result<Self> {
+        let mut values: HashMap<u16, Vec<u8>> = HashMap::new();
+        while !payload.is_empty() {
+            if payload.len() < 2 {
+                return Err(invalid("truncated attribute"));
+            }
+            let attr = u16::from_le_bytes([payload[0], payload[1]]);
+            // Since version 2, the data attribute has no length and spans the rest of the command.
+            if attr == BTRFS_SEND_A_DATA && version >= 2 {
+                values.insert(attr, payload[2..].to_vec());
+                break;
+            }
+            if payload.len() < 4 {
+                return Err(invalid("truncated attribute"));
+            }
+            let len = u16::from_le_bytes([payload[2], payload[3]]) as usize;
+            let value = payload
+                .get(4..4 + len)
+                .ok_or_else(|| invalid("truncated attribute"))?;
+            values.insert(attr, value.to_vec());
+            payload = &payload[4 + len..];
+        }
+        Ok(Self { cmd, values })
+    }
+
+    fn bytes(&mut self, attr: u16) -> Result<Vec<u8>> {
+        self.values.remove(&attr).ok_or_else(|| {
+            invalid(format!(
+                "missing attribute {} in command {}",
+                attr, self.cmd
+            ))
+        })
+    }
+
+    fn u64(&mut self, attr: u16) -> Result<u64> {
+        let bytes = self.bytes(attr)?;
+        match bytes.len() {
+            8 => Ok(u64::from_le_bytes(bytes[..].try_into().unwrap())),
+            4 => Ok(u32::from_le_bytes(bytes[..].try_into().unwrap()) as u64),
+            _ => Err(invalid(format!("bad integer attribute {}", attr))),
+        }
+    }
+
+    fn u32(&mut self, attr: u16) -> Result<u32> {
+        Ok(self.u64(attr)? as u32)
+    }
+
+    /// Get an integer attribute which is left out when zero.
+    fn u32_or_zero(&mut self, attr: u16) -> Result<u32> {
+        if self.values.contains_key(&attr) {
+            self.u32(attr)
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn path(&mut self, attr: u16) -> Result<PathBuf> {
+        Ok(PathBuf::from(OsStr::from_bytes(&self.bytes(attr)?)))
+    }
+
+    fn uuid(&mut self, attr: u16) -> Result<Uuid> {
+        Uuid::from_slice(&self.bytes(attr)?)
+            .map_err(|_| invalid(format!("bad UUID attribute {}", attr)))
+    }
+
+    fn time(&mut self, attr: u16) -> Result<NaiveDateTime> {
+        let bytes = self.bytes(attr)?;
+        if bytes.len() != 12 {
+            return Err(invalid(format!("bad timestamp attribute {}", attr)));
+        }
+        let secs = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let nsecs = u32::from_le_bytes(bytes[8..].try_into().unwrap());
+        NaiveDateTime::from_timestamp_opt(secs as i64, nsecs)
+            .ok_or_else(|| invalid(format!("bad timestamp attribute {}", attr)))
+    }
+}
+
+impl Command {
+    /// Get the path a command operates on, i.e. the source of a rename, or `None` for the end of
+    /// a stream.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Command::Subvol { path, .. }
+            | Command::Snapshot { path, .. }
+            | Command::Mkfile { path, .. }
+            | Command::Mkdir { path, .. }
+            | Command::Mknod { path, .. }
+            | Command::Mkfifo { path, .. }
+            | Command::Mksock { path, .. }
+            | Command::Symlink { path, .. }
+            | Command::Link { path, .. }
+            | Command::Unlink { path }
+            | Command::Rmdir { path }
+            | Command::SetXattr { path, .. }
+            | Command::RemoveXattr { path, .. }
+            | Command::Write { path, .. }
+            | Command::Clone { path, .. }
+            | Command::Truncate { path, .. }
+            | Command::Chmod { path, .. }
+            | Command::Chown { path, .. }
+            | Command::Utimes { path, .. }
+            | Command::UpdateExtent { path, .. }
+            | Command::Fallocate { path, .. }
+            | Command::SetFlags { path, .. }
+            | Command::EncodedWrite { path, .. } => Some(path),
+            Command::Rename { from, .. } => Some(from),
+            Command::End => None,
+        }
+    }
+
+    fn parse(cmd: u16, payload: &[u8], version: u32) -> Result<Self> {
+        let mut attrs = Attributes::parse(cmd, payload, version)?;
+        let a = &mut attrs;
+        let command = match cmd {
+            BTRFS_SEND_C_SUBVOL => Command::Subvol {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                uuid: a.uuid(BTRFS_SEND_A_UUID)?,
+                ctransid: a.u64(BTRFS_SEND_A_CTRANSID)?,
+            },
+            BTRFS_SEND_C_SNAPSHOT => Command::Snapshot {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                uuid: a.uuid(BTRFS_SEND_A_UUID)?,
+                ctransid: a.u64(BTRFS_SEND_A_CTRANSID)?,
+                clone_uuid: a.uuid(BTRFS_SEND_A_CLONE_UUID)?,
+                clone_ctransid: a.u64(BTRFS_SEND_A_CLONE_CTRANSID)?,
+            },
+            BTRFS_SEND_C_MKFILE => Command::Mkfile {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                ino: a.u64(BTRFS_SEND_A_INO)?,
+            },
+            BTRFS_SEND_C_MKDIR => Command::Mkdir {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                ino: a.u64(BTRFS_SEND_A_INO)?,
+            },
+            BTRFS_SEND_C_MKNOD => Command::Mknod {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                ino: a.u64(BTRFS_SEND_A_INO)?,
+                mode: a.u32(BTRFS_SEND_A_MODE)?,
+                rdev: a.u64(BTRFS_SEND_A_RDEV)?,
+            },
+            BTRFS_SEND_C_MKFIFO => Command::Mkfifo {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                ino: a.u64(BTRFS_SEND_A_INO)?,
+            },
+            BTRFS_SEND_C_MKSOCK => Command::Mksock {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                ino: a.u64(BTRFS_SEND_A_INO)?,
+            },
+            BTRFS_SEND_C_SYMLINK => Command::Symlink {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                ino: a.u64(BTRFS_SEND_A_INO)?,
+                target: a.path(BTRFS_SEND_A_PATH_LINK)?,
+            },
+            BTRFS_SEND_C_RENAME => Command::Rename {
+                from: a.path(BTRFS_SEND_A_PATH)?,
+                to: a.path(BTRFS_SEND_A_PATH_TO)?,
+            },
+            BTRFS_SEND_C_LINK => Command::Link {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                target: a.path(BTRFS_SEND_A_PATH_LINK)?,
+            },
+            BTRFS_SEND_C_UNLINK => Command::Unlink {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+            },
+            BTRFS_SEND_C_RMDIR => Command::Rmdir {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+            },
+            BTRFS_SEND_C_SET_XATTR => Command::SetXattr {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                name: a.bytes(BTRFS_SEND_A_XATTR_NAME)?,
+                data: a.bytes(BTRFS_SEND_A_XATTR_DATA)?,
+            },
+            BTRFS_SEND_C_REMOVE_XATTR => Command::RemoveXattr {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                name: a.bytes(BTRFS_SEND_A_XATTR_NAME)?,
+            },
+            BTRFS_SEND_C_WRITE => Command::Write {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                offset: a.u64(BTRFS_SEND_A_FILE_OFFSET)?,
+                data: a.bytes(BTRFS_SEND_A_DATA)?,
+            },
+            BTRFS_SEND_C_CLONE => Command::Clone {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                offset: a.u64(BTRFS_SEND_A_FILE_OFFSET)?,
+                len: a.u64(BTRFS_SEND_A_CLONE_LEN)?,
+                clone_uuid: a.uuid(BTRFS_SEND_A_CLONE_UUID)?,
+                clone_ctransid: a.u64(BTRFS_SEND_A_CLONE_CTRANSID)?,
+                clone_path: a.path(BTRFS_SEND_A_CLONE_PATH)?,
+                clone_offset: a.u64(BTRFS_SEND_A_CLONE_OFFSET)?,
+            },
+            BTRFS_SEND_C_TRUNCATE => Command::Truncate {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                size: a.u64(BTRFS_SEND_A_SIZE)?,
+            },
+            BTRFS_SEND_C_CHMOD => Command::Chmod {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                mode: a.u32(BTRFS_SEND_A_MODE)?,
+            },
+            BTRFS_SEND_C_CHOWN => Command::Chown {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                uid: a.u32(BTRFS_SEND_A_UID)?,
+                gid: a.u32(BTRFS_SEND_A_GID)?,
+            },
+            BTRFS_SEND_C_UTIMES => Command::Utimes {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                atime: a.time(BTRFS_SEND_A_ATIME)?,
+                mtime: a.time(BTRFS_SEND_A_MTIME)?,
+                ctime: a.time(BTRFS_SEND_A_CTIME)?,
+            },
+            BTRFS_SEND_C_UPDATE_EXTENT => Command::UpdateExtent {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                offset: a.u64(BTRFS_SEND_A_FILE_OFFSET)?,
+                len: a.u64(BTRFS_SEND_A_SIZE)?,
+            },
+            BTRFS_SEND_C_FALLOCATE if version >= 2 => Command::Fallocate {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                mode: a.u32(BTRFS_SEND_A_FALLOCATE_MODE)?,
+                offset: a.u64(BTRFS_SEND_A_FILE_OFFSET)?,
+                len: a.u64(BTRFS_SEND_A_SIZE)?,
+            },
+            BTRFS_SEND_C_FILEATTR if version >= 2 => Command::SetFlags {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                flags: a.u64(BTRFS_SEND_A_FILEATTR)?,
+            },
+            BTRFS_SEND_C_ENCODED_WRITE if version >= 2 => Command::EncodedWrite {
+                path: a.path(BTRFS_SEND_A_PATH)?,
+                offset: a.u64(BTRFS_SEND_A_FILE_OFFSET)?,
+                unencoded_file_len: a.u64(BTRFS_SEND_A_UNENCODED_FILE_LEN)?,
+                unencoded_len: a.u64(BTRFS_SEND_A_UNENCODED_LEN)?,
+                unencoded_offset: a.u64(BTRFS_SEND_A_UNENCODED_OFFSET)?,
+                compression: a.u32_or_zero(BTRFS_SEND_A_COMPRESSION)?,
+                encryption: a.u32_or_zero(BTRFS_SEND_A_ENCRYPTION)?,
+                data: a.bytes(BTRFS_SEND_A_DATA)?,
+            },
+            BTRFS_SEND_C_END => Command::End,
+            _ => return Err(invalid(format!("unknown command {}", cmd))),
+        };
+        Ok(command)
+    }
+}
+
+/// Read exactly enough bytes to fill a buffer, returning `false` on a clean end of file before
+/// the first byte.
+fn read_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(invalid("unexpected end of stream")),
+            Ok(len) => filled += len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+/// Parser of send streams, reading commands one at a time from a reader.
+///
+/// Several streams may be concatenated, as produced when sending several subvolumes at once.
+/// Nothing is applied to the filesystem, so streams can be indexed or validated without a Btrfs
+/// filesystem at hand.
+///
+/// Also an [Iterator] over the commands, which ends after the first error.
+///
+/// [Iterator]: https://doc.rust-lang.org/std/iter/trait.Iterator.html
+pub struct SendStreamReader<R> {
+    reader: R,
+    /// Version of the current stream, or `None` before its header.
+    version: Option<u32>,
+    verify_checksums: bool,
+    payload: Vec<u8>,
+    failed: bool,
+}
+
+impl<R: Read> SendStreamReader<R> {
+    /// Create a parser reading a stream, verifying the checksums of the commands.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            version: None,
+            verify_checksums: true,
+            payload: Vec::new(),
+            failed: false,
+        }
+    }
+
+    /// Skip the verification of the checksums of the commands.
+    pub fn skip_checksums(mut self, skip: bool) -> Self {
+        self.verify_checksums = !skip;
+        self
+    }
+
+    /// Get the version of the stream being read, or `None` before its header or between
+    /// concatenated streams.
+    pub fn version(&self) -> Option<u32> {
+        self.version
+    }
+
+    /// Get a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Get back the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Read the next command, or `None` at the end of the input.
+    pub fn read_command(&mut self) -> Result<Option<Command>> {
+        let version = match self.version {
+            Some(val) => val,
+            None => {
+                let mut header = [0u8; 17];
+                if !read_or_eof(&mut self.reader, &mut header)? {
+                    return Ok(None);
+                }
+                if &header[..13] != BTRFS_SEND_STREAM_MAGIC {
+                    return Err(invalid("bad magic"));
+                }
+                let version = u32::from_le_bytes(header[13..].try_into().unwrap());
+                if version == 0 || version > BTRFS_SEND_STREAM_VERSION {
+                    return Err(invalid(format!("unsupported version {}", version)));
+                }
+                self.version = Some(version);
+                version
+            }
+        };
+
+        let mut header = [0u8; CMD_HEADER_SIZE];
+        if !read_or_eof(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let cmd = u16::from_le_bytes(header[4..6].try_into().unwrap());
+        let crc = u32::from_le_bytes(header[6..].try_into().unwrap());
+        let max_len = match version {
+            1 => BTRFS_SEND_BUF_SIZE_V1,
+            _ => BTRFS_SEND_BUF_SIZE_V2,
+        };
+        // Checked before the checksum, so that a corrupt length does not allocate gigabytes.
+        if len > max_len {
+            return Err(invalid(format!(
+                "command {} is too long: {} bytes",
+                cmd, len
+            )));
+        }
+        self.payload.resize(len, 0);
+        if len > 0 && !read_or_eof(&mut self.reader, &mut self.payload)? {
+            return Err(invalid("unexpected end of stream"));
+        }
+
+        if self.verify_checksums {
+            header[6..].copy_from_slice(&[0; 4]);
+            if crc32c(crc32c(0, &header), &self.payload) != crc {
+                return Err(invalid(format!("bad checksum of command {}", cmd)));
+            }
+        }
+
+        let command = Command::parse(cmd, &self.payload, version)?;
+        if command == Command::End {
+            // Another stream may follow.
+            self.version = None;
+        }
+        Ok(Some(command))
+    }
+}
+
+/// Follower of a stream as it is produced, counting its commands without parsing them.
+pub(crate) struct StreamTracker {
+    /// Stream header or command read so far.
+    pending: Vec<u8>,
+    /// Whether the header of the current stream was read.
+    in_stream: bool,
+    pub(crate) commands: u64,
+    /// Path of the last command, if its first attribute is a path.
+    pub(crate) path: Option<PathBuf>,
+}
+
+impl StreamTracker {
+    pub(crate) fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+            in_stream: false,
+            commands: 0,
+            path: None,
+        }
+    }
+
+    /// Follow the next bytes of the stream.
+    pub(crate) fn feed(&mut self, mut data: &[u8]) {
+        loop {
+            let needed = if !self.in_stream {
+                BTRFS_SEND_STREAM_MAGIC.len() + 4
+            } else if self.pending.len() < CMD_HEADER_SIZE {
+                CMD_HEADER_SIZE
+            } else {
+                CMD_HEADER_SIZE + u32::from_le_bytes(self.pending[..4].try_into().unwrap()) as usize
+            };
+            if self.pending.len() < needed {
+                if data.is_empty() {
+                    return;
+                }
+                let len = (needed - self.pending.len()).min(data.len());
+                self.pending.extend_from_slice(&data[..len]);
+                data = &data[len..];
+                continue;
+            }
+
+            if self.in_stream {
+                let cmd = u16::from_le_bytes([self.pending[4], self.pending[5]]);
+                self.commands += 1;
+                if cmd == BTRFS_SEND_C_END {
+                    self.in_stream = false;
+                }
+                let attrs = &self.pending[CMD_HEADER_SIZE..];
+                if attrs.len() >= 4 && u16::from_le_bytes([attrs[0], attrs[1]]) == BTRFS_SEND_A_PATH
+                {
+                    let len = u16::from_le_bytes([attrs[2], attrs[3]]) as usize;
+                    if let Some(path) = attrs.get(4..4 + len) {
+                        self.path = Some(PathBuf::from(OsStr::from_bytes(path)));
+                    }
+                }
+            } else {
+                self.in_stream = true;
+            }
+            self.pending.clear();
+        }
+    }
+}
+
+/// Filter restarting a stream at an offset, keeping its header and first command so the
+/// receiving side can check which subvolume it continues.
+pub(crate) struct ResumeFilter {
+    offset: u64,
+    /// Offset in the stream of the next byte.
+    position: u64,
+    /// Start of the stream, up to the length of the first command.
+    head: Vec<u8>,
+    /// Length of the stream header and first command.
+    prefix_len: Option<u64>,
+}
+
+impl ResumeFilter {
+    pub(crate) fn new(offset: u64) -> Self {
+        Self {
+            offset,
+            position: 0,
+            head: Vec::new(),
+            prefix_len: None,
+        }
+    }
+
+    /// Write the next bytes of the stream which are kept, returning how many were written.
+    pub(crate) fn write_to<W: Write>(
+        &mut self,
+        mut data: &[u8],
+        writer: &mut W,
+    ) -> io::Result<u64> {
+        // The stream header and the length of the first command.
+        const HEAD_LEN: usize = BTRFS_SEND_STREAM_MAGIC.len() + 4 + 4;
+        let mut written = 0;
+        while !data.is_empty() {
+            let keep_until = self.prefix_len.unwrap_or(HEAD_LEN as u64);
+            let skip_until = self.offset.max(keep_until);
+            let (until, keep) = if self.position < keep_until {
+                (keep_until, true)
+            } else if self.position < skip_until {
+                (skip_until, false)
+            } else {
+                (u64::MAX, true)
+            };
+            let len = ((until - self.position) as usize).min(data.len());
+            if keep {
+                writer.write_all(&data[..len])?;
+                written += len as u64;
+            }
+            if self.prefix_len.is_none() {
+                self.head.extend_from_slice(&data[..len]);
+                if self.head.len() == HEAD_LEN {
+                    let cmd_len = u32::from_le_bytes(self.head[HEAD_LEN - 4..].try_into().unwrap());
+                    self.prefix_len =
+                        Some((HEAD_LEN - 4 + CMD_HEADER_SIZE) as u64 + cmd_len as u64);
+                }
+            }
+            self.position += len as u64;
+            data = &data[len..];
+        }
+        Ok(written)
+    }
+}
+
+impl<R: Read> Iterator for SendStreamReader<R> {
+    type Item = Result<Command>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.read_command() {
+            Ok(command) => command.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: [u8; 16] = [7; 16];
+
+    /// Encode a command with its checksum.
+    fn command(cmd: u16, attrs: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut payload = Vec::new();
+        for (attr, value) in attrs {
+            payload.extend_from_slice(&attr.to_le_bytes());
+            payload.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            payload.extend_from_slice(value);
+        }
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&cmd.to_le_bytes());
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(&payload);
+        let crc = crc32c(0, &buf);
+        buf[6..10].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    /// Encode a version 1 stream creating a subvolume holding a file.
+    fn stream() -> Vec<u8> {
+        let mut buf = BTRFS_SEND_STREAM_MAGIC.to_vec();
+        buf.extend_from_slice(&1u32.to_le_bytes());
+        buf.extend(command(
+            BTRFS_SEND_C_SUBVOL,
+            &[
+                (BTRFS_SEND_A_PATH, b"vol"),
+                (BTRFS_SEND_A_UUID, &UUID),
+                (BTRFS_SEND_A_CTRANSID, &42u64.to_le_bytes()),
+            ],
+        ));
+        buf.extend(command(
+            BTRFS_SEND_C_MKFILE,
+            &[
+                (BTRFS_SEND_A_PATH, b"file"),
+                (BTRFS_SEND_A_INO, &257u64.to_le_bytes()),
+            ],
+        ));
+        buf.extend(command(
+            BTRFS_SEND_C_WRITE,
+            &[
+                (BTRFS_SEND_A_PATH, b"file"),
+                (BTRFS_SEND_A_FILE_OFFSET, &0u64.to_le_bytes()),
+                (BTRFS_SEND_A_DATA, b"hello"),
+            ],
+        ));
+        buf.extend(command(
+            BTRFS_SEND_C_CHMOD,
+            &[
+                (BTRFS_SEND_A_PATH, b"file"),
+                (BTRFS_SEND_A_MODE, &0o644u64.to_le_bytes()),
+            ],
+        ));
+        buf.extend(command(BTRFS_SEND_C_END, &[]));
+        buf
+    }
+
+    fn expected() -> Vec<Command> {
+        vec![
+            Command::Subvol {
+                path: PathBuf::from("vol"),
+                uuid: Uuid::from_bytes(UUID),
+                ctransid: 42,
+            },
+            Command::Mkfile {
+                path: PathBuf::from("file"),
+                ino: 257,
+            },
+            Command::Write {
+                path: PathBuf::from("file"),
+                offset: 0,
+                data: b"hello".to_vec(),
+            },
+            Command::Chmod {
+                path: PathBuf::from("file"),
+                mode: 0o644,
+            },
+            Command::End,
+        ]
+    }
+
+    #[test]
+    fn crc32c_check_value() {
+        // The check value of CRC-32C, which is seeded with ones and inverted.
+        assert_eq!(!crc32c(!0, b"123456789"), 0xe306_9283);
+        assert_eq!(crc32c(0, b""), 0);
+    }
+
+    #[test]
+    fn read_stream() {
+        let input = stream();
+        let mut reader = SendStreamReader::new(&input[..]);
+        assert_eq!(reader.version(), None);
+        assert_eq!(reader.read_command().unwrap(), Some(expected()[0].clone()));
+        assert_eq!(reader.version(), Some(1));
+        let rest: Vec<Command> = reader.map(|command| command.unwrap()).collect();
+        assert_eq!(rest, expected()[1..]);
+    }
+
+    #[test]
+    fn read_concatenated_streams() {
+        let input = [stream(), stream()].concat();
+        let commands: Vec<Command> = SendStreamReader::new(&input[..])
+            .map(|command| command.unwrap())
+            .collect();
+        assert_eq!(commands, [expected(), expected()].concat());
+    }
+
+    #[test]
+    fn read_version_2_data() {
+        let mut input = BTRFS_SEND_STREAM_MAGIC.to_vec();
+        input.extend_from_slice(&2u32.to_le_bytes());
+        // The data attribute has no length and spans the rest of the command.
+        let mut write = command(
+            BTRFS_SEND_C_WRITE,
+            &[
+                (BTRFS_SEND_A_PATH, b"file"),
+                (BTRFS_SEND_A_FILE_OFFSET, &4096u64.to_le_bytes()),
+            ],
+        );
+        write.extend_from_slice(&BTRFS_SEND_A_DATA.to_le_bytes());
+        write.extend_from_slice(b"data");
+        let len = write.len() - CMD_HEADER_SIZE;
+        write[..4].copy_from_slice(&(len as u32).to_le_bytes());
+        write[6..10].copy_from_slice(&[0; 4]);
+        let crc = crc32c(0, &write);
+        write[6..10].copy_from_slice(&crc.to_le_bytes());
+        input.extend(write);
+
+        let mut reader = SendStreamReader::new(&input[..]);
+        assert_eq!(
+            reader.read_command().unwrap(),
+            Some(Command::Write {
+                path: PathBuf::from("file"),
+                offset: 4096,
+                data: b"data".to_vec(),
+            })
+        );
+        assert_eq!(reader.read_command().unwrap(), None);
+    }
+
+    #[test]
+    fn reject_corrupt_streams() {
+        let mut input = stream();
+        // Flip a byte of the data of the write command.
+        let pos = input.windows(5).position(|w| w == b"hello").unwrap();
+        input[pos] = b'j';
+        let result: Result<Vec<Command>> = SendStreamReader::new(&input[..]).collect();
+        assert!(matches!(result, Err(BtrfsUtilError::InvalidStream(_))));
+        let commands: Vec<Command> = SendStreamReader::new(&input[..])
+            .skip_checksums(true)
+            .map(|command| command.unwrap())
+            .collect();
+        assert_eq!(commands.len(), 5);
+
+        let truncated = &stream()[..40];
+        let result: Result<Vec<Command>> = SendStreamReader::new(truncated).collect();
+        assert!(result.is_err());
+        let result: Result<Vec<Command>> =
+            SendStreamReader::new(&b"not-a-stream\0\x01\0\0\0"[..]).collect();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn reject_oversized_commands() {
+        for (version, len) in [
+            (1u32, BTRFS_SEND_BUF_SIZE_V1 + 1),
+            (2, BTRFS_SEND_BUF_SIZE_V2 + 1),
+            (2, u32::MAX as usize),
+        ] {
+            let mut input = BTRFS_SEND_STREAM_MAGIC.to_vec();
+            input.extend_from_slice(&version.to_le_bytes());
+            input.extend_from_slice(&(len as u32).to_le_bytes());
+            input.extend_from_slice(&BTRFS_SEND_C_WRITE.to_le_bytes());
+            input.extend_from_slice(&[0; 4]);
+            let result = SendStreamReader::new(&input[..]).read_command();
+            assert!(matches!(result, Err(BtrfsUtilError::InvalidStream(_))));
+        }
+    }
+
+    #[test]
+    fn track_stream_fed_in_pieces() {
+        let input = [stream(), stream()].concat();
+        let mut tracker = StreamTracker::new();
+        for chunk in input.chunks(3) {
+            tracker.feed(chunk);
+        }
+        assert_eq!(tracker.commands, 10);
+        // The end command has no path.
+        assert_eq!(tracker.path, Some(PathBuf::from("file")));
+
+        let mut tracker = StreamTracker::new();
+        tracker.feed(&input[..input.len() / 2 - 1]);
+        assert_eq!(tracker.commands, 4);
+    }
+
+    #[test]
+    fn resume_stream_at_offset() {
+        let input = stream();
+        let header_len = BTRFS_SEND_STREAM_MAGIC.len() + 4;
+        let subvol_len =
+            CMD_HEADER_SIZE + u32::from_le_bytes(input[17..21].try_into().unwrap()) as usize;
+        let mkfile_end = header_len
+            + subvol_len
+            + CMD_HEADER_SIZE
+            + u32::from_le_bytes(input[header_len + subvol_len..][..4].try_into().unwrap())
+                as usize;
+
+        for chunk_len in &[1, 7, input.len()] {
+            let mut filter = ResumeFilter::new(mkfile_end as u64);
+            let mut output = Vec::new();
+            let mut written = 0;
+            for chunk in input.chunks(*chunk_len) {
+                written += filter.write_to(chunk, &mut output).unwrap();
+            }
+            assert_eq!(written, output.len() as u64);
+            let kept = [&input[..header_len + subvol_len], &input[mkfile_end..]].concat();
+            assert_eq!(output, kept);
+        }
+
+        let commands: Vec<Command> = {
+            let mut filter = ResumeFilter::new(mkfile_end as u64);
+            let mut output = Vec::new();
+            filter.write_to(&input, &mut output).unwrap();
+            SendStreamReader::new(io::Cursor::new(output))
+                .map(|command| command.unwrap())
+                .collect()
+        };
+        let mut expected = expected();
+        expected.remove(1);
+        assert_eq!(commands, expected);
+
+        // Offsets within the first command keep the whole stream.
+        let mut output = Vec::new();
+        ResumeFilter::new(0).write_to(&input, &mut output).unwrap();
+        assert_eq!(output, input);
+    }
+}
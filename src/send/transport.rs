@@ -0,0 +1,209 @@
+use crate::send::pipe;
+use crate::send::receive;
+use crate::send::send;
+use crate::send::ChainSnapshot;
+use crate::send::ReceiveOptions;
+use crate::send::SendChain;
+use crate::send::SendOptions;
+use crate::subvolume::Subvolume;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::thread;
+use std::thread::JoinHandle;
+
+/// A replication target which send streams are written to.
+///
+/// Implementations move streams to where they are received, e.g. over SSH or into object
+/// storage, while [replicate] picks the snapshots to send and their parents.
+///
+/// [replicate]: fn.replicate.html
+pub trait StreamTransport {
+    /// Writer of a single stream.
+    type Writer: Write;
+
+    /// Open the target, e.g. by connecting to it. Called once before any other method.
+    fn open(&mut self) -> Result<()>;
+
+    /// List the snapshots on the target. Received snapshots are matched with their source by
+    /// their received UUID and transaction id.
+    fn snapshots(&mut self) -> Result<Vec<ChainSnapshot>>;
+
+    /// Start writing the stream of a snapshot, incremental to a parent the target already has.
+    fn write_stream(
+        &mut self,
+        snapshot: &ChainSnapshot,
+        parent: Option<&ChainSnapshot>,
+    ) -> Result<Self::Writer>;
+
+    /// Complete a stream which was written entirely, returning the snapshot received from it.
+    fn finalize(&mut self, writer: Self::Writer) -> Result<ChainSnapshot>;
+
+    /// Discard a stream which could not be written entirely.
+    fn abort(&mut self, writer: Self::Writer) -> Result<()> {
+        drop(writer);
+        Ok(())
+    }
+}
+
+/// Replicate a read-only snapshot to a target, returning its received copy.
+///
+/// The stream is incremental to the best common snapshot of the source directory and the target,
+/// see [SendChain::best_parent], and nothing is sent if the target already has the snapshot. The
+/// snapshot reported by the target is checked to be a copy of the sent one. This requires
+/// elevated privileges(CAP_SYS_ADMIN).
+///
+/// [SendChain::best_parent]: struct.SendChain.html#method.best_parent
+pub fn replicate<P, Q, T>(snapshot: P, source_dir: Q, transport: &mut T) -> Result<ChainSnapshot>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    T: StreamTransport,
+{
+    let subvol = Subvolume::get(snapshot.as_ref())?;
+    let snapshot = ChainSnapshot::new(snapshot.as_ref(), &subvol.info()?);
+
+    transport.open()?;
+    let mut chain = SendChain::new();
+    for source in ChainSnapshot::scan(source_dir)? {
+        chain.add_source(source);
+    }
+    for target in transport.snapshots()? {
+        chain.add_target(target);
+    }
+    if let Some(received) = chain.received(&snapshot) {
+        return Ok(received.clone());
+    }
+
+    let parent = chain.best_parent(&snapshot).cloned();
+    let parent_subvol = match &parent {
+        Some(parent) => Some(Subvolume::get(parent.path.as_path())?),
+        None => None,
+    };
+    let mut options = SendOptions::new();
+    if let Some(parent_subvol) = &parent_subvol {
+        options = options.parent(parent_subvol);
+    }
+
+    let mut writer = transport.write_stream(&snapshot, parent.as_ref())?;
+    if let Err(e) = send(&subvol, &options, &mut writer) {
+        let _ = transport.abort(writer);
+        return Err(e);
+    }
+    let received = transport.finalize(writer)?;
+    let (uuid, transid) = snapshot.send_identity();
+    if received.received_uuid != Some(uuid) || received.stransid != Some(transid) {
+        return Err(BtrfsUtilError::InvalidStream(format!(
+            "target received {:?} as {:?}",
+            snapshot.path, received.received_uuid
+        )));
+    }
+    Ok(received)
+}
+
+/// A transport receiving streams into a directory on a local Btrfs filesystem.
+#[derive(Clone, Debug)]
+pub struct LocalTransport {
+    dir: PathBuf,
+    options: ReceiveOptions,
+}
+
+impl LocalTransport {
+    /// Create a transport receiving into a directory, which is created when the transport is
+    /// opened.
+    pub fn new<T: Into<PathBuf>>(dir: T) -> Self {
+        Self {
+            dir: dir.into(),
+            options: ReceiveOptions::new(),
+        }
+    }
+
+    /// Set the options streams are received with.
+    pub fn receive_options(mut self, options: ReceiveOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+/// Writer of a stream into a [LocalTransport], received on another thread as it is written.
+///
+/// [LocalTransport]: struct.LocalTransport.html
+#[derive(Debug)]
+pub struct LocalStreamWriter {
+    pipe: File,
+    receiver: JoinHandle<Result<Vec<PathBuf>>>,
+}
+
+impl LocalStreamWriter {
+    /// Close the stream and wait for it to be received.
+    fn join(self) -> Result<Vec<PathBuf>> {
+        drop(self.pipe);
+        match self.receiver.join() {
+            Ok(val) => val,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl Write for LocalStreamWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pipe.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pipe.flush()
+    }
+}
+
+impl StreamTransport for LocalTransport {
+    type Writer = LocalStreamWriter;
+
+    fn open(&mut self) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        Ok(())
+    }
+
+    fn snapshots(&mut self) -> Result<Vec<ChainSnapshot>> {
+        ChainSnapshot::scan(&self.dir)
+    }
+
+    fn write_stream(
+        &mut self,
+        _snapshot: &ChainSnapshot,
+        _parent: Option<&ChainSnapshot>,
+    ) -> Result<Self::Writer> {
+        let (reader, pipe) = pipe()?;
+        let dir = self.dir.clone();
+        let options = self.options.clone();
+        let receiver = thread::spawn(move || receive(dir, reader, &options));
+        Ok(LocalStreamWriter { pipe, receiver })
+    }
+
+    fn finalize(&mut self, writer: Self::Writer) -> Result<ChainSnapshot> {
+        let received = writer.join()?;
+        let path = match received.as_slice() {
+            [path] => path,
+            _ => {
+                return Err(BtrfsUtilError::InvalidStream(format!(
+                    "{} subvolumes received",
+                    received.len()
+                )))
+            }
+        };
+        let info = Subvolume::get(path.as_path())?.info()?;
+        Ok(ChainSnapshot::new(path.as_path(), &info))
+    }
+
+    /// Close the stream early. The receive fails, leaving the partially received subvolume in
+    /// the directory, writable and without a received UUID.
+    fn abort(&mut self, writer: Self::Writer) -> Result<()> {
+        let _ = writer.join();
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use crate::send::stream::invalid;
+use crate::send::stream::Command;
+use crate::send::stream::SendStreamReader;
+use crate::Result;
+
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+/// A subvolume sent in a stream, as found by [verify_stream].
+///
+/// [verify_stream]: fn.verify_stream.html
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamSubvolume {
+    /// Path of the subvolume, relative to the destination directory.
+    pub path: PathBuf,
+    /// UUID of the sent subvolume.
+    pub uuid: Uuid,
+    /// Transaction id of the last change of the sent subvolume.
+    pub ctransid: u64,
+    /// UUID of the parent subvolume, for an incremental stream.
+    pub parent_uuid: Option<Uuid>,
+    /// Transaction id of the last change of the parent subvolume, for an incremental stream.
+    pub parent_ctransid: Option<u64>,
+    /// Number of commands changing the subvolume.
+    pub commands: u64,
+}
+
+/// Summary of a verified send stream.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StreamSummary {
+    /// Size of the stream, in bytes.
+    pub bytes: u64,
+    /// Highest version of the concatenated streams.
+    pub version: u32,
+    /// Number of commands.
+    pub commands: u64,
+    /// Subvolumes sent, in order.
+    pub subvolumes: Vec<StreamSubvolume>,
+    /// Size of the file data written, in bytes, as sent, e.g. compressed.
+    pub data_bytes: u64,
+    /// Number of ranges cloned from other files instead of sent.
+    pub clones: u64,
+}
+
+/// Reader counting the bytes read through it and copying them into a digest.
+struct DigestReader<R, D> {
+    inner: R,
+    digest: D,
+    bytes: u64,
+}
+
+impl<R: Read, D: Write> Read for DigestReader<R, D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.digest.write_all(&buf[..len])?;
+        self.bytes += len as u64;
+        Ok(len)
+    }
+}
+
+/// Check that a path stays within the subvolume.
+fn check_path(path: &Path) -> Result<()> {
+    if path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        Ok(())
+    } else {
+        Err(invalid(format!("unsafe path {:?}", path)))
+    }
+}
+
+/// Verify a send stream without applying it, e.g. a stream stored as a backup.
+///
+/// The magic and version of each stream header, the checksum and attributes of each command, and
+/// the order of the commands are checked, and the stream must end with an end command. Returns a
+/// summary of the stream, or an [InvalidStream] error at the first problem found.
+///
+/// [InvalidStream]: ../error/enum.BtrfsUtilError.html#variant.InvalidStream
+pub fn verify_stream<R: Read>(reader: R) -> Result<StreamSummary> {
+    verify_stream_with_digest(reader, io::sink())
+}
+
+/// Verify a send stream without applying it, also computing a digest of its content.
+///
+/// Like [verify_stream], writing every byte of the stream into a digest, e.g. a hasher
+/// implementing [Write].
+///
+/// [verify_stream]: fn.verify_stream.html
+/// [Write]: https://doc.rust-lang.org/std/io/trait.Write.html
+pub fn verify_stream_with_digest<R: Read, D: Write>(reader: R, digest: D) -> Result<StreamSummary> {
+    let mut stream = SendStreamReader::new(DigestReader {
+        inner: reader,
+        digest,
+        bytes: 0,
+    });
+    let mut summary = StreamSummary::default();
+    let mut ended = false;
+
+    while let Some(command) = stream.read_command()? {
+        if let Some(version) = stream.version() {
+            summary.version = summary.version.max(version);
+        }
+        summary.commands += 1;
+        if let Some(path) = command.path() {
+            check_path(path)?;
+        }
+        match &command {
+            Command::Subvol {
+                path,
+                uuid,
+                ctransid,
+            } => summary.subvolumes.push(StreamSubvolume {
+                path: path.clone(),
+                uuid: *uuid,
+                ctransid: *ctransid,
+                parent_uuid: None,
+                parent_ctransid: None,
+                commands: 0,
+            }),
+            Command::Snapshot {
+                path,
+                uuid,
+                ctransid,
+                clone_uuid,
+                clone_ctransid,
+            } => summary.subvolumes.push(StreamSubvolume {
+                path: path.clone(),
+                uuid: *uuid,
+                ctransid: *ctransid,
+                parent_uuid: Some(*clone_uuid),
+                parent_ctransid: Some(*clone_ctransid),
+                commands: 0,
+            }),
+            _ => match (ended, summary.subvolumes.last_mut()) {
+                (false, Some(subvolume)) => subvolume.commands += 1,
+                _ => return Err(invalid("command outside of a subvolume")),
+            },
+        }
+        match &command {
+            Command::Write { data, .. } | Command::EncodedWrite { data, .. } => {
+                summary.data_bytes += data.len() as u64
+            }
+            Command::Clone { clone_path, .. } => {
+                check_path(clone_path)?;
+                summary.clones += 1;
+            }
+            _ => (),
+        }
+        ended = command == Command::End;
+    }
+
+    if summary.commands == 0 {
+        return Err(invalid("empty stream"));
+    }
+    if !ended {
+        return Err(invalid("stream ends without an end command"));
+    }
+    summary.bytes = stream.get_ref().bytes;
+    Ok(summary)
+}
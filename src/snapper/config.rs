@@ -0,0 +1,177 @@
+use crate::error::ParseError;
+use crate::scheduler::RetentionPolicy;
+use crate::Result;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Directory of the snapper configurations.
+pub const SNAPPER_CONFIG_DIR: &str = "/etc/snapper/configs";
+
+/// A snapper configuration, read from a file of `KEY="value"` lines.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SnapperConfig {
+    /// Name of the configuration, which is the name of its file.
+    pub name: String,
+    /// Path of the subvolume snapshots are taken of.
+    pub subvolume: PathBuf,
+    /// All values of the configuration, by key.
+    pub values: BTreeMap<String, String>,
+}
+
+impl SnapperConfig {
+    /// Read all snapper configurations, ordered by name.
+    pub fn list() -> Result<Vec<Self>> {
+        let mut configs = Vec::new();
+        let entries = match fs::read_dir(SNAPPER_CONFIG_DIR) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(configs),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_file() {
+                configs.push(Self::read(path)?);
+            }
+        }
+        configs.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(configs)
+    }
+
+    /// Read a snapper configuration by name.
+    pub fn get(name: &str) -> Result<Self> {
+        Self::read(Path::new(SNAPPER_CONFIG_DIR).join(name))
+    }
+
+    /// Read a snapper configuration from a file.
+    pub fn read<T: AsRef<Path>>(path: T) -> Result<Self> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut values = BTreeMap::new();
+        for line in fs::read_to_string(path)?.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| ParseError::new("snapper configuration line", line))?;
+            values.insert(key.trim().to_owned(), unquote(value.trim()));
+        }
+        let subvolume = values
+            .get("SUBVOLUME")
+            .map(PathBuf::from)
+            .ok_or_else(|| ParseError::new("snapper configuration", name.as_str()))?;
+        Ok(Self {
+            name,
+            subvolume,
+            values,
+        })
+    }
+
+    /// Get a value of the configuration.
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    /// Get the directory holding the snapshots, the `.snapshots` subvolume.
+    pub fn snapshot_dir(&self) -> PathBuf {
+        self.subvolume.join(".snapshots")
+    }
+
+    /// Check whether snapper takes timeline snapshots.
+    pub fn timeline_create(&self) -> bool {
+        self.value("TIMELINE_CREATE") == Some("yes")
+    }
+
+    /// Get the retention policy of the timeline snapshots.
+    ///
+    /// Missing or invalid limits keep no snapshot for their period. Ranges such as `"2-10"` are
+    /// reduced to their upper bound.
+    pub fn timeline_retention(&self) -> RetentionPolicy {
+        let limit = |key: &str| {
+            self.value(key)
+                .and_then(|value| value.rsplit('-').next())
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0)
+        };
+        RetentionPolicy {
+            keep_last: 0,
+            hourly: limit("TIMELINE_LIMIT_HOURLY"),
+            daily: limit("TIMELINE_LIMIT_DAILY"),
+            weekly: limit("TIMELINE_LIMIT_WEEKLY"),
+            monthly: limit("TIMELINE_LIMIT_MONTHLY"),
+            yearly: limit("TIMELINE_LIMIT_YEARLY"),
+        }
+    }
+}
+
+/// Remove the shell quotes around a configuration value.
+fn unquote(value: &str) -> String {
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+    let mut unquoted = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unquote_values() {
+        assert_eq!(unquote(r#""yes""#), "yes");
+        assert_eq!(unquote("yes"), "yes");
+        assert_eq!(unquote(r#""""#), "");
+        assert_eq!(
+            unquote(r#""a \"quoted\" \\ value""#),
+            r#"a "quoted" \ value"#
+        );
+        // A lone quote is not a pair of quotes.
+        assert_eq!(unquote(r#"""#), r#"""#);
+    }
+
+    #[test]
+    fn read_config() {
+        let dir = std::env::temp_dir().join(format!("btrfsutil-snapper-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("home");
+        fs::write(
+            &path,
+            "# subvolume to snapshot\nSUBVOLUME=\"/home\"\n\nTIMELINE_CREATE=\"yes\"\n\
+             TIMELINE_LIMIT_HOURLY=\"10\"\nTIMELINE_LIMIT_DAILY=\"2-7\"\n\
+             TIMELINE_LIMIT_MONTHLY=\"many\"\n",
+        )
+        .unwrap();
+        let config = SnapperConfig::read(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(config.name, "home");
+        assert_eq!(config.subvolume, Path::new("/home"));
+        assert_eq!(config.snapshot_dir(), Path::new("/home/.snapshots"));
+        assert!(config.timeline_create());
+        assert_eq!(
+            config.timeline_retention(),
+            RetentionPolicy {
+                hourly: 10,
+                daily: 7,
+                ..Default::default()
+            }
+        );
+    }
+}
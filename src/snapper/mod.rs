@@ -0,0 +1,7 @@
+//! Compatibility with snapper
+
+mod config;
+mod snapshot;
+
+pub use config::*;
+pub use snapshot::*;
@@ -0,0 +1,327 @@
+use crate::error::ParseError;
+use crate::scheduler::DatedSnapshot;
+use crate::snapper::SnapperConfig;
+use crate::subvolume::SnapshotOptions;
+use crate::subvolume::Subvolume;
+use crate::subvolume::SubvolumeInfo;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use chrono::NaiveDateTime;
+use chrono::Utc;
+
+/// Format of the dates in `info.xml`, which are in UTC.
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Kind of a snapper snapshot.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SnapperSnapshotKind {
+    /// A snapshot on its own.
+    Single,
+    /// A snapshot taken before an operation.
+    Pre,
+    /// A snapshot taken after an operation, paired with a pre snapshot.
+    Post,
+}
+
+impl SnapperSnapshotKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            SnapperSnapshotKind::Single => "single",
+            SnapperSnapshotKind::Pre => "pre",
+            SnapperSnapshotKind::Post => "post",
+        }
+    }
+}
+
+/// A snapshot managed by snapper.
+///
+/// Snapper keeps the snapshots of a subvolume in its `.snapshots` subvolume, each as
+/// `.snapshots/<number>/snapshot` next to an `info.xml` file describing it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SnapperSnapshot {
+    /// Number of the snapshot.
+    pub number: u32,
+    /// Kind of the snapshot.
+    pub kind: SnapperSnapshotKind,
+    /// Time the snapshot was taken, in UTC.
+    pub date: NaiveDateTime,
+    /// Number of the pre snapshot of a post snapshot.
+    pub pre_number: Option<u32>,
+    /// Description of the snapshot.
+    pub description: Option<String>,
+    /// Cleanup algorithm of the snapshot, e.g. `number` or `timeline`.
+    pub cleanup: Option<String>,
+    /// User data of the snapshot.
+    pub userdata: BTreeMap<String, String>,
+    /// Path of the snapshot subvolume.
+    pub path: PathBuf,
+}
+
+impl SnapperSnapshot {
+    /// Get information about the snapshot subvolume.
+    pub fn info(&self) -> Result<SubvolumeInfo> {
+        Subvolume::get(self.path.as_path())?.info()
+    }
+
+    /// Read a snapshot from its numbered directory.
+    fn read(dir: &Path) -> Result<Self> {
+        let path = dir.join("info.xml");
+        let xml = fs::read_to_string(&path)?;
+        let invalid = || ParseError::new("snapper snapshot info", path.to_string_lossy());
+        let number = element(&xml, "num")
+            .and_then(|num| num.parse().ok())
+            .ok_or_else(invalid)?;
+        let kind = match element(&xml, "type").as_deref() {
+            Some("single") => SnapperSnapshotKind::Single,
+            Some("pre") => SnapperSnapshotKind::Pre,
+            Some("post") => SnapperSnapshotKind::Post,
+            _ => return Err(invalid().into()),
+        };
+        let date = element(&xml, "date")
+            .and_then(|date| NaiveDateTime::parse_from_str(&date, DATE_FORMAT).ok())
+            .ok_or_else(invalid)?;
+        let pre_number = match element(&xml, "pre_num") {
+            Some(num) => Some(num.parse().map_err(|_| invalid())?),
+            None => None,
+        };
+        let mut userdata = BTreeMap::new();
+        let mut rest = xml.as_str();
+        while let Some((data, after)) = next_element(rest, "userdata") {
+            if let (Some(key), Some(value)) = (element(data, "key"), element(data, "value")) {
+                userdata.insert(key, value);
+            }
+            rest = after;
+        }
+        Ok(Self {
+            number,
+            kind,
+            date,
+            pre_number,
+            description: element(&xml, "description"),
+            cleanup: element(&xml, "cleanup"),
+            userdata,
+            path: dir.join("snapshot"),
+        })
+    }
+
+    /// Serialize the snapshot as `info.xml`.
+    fn to_xml(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\"?>\n<snapshot>\n");
+        let _ = writeln!(xml, "  <type>{}</type>", self.kind.as_str());
+        let _ = writeln!(xml, "  <num>{}</num>", self.number);
+        let _ = writeln!(xml, "  <date>{}</date>", self.date.format(DATE_FORMAT));
+        if let Some(pre_number) = self.pre_number {
+            let _ = writeln!(xml, "  <pre_num>{}</pre_num>", pre_number);
+        }
+        if let Some(description) = &self.description {
+            let _ = writeln!(xml, "  <description>{}</description>", escape(description));
+        }
+        if let Some(cleanup) = &self.cleanup {
+            let _ = writeln!(xml, "  <cleanup>{}</cleanup>", escape(cleanup));
+        }
+        for (key, value) in &self.userdata {
+            let _ = writeln!(
+                xml,
+                "  <userdata>\n    <key>{}</key>\n    <value>{}</value>\n  </userdata>",
+                escape(key),
+                escape(value)
+            );
+        }
+        xml.push_str("</snapshot>\n");
+        xml
+    }
+}
+
+impl DatedSnapshot for SnapperSnapshot {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn timestamp(&self) -> NaiveDateTime {
+        self.date
+    }
+}
+
+impl SnapperConfig {
+    /// List the snapshots of this configuration, ordered by number.
+    ///
+    /// Directories without a readable `info.xml`, e.g. of snapshots being deleted, are skipped.
+    pub fn snapshots(&self) -> Result<Vec<SnapperSnapshot>> {
+        let mut snapshots = Vec::new();
+        let entries = match fs::read_dir(self.snapshot_dir()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(snapshots),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let numbered = entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.parse::<u32>().is_ok());
+            if !numbered {
+                continue;
+            }
+            match SnapperSnapshot::read(&entry.path()) {
+                Ok(snapshot) => snapshots.push(snapshot),
+                Err(BtrfsUtilError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        snapshots.sort_by_key(|snapshot| snapshot.number);
+        Ok(snapshots)
+    }
+
+    /// Take a read-only single snapshot which snapper recognizes.
+    ///
+    /// The snapshot is numbered after the latest existing one. A running `snapperd` only notices
+    /// snapshots it did not take itself once it is restarted. This requires elevated
+    /// privileges(CAP_SYS_ADMIN).
+    pub fn create_snapshot(
+        &self,
+        description: Option<&str>,
+        cleanup: Option<&str>,
+        userdata: BTreeMap<String, String>,
+    ) -> Result<SnapperSnapshot> {
+        let snapshot_dir = self.snapshot_dir();
+        let mut number = self
+            .snapshots()?
+            .last()
+            .map_or(0, |snapshot| snapshot.number);
+        // Claim a directory, in case another snapshot is being taken concurrently.
+        let dir = loop {
+            number += 1;
+            let dir = snapshot_dir.join(number.to_string());
+            match fs::create_dir(&dir) {
+                Ok(()) => break dir,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        let snapshot = SnapperSnapshot {
+            number,
+            kind: SnapperSnapshotKind::Single,
+            date: Utc::now().naive_utc(),
+            pre_number: None,
+            description: description.map(str::to_owned),
+            cleanup: cleanup.map(str::to_owned),
+            userdata,
+            path: dir.join("snapshot"),
+        };
+        let created = Subvolume::get(self.subvolume.as_path()).and_then(|subvol| {
+            subvol.snapshot_with(
+                snapshot.path.as_path(),
+                SnapshotOptions::new().read_only(true),
+            )
+        });
+        if let Err(e) = created {
+            let _ = fs::remove_dir(&dir);
+            return Err(e);
+        }
+        // Written last, so that snapper never sees a snapshot without its subvolume.
+        let tmp = dir.join("info.xml.tmp");
+        fs::write(&tmp, snapshot.to_xml())?;
+        fs::rename(tmp, dir.join("info.xml"))?;
+        Ok(snapshot)
+    }
+}
+
+/// Get the unescaped text of the first element with a tag.
+fn element(xml: &str, tag: &str) -> Option<String> {
+    next_element(xml, tag).map(|(text, _)| unescape(text.trim()))
+}
+
+/// Find the first element with a tag, returning its raw content and the text after it.
+fn next_element<'a>(xml: &'a str, tag: &str) -> Option<(&'a str, &'a str)> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let len = xml[start..].find(&close)?;
+    Some((&xml[start..start + len], &xml[start + len + close.len()..]))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    #[test]
+    fn escape_text() {
+        let text = r#"<a href="x">&amp;</a>"#;
+        assert_eq!(
+            escape(text),
+            "&lt;a href=&quot;x&quot;&gt;&amp;amp;&lt;/a&gt;"
+        );
+        assert_eq!(unescape(&escape(text)), text);
+        assert_eq!(unescape("it&apos;s"), "it's");
+    }
+
+    #[test]
+    fn info_round_trip() {
+        let dir =
+            std::env::temp_dir().join(format!("btrfsutil-snapper-info-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let snapshot = SnapperSnapshot {
+            number: 42,
+            kind: SnapperSnapshotKind::Post,
+            date: NaiveDate::from_ymd_opt(2024, 5, 17)
+                .and_then(|date| date.and_hms_opt(8, 30, 0))
+                .unwrap(),
+            pre_number: Some(41),
+            description: Some("zypper <dup> & \"reboot\"".to_owned()),
+            cleanup: Some("number".to_owned()),
+            userdata: vec![
+                ("important".to_owned(), "yes".to_owned()),
+                ("tool".to_owned(), "btrfsutil".to_owned()),
+            ]
+            .into_iter()
+            .collect(),
+            path: dir.join("snapshot"),
+        };
+        fs::write(dir.join("info.xml"), snapshot.to_xml()).unwrap();
+        let read = SnapperSnapshot::read(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(read.unwrap(), snapshot);
+    }
+
+    #[test]
+    fn read_snapper_info() {
+        let xml = "<?xml version=\"1.0\"?>\n<snapshot>\n  <type>single</type>\n  <num>1</num>\n  \
+                   <date>2024-01-02 03:04:05</date>\n  <description>first root filesystem</description>\n\
+                   </snapshot>\n";
+        assert_eq!(element(xml, "num").as_deref(), Some("1"));
+        assert_eq!(element(xml, "pre_num"), None);
+        assert_eq!(
+            element(xml, "description").as_deref(),
+            Some("first root filesystem")
+        );
+        let (text, rest) = next_element(xml, "type").unwrap();
+        assert_eq!(text, "single");
+        assert!(rest.starts_with("\n  <num>"));
+    }
+}
@@ -0,0 +1,55 @@
+use crate::common;
+use crate::subvolume::CreateOptions;
+use crate::subvolume::DeleteOptions;
+use crate::subvolume::SnapshotOptions;
+use crate::subvolume::Subvolume;
+use crate::subvolume::SubvolumeInfo;
+use crate::Result;
+
+use std::path::PathBuf;
+
+impl Subvolume {
+    /// Create a new subvolume with options, without blocking the async runtime.
+    ///
+    /// Runs [create_with] on a blocking thread pool.
+    ///
+    /// [create_with]: #method.create_with
+    pub async fn create_async<T: Into<PathBuf>>(path: T, options: CreateOptions) -> Result<Self> {
+        let path: PathBuf = path.into();
+        common::spawn_blocking(move || Self::create_with(path, options)).await
+    }
+
+    /// Create a snapshot of this subvolume with options, without blocking the async runtime.
+    ///
+    /// Runs [snapshot_with] on a blocking thread pool.
+    ///
+    /// [snapshot_with]: #method.snapshot_with
+    pub async fn snapshot_async<T: Into<PathBuf>>(
+        &self,
+        path: T,
+        options: SnapshotOptions,
+    ) -> Result<Self> {
+        let subvol = self.clone();
+        let path: PathBuf = path.into();
+        common::spawn_blocking(move || subvol.snapshot_with(path, options)).await
+    }
+
+    /// Delete a subvolume with options, without blocking the async runtime.
+    ///
+    /// Runs [delete_with] on a blocking thread pool.
+    ///
+    /// [delete_with]: #method.delete_with
+    pub async fn delete_async(self, options: DeleteOptions) -> Result<()> {
+        common::spawn_blocking(move || self.delete_with(options)).await
+    }
+
+    /// Get information about this subvolume, without blocking the async runtime.
+    ///
+    /// Runs [info] on a blocking thread pool.
+    ///
+    /// [info]: #method.info
+    pub async fn info_async(&self) -> Result<SubvolumeInfo> {
+        let subvol = self.clone();
+        common::spawn_blocking(move || subvol.info()).await
+    }
+}
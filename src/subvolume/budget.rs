@@ -0,0 +1,208 @@
+use crate::filesystem::Filesystem;
+use crate::subvolume::DeleteOptions;
+use crate::subvolume::Subvolume;
+use crate::sync::sync_filesystem_fd;
+use crate::sync::DEFAULT_WATCH_INTERVAL;
+use crate::Result;
+
+use chrono::NaiveDateTime;
+
+use std::cmp::Reverse;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::thread;
+
+/// Order in which [ensure_free_space] deletes snapshots.
+///
+/// [ensure_free_space]: fn.ensure_free_space.html
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DeletionOrder {
+    /// Delete the oldest snapshots first.
+    OldestFirst,
+    /// Delete the snapshots using the most space exclusively first. This requires quotas to be
+    /// enabled.
+    LargestExclusiveFirst,
+}
+
+/// Policy of [ensure_free_space].
+///
+/// [ensure_free_space]: fn.ensure_free_space.html
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SpacePolicy {
+    pub(crate) order: DeletionOrder,
+    pub(crate) dry_run: bool,
+}
+
+impl SpacePolicy {
+    /// Create a policy deleting snapshots in an order.
+    pub fn new(order: DeletionOrder) -> Self {
+        Self {
+            order,
+            dry_run: false,
+        }
+    }
+
+    /// Only report the snapshots which would be deleted, without deleting them.
+    ///
+    /// The freed space is then estimated from the space the snapshots use exclusively, which is
+    /// only known if quotas are enabled.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+}
+
+/// Result of [ensure_free_space].
+///
+/// [ensure_free_space]: fn.ensure_free_space.html
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SpaceReport {
+    /// Snapshots which were deleted, or would be deleted by a dry run, in order.
+    pub deleted: Vec<PathBuf>,
+    /// Estimated free space before, in bytes.
+    pub free_before: u64,
+    /// Estimated free space after, in bytes.
+    pub free_after: u64,
+    /// Whether the target free space was reached.
+    pub reached: bool,
+}
+
+/// A snapshot which may be deleted to free space.
+struct Candidate {
+    path: PathBuf,
+    subvol: Subvolume,
+    exclusive: Option<u64>,
+}
+
+/// Delete snapshots among candidates until a filesystem has a target amount of free space.
+///
+/// Free space is the estimate of [FilesystemUsage::free_estimated]. After each deletion, the
+/// filesystem is synced and the cleanup of the snapshot is awaited, so that its space is actually
+/// freed before the next check. Candidates which are not needed are left alone. This requires
+/// elevated privileges(CAP_SYS_ADMIN).
+///
+/// [FilesystemUsage::free_estimated]: ../filesystem/struct.FilesystemUsage.html#structfield.free_estimated
+pub fn ensure_free_space(
+    fs: &Filesystem,
+    target_free_bytes: u64,
+    candidates: &[PathBuf],
+    policy: SpacePolicy,
+) -> Result<SpaceReport> {
+    let free_before = fs.usage()?.free_estimated;
+    let mut report = SpaceReport {
+        free_before,
+        free_after: free_before,
+        ..Default::default()
+    };
+    if free_before >= target_free_bytes {
+        report.reached = true;
+        return Ok(report);
+    }
+
+    let mut found = Vec::new();
+    for path in candidates {
+        let subvol = Subvolume::get(path.as_path())?;
+        let exclusive = match policy.order {
+            DeletionOrder::LargestExclusiveFirst => Some(subvol.exclusive_size()?.bytes),
+            DeletionOrder::OldestFirst if policy.dry_run => {
+                subvol.exclusive_size().ok().map(|size| size.bytes)
+            }
+            DeletionOrder::OldestFirst => None,
+        };
+        let otime = subvol.info()?.otime;
+        found.push((
+            otime,
+            Candidate {
+                path: path.clone(),
+                subvol,
+                exclusive,
+            },
+        ));
+    }
+    sort_candidates(&mut found, policy.order, |candidate| candidate.exclusive);
+
+    for (_, candidate) in found {
+        if report.free_after >= target_free_bytes {
+            break;
+        }
+        if policy.dry_run {
+            report.free_after += candidate.exclusive.unwrap_or(0);
+        } else {
+            let id = candidate.subvol.id();
+            candidate.subvol.delete_with(DeleteOptions::default())?;
+            wait_cleaned(fs, id)?;
+            report.free_after = fs.usage()?.free_estimated;
+        }
+        report.deleted.push(candidate.path);
+    }
+    report.reached = report.free_after >= target_free_bytes;
+    Ok(report)
+}
+
+/// Sort candidates by creation time into deletion order, with the space they use exclusively,
+/// if known, given by a function. Candidates whose exclusive space is unknown come last when
+/// deleting the largest first.
+fn sort_candidates<T, F>(candidates: &mut [(NaiveDateTime, T)], order: DeletionOrder, exclusive: F)
+where
+    F: Fn(&T) -> Option<u64>,
+{
+    match order {
+        DeletionOrder::OldestFirst => candidates.sort_by_key(|(otime, _)| *otime),
+        DeletionOrder::LargestExclusiveFirst => {
+            candidates.sort_by_key(|(_, candidate)| Reverse(exclusive(candidate)))
+        }
+    }
+}
+
+/// Wait until a deleted subvolume has been cleaned up by the kernel.
+pub(crate) fn wait_cleaned(fs: &Filesystem, id: u64) -> Result<()> {
+    loop {
+        sync_filesystem_fd(fs.as_raw_fd())?;
+        let deleted = Subvolume::deleted(Some(fs.path()))?;
+        if deleted.iter().all(|subvol| subvol.id() != id) {
+            return Ok(());
+        }
+        thread::sleep(DEFAULT_WATCH_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates() -> Vec<(NaiveDateTime, (&'static str, Option<u64>))> {
+        let time = |hour| {
+            chrono::NaiveDate::from_ymd_opt(2024, 1, 1)
+                .and_then(|date| date.and_hms_opt(hour, 0, 0))
+                .unwrap()
+        };
+        vec![
+            (time(4), ("newest", Some(1 << 30))),
+            (time(1), ("oldest", Some(4096))),
+            (time(2), ("unknown", None)),
+            (time(3), ("middle", Some(1 << 20))),
+        ]
+    }
+
+    fn names(candidates: &[(NaiveDateTime, (&'static str, Option<u64>))]) -> Vec<&'static str> {
+        candidates.iter().map(|(_, (name, _))| *name).collect()
+    }
+
+    #[test]
+    fn oldest_first() {
+        let mut found = candidates();
+        sort_candidates(&mut found, DeletionOrder::OldestFirst, |(_, size)| *size);
+        assert_eq!(names(&found), vec!["oldest", "unknown", "middle", "newest"]);
+    }
+
+    #[test]
+    fn largest_exclusive_first() {
+        let mut found = candidates();
+        sort_candidates(
+            &mut found,
+            DeletionOrder::LargestExclusiveFirst,
+            |(_, size)| *size,
+        );
+        assert_eq!(names(&found), vec!["newest", "middle", "oldest", "unknown"]);
+    }
+}
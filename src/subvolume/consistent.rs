@@ -0,0 +1,121 @@
+use crate::filesystem::Filesystem;
+use crate::subvolume::SnapshotOptions;
+use crate::subvolume::Subvolume;
+use crate::sync::sync_filesystem_fd;
+use crate::Result;
+
+use std::fmt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Phase of a [Quiesce::Hook] call.
+///
+/// [Quiesce::Hook]: enum.Quiesce.html#variant.Hook
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QuiescePhase {
+    /// Bring the application into a consistent state on disk and hold it there, e.g. with
+    /// `FLUSH TABLES WITH READ LOCK`.
+    Quiesce,
+    /// Let the application continue.
+    Unquiesce,
+}
+
+/// How writes are quiesced around a [consistent_snapshot].
+///
+/// [consistent_snapshot]: fn.consistent_snapshot.html
+pub enum Quiesce<'a> {
+    /// Freeze the filesystem, which flushes all dirty data and waits for writers to drain, then
+    /// thaw it right before the snapshot. Btrfs cannot take a snapshot of a frozen filesystem, so
+    /// writes may slip in between the thaw and the snapshot.
+    Freeze,
+    /// Only sync the filesystem before the snapshot.
+    SyncOnly,
+    /// Call a hook to quiesce the application before the snapshot and to unquiesce it after.
+    Hook(&'a mut dyn FnMut(QuiescePhase) -> Result<()>),
+}
+
+impl fmt::Debug for Quiesce<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Quiesce::Freeze => write!(f, "Freeze"),
+            Quiesce::SyncOnly => write!(f, "SyncOnly"),
+            Quiesce::Hook(_) => write!(f, "Hook(..)"),
+        }
+    }
+}
+
+/// A snapshot taken by [consistent_snapshot], with the time spent in each step.
+///
+/// [consistent_snapshot]: fn.consistent_snapshot.html
+#[derive(Clone, Debug)]
+pub struct ConsistentSnapshot {
+    /// The read-only snapshot.
+    pub snapshot: Subvolume,
+    /// Path of the snapshot.
+    pub path: PathBuf,
+    /// Time spent quiescing, before the snapshot was started.
+    pub quiesce: Duration,
+    /// Time spent taking the snapshot.
+    pub snapshot_time: Duration,
+    /// Time spent unquiescing.
+    pub unquiesce: Duration,
+}
+
+impl ConsistentSnapshot {
+    /// Get the time writes were held back, from the start of quiescing to the end of
+    /// unquiescing.
+    pub fn quiesced(&self) -> Duration {
+        self.quiesce + self.snapshot_time + self.unquiesce
+    }
+}
+
+/// Take a read-only snapshot of a subvolume while its writes are quiesced.
+///
+/// The sequence is guaranteed: quiesce, snapshot, unquiesce. The unquiesce step always runs once
+/// quiescing was started, even if quiescing or the snapshot failed, in which case that error is
+/// returned. If only unquiescing fails, its error is returned and the snapshot is kept. Freezing
+/// requires elevated privileges(CAP_SYS_ADMIN), like taking the snapshot.
+pub fn consistent_snapshot<P: AsRef<Path>, Q: AsRef<Path>>(
+    subvol: P,
+    dest: Q,
+    quiesce: Quiesce<'_>,
+) -> Result<ConsistentSnapshot> {
+    let subvol = subvol.as_ref();
+    let dest = dest.as_ref();
+    let source = Subvolume::get(subvol)?;
+    let fs = Filesystem::open(subvol)?;
+
+    let start = Instant::now();
+    let (quiesced, mut hook) = match quiesce {
+        Quiesce::Freeze => (fs.freeze().and_then(|guard| guard.thaw()), None),
+        Quiesce::SyncOnly => (sync_filesystem_fd(fs.as_raw_fd()), None),
+        Quiesce::Hook(hook) => (hook(QuiescePhase::Quiesce), Some(hook)),
+    };
+    let quiesce_time = start.elapsed();
+
+    let start = Instant::now();
+    let snapshot = quiesced.and_then(|()| {
+        source.snapshot_with(dest.to_path_buf(), SnapshotOptions::new().read_only(true))
+    });
+    let snapshot_time = start.elapsed();
+
+    let start = Instant::now();
+    let unquiesced = match hook.as_mut() {
+        Some(hook) => hook(QuiescePhase::Unquiesce),
+        None => Ok(()),
+    };
+    let unquiesce_time = start.elapsed();
+
+    let snapshot = snapshot?;
+    unquiesced?;
+    Ok(ConsistentSnapshot {
+        snapshot,
+        path: dest.to_path_buf(),
+        quiesce: quiesce_time,
+        snapshot_time,
+        unquiesce: unquiesce_time,
+    })
+}
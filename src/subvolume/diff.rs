@@ -0,0 +1,254 @@
+use crate::ioctl;
+use crate::search;
+use crate::search::SearchItem;
+use crate::search::SearchKey;
+use crate::subvolume::Subvolume;
+use crate::Result;
+
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Inode number of the root directory of a subvolume.
+const BTRFS_FIRST_FREE_OBJECTID: u64 = 256;
+
+/// A change between two snapshots, found by [diff].
+///
+/// Paths are relative to the snapshots.
+///
+/// [diff]: fn.diff.html
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum SubvolumeChange {
+    /// A file or directory was created, or a hard link was added to an existing file.
+    Created(PathBuf),
+    /// A file or directory was deleted, or a hard link was removed.
+    Deleted(PathBuf),
+    /// A file or directory was moved.
+    Renamed {
+        /// Path in the old snapshot.
+        from: PathBuf,
+        /// Path in the new snapshot.
+        to: PathBuf,
+    },
+    /// The content of a file changed.
+    Modified(PathBuf),
+    /// The permissions, owner or other metadata of a file or directory changed, but not its
+    /// content.
+    MetadataChanged(PathBuf),
+}
+
+impl SubvolumeChange {
+    /// Get the path affected by the change, which is the old path for deletions and the new one
+    /// otherwise.
+    pub fn path(&self) -> &Path {
+        match self {
+            SubvolumeChange::Created(path)
+            | SubvolumeChange::Deleted(path)
+            | SubvolumeChange::Modified(path)
+            | SubvolumeChange::MetadataChanged(path) => path,
+            SubvolumeChange::Renamed { to, .. } => to,
+        }
+    }
+}
+
+/// Fields of an inode item compared by [diff].
+///
+/// [diff]: fn.diff.html
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct InodeState {
+    /// Transaction which created the inode, telling apart inodes reusing a number.
+    generation: u64,
+    /// Last transaction which changed the inode.
+    transid: u64,
+    size: u64,
+    uid: u32,
+    gid: u32,
+    mode: u32,
+    mtime: (u64, u32),
+}
+
+impl InodeState {
+    fn is_dir(&self) -> bool {
+        self.mode & libc::S_IFMT == libc::S_IFDIR
+    }
+}
+
+impl From<&SearchItem> for InodeState {
+    fn from(item: &SearchItem) -> Self {
+        // Offsets within btrfs_inode_item.
+        Self {
+            generation: item.u64_at(0),
+            transid: item.u64_at(8),
+            size: item.u64_at(16),
+            uid: item.u32_at(44),
+            gid: item.u32_at(48),
+            mode: item.u32_at(52),
+            mtime: (item.u64_at(136), item.u32_at(144)),
+        }
+    }
+}
+
+fn inode(fd: RawFd, ino: u64) -> io::Result<Option<InodeState>> {
+    SearchKey::new(0)
+        .objectids(ino, ino)
+        .types(search::BTRFS_INODE_ITEM_KEY, search::BTRFS_INODE_ITEM_KEY)
+        .offsets(0, 0)
+        .search(fd)
+        .find(|item| {
+            item.as_ref()
+                .map_or(true, |item| item.item_type == search::BTRFS_INODE_ITEM_KEY)
+        })
+        .map(|item| item.map(|item| InodeState::from(&item)))
+        .transpose()
+}
+
+/// Get the entries of a directory as (name, inode number) pairs, skipping nested subvolumes.
+fn dir_entries(fd: RawFd, dir: u64) -> io::Result<HashSet<(Vec<u8>, u64)>> {
+    let mut entries: HashSet<(Vec<u8>, u64)> = HashSet::new();
+    for item in SearchKey::new(0)
+        .objectids(dir, dir)
+        .types(search::BTRFS_DIR_INDEX_KEY, search::BTRFS_DIR_INDEX_KEY)
+        .search(fd)
+    {
+        let item = item?;
+        // Offsets within btrfs_dir_item: the location key, then the name after the header.
+        if item.item_type != search::BTRFS_DIR_INDEX_KEY
+            || item.u8_at(8) as u32 != search::BTRFS_INODE_ITEM_KEY
+        {
+            continue;
+        }
+        let name_len = u16::from_le_bytes([item.u8_at(27), item.u8_at(28)]) as usize;
+        if let Some(name) = item.data.get(30..30 + name_len) {
+            entries.insert((name.to_vec(), item.u64_at(0)));
+        }
+    }
+    Ok(entries)
+}
+
+/// Get a path of an inode, relative to the subvolume.
+fn inode_path(fd: RawFd, ino: u64) -> io::Result<Option<PathBuf>> {
+    if ino == BTRFS_FIRST_FREE_OBJECTID {
+        return Ok(Some(PathBuf::new()));
+    }
+    Ok(ioctl::ino_paths(fd, ino)?
+        .0
+        .first()
+        .map(|path| PathBuf::from(OsStr::from_bytes(path))))
+}
+
+/// Find the changes between two snapshots of the same subvolume, e.g. yesterday's and today's.
+///
+/// Only the inodes of the new snapshot changed since the generation of the old one are compared,
+/// so this is much faster than walking both trees. Deleting a directory reports the directory but
+/// not its content. Changes are sorted by path. This requires elevated privileges(CAP_SYS_ADMIN).
+pub fn diff(old: &Subvolume, new: &Subvolume) -> Result<Vec<SubvolumeChange>> {
+    let old_file = old.open()?;
+    let new_file = new.open()?;
+    let old_fd = old_file.as_raw_fd();
+    let new_fd = new_file.as_raw_fd();
+    let old_gen = old.info()?.generation;
+
+    let mut changes: Vec<SubvolumeChange> = Vec::new();
+    let mut created: HashSet<u64> = HashSet::new();
+    let mut changed_dirs: Vec<u64> = Vec::new();
+
+    for item in SearchKey::new(0)
+        .types(search::BTRFS_INODE_ITEM_KEY, search::BTRFS_INODE_ITEM_KEY)
+        .transids(old_gen + 1, u64::MAX)
+        .search(new_fd)
+    {
+        let item = item?;
+        if item.item_type != search::BTRFS_INODE_ITEM_KEY {
+            continue;
+        }
+        let new_state = InodeState::from(&item);
+        if new_state.transid <= old_gen {
+            continue;
+        }
+        let ino = item.objectid;
+        let old_state = match inode(old_fd, ino)? {
+            Some(val) if val.generation == new_state.generation => val,
+            _ => {
+                created.insert(ino);
+                continue;
+            }
+        };
+
+        if new_state.is_dir() {
+            changed_dirs.push(ino);
+        }
+        let content_changed = !new_state.is_dir()
+            && (new_state.size != old_state.size || new_state.mtime != old_state.mtime);
+        let metadata_changed = new_state.mode != old_state.mode
+            || new_state.uid != old_state.uid
+            || new_state.gid != old_state.gid;
+        if content_changed || metadata_changed {
+            if let Some(path) = inode_path(new_fd, ino)? {
+                changes.push(if content_changed {
+                    SubvolumeChange::Modified(path)
+                } else {
+                    SubvolumeChange::MetadataChanged(path)
+                });
+            }
+        }
+    }
+
+    // Compare the entries of the directories present in both snapshots, matching removed and
+    // added entries of the same inode as renames.
+    let mut removed: BTreeMap<u64, Vec<PathBuf>> = BTreeMap::new();
+    let mut added: BTreeMap<u64, Vec<PathBuf>> = BTreeMap::new();
+    for dir in changed_dirs {
+        let old_entries = dir_entries(old_fd, dir)?;
+        let new_entries = dir_entries(new_fd, dir)?;
+        if let Some(dir_path) = inode_path(old_fd, dir)? {
+            for (name, ino) in old_entries.difference(&new_entries) {
+                let path = dir_path.join(OsStr::from_bytes(name));
+                removed.entry(*ino).or_default().push(path);
+            }
+        }
+        if let Some(dir_path) = inode_path(new_fd, dir)? {
+            for (name, ino) in new_entries.difference(&old_entries) {
+                let path = dir_path.join(OsStr::from_bytes(name));
+                added.entry(*ino).or_default().push(path);
+            }
+        }
+    }
+
+    for (ino, from_paths) in removed {
+        let mut to_paths = added.remove(&ino).unwrap_or_default().into_iter();
+        for from in from_paths {
+            match to_paths.next() {
+                Some(to) if !created.contains(&ino) => {
+                    changes.push(SubvolumeChange::Renamed { from, to })
+                }
+                Some(to) => {
+                    changes.push(SubvolumeChange::Deleted(from));
+                    changes.push(SubvolumeChange::Created(to));
+                }
+                None => changes.push(SubvolumeChange::Deleted(from)),
+            }
+        }
+        changes.extend(to_paths.map(SubvolumeChange::Created));
+        created.remove(&ino);
+    }
+    for (ino, to_paths) in added {
+        changes.extend(to_paths.into_iter().map(SubvolumeChange::Created));
+        created.remove(&ino);
+    }
+
+    // Inodes created in new directories have no entry in a directory present in both snapshots.
+    for ino in created {
+        if let Some(path) = inode_path(new_fd, ino)? {
+            changes.push(SubvolumeChange::Created(path));
+        }
+    }
+
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(changes)
+}
@@ -0,0 +1,158 @@
+use crate::ioctl;
+use crate::search;
+use crate::search::SearchItem;
+use crate::search::SearchKey;
+use crate::search::TreeSearch;
+use crate::subvolume::Subvolume;
+use crate::sync::Transid;
+use crate::Result;
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+/// How the data of a file extent is stored.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum FileExtentKind {
+    /// Data stored inline in the metadata.
+    Inline,
+    /// Data stored in a regular extent.
+    Regular,
+    /// Space preallocated with `fallocate()`, not written yet.
+    Prealloc,
+}
+
+/// A file extent written since a given generation, found by [Subvolume::find_new].
+///
+/// [Subvolume::find_new]: struct.Subvolume.html#method.find_new
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChangedExtent {
+    /// Path of the file, or `None` if it has no link left, e.g. an unlinked file still open.
+    pub path: Option<PathBuf>,
+    /// Inode number of the file.
+    pub inode: u64,
+    /// Offset of the extent within the file, in bytes.
+    pub offset: u64,
+    /// Length of the extent within the file, in bytes.
+    pub len: u64,
+    /// Transaction which wrote the extent.
+    pub generation: Transid,
+    /// How the data is stored.
+    pub kind: FileExtentKind,
+    /// Whether the data is compressed.
+    pub compressed: bool,
+}
+
+/// Iterator over the file extents of a subvolume written since a given generation.
+///
+/// Created by [Subvolume::find_new]. Extents are ordered by inode number, then file offset. The
+/// subvolume is kept open until the iterator is dropped.
+///
+/// [Subvolume::find_new]: struct.Subvolume.html#method.find_new
+pub struct FindNew {
+    /// Open for as long as the search, which refers to its file descriptor.
+    file: File,
+    root: PathBuf,
+    search: TreeSearch,
+    since: Transid,
+    generation: Transid,
+    /// Path of the last inode resolved, since consecutive extents usually belong to the same file.
+    last_path: Option<(u64, Option<PathBuf>)>,
+}
+
+impl FindNew {
+    /// Get the generation of the subvolume when the search started, to pass as `since_gen` to the
+    /// next search. This is the marker `btrfs subvolume find-new` prints.
+    pub fn generation(&self) -> Transid {
+        self.generation
+    }
+
+    fn path(&mut self, inode: u64) -> Result<Option<PathBuf>> {
+        if let Some((last_inode, path)) = &self.last_path {
+            if *last_inode == inode {
+                return Ok(path.clone());
+            }
+        }
+        let path = ioctl::ino_paths(self.file.as_raw_fd(), inode)?
+            .0
+            .first()
+            .map(|path| self.root.join(OsStr::from_bytes(path)));
+        self.last_path = Some((inode, path.clone()));
+        Ok(path)
+    }
+
+    fn changed_extent(&mut self, item: &SearchItem) -> Result<Option<ChangedExtent>> {
+        // The key range is compound, so other item types of the inodes in range show up as well.
+        if item.item_type != search::BTRFS_EXTENT_DATA_KEY {
+            return Ok(None);
+        }
+        // Offsets within btrfs_file_extent_item.
+        let generation = Transid(item.u64_at(0));
+        if generation < self.since {
+            return Ok(None);
+        }
+        let (kind, len) = match item.u8_at(20) {
+            0 => (FileExtentKind::Inline, item.u64_at(8)),
+            1 => (FileExtentKind::Regular, item.u64_at(45)),
+            2 => (FileExtentKind::Prealloc, item.u64_at(45)),
+            _ => return Ok(None),
+        };
+        Ok(Some(ChangedExtent {
+            path: self.path(item.objectid)?,
+            inode: item.objectid,
+            offset: item.offset,
+            len,
+            generation,
+            kind,
+            compressed: item.u8_at(16) != 0,
+        }))
+    }
+}
+
+impl Iterator for FindNew {
+    type Item = Result<ChangedExtent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let item = match self.search.next()? {
+                Ok(val) => val,
+                Err(e) => return Some(Err(e.into())),
+            };
+            match self.changed_extent(&item) {
+                Ok(Some(val)) => return Some(Ok(val)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl Subvolume {
+    /// Find the file extents of this subvolume written since a generation, like
+    /// `btrfs subvolume find-new`.
+    ///
+    /// Use [FindNew::generation] as `since_gen` of the next call to get the changes in between.
+    /// Deleted files are not reported. This requires elevated privileges(CAP_SYS_ADMIN).
+    ///
+    /// [FindNew::generation]: struct.FindNew.html#method.generation
+    pub fn find_new(&self, since_gen: Transid) -> Result<FindNew> {
+        let generation = Transid(self.info()?.generation);
+        let root = self.path()?;
+        let file = self.open()?;
+        // Tree id zero searches the subvolume of the file descriptor.
+        let search = SearchKey::new(0)
+            .types(search::BTRFS_EXTENT_DATA_KEY, search::BTRFS_EXTENT_DATA_KEY)
+            .transids(since_gen.0, u64::MAX)
+            .search(file.as_raw_fd());
+        Ok(FindNew {
+            file,
+            root,
+            search,
+            since: since_gen,
+            generation,
+            last_path: None,
+        })
+    }
+}
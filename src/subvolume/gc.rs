@@ -0,0 +1,205 @@
+use crate::filesystem::Filesystem;
+use crate::search;
+use crate::search::SearchKey;
+use crate::send::subvolume_root;
+use crate::subvolume::DeleteOptions;
+use crate::subvolume::Subvolume;
+use crate::subvolume::SubvolumeIterator;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::convert::TryInto;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::Utc;
+use uuid::Uuid;
+
+/// Rules selecting the snapshots to collect with [gc_snapshots].
+///
+/// A snapshot is collected if any rule matches it. No rule matches by default. Only read-only
+/// snapshots are considered unless [writable] is set, and the default subvolume never is.
+///
+/// [writable]: struct.GcRules.html#method.writable
+/// [gc_snapshots]: fn.gc_snapshots.html
+#[derive(Clone, Debug, Default)]
+pub struct GcRules {
+    pub(crate) older_than: Option<Duration>,
+    pub(crate) orphaned: bool,
+    pub(crate) temporary: Vec<String>,
+    pub(crate) writable: bool,
+    pub(crate) dry_run: bool,
+}
+
+impl GcRules {
+    /// Create rules matching no snapshot.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collect snapshots created longer ago than a duration.
+    pub fn older_than(mut self, age: Duration) -> Self {
+        self.older_than = Some(age);
+        self
+    }
+
+    /// Collect snapshots whose source subvolume was deleted.
+    pub fn orphaned(mut self, orphaned: bool) -> Self {
+        self.orphaned = orphaned;
+        self
+    }
+
+    /// Collect snapshots whose file name matches a pattern, in which `*` matches any sequence of
+    /// characters and `?` any single character, e.g. `*.tmp`.
+    pub fn temporary<T: Into<String>>(mut self, pattern: T) -> Self {
+        self.temporary.push(pattern.into());
+        self
+    }
+
+    /// Consider writable snapshots as well, e.g. clones of a template which are in use.
+    pub fn writable(mut self, writable: bool) -> Self {
+        self.writable = writable;
+        self
+    }
+
+    /// Only report the snapshots which would be collected, without deleting them.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+}
+
+/// Why a snapshot is collected.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GcReason {
+    /// The snapshot is older than allowed.
+    TooOld,
+    /// The source subvolume of the snapshot no longer exists.
+    Orphaned,
+    /// The name of the snapshot marks it as temporary.
+    Temporary,
+}
+
+/// A snapshot collected by [gc_snapshots].
+///
+/// [gc_snapshots]: fn.gc_snapshots.html
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GcSnapshot {
+    /// Path of the snapshot.
+    pub path: PathBuf,
+    /// Id of the snapshot.
+    pub id: u64,
+    /// Rules which matched the snapshot.
+    pub reasons: Vec<GcReason>,
+}
+
+/// Result of [gc_snapshots].
+///
+/// [gc_snapshots]: fn.gc_snapshots.html
+#[derive(Debug, Default)]
+pub struct GcReport {
+    /// Snapshots which were deleted, or would be deleted by a dry run.
+    pub collected: Vec<GcSnapshot>,
+    /// Snapshots which matched but could not be deleted, with the error.
+    pub failed: Vec<(GcSnapshot, BtrfsUtilError)>,
+}
+
+/// Delete the snapshots of a filesystem which match staleness rules, reporting them.
+///
+/// The snapshots beneath the subvolume containing the path the filesystem was opened with are
+/// considered, except the default subvolume. A failure to delete a snapshot is reported without
+/// stopping the collection. This requires elevated privileges(CAP_SYS_ADMIN).
+pub fn gc_snapshots(fs: &Filesystem, rules: &GcRules) -> Result<GcReport> {
+    let root = subvolume_root(fs.path())?;
+    let default_id = Subvolume::get_default(Some(root.clone()))?.id();
+    let now = Utc::now().naive_utc();
+    let mut matched = Vec::new();
+    for entry in SubvolumeIterator::builder_for_path(root.clone()).iter_with_info()? {
+        let (path, info) = entry?;
+        let parent_uuid = match info.parent_uuid {
+            Some(parent_uuid) => parent_uuid,
+            None => continue,
+        };
+        if info.id == default_id || !(rules.writable || info.is_read_only()) {
+            continue;
+        }
+        let path = root.join(path);
+
+        let mut reasons = Vec::new();
+        if let Some(age) = rules.older_than {
+            let older = (now - info.otime)
+                .to_std()
+                .is_ok_and(|elapsed| elapsed > age);
+            if older {
+                reasons.push(GcReason::TooOld);
+            }
+        }
+        if rules.orphaned && !uuid_exists(fs.as_raw_fd(), parent_uuid)? {
+            reasons.push(GcReason::Orphaned);
+        }
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if rules
+            .temporary
+            .iter()
+            .any(|pattern| glob_match(pattern.as_bytes(), name.as_bytes()))
+        {
+            reasons.push(GcReason::Temporary);
+        }
+
+        if !reasons.is_empty() {
+            matched.push(GcSnapshot {
+                path,
+                id: info.id,
+                reasons,
+            });
+        }
+    }
+
+    let mut report = GcReport::default();
+    for snapshot in matched {
+        if rules.dry_run {
+            report.collected.push(snapshot);
+            continue;
+        }
+        match Subvolume::get(snapshot.path.as_path())
+            .and_then(|subvol| subvol.delete_with(DeleteOptions::default()))
+        {
+            Ok(()) => report.collected.push(snapshot),
+            Err(e) => report.failed.push((snapshot, e)),
+        }
+    }
+    Ok(report)
+}
+
+/// Check whether a subvolume with a UUID exists, through the UUID tree.
+fn uuid_exists(fd: RawFd, uuid: Uuid) -> Result<bool> {
+    let bytes = uuid.as_bytes();
+    let objectid = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+    let offset = u64::from_le_bytes(bytes[8..].try_into().unwrap());
+    let item = SearchKey::new(search::BTRFS_UUID_TREE_OBJECTID)
+        .objectids(objectid, objectid)
+        .types(search::BTRFS_UUID_KEY_SUBVOL, search::BTRFS_UUID_KEY_SUBVOL)
+        .offsets(offset, offset)
+        .search(fd)
+        .next()
+        .transpose()?;
+    Ok(item.is_some())
+}
+
+/// Match a name against a pattern of `*` and `?` wildcards.
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => {
+            glob_match(rest, name) || (!name.is_empty() && glob_match(pattern, &name[1..]))
+        }
+        (Some((b'?', rest)), Some((_, name_rest))) => glob_match(rest, name_rest),
+        (Some((p, rest)), Some((n, name_rest))) => p == n && glob_match(rest, name_rest),
+        _ => false,
+    }
+}
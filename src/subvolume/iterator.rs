@@ -1,17 +1,32 @@
 use crate::bindings;
 use crate::common;
+use crate::common::LibString;
 use crate::error::GlueError;
 use crate::error::LibError;
 use crate::error::LibErrorCode;
+use crate::subvolume::empty_raw_info;
 use crate::subvolume::Subvolume;
+use crate::subvolume::SubvolumeInfo;
 use crate::Result;
 
 use std::convert::TryFrom;
+use std::ops::Bound;
+use std::ops::ControlFlow;
+use std::ops::RangeBounds;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::path::PathBuf;
 
 use bindings::btrfs_util_create_subvolume_iterator;
+use bindings::btrfs_util_create_subvolume_iterator_fd;
 use bindings::btrfs_util_destroy_subvolume_iterator;
+use bindings::btrfs_util_subvolume_info;
 use bindings::btrfs_util_subvolume_iterator;
 use bindings::btrfs_util_subvolume_iterator_next;
+use bindings::btrfs_util_subvolume_iterator_next_info;
+
+use chrono::NaiveDateTime;
+use uuid::Uuid;
 
 bitflags! {
     /// Subvolume iterator options
@@ -21,11 +36,31 @@ bitflags! {
     }
 }
 
+/// Order in which a subvolume iterator visits subvolumes.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum IterOrder {
+    /// Parents are visited before their children.
+    #[default]
+    PreOrder,
+    /// Children are visited before their parents. Useful for recursive deletion or bottom-up
+    /// accounting.
+    PostOrder,
+}
+
+impl From<IterOrder> for SubvolumeIteratorFlags {
+    fn from(order: IterOrder) -> Self {
+        match order {
+            IterOrder::PreOrder => SubvolumeIteratorFlags::empty(),
+            IterOrder::PostOrder => SubvolumeIteratorFlags::POST_ORDER,
+        }
+    }
+}
+
 /// Wrapper around the raw subvolume iterator
-struct RawIterator(*mut btrfs_util_subvolume_iterator);
+pub(super) struct RawIterator(*mut btrfs_util_subvolume_iterator);
 
 impl RawIterator {
-    fn next(&self) -> Result<Subvolume> {
+    pub(super) fn next(&self) -> Result<(LibString, u64)> {
         let mut str_ptr: *mut std::os::raw::c_char = std::ptr::null_mut();
         let mut id: u64 = 0;
 
@@ -34,12 +69,80 @@ impl RawIterator {
         });
 
         glue_error!(str_ptr.is_null(), GlueError::NullPointerReceived);
+        let path: LibString = unsafe { LibString::from_raw(str_ptr) };
         glue_error!(id < bindings::BTRFS_FS_TREE_OBJECTID, GlueError::BadId(id));
 
-        Ok(Subvolume::new(id))
+        Ok((path, id))
+    }
+
+    fn next_info(&self) -> Result<(LibString, SubvolumeInfo)> {
+        let mut info: Box<btrfs_util_subvolume_info> = empty_raw_info();
+        let path = self.next_info_into(&mut info)?;
+        Ok((path, SubvolumeInfo::try_from(info)?))
+    }
+
+    /// Retrieve the next entry into a caller-provided information struct, so that it can be
+    /// reused between calls.
+    fn next_info_into(&self, info: &mut btrfs_util_subvolume_info) -> Result<LibString> {
+        let mut str_ptr: *mut std::os::raw::c_char = std::ptr::null_mut();
+
+        unsafe_wrapper!(errcode, {
+            errcode = btrfs_util_subvolume_iterator_next_info(self.0, &mut str_ptr, info);
+        });
+
+        glue_error!(str_ptr.is_null(), GlueError::NullPointerReceived);
+        let path: LibString = unsafe { LibString::from_raw(str_ptr) };
+        glue_error!(
+            info.id < bindings::BTRFS_FS_TREE_OBJECTID,
+            GlueError::BadId(info.id)
+        );
+
+        Ok(path)
+    }
+
+    /// Get the next entry which passes the filters.
+    ///
+    /// Information about the subvolume is only retrieved if one of the filters requires it.
+    fn next_filtered(&self, filters: &Filters) -> Result<(LibString, Subvolume)> {
+        loop {
+            if filters.needs_info() {
+                let (path, info) = self.next_info()?;
+                if filters.matches_entry(path.as_path(), info.id) && filters.matches_info(&info) {
+                    return Ok((path, info.into()));
+                }
+            } else {
+                let (path, id) = self.next()?;
+                if filters.matches_entry(path.as_path(), id) {
+                    return Ok((path, Subvolume::new(id)));
+                }
+            }
+        }
+    }
+
+    /// Drain the iterator into a vector of subvolumes which pass the filters.
+    fn collect(self, filters: &Filters) -> Result<Vec<Subvolume>> {
+        let mut items = Vec::new();
+        loop {
+            match self.next_filtered(filters) {
+                Ok((_, val)) => items.push(val),
+                Err(e) => {
+                    if e == LibError::StopIteration {
+                        break;
+                    } else {
+                        return Result::Err(e);
+                    }
+                }
+            }
+        }
+        Ok(items)
     }
 }
 
+// The raw iterator is an open file descriptor plus heap-allocated search state owned exclusively by
+// this struct. libbtrfsutil keeps no thread-local state for it, so it can be moved to another
+// thread. It is not Sync, since advancing it mutates that state.
+unsafe impl Send for RawIterator {}
+
 impl Drop for RawIterator {
     fn drop(&mut self) {
         unsafe {
@@ -52,51 +155,413 @@ impl Drop for RawIterator {
 pub struct SubvolumeIterator(Vec<Subvolume>);
 
 impl SubvolumeIterator {
+    /// Create a builder for a subvolume iterator over the subvolumes beneath a subvolume.
+    ///
+    /// This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn builder(subvolume: Subvolume) -> SubvolumeIteratorBuilder {
+        SubvolumeIteratorBuilder::new(IteratorSource::Subvolume(subvolume))
+    }
+
+    /// Create a builder for a subvolume iterator over the subvolumes beneath the subvolume
+    /// containing a path.
+    ///
+    /// On Linux 4.18 and newer, this does not require elevated privileges. Only subvolumes which
+    /// are accessible to the caller are listed.
+    pub fn builder_for_path<T: Into<PathBuf>>(path: T) -> SubvolumeIteratorBuilder {
+        SubvolumeIteratorBuilder::new(IteratorSource::Path(path.into()))
+    }
+
+    /// Create a builder for a subvolume iterator over the subvolumes beneath the subvolume
+    /// containing an open file descriptor.
+    ///
+    /// The file descriptor is not closed and must stay open for as long as the iterator is in use.
+    /// On Linux 4.18 and newer, this does not require elevated privileges.
+    pub fn builder_for_fd(fd: RawFd) -> SubvolumeIteratorBuilder {
+        SubvolumeIteratorBuilder::new(IteratorSource::Fd(fd))
+    }
+
     /// Create a new subvolume iterator.
-    #[allow(clippy::identity_conversion)]
     pub fn create(subvolume: Subvolume, flags: Option<SubvolumeIteratorFlags>) -> Result<Self> {
-        let path_cstr = common::path_to_cstr(subvolume.path()?)?;
-        let flags_val = if let Some(val) = flags { val.bits() } else { 0 };
+        let mut builder = Self::builder(subvolume);
+        if let Some(val) = flags {
+            builder.flags = val;
+        }
+        builder.build()
+    }
+}
+
+impl IntoIterator for SubvolumeIterator {
+    type Item = Subvolume;
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+/// A lazy subvolume iterator which yields the path and information of every subvolume.
+///
+/// Paths are relative to the subvolume the iterator was created for. Created by
+/// [SubvolumeIteratorBuilder::iter_with_info].
+///
+/// [SubvolumeIteratorBuilder::iter_with_info]: struct.SubvolumeIteratorBuilder.html#method.iter_with_info
+pub struct SubvolumeInfoIterator {
+    raw: RawIterator,
+    filters: Filters,
+    last_id: Option<u64>,
+}
+
+impl SubvolumeInfoIterator {
+    /// Get the id of the last subvolume yielded by this iterator.
+    ///
+    /// This can be stored as a checkpoint and passed to [SubvolumeIteratorBuilder::start_after]
+    /// to resume the iteration later.
+    ///
+    /// [SubvolumeIteratorBuilder::start_after]: struct.SubvolumeIteratorBuilder.html#method.start_after
+    pub fn last_id(&self) -> Option<u64> {
+        self.last_id
+    }
+}
+
+impl Iterator for SubvolumeInfoIterator {
+    type Item = Result<(PathBuf, SubvolumeInfo)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.raw.next_info() {
+                Ok((path, info)) => {
+                    if self.filters.matches_entry(path.as_path(), info.id)
+                        && self.filters.matches_info(&info)
+                    {
+                        self.last_id = Some(info.id);
+                        return Some(Ok((path.as_path().to_path_buf(), info)));
+                    }
+                }
+                Err(e) => {
+                    if e == LibError::StopIteration {
+                        return None;
+                    } else {
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Filters applied to the subvolumes visited by an iterator.
+#[derive(Clone, Debug)]
+pub(super) struct Filters {
+    path_prefix: Option<PathBuf>,
+    id_range: (Bound<u64>, Bound<u64>),
+    read_only: bool,
+    snapshots: bool,
+    created_after: Option<NaiveDateTime>,
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Self {
+            path_prefix: None,
+            id_range: (Bound::Unbounded, Bound::Unbounded),
+            read_only: false,
+            snapshots: false,
+            created_after: None,
+        }
+    }
+}
+
+impl Filters {
+    /// Whether any of the filters needs information about the subvolume.
+    fn needs_info(&self) -> bool {
+        self.read_only || self.snapshots || self.created_after.is_some()
+    }
+
+    /// Apply the filters which only need the path and id of a subvolume.
+    pub(super) fn matches_entry(&self, path: &Path, id: u64) -> bool {
+        if let Some(prefix) = &self.path_prefix {
+            if !path.starts_with(prefix) {
+                return false;
+            }
+        }
+        self.id_range.contains(&id)
+    }
+
+    /// Apply the filters which need information about a subvolume.
+    pub(super) fn matches_info(&self, info: &SubvolumeInfo) -> bool {
+        if self.read_only && !info.is_read_only() {
+            return false;
+        }
+        if self.snapshots && !info.is_snapshot() {
+            return false;
+        }
+        if let Some(created_after) = self.created_after {
+            if info.otime <= created_after {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Where a subvolume iterator starts from.
+#[derive(Clone, Debug)]
+pub(super) enum IteratorSource {
+    /// A subvolume, resolved through the filesystem root.
+    Subvolume(Subvolume),
+    /// The subvolume containing a path.
+    Path(PathBuf),
+    /// The subvolume containing an open file descriptor.
+    Fd(RawFd),
+}
+
+/// Builder for a [SubvolumeIterator].
+///
+/// [SubvolumeIterator]: struct.SubvolumeIterator.html
+#[derive(Clone, Debug)]
+pub struct SubvolumeIteratorBuilder {
+    pub(super) source: IteratorSource,
+    flags: SubvolumeIteratorFlags,
+    pub(super) filters: Filters,
+    start_after: Option<u64>,
+}
+
+impl SubvolumeIteratorBuilder {
+    fn new(source: IteratorSource) -> Self {
+        Self {
+            source,
+            flags: IterOrder::default().into(),
+            filters: Filters::default(),
+            start_after: None,
+        }
+    }
+
+    /// Resume an iteration after the subvolume with an id, as returned by
+    /// [SubvolumeInfoIterator::last_id].
+    ///
+    /// The subvolumes up to and including that subvolume are skipped without retrieving
+    /// information about them. If that subvolume no longer exists, creating the iterator fails with
+    /// [LibError::SubvolumeNotFound].
+    ///
+    /// [SubvolumeInfoIterator::last_id]: struct.SubvolumeInfoIterator.html#method.last_id
+    /// [LibError::SubvolumeNotFound]: ../error/enum.LibError.html#variant.SubvolumeNotFound
+    pub fn start_after(mut self, id: u64) -> Self {
+        self.start_after = Some(id);
+        self
+    }
+
+    /// Set the order in which subvolumes are visited.
+    pub fn order(mut self, order: IterOrder) -> Self {
+        self.flags = order.into();
+        self
+    }
+
+    /// Only visit subvolumes whose path, relative to the subvolume the iterator was created for,
+    /// starts with a prefix.
+    pub fn path_prefix<T: Into<PathBuf>>(mut self, prefix: T) -> Self {
+        self.filters.path_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Only visit subvolumes whose id is within a range.
+    pub fn id_range<R: RangeBounds<u64>>(mut self, range: R) -> Self {
+        self.filters.id_range = (range.start_bound().cloned(), range.end_bound().cloned());
+        self
+    }
+
+    /// Only visit read-only subvolumes.
+    pub fn read_only(mut self) -> Self {
+        self.filters.read_only = true;
+        self
+    }
+
+    /// Only visit snapshots, i.e. subvolumes with a parent UUID.
+    pub fn snapshots(mut self) -> Self {
+        self.filters.snapshots = true;
+        self
+    }
+
+    /// Only visit subvolumes created after a point in time.
+    pub fn created_after(mut self, time: NaiveDateTime) -> Self {
+        self.filters.created_after = Some(time);
+        self
+    }
+
+    /// Create the subvolume iterator.
+    ///
+    /// Path and id filters are applied without retrieving information about each subvolume.
+    pub fn build(self) -> Result<SubvolumeIterator> {
+        let filters = self.filters.clone();
+        Ok(SubvolumeIterator(self.create_raw()?.collect(&filters)?))
+    }
+
+    /// Create a lazy subvolume iterator which also retrieves information about every subvolume.
+    ///
+    /// This avoids a second lookup per subvolume when information about all of them is needed.
+    pub fn iter_with_info(self) -> Result<SubvolumeInfoIterator> {
+        let filters = self.filters.clone();
+        Ok(SubvolumeInfoIterator {
+            raw: self.create_raw()?,
+            filters,
+            last_id: None,
+        })
+    }
+
+    /// Count the subvolumes, without collecting them.
+    ///
+    /// Information about the subvolumes is only retrieved if one of the filters requires it.
+    pub fn count(self) -> Result<usize> {
+        let filters = self.filters.clone();
+        let raw = self.create_raw()?;
+        let mut count: usize = 0;
+        loop {
+            match raw.next_filtered(&filters) {
+                Ok(_) => count += 1,
+                Err(e) => {
+                    if e == LibError::StopIteration {
+                        return Ok(count);
+                    } else {
+                        return Result::Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Visit every subvolume with a callback, stopping early if it returns
+    /// [ControlFlow::Break].
+    ///
+    /// Unlike [iter_with_info], no path is allocated for every subvolume and the buffer used for
+    /// retrieving subvolume information is reused. The path passed to the callback is relative to
+    /// the subvolume the iteration was created for. Returns the value the callback stopped with,
+    /// if any.
+    ///
+    /// [ControlFlow::Break]: https://doc.rust-lang.org/stable/std/ops/enum.ControlFlow.html
+    /// [iter_with_info]: #method.iter_with_info
+    pub fn for_each<B, F>(self, mut f: F) -> Result<Option<B>>
+    where
+        F: FnMut(&Path, &SubvolumeInfo) -> ControlFlow<B>,
+    {
+        let filters = self.filters.clone();
+        let raw = self.create_raw()?;
+        let mut raw_info: Box<btrfs_util_subvolume_info> = empty_raw_info();
+
+        loop {
+            let path = match raw.next_info_into(&mut raw_info) {
+                Ok(val) => val,
+                Err(e) => {
+                    if e == LibError::StopIteration {
+                        return Ok(None);
+                    } else {
+                        return Result::Err(e);
+                    }
+                }
+            };
+            let info = SubvolumeInfo::try_from(&*raw_info)?;
+            if !filters.matches_entry(path.as_path(), info.id) || !filters.matches_info(&info) {
+                continue;
+            }
+            if let ControlFlow::Break(val) = f(path.as_path(), &info) {
+                return Ok(Some(val));
+            }
+        }
+    }
+
+    pub(super) fn create_raw(self) -> Result<RawIterator> {
+        let start_after = self.start_after;
+        let flags_val = self.flags.bits();
         let mut iterator_ptr: *mut btrfs_util_subvolume_iterator = std::ptr::null_mut();
 
-        unsafe_wrapper!(errcode, {
-            errcode = btrfs_util_create_subvolume_iterator(
-                path_cstr.as_ptr(),
-                subvolume.id(),
-                flags_val,
-                &mut iterator_ptr,
-            );
-        });
+        match self.source {
+            IteratorSource::Subvolume(subvolume) => {
+                let path_cstr = common::path_to_cstr(subvolume.path()?)?;
+                unsafe_wrapper!(errcode, {
+                    errcode = btrfs_util_create_subvolume_iterator(
+                        path_cstr.as_ptr(),
+                        subvolume.id(),
+                        flags_val,
+                        &mut iterator_ptr,
+                    );
+                });
+            }
+            IteratorSource::Path(path) => {
+                let path_cstr = common::path_to_cstr(path)?;
+                unsafe_wrapper!(errcode, {
+                    errcode = btrfs_util_create_subvolume_iterator(
+                        path_cstr.as_ptr(),
+                        0,
+                        flags_val,
+                        &mut iterator_ptr,
+                    );
+                });
+            }
+            IteratorSource::Fd(fd) => {
+                unsafe_wrapper!(errcode, {
+                    errcode = btrfs_util_create_subvolume_iterator_fd(
+                        fd,
+                        0,
+                        flags_val,
+                        &mut iterator_ptr,
+                    );
+                });
+            }
+        }
 
         glue_error!(iterator_ptr.is_null(), GlueError::NullPointerReceived);
 
-        let items: Vec<Subvolume> = {
-            let mut items = Vec::new();
-            let raw_iterator = RawIterator(iterator_ptr);
+        let raw = RawIterator(iterator_ptr);
+        if let Some(start_after) = start_after {
             loop {
-                match raw_iterator.next() {
-                    Ok(val) => items.push(val),
-                    Err(e) => {
-                        if e == LibError::StopIteration.into() {
+                match raw.next() {
+                    Ok((_, id)) => {
+                        if id == start_after {
                             break;
+                        }
+                    }
+                    Err(e) => {
+                        if e == LibError::StopIteration {
+                            return Result::Err(LibError::SubvolumeNotFound.into());
                         } else {
                             return Result::Err(e);
                         }
                     }
                 }
             }
-            items
-        };
+        }
 
-        Ok(Self(items))
+        Ok(raw)
     }
 }
 
-impl IntoIterator for SubvolumeIterator {
-    type Item = Subvolume;
-    type IntoIter = std::vec::IntoIter<Self::Item>;
+/// Visit every subvolume beneath a subvolume with a callback.
+///
+/// Shorthand for [SubvolumeIteratorBuilder::for_each] without any filters.
+///
+/// [SubvolumeIteratorBuilder::for_each]: struct.SubvolumeIteratorBuilder.html#method.for_each
+pub fn for_each_subvolume<B, F>(top: Subvolume, f: F) -> Result<Option<B>>
+where
+    F: FnMut(&Path, &SubvolumeInfo) -> ControlFlow<B>,
+{
+    SubvolumeIterator::builder(top).for_each(f)
+}
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
-    }
+/// Count the subvolumes beneath a subvolume.
+pub fn count_subvolumes(top: Subvolume) -> Result<usize> {
+    SubvolumeIterator::builder(top).count()
+}
+
+/// Check whether a snapshot of the subvolume with a UUID exists beneath a subvolume.
+///
+/// The iteration stops at the first snapshot found.
+pub fn snapshot_exists_for(top: Subvolume, uuid: &Uuid) -> Result<bool> {
+    let found = SubvolumeIterator::builder(top)
+        .snapshots()
+        .for_each(|_, info| {
+            if info.parent_uuid.as_ref() == Some(uuid) {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })?;
+    Ok(found.is_some())
 }
@@ -0,0 +1,178 @@
+use crate::filesystem::Filesystem;
+use crate::qgroup::Qgroup;
+use crate::qgroup::QgroupId;
+use crate::send::subvolume_root;
+use crate::subvolume::SubvolumeInfo;
+use crate::subvolume::SubvolumeIterator;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Version of the [SnapshotManifest] format. Bumped whenever a field is removed or changes
+/// meaning.
+///
+/// [SnapshotManifest]: struct.SnapshotManifest.html
+pub const SNAPSHOT_MANIFEST_VERSION: u32 = 1;
+
+/// Where the snapshots of a [SnapshotManifest] were taken.
+///
+/// [SnapshotManifest]: struct.SnapshotManifest.html
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotOrigin {
+    /// Name of the host.
+    pub host: Option<String>,
+    /// UUID of the filesystem.
+    pub fsid: Option<Uuid>,
+}
+
+/// A snapshot in a [SnapshotManifest].
+///
+/// [SnapshotManifest]: struct.SnapshotManifest.html
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ManifestSnapshot {
+    /// Path of the snapshot.
+    pub path: PathBuf,
+    /// UUID of the snapshot.
+    pub uuid: Uuid,
+    /// UUID of the subvolume the snapshot was taken of.
+    pub parent_uuid: Option<Uuid>,
+    /// UUID of the subvolume the snapshot was received from.
+    pub received_uuid: Option<Uuid>,
+    /// Transaction id of the last change of the snapshot.
+    pub ctransid: u64,
+    /// Transaction id of the subvolume the snapshot was received from.
+    pub stransid: Option<u64>,
+    /// Time the snapshot was created.
+    pub created: NaiveDateTime,
+    /// Whether the snapshot is read-only.
+    pub read_only: bool,
+    /// Space referenced by the snapshot in bytes, if quotas are enabled.
+    pub referenced: Option<u64>,
+    /// Space used exclusively by the snapshot in bytes, if quotas are enabled.
+    pub exclusive: Option<u64>,
+}
+
+impl ManifestSnapshot {
+    /// Describe a snapshot from its information, without sizes.
+    pub fn new<T: Into<PathBuf>>(path: T, info: &SubvolumeInfo) -> Self {
+        Self {
+            path: path.into(),
+            uuid: info.uuid,
+            parent_uuid: info.parent_uuid,
+            received_uuid: info.received_uuid,
+            ctransid: info.ctransid,
+            stransid: info.stransid,
+            created: info.otime,
+            read_only: info.is_read_only(),
+            referenced: None,
+            exclusive: None,
+        }
+    }
+}
+
+/// A machine-readable description of snapshots, for interchange between hosts.
+///
+/// Serialized as a single JSON document. UUIDs are hyphenated strings and timestamps are ISO 8601
+/// strings without a timezone. Documents of a newer version are refused, while unknown fields are
+/// ignored, so fields may be added without bumping the version.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    /// Version of the manifest format, see [SNAPSHOT_MANIFEST_VERSION].
+    ///
+    /// [SNAPSHOT_MANIFEST_VERSION]: constant.SNAPSHOT_MANIFEST_VERSION.html
+    pub version: u32,
+    /// Where the snapshots were taken.
+    pub origin: SnapshotOrigin,
+    /// Described snapshots.
+    pub snapshots: Vec<ManifestSnapshot>,
+}
+
+impl SnapshotManifest {
+    /// Create an empty manifest.
+    pub fn new(origin: SnapshotOrigin) -> Self {
+        Self {
+            version: SNAPSHOT_MANIFEST_VERSION,
+            origin,
+            snapshots: Vec::new(),
+        }
+    }
+
+    /// Describe the snapshots and received subvolumes beneath a directory, with their sizes if
+    /// quotas are enabled.
+    ///
+    /// This requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn collect<T: AsRef<Path>>(dir: T) -> Result<Self> {
+        let dir = dir.as_ref().canonicalize()?;
+        let fs = Filesystem::open(&dir)?;
+        let origin = SnapshotOrigin {
+            host: fs::read_to_string("/proc/sys/kernel/hostname")
+                .ok()
+                .map(|host| host.trim_end().to_owned()),
+            fsid: Some(fs.info()?.fsid),
+        };
+        // Quotas may be disabled, which only leaves the sizes out.
+        let usage: HashMap<u64, (u64, u64)> = Qgroup::list(&fs)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|usage| (usage.qgroupid.into(), (usage.referenced, usage.exclusive)))
+            .collect();
+
+        let root = subvolume_root(&dir)?;
+        let mut manifest = Self::new(origin);
+        for entry in SubvolumeIterator::builder_for_path(dir.clone()).iter_with_info()? {
+            let (path, info) = entry?;
+            let path = root.join(path);
+            // Received snapshots are only snapshots of their origin.
+            if !path.starts_with(&dir) || !(info.is_snapshot() || info.received_uuid.is_some()) {
+                continue;
+            }
+            let mut snapshot = ManifestSnapshot::new(path, &info);
+            if let Some((referenced, exclusive)) = usage.get(&QgroupId::new(0, info.id).into()) {
+                snapshot.referenced = Some(*referenced);
+                snapshot.exclusive = Some(*exclusive);
+            }
+            manifest.snapshots.push(snapshot);
+        }
+        Ok(manifest)
+    }
+
+    /// Read a manifest from a JSON document.
+    pub fn read_json<R: Read>(reader: R) -> Result<Self> {
+        let manifest: Self = serde_json::from_reader(reader)?;
+        if manifest.version > SNAPSHOT_MANIFEST_VERSION {
+            return Err(BtrfsUtilError::InvalidManifest(format!(
+                "unsupported version {}",
+                manifest.version
+            )));
+        }
+        Ok(manifest)
+    }
+
+    /// Write this manifest as a JSON document.
+    pub fn write_json<W: Write>(&self, writer: W) -> Result<()> {
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    /// Find a snapshot by its UUID.
+    pub fn get(&self, uuid: Uuid) -> Option<&ManifestSnapshot> {
+        self.snapshots.iter().find(|snapshot| snapshot.uuid == uuid)
+    }
+
+    /// Find the snapshots received from a snapshot with a UUID.
+    pub fn received_from(&self, uuid: Uuid) -> impl Iterator<Item = &ManifestSnapshot> {
+        self.snapshots
+            .iter()
+            .filter(move |snapshot| snapshot.received_uuid == Some(uuid))
+    }
+}
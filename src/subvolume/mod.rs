@@ -1,10 +1,45 @@
 //! Btrfs subvolumes
 
+#[cfg(feature = "async")]
+mod async_ops;
+mod budget;
+mod consistent;
+mod diff;
+mod find_new;
+mod gc;
 #[macro_use]
 mod iterator;
+#[cfg(feature = "json")]
+mod manifest;
+mod mount_point;
+mod options;
+mod pair;
+#[cfg(feature = "rayon")]
+mod parallel;
+#[cfg(feature = "json")]
+mod report;
+#[cfg(feature = "tokio")]
+mod stream;
 mod subvol;
 mod subvol_info;
+mod tree;
 
+pub use budget::*;
+pub use consistent::*;
+pub use diff::*;
+pub use find_new::*;
+pub use gc::*;
 pub use iterator::*;
+#[cfg(feature = "json")]
+pub use manifest::*;
+pub use options::*;
+pub use pair::*;
+#[cfg(feature = "rayon")]
+pub use parallel::*;
+#[cfg(feature = "json")]
+pub use report::*;
+#[cfg(feature = "tokio")]
+pub use stream::*;
 pub use subvol::*;
 pub use subvol_info::*;
+pub use tree::*;
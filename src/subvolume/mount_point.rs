@@ -0,0 +1,52 @@
+use crate::filesystem::Filesystem;
+use crate::mount::btrfs_mounts;
+use crate::subvolume::Subvolume;
+use crate::Result;
+
+use std::path::Path;
+use std::path::PathBuf;
+
+impl Subvolume {
+    /// Get the paths through which this subvolume is visible, one per mount of its filesystem
+    /// which exposes it.
+    ///
+    /// Mounts restricted to a subvolume with `subvol=` or `subvolid=` are taken into account, so
+    /// subvolumes which are not reachable from a mount of the filesystem root are found too.
+    /// Paths through mounts of the subvolume itself come first, then by increasing depth.
+    pub fn mount_points(&self) -> Result<Vec<PathBuf>> {
+        // Subvolumes are resolved through the filesystem mounted at the root directory.
+        let fs_path = self.path()?;
+        let fsid = Filesystem::open("/")?.info()?.fsid;
+        let mounts = btrfs_mounts()?;
+        let root_device = mounts
+            .iter()
+            .rev()
+            .find(|mount| mount.mount_point == Path::new("/"))
+            .map(|mount| mount.device.clone());
+
+        let mut visible: Vec<(usize, PathBuf)> = Vec::new();
+        for mount in &mounts {
+            let same_fs = match mount.fsid {
+                Some(mount_fsid) => mount_fsid == fsid,
+                None => root_device.as_ref() == Some(&mount.device),
+            };
+            if !same_fs {
+                continue;
+            }
+            if let Ok(rest) = fs_path.strip_prefix(&mount.root) {
+                let path = mount.mount_point.join(rest);
+                if !visible.iter().any(|(_, known)| known == &path) {
+                    visible.push((rest.components().count(), path));
+                }
+            }
+        }
+        visible.sort_by_key(|(depth, _)| *depth);
+        Ok(visible.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Get the shortest path through which this subvolume is visible, preferring a mount of the
+    /// subvolume itself, or `None` if no mount exposes it.
+    pub fn mount_point(&self) -> Result<Option<PathBuf>> {
+        Ok(self.mount_points()?.into_iter().next())
+    }
+}
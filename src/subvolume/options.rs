@@ -0,0 +1,188 @@
+use crate::cancel::CancelToken;
+use crate::progress::Progress;
+use crate::qgroup::QgroupInherit;
+use crate::subvolume::DeleteFlags;
+use crate::subvolume::SnapshotFlags;
+
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Options for deleting a subvolume.
+///
+/// Used with [Subvolume::delete_with].
+///
+/// [Subvolume::delete_with]: struct.Subvolume.html#method.delete_with
+#[derive(Clone, Debug, Default)]
+pub struct DeleteOptions {
+    pub(crate) flags: DeleteFlags,
+    pub(crate) sync: bool,
+    pub(crate) cancel: Option<CancelToken>,
+}
+
+impl DeleteOptions {
+    /// Create the default delete options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delete the subvolumes beneath the subvolume as well.
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.flags.set(DeleteFlags::RECURSIVE, recursive);
+        self
+    }
+
+    /// Start a filesystem sync after the deletion and wait for it to commit, so that the deletion
+    /// is durable once the call returns.
+    pub fn sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    /// Stop a recursive deletion between two subvolumes when a token is cancelled, failing with
+    /// [BtrfsUtilError::Cancelled]. The subvolumes beneath are then deleted one at a time,
+    /// children first, so the ones deleted so far stay deleted.
+    ///
+    /// [BtrfsUtilError::Cancelled]: ../error/enum.BtrfsUtilError.html#variant.Cancelled
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+}
+
+impl From<DeleteFlags> for DeleteOptions {
+    fn from(flags: DeleteFlags) -> Self {
+        Self {
+            flags,
+            ..Default::default()
+        }
+    }
+}
+
+/// Progress of a deletion, passed to the progress callback.
+///
+/// Used with [Subvolume::delete_with_progress].
+///
+/// [Subvolume::delete_with_progress]: struct.Subvolume.html#method.delete_with_progress
+#[derive(Clone, Debug)]
+pub struct DeleteProgress {
+    /// Number of subvolumes deleted so far.
+    pub deleted: u64,
+    /// Number of subvolumes to delete, including the subvolumes beneath.
+    pub total: u64,
+    /// Path of the last subvolume deleted, relative to the filesystem root.
+    pub path: Option<PathBuf>,
+}
+
+impl Progress for DeleteProgress {
+    fn phase(&self) -> &'static str {
+        "deleting"
+    }
+
+    fn items_done(&self) -> u64 {
+        self.deleted
+    }
+
+    fn items_total(&self) -> Option<u64> {
+        Some(self.total)
+    }
+
+    fn current_path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+}
+
+/// Options for creating a subvolume.
+///
+/// Used with [Subvolume::create_with].
+///
+/// [Subvolume::create_with]: struct.Subvolume.html#method.create_with
+#[derive(Clone, Debug, Default)]
+pub struct CreateOptions {
+    pub(crate) qgroup: Option<QgroupInherit>,
+    pub(crate) quota_check: bool,
+}
+
+impl CreateOptions {
+    /// Create the default subvolume creation options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the subvolume to the qgroups of an inheritance specifier.
+    pub fn qgroup_inherit(mut self, qgroup: QgroupInherit) -> Self {
+        self.qgroup = Some(qgroup);
+        self
+    }
+
+    /// Check that the qgroups the subvolume is added to have enough quota left before creating it,
+    /// failing with [BtrfsUtilError::QuotaExceeded] otherwise.
+    ///
+    /// [BtrfsUtilError::QuotaExceeded]: ../error/enum.BtrfsUtilError.html#variant.QuotaExceeded
+    pub fn quota_check(mut self, check: bool) -> Self {
+        self.quota_check = check;
+        self
+    }
+}
+
+/// Options for creating a snapshot.
+///
+/// Used with [Subvolume::snapshot_with].
+///
+/// [Subvolume::snapshot_with]: struct.Subvolume.html#method.snapshot_with
+#[derive(Clone, Debug, Default)]
+pub struct SnapshotOptions {
+    pub(crate) flags: SnapshotFlags,
+    pub(crate) qgroup: Option<QgroupInherit>,
+    pub(crate) inherit_parent_qgroups: bool,
+    pub(crate) quota_check: bool,
+}
+
+impl SnapshotOptions {
+    /// Create the default snapshot options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make the snapshot read-only.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.flags.set(SnapshotFlags::READ_ONLY, read_only);
+        self
+    }
+
+    /// Snapshot the subvolumes beneath the subvolume as well.
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.flags.set(SnapshotFlags::RECURSIVE, recursive);
+        self
+    }
+
+    /// Add the snapshot to the qgroups of an inheritance specifier.
+    pub fn qgroup_inherit(mut self, qgroup: QgroupInherit) -> Self {
+        self.qgroup = Some(qgroup);
+        self
+    }
+
+    /// Add the snapshot to the qgroups the source subvolume is a member of, in addition to any
+    /// inheritance specifier.
+    pub fn inherit_parent_qgroups(mut self, inherit: bool) -> Self {
+        self.inherit_parent_qgroups = inherit;
+        self
+    }
+
+    /// Check that the qgroups the snapshot is added to have enough quota left before creating it,
+    /// failing with [BtrfsUtilError::QuotaExceeded] otherwise.
+    ///
+    /// [BtrfsUtilError::QuotaExceeded]: ../error/enum.BtrfsUtilError.html#variant.QuotaExceeded
+    pub fn quota_check(mut self, check: bool) -> Self {
+        self.quota_check = check;
+        self
+    }
+}
+
+impl From<SnapshotFlags> for SnapshotOptions {
+    fn from(flags: SnapshotFlags) -> Self {
+        Self {
+            flags,
+            ..Self::default()
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use crate::common;
+use crate::subvolume::SnapshotOptions;
+use crate::subvolume::Subvolume;
+use crate::Result;
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::str;
+
+use chrono::Local;
+use uuid::Uuid;
+
+/// Extended attribute holding the kind of a paired snapshot, `pre` or `post`.
+pub const PAIR_KIND_XATTR: &str = "user.btrfsutil.pair.kind";
+/// Extended attribute holding the description of a paired snapshot.
+pub const PAIR_DESCRIPTION_XATTR: &str = "user.btrfsutil.pair.description";
+/// Extended attribute of a post snapshot holding the UUID of its pre snapshot.
+pub const PAIR_PRE_XATTR: &str = "user.btrfsutil.pair.pre";
+
+/// Kind of a snapshot of a pair.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PairKind {
+    /// Snapshot taken before a change.
+    Pre,
+    /// Snapshot taken after a change.
+    Post,
+}
+
+impl PairKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            PairKind::Pre => "pre",
+            PairKind::Post => "post",
+        }
+    }
+}
+
+/// A read-only snapshot of a pair, taken before or after a change.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PairedSnapshot {
+    /// Path of the snapshot.
+    pub path: PathBuf,
+    /// UUID of the snapshot.
+    pub uuid: Uuid,
+    /// Kind of the snapshot.
+    pub kind: PairKind,
+    /// Description of the change.
+    pub description: String,
+    /// UUID of the pre snapshot, for a post snapshot.
+    pub pre: Option<Uuid>,
+}
+
+impl PairedSnapshot {
+    /// Read the pair metadata of a snapshot, or `None` if it is not part of a pair.
+    pub fn read<T: AsRef<Path>>(path: T) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        let kind = match common::get_xattr(path, PAIR_KIND_XATTR)?.as_deref() {
+            Some(b"pre") => PairKind::Pre,
+            Some(b"post") => PairKind::Post,
+            _ => return Ok(None),
+        };
+        let description = common::get_xattr(path, PAIR_DESCRIPTION_XATTR)?
+            .map(|value| String::from_utf8_lossy(&value).into_owned())
+            .unwrap_or_default();
+        let pre = common::get_xattr(path, PAIR_PRE_XATTR)?
+            .and_then(|value| str::from_utf8(&value).ok()?.parse().ok());
+        Ok(Some(Self {
+            path: path.to_path_buf(),
+            uuid: Subvolume::get(path)?.info()?.uuid,
+            kind,
+            description,
+            pre,
+        }))
+    }
+
+    /// Take a snapshot of a pair, setting its metadata before making it read-only.
+    fn create(
+        subvol: &Subvolume,
+        path: PathBuf,
+        kind: PairKind,
+        description: &str,
+        pre: Option<Uuid>,
+    ) -> Result<Self> {
+        let snapshot = subvol.snapshot_with(path.as_path(), SnapshotOptions::new())?;
+        let tagged = (|| -> Result<()> {
+            common::set_xattr(&path, PAIR_KIND_XATTR, kind.as_str().as_bytes())?;
+            common::set_xattr(&path, PAIR_DESCRIPTION_XATTR, description.as_bytes())?;
+            if let Some(pre) = pre {
+                common::set_xattr(&path, PAIR_PRE_XATTR, pre.to_string().as_bytes())?;
+            }
+            snapshot.set_ro(true)
+        })();
+        if let Err(e) = tagged {
+            let _ = snapshot.delete(None);
+            return Err(e);
+        }
+        Ok(Self {
+            uuid: Subvolume::get(path.as_path())?.info()?.uuid,
+            path,
+            kind,
+            description: description.to_owned(),
+            pre,
+        })
+    }
+}
+
+/// Guard of a change tracked by a pair of snapshots, created by [snapshot_pair].
+///
+/// Completing the guard takes the post snapshot. If the guard is dropped without being completed,
+/// e.g. because the change failed, the post snapshot is taken anyway, ignoring errors.
+///
+/// [snapshot_pair]: fn.snapshot_pair.html
+#[derive(Debug)]
+pub struct SnapshotPair {
+    subvol: Subvolume,
+    post_path: PathBuf,
+    pre: Option<PairedSnapshot>,
+}
+
+impl SnapshotPair {
+    /// Get the pre snapshot.
+    pub fn pre(&self) -> &PairedSnapshot {
+        self.pre.as_ref().unwrap()
+    }
+
+    /// Take the post snapshot, returning the pre and post snapshots.
+    pub fn complete(mut self) -> Result<(PairedSnapshot, PairedSnapshot)> {
+        let pre = self.pre.take().unwrap();
+        let post = self.post(&pre)?;
+        Ok((pre, post))
+    }
+
+    fn post(&self, pre: &PairedSnapshot) -> Result<PairedSnapshot> {
+        PairedSnapshot::create(
+            &self.subvol,
+            self.post_path.clone(),
+            PairKind::Post,
+            &pre.description,
+            Some(pre.uuid),
+        )
+    }
+}
+
+impl Drop for SnapshotPair {
+    fn drop(&mut self) {
+        if let Some(pre) = self.pre.take() {
+            let _ = self.post(&pre);
+        }
+    }
+}
+
+/// Take the pre snapshot of a change to a subvolume, like package managers do before an update.
+///
+/// The snapshots are taken into a directory, as `<name>.<time>.pre` and `<name>.<time>.post`,
+/// where `<name>` is the file name of the subvolume and `<time>` the time of the pre snapshot.
+/// Their kind, the description and the UUID of the pre snapshot are stored as extended
+/// attributes on the snapshot roots, see [PairedSnapshot::read]. This requires elevated
+/// privileges(CAP_SYS_ADMIN).
+///
+/// [PairedSnapshot::read]: struct.PairedSnapshot.html#method.read
+pub fn snapshot_pair<P: AsRef<Path>, Q: AsRef<Path>>(
+    subvol: P,
+    snapshot_dir: Q,
+    description: &str,
+) -> Result<SnapshotPair> {
+    let subvol_path = subvol.as_ref();
+    let subvol = Subvolume::get(subvol_path)?;
+    let base = format!(
+        "{}.{}",
+        subvol_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        Local::now().naive_local().format("%Y-%m-%dT%H:%M:%S")
+    );
+    let dir = snapshot_dir.as_ref();
+    let pre = PairedSnapshot::create(
+        &subvol,
+        dir.join(format!("{}.pre", base)),
+        PairKind::Pre,
+        description,
+        None,
+    )?;
+    Ok(SnapshotPair {
+        subvol,
+        post_path: dir.join(format!("{}.post", base)),
+        pre: Some(pre),
+    })
+}
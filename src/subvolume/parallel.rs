@@ -0,0 +1,110 @@
+use crate::bindings;
+use crate::common;
+use crate::error::LibError;
+use crate::error::LibErrorCode;
+use crate::subvolume::empty_raw_info;
+use crate::subvolume::IteratorSource;
+use crate::subvolume::Subvolume;
+use crate::subvolume::SubvolumeInfo;
+use crate::subvolume::SubvolumeIterator;
+use crate::subvolume::SubvolumeIteratorBuilder;
+use crate::Result;
+
+use std::convert::TryFrom;
+use std::ffi::CString;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+
+use bindings::btrfs_util_subvolume_info;
+use bindings::btrfs_util_subvolume_info_fd;
+
+use rayon::prelude::*;
+
+/// Handle through which information about subvolumes is retrieved by id.
+enum InfoSource {
+    Path(CString),
+    Fd(RawFd),
+}
+
+impl InfoSource {
+    fn fetch(&self, id: u64) -> Result<SubvolumeInfo> {
+        match self {
+            InfoSource::Path(path_cstr) => SubvolumeInfo::fetch(path_cstr, id),
+            InfoSource::Fd(fd) => {
+                let mut info: Box<btrfs_util_subvolume_info> = empty_raw_info();
+
+                unsafe_wrapper!(errcode, {
+                    errcode = btrfs_util_subvolume_info_fd(*fd, id, &mut *info);
+                });
+
+                SubvolumeInfo::try_from(info)
+            }
+        }
+    }
+}
+
+impl SubvolumeIteratorBuilder {
+    /// List the subvolumes and retrieve information about them in parallel, on the [rayon] thread
+    /// pool.
+    ///
+    /// The listing itself is done up front, without retrieving information, and applies the path
+    /// and id filters. The remaining filters are applied once the information has been retrieved.
+    /// Retrieving information about subvolumes by id requires elevated privileges(CAP_SYS_ADMIN).
+    ///
+    /// [rayon]: https://docs.rs/rayon
+    pub fn par_iter_with_info(
+        self,
+    ) -> Result<impl ParallelIterator<Item = Result<(PathBuf, SubvolumeInfo)>>> {
+        let source = match &self.source {
+            IteratorSource::Subvolume(subvolume) => {
+                InfoSource::Path(common::path_to_cstr(subvolume.path()?)?)
+            }
+            IteratorSource::Path(path) => InfoSource::Path(common::path_to_cstr(path.clone())?),
+            IteratorSource::Fd(fd) => InfoSource::Fd(*fd),
+        };
+        let filters = self.filters.clone();
+        let raw = self.create_raw()?;
+
+        let mut entries: Vec<(PathBuf, u64)> = Vec::new();
+        loop {
+            match raw.next() {
+                Ok((path, id)) => {
+                    if filters.matches_entry(path.as_path(), id) {
+                        entries.push((path.as_path().to_path_buf(), id));
+                    }
+                }
+                Err(e) => {
+                    if e == LibError::StopIteration {
+                        break;
+                    } else {
+                        return Result::Err(e);
+                    }
+                }
+            }
+        }
+
+        Ok(entries
+            .into_par_iter()
+            .filter_map(move |(path, id)| match source.fetch(id) {
+                Ok(info) => {
+                    if filters.matches_info(&info) {
+                        Some(Ok((path, info)))
+                    } else {
+                        None
+                    }
+                }
+                Err(e) => Some(Err(e)),
+            }))
+    }
+}
+
+/// Retrieve information about every subvolume beneath a subvolume in parallel.
+///
+/// Shorthand for [SubvolumeIteratorBuilder::par_iter_with_info] without any filters.
+///
+/// [SubvolumeIteratorBuilder::par_iter_with_info]: struct.SubvolumeIteratorBuilder.html#method.par_iter_with_info
+pub fn par_subvolumes(
+    top: Subvolume,
+) -> Result<impl ParallelIterator<Item = Result<(PathBuf, SubvolumeInfo)>>> {
+    SubvolumeIterator::builder(top).par_iter_with_info()
+}
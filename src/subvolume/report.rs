@@ -0,0 +1,123 @@
+use crate::progress::Progress;
+use crate::subvolume::Subvolume;
+use crate::subvolume::SubvolumeInfo;
+use crate::subvolume::SubvolumeIterator;
+use crate::subvolume::SubvolumeIteratorBuilder;
+use crate::Result;
+
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+/// Version of the [SubvolumeReport] format. Bumped whenever a field is removed or changes meaning.
+///
+/// [SubvolumeReport]: struct.SubvolumeReport.html
+pub const SUBVOLUME_REPORT_VERSION: u32 = 1;
+
+/// A machine-readable listing of subvolumes.
+///
+/// Serialized as a single JSON document. UUIDs are hyphenated strings and timestamps are ISO 8601
+/// strings without a timezone.
+#[derive(Clone, Debug, Serialize)]
+pub struct SubvolumeReport {
+    /// Version of the report format, see [SUBVOLUME_REPORT_VERSION].
+    ///
+    /// [SUBVOLUME_REPORT_VERSION]: constant.SUBVOLUME_REPORT_VERSION.html
+    pub version: u32,
+    /// Listed subvolumes, in iteration order.
+    pub subvolumes: Vec<SubvolumeReportEntry>,
+}
+
+/// A subvolume in a [SubvolumeReport].
+///
+/// [SubvolumeReport]: struct.SubvolumeReport.html
+#[derive(Clone, Debug, Serialize)]
+pub struct SubvolumeReportEntry {
+    /// Path of the subvolume, relative to the subvolume the listing was created for.
+    pub path: PathBuf,
+    /// Whether the subvolume is read-only.
+    pub read_only: bool,
+    /// Information about the subvolume. Its fields are serialized inline with the entry.
+    #[serde(flatten)]
+    pub info: SubvolumeInfo,
+}
+
+/// Progress of the listing of a [SubvolumeReport], passed to the progress callback.
+///
+/// Used with [SubvolumeReport::collect_with_progress].
+///
+/// [SubvolumeReport]: struct.SubvolumeReport.html
+/// [SubvolumeReport::collect_with_progress]: struct.SubvolumeReport.html#method.collect_with_progress
+#[derive(Clone, Debug)]
+pub struct ReportProgress {
+    /// Number of subvolumes listed so far.
+    pub subvolumes: u64,
+    /// Path of the last subvolume listed, relative to the subvolume the listing was created for.
+    pub path: PathBuf,
+}
+
+impl Progress for ReportProgress {
+    fn phase(&self) -> &'static str {
+        "listing"
+    }
+
+    fn items_done(&self) -> u64 {
+        self.subvolumes
+    }
+
+    fn current_path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+impl SubvolumeReport {
+    /// List the subvolumes of an iterator builder into a report.
+    pub fn collect(builder: SubvolumeIteratorBuilder) -> Result<Self> {
+        Self::collect_with_progress(builder, |_| {})
+    }
+
+    /// List the subvolumes of an iterator builder into a report, calling the progress callback
+    /// after each subvolume.
+    pub fn collect_with_progress<F>(
+        builder: SubvolumeIteratorBuilder,
+        mut progress: F,
+    ) -> Result<Self>
+    where
+        F: FnMut(&ReportProgress),
+    {
+        let mut subvolumes = Vec::new();
+        for item in builder.iter_with_info()? {
+            let (path, info) = item?;
+            progress(&ReportProgress {
+                subvolumes: subvolumes.len() as u64 + 1,
+                path: path.clone(),
+            });
+            subvolumes.push(SubvolumeReportEntry {
+                path,
+                read_only: info.is_read_only(),
+                info,
+            });
+        }
+        Ok(Self {
+            version: SUBVOLUME_REPORT_VERSION,
+            subvolumes,
+        })
+    }
+
+    /// Write this report as a JSON document.
+    pub fn write_json<W: Write>(&self, writer: W) -> Result<()> {
+        serde_json::to_writer(writer, self)?;
+        Ok(())
+    }
+}
+
+/// List every subvolume beneath a subvolume and write the listing as a JSON document.
+///
+/// See [SubvolumeReport] for the format of the document.
+///
+/// [SubvolumeReport]: struct.SubvolumeReport.html
+pub fn list_subvolumes_json<W: Write>(top: Subvolume, writer: W) -> Result<()> {
+    SubvolumeReport::collect(SubvolumeIterator::builder(top))?.write_json(writer)
+}
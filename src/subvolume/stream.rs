@@ -0,0 +1,73 @@
+use crate::subvolume::Subvolume;
+use crate::subvolume::SubvolumeInfo;
+use crate::subvolume::SubvolumeIterator;
+use crate::subvolume::SubvolumeIteratorBuilder;
+use crate::Result;
+
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+use futures_core::Stream;
+use tokio::sync::mpsc;
+
+/// Number of subvolumes buffered between the blocking iterator and the stream.
+const STREAM_BUFFER: usize = 64;
+
+/// An asynchronous stream of subvolume paths and information.
+///
+/// The subvolumes are listed by a blocking iterator running on the tokio blocking thread pool.
+/// Dropping the stream stops the iteration. Created by [SubvolumeIteratorBuilder::stream].
+///
+/// [SubvolumeIteratorBuilder::stream]: struct.SubvolumeIteratorBuilder.html#method.stream
+pub struct SubvolumeStream {
+    receiver: mpsc::Receiver<Result<(PathBuf, SubvolumeInfo)>>,
+}
+
+impl Stream for SubvolumeStream {
+    type Item = Result<(PathBuf, SubvolumeInfo)>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl SubvolumeIteratorBuilder {
+    /// List the subvolumes as an asynchronous stream, yielding the same items as
+    /// [iter_with_info].
+    ///
+    /// Must be called from within a tokio runtime.
+    ///
+    /// [iter_with_info]: #method.iter_with_info
+    pub fn stream(self) -> SubvolumeStream {
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+
+        tokio::task::spawn_blocking(move || {
+            let iterator = match self.iter_with_info() {
+                Ok(val) => val,
+                Err(e) => {
+                    let _ = sender.blocking_send(Err(e));
+                    return;
+                }
+            };
+            for item in iterator {
+                if sender.blocking_send(item).is_err() {
+                    break;
+                }
+            }
+        });
+
+        SubvolumeStream { receiver }
+    }
+}
+
+/// List every subvolume beneath a subvolume as an asynchronous stream.
+///
+/// Shorthand for [SubvolumeIteratorBuilder::stream] without any filters. Must be called from
+/// within a tokio runtime.
+///
+/// [SubvolumeIteratorBuilder::stream]: struct.SubvolumeIteratorBuilder.html#method.stream
+pub fn subvolume_stream(top: Subvolume) -> SubvolumeStream {
+    SubvolumeIterator::builder(top).stream()
+}
@@ -1,15 +1,29 @@
 use crate::bindings;
+use crate::cancel;
 use crate::common;
+use crate::common::LibString;
 use crate::error::GlueError;
 use crate::error::LibError;
 use crate::error::LibErrorCode;
+use crate::qgroup;
+use crate::qgroup::QgroupId;
 use crate::qgroup::QgroupInherit;
+use crate::subvolume::CreateOptions;
+use crate::subvolume::DeleteOptions;
+use crate::subvolume::DeleteProgress;
+use crate::subvolume::IterOrder;
+use crate::subvolume::SnapshotOptions;
 use crate::subvolume::SubvolumeInfo;
 use crate::subvolume::SubvolumeIterator;
+use crate::sync;
+use crate::sync::Transid;
 use crate::Result;
 
 use std::convert::TryFrom;
-use std::ffi::CString;
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
+use std::path::Path;
 use std::path::PathBuf;
 
 use bindings::btrfs_util_create_snapshot;
@@ -27,6 +41,7 @@ use bindings::btrfs_util_subvolume_path;
 
 bitflags! {
     /// Subvolume delete flags.
+    #[derive(Default)]
     pub struct DeleteFlags: i32 {
         /// Recursive.
         const RECURSIVE = bindings::BTRFS_UTIL_DELETE_SUBVOLUME_RECURSIVE as i32;
@@ -34,6 +49,7 @@ bitflags! {
 }
 bitflags! {
     /// Subvolume snapshot flags.
+    #[derive(Default)]
     pub struct SnapshotFlags: i32 {
         /// Read-only.
         const READ_ONLY	= bindings::BTRFS_UTIL_CREATE_SNAPSHOT_READ_ONLY as i32;
@@ -44,7 +60,8 @@ bitflags! {
 
 /// A Btrfs subvolume.
 ///
-/// Internally, this contains just the id of the subvolume.
+/// Internally, this contains just the id of the subvolume. Operations which need a file descriptor
+/// open one for their duration, so a subvolume does not keep its filesystem busy.
 #[derive(Clone, Debug)]
 pub struct Subvolume(u64);
 
@@ -54,9 +71,33 @@ impl Subvolume {
         path: T,
         qgroup: Option<QgroupInherit>,
     ) -> Result<Self> {
-        let path_cstr = common::into_path_to_cstr(path.clone())?;
-        let qgroup_ptr: *mut btrfs_util_qgroup_inherit =
-            if_let_some!(qgroup, val, val.into(), std::ptr::null_mut());
+        Self::create_with(
+            path,
+            CreateOptions {
+                qgroup,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Create a new subvolume with options.
+    pub fn create_with<T: Into<PathBuf> + Clone>(path: T, options: CreateOptions) -> Result<Self> {
+        let path_buf: PathBuf = path.clone().into();
+        let path_cstr = common::path_to_cstr(path_buf.clone())?;
+        let mut qgroup: Option<QgroupInherit> = options.qgroup;
+
+        if options.quota_check {
+            if let Some(inherit) = qgroup.as_ref() {
+                let parent = path_buf.parent().unwrap_or_else(|| Path::new("/"));
+                let dir = File::open(parent)?;
+                check_inherit_quota(dir.as_raw_fd(), inherit)?;
+            }
+        }
+
+        // Borrow the specifier so that it is only destroyed after the call.
+        let qgroup_ptr: *mut btrfs_util_qgroup_inherit = qgroup
+            .as_mut()
+            .map_or(std::ptr::null_mut(), |val| val.into());
 
         unsafe_wrapper!(errcode, {
             errcode = btrfs_util_create_subvolume(
@@ -72,13 +113,77 @@ impl Subvolume {
 
     /// Delete a subvolume.
     pub fn delete(self, flags: Option<DeleteFlags>) -> Result<()> {
-        let path_cstr = common::path_to_cstr(self.path()?)?;
-        let flags_val = if_let_some!(flags, val, val.bits(), 0);
+        self.delete_with(if_let_some!(
+            flags,
+            val,
+            val.into(),
+            DeleteOptions::default()
+        ))
+    }
+
+    /// Delete a subvolume with options.
+    pub fn delete_with(self, options: DeleteOptions) -> Result<()> {
+        if let Some(token) = options.cancel.as_ref() {
+            if options.flags.contains(DeleteFlags::RECURSIVE) {
+                return self.delete_with_progress(options, |_| {});
+            }
+            token.check()?;
+        }
+
+        let path = self.path()?;
+        let path_cstr = common::path_to_cstr(path.clone())?;
+        let flags_val = options.flags.bits();
 
         unsafe_wrapper!(errcode, {
             errcode = btrfs_util_delete_subvolume(path_cstr.as_ptr(), flags_val);
         });
 
+        if options.sync {
+            let parent: PathBuf = path.parent().unwrap_or_else(|| Path::new("/")).into();
+            let transid = sync::start_sync(parent.clone())?;
+            sync::wait_sync(parent, transid)?;
+        }
+
+        Ok(())
+    }
+
+    /// Delete a subvolume with options, reporting progress.
+    ///
+    /// A recursive deletion deletes the subvolumes beneath one at a time, children first, calling
+    /// the progress callback after each, and finally this subvolume. Listing the subvolumes
+    /// beneath requires elevated privileges(CAP_SYS_ADMIN).
+    pub fn delete_with_progress<F>(self, options: DeleteOptions, mut progress: F) -> Result<()>
+    where
+        F: FnMut(&DeleteProgress),
+    {
+        let mut nested = Vec::new();
+        if options.flags.contains(DeleteFlags::RECURSIVE) {
+            nested.extend(
+                SubvolumeIterator::builder(self.clone())
+                    .order(IterOrder::PostOrder)
+                    .build()?,
+            );
+        }
+        let mut state = DeleteProgress {
+            deleted: 0,
+            total: nested.len() as u64 + 1,
+            path: None,
+        };
+        for subvol in nested {
+            cancel::check(options.cancel.as_ref())?;
+            let path = subvol.path()?;
+            subvol.delete_with(DeleteOptions::new())?;
+            state.deleted += 1;
+            state.path = Some(path);
+            progress(&state);
+        }
+
+        cancel::check(options.cancel.as_ref())?;
+        let path = self.path()?;
+        self.delete_with(DeleteOptions::new().sync(options.sync))?;
+        state.deleted += 1;
+        state.path = Some(path);
+        progress(&state);
         Ok(())
     }
 
@@ -99,13 +204,16 @@ impl Subvolume {
 
         glue_error!(ids_ptr.is_null(), GlueError::NullPointerReceived);
 
-        let subvolume_ids: Vec<u64> =
-            unsafe { std::slice::from_raw_parts(ids_ptr, ids_count as usize).to_owned() };
+        let subvolume_ids: Vec<u64> = unsafe {
+            let ids = std::slice::from_raw_parts(ids_ptr, ids_count as usize).to_owned();
+            libc::free(ids_ptr as *mut libc::c_void);
+            ids
+        };
 
         let subvolumes: Vec<Subvolume> = {
             let mut subvolumes: Vec<Subvolume> = Vec::with_capacity(ids_count as usize);
             for item in subvolume_ids {
-                subvolumes.push(Subvolume(item));
+                subvolumes.push(Subvolume::new(item));
             }
             subvolumes
         };
@@ -122,7 +230,7 @@ impl Subvolume {
             errcode = btrfs_util_get_default_subvolume(path_cstr.as_ptr(), &mut id);
         });
 
-        Ok(Subvolume(id))
+        Ok(Subvolume::new(id))
     }
 
     /// Set this subvolume as the default subvolume.
@@ -171,7 +279,7 @@ impl Subvolume {
         glue_error!(id.is_null(), GlueError::NullPointerReceived);
 
         let subvol_id: u64 = unsafe { *id };
-        Ok(Self(subvol_id))
+        Ok(Self::new(subvol_id))
     }
 
     /// Check if a path is a Btrfs subvolume.
@@ -201,11 +309,8 @@ impl Subvolume {
 
         glue_error!(str_ptr.is_null(), GlueError::NullPointerReceived);
 
-        let cstr: CString = unsafe { CString::from_raw(str_ptr) };
-        match cstr.to_str() {
-            Ok(val) => Ok(PathBuf::from(format!("/{}", val))),
-            Err(e) => glue_error!(GlueError::Utf8Error(e)),
-        }
+        let path: LibString = unsafe { LibString::from_raw(str_ptr) };
+        Ok(Path::new("/").join(path.as_path()))
     }
 
     /// Create a snapshot of this subvolume.
@@ -214,12 +319,47 @@ impl Subvolume {
         path: T,
         flags: Option<SnapshotFlags>,
         qgroup: Option<QgroupInherit>,
+    ) -> Result<Self> {
+        let mut options: SnapshotOptions = if_let_some!(flags, val, val.into(), Default::default());
+        options.qgroup = qgroup;
+        self.snapshot_with(path, options)
+    }
+
+    /// Create a snapshot of this subvolume with options.
+    pub fn snapshot_with<T: Into<PathBuf> + Clone>(
+        &self,
+        path: T,
+        options: SnapshotOptions,
     ) -> Result<Self> {
         let path_src_cstr = common::path_to_cstr(self.path()?)?;
         let path_dest_cstr = common::into_path_to_cstr(path.clone())?;
-        let flags_val = if_let_some!(flags, val, val.bits(), 0);
-        let qgroup_ptr: *mut btrfs_util_qgroup_inherit =
-            if_let_some!(qgroup, val, val.into(), std::ptr::null_mut());
+        let flags_val = options.flags.bits();
+        let mut qgroup: Option<QgroupInherit> = options.qgroup;
+
+        if options.inherit_parent_qgroups {
+            let parents =
+                qgroup::parent_qgroups(self.open()?.as_raw_fd(), QgroupId::for_subvolume(self))?;
+            if !parents.is_empty() {
+                let inherit = match qgroup.as_mut() {
+                    Some(inherit) => inherit,
+                    None => qgroup.insert(QgroupInherit::create()?),
+                };
+                for parent in parents {
+                    inherit.add(parent.into())?;
+                }
+            }
+        }
+
+        if options.quota_check {
+            if let Some(inherit) = qgroup.as_ref() {
+                check_inherit_quota(self.open()?.as_raw_fd(), inherit)?;
+            }
+        }
+
+        // Borrow the specifier so that it is only destroyed after the call.
+        let qgroup_ptr: *mut btrfs_util_qgroup_inherit = qgroup
+            .as_mut()
+            .map_or(std::ptr::null_mut(), |val| val.into());
 
         unsafe_wrapper!(errcode, {
             errcode = btrfs_util_create_snapshot(
@@ -245,6 +385,26 @@ impl Subvolume {
     pub(crate) fn new(id: u64) -> Self {
         Self(id)
     }
+
+    /// Force a sync on the filesystem containing this subvolume, through its file descriptor.
+    pub fn sync(&self) -> Result<()> {
+        sync::sync_filesystem_fd(self.open()?.as_raw_fd())
+    }
+
+    /// Start a sync on the filesystem containing this subvolume through its file descriptor,
+    /// without waiting for it to commit.
+    ///
+    /// Returns the id of the transaction being committed, which can be passed to [wait_sync_fd].
+    ///
+    /// [wait_sync_fd]: ../sync/fn.wait_sync_fd.html
+    pub fn start_sync(&self) -> Result<Transid> {
+        sync::start_sync_fd(self.open()?.as_raw_fd())
+    }
+
+    /// Open this subvolume, for operations which need a file descriptor.
+    pub(crate) fn open(&self) -> Result<File> {
+        Ok(File::open(self.path()?)?)
+    }
 }
 
 impl Into<Result<SubvolumeIterator>> for Subvolume {
@@ -252,3 +412,37 @@ impl Into<Result<SubvolumeIterator>> for Subvolume {
         SubvolumeIterator::create(self, None)
     }
 }
+
+/// Check the quota of the qgroups of an inheritance specifier, through a file descriptor on the
+/// filesystem.
+fn check_inherit_quota(fd: RawFd, inherit: &QgroupInherit) -> Result<()> {
+    let qgroupids: Vec<QgroupId> = inherit
+        .get_groups()?
+        .into_iter()
+        .map(QgroupId::from)
+        .collect();
+    qgroup::check_quota(fd, &qgroupids)
+}
+
+/// Check whether a path exists and is a Btrfs subvolume.
+///
+/// Unlike [Subvolume::is_subvolume], paths which do not exist or are not on a Btrfs filesystem are
+/// not errors.
+///
+/// [Subvolume::is_subvolume]: struct.Subvolume.html#method.is_subvolume
+pub fn subvolume_exists<T: Into<PathBuf>>(path: T) -> Result<bool> {
+    let path: PathBuf = path.into();
+    if !path.exists() {
+        return Ok(false);
+    }
+    match Subvolume::is_subvolume(path) {
+        Ok(()) => Ok(true),
+        Err(e) => {
+            if e == LibError::NotSubvolume || e == LibError::NotBtrfs {
+                Ok(false)
+            } else {
+                Err(e)
+            }
+        }
+    }
+}
@@ -9,17 +9,21 @@ use crate::Result;
 
 use std::convert::Into;
 use std::convert::TryFrom;
+use std::ffi::CStr;
 
 use bindings::btrfs_util_subvolume_info;
 
 use chrono::NaiveDateTime;
 use chrono::Timelike;
+#[cfg(feature = "json")]
+use serde::Serialize;
 use uuid::Uuid;
 
 /// Information about a Btrfs subvolume.
 ///
 /// Analogous to [btrfs_util_subvolume_info](../bindings/struct.btrfs_util_subvolume_info.html).
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize))]
 pub struct SubvolumeInfo {
     /// ID of this subvolume, unique across the filesystem.
     pub id: u64,
@@ -70,54 +74,71 @@ pub struct SubvolumeInfo {
     pub rtime: Option<NaiveDateTime>,
 }
 
-impl TryFrom<&Subvolume> for SubvolumeInfo {
-    type Error = BtrfsUtilError;
+impl SubvolumeInfo {
+    /// Check whether the root item flags mark this subvolume as read-only.
+    pub fn is_read_only(&self) -> bool {
+        self.flags & bindings::BTRFS_ROOT_SUBVOL_RDONLY != 0
+    }
 
-    fn try_from(src: &Subvolume) -> Result<Self> {
-        let path_cstr = common::path_to_cstr(src.path()?)?;
-        let btrfs_subvolume_info_ptr: *mut btrfs_util_subvolume_info =
-            Box::into_raw(Box::from(btrfs_util_subvolume_info {
-                id: 0,
-                parent_id: 0,
-                dir_id: 0,
-                flags: 0,
-                uuid: [0; 16],
-                parent_uuid: [0; 16],
-                received_uuid: [0; 16],
-                generation: 0,
-                ctransid: 0,
-                otransid: 0,
-                stransid: 0,
-                rtransid: 0,
-                ctime: bindings::timespec {
-                    tv_nsec: 0 as bindings::__time_t,
-                    tv_sec: 0 as bindings::__syscall_slong_t,
-                },
-                otime: bindings::timespec {
-                    tv_nsec: 0 as bindings::__time_t,
-                    tv_sec: 0 as bindings::__syscall_slong_t,
-                },
-                stime: bindings::timespec {
-                    tv_nsec: 0 as bindings::__time_t,
-                    tv_sec: 0 as bindings::__syscall_slong_t,
-                },
-                rtime: bindings::timespec {
-                    tv_nsec: 0 as bindings::__time_t,
-                    tv_sec: 0 as bindings::__syscall_slong_t,
-                },
-            }));
+    /// Check whether this subvolume is a snapshot of another subvolume.
+    pub fn is_snapshot(&self) -> bool {
+        self.parent_uuid.is_some()
+    }
+
+    /// Retrieve information about a subvolume by id, through any path on the same filesystem.
+    pub(crate) fn fetch(path: &CStr, id: u64) -> Result<Self> {
+        let mut info: Box<btrfs_util_subvolume_info> = empty_raw_info();
 
         unsafe_wrapper!(errcode, {
-            errcode =
-                btrfs_util_subvolume_info(path_cstr.as_ptr(), src.id(), btrfs_subvolume_info_ptr);
+            errcode = btrfs_util_subvolume_info(path.as_ptr(), id, &mut *info);
         });
 
-        glue_error!(
-            btrfs_subvolume_info_ptr.is_null(),
-            GlueError::NullPointerReceived
-        );
+        SubvolumeInfo::try_from(info)
+    }
+}
+
+/// Create a zeroed [btrfs_util_subvolume_info] to be filled in by libbtrfsutil.
+///
+/// [btrfs_util_subvolume_info]: ../bindings/struct.btrfs_util_subvolume_info.html
+pub(crate) fn empty_raw_info() -> Box<btrfs_util_subvolume_info> {
+    Box::new(btrfs_util_subvolume_info {
+        id: 0,
+        parent_id: 0,
+        dir_id: 0,
+        flags: 0,
+        uuid: [0; 16],
+        parent_uuid: [0; 16],
+        received_uuid: [0; 16],
+        generation: 0,
+        ctransid: 0,
+        otransid: 0,
+        stransid: 0,
+        rtransid: 0,
+        ctime: bindings::timespec {
+            tv_nsec: 0 as bindings::__time_t,
+            tv_sec: 0 as bindings::__syscall_slong_t,
+        },
+        otime: bindings::timespec {
+            tv_nsec: 0 as bindings::__time_t,
+            tv_sec: 0 as bindings::__syscall_slong_t,
+        },
+        stime: bindings::timespec {
+            tv_nsec: 0 as bindings::__time_t,
+            tv_sec: 0 as bindings::__syscall_slong_t,
+        },
+        rtime: bindings::timespec {
+            tv_nsec: 0 as bindings::__time_t,
+            tv_sec: 0 as bindings::__syscall_slong_t,
+        },
+    })
+}
+
+impl TryFrom<&Subvolume> for SubvolumeInfo {
+    type Error = BtrfsUtilError;
 
-        SubvolumeInfo::try_from(unsafe { Box::from_raw(btrfs_subvolume_info_ptr) })
+    fn try_from(src: &Subvolume) -> Result<Self> {
+        let path_cstr = common::path_to_cstr(src.path()?)?;
+        SubvolumeInfo::fetch(&path_cstr, src.id())
     }
 }
 
@@ -143,6 +164,14 @@ impl TryFrom<Box<btrfs_util_subvolume_info>> for SubvolumeInfo {
     type Error = BtrfsUtilError;
 
     fn try_from(src: Box<btrfs_util_subvolume_info>) -> Result<Self> {
+        SubvolumeInfo::try_from(&*src)
+    }
+}
+
+impl TryFrom<&btrfs_util_subvolume_info> for SubvolumeInfo {
+    type Error = BtrfsUtilError;
+
+    fn try_from(src: &btrfs_util_subvolume_info) -> Result<Self> {
         let uuid: Uuid = handle_uuid!(&src.uuid);
         let parent_uuid_val: Uuid = handle_uuid!(&src.parent_uuid);
         let received_uuid_val: Uuid = handle_uuid!(&src.received_uuid);
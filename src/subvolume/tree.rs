@@ -0,0 +1,316 @@
+use crate::subvolume::SubvolumeInfo;
+use crate::subvolume::SubvolumeIteratorBuilder;
+use crate::Result;
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::VecDeque;
+use std::io;
+use std::io::Write;
+use std::iter::FromIterator;
+use std::path::Path;
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+/// An entry of a subvolume tree.
+#[derive(Clone, Debug)]
+struct TreeEntry {
+    path: PathBuf,
+    info: SubvolumeInfo,
+    children: Vec<u64>,
+}
+
+/// A hierarchy of subvolumes, built from a listing and keyed by parent id.
+///
+/// Subvolumes whose parent is not part of the listing are roots of the tree.
+#[derive(Clone, Debug, Default)]
+pub struct SubvolumeTree {
+    entries: BTreeMap<u64, TreeEntry>,
+    roots: Vec<u64>,
+}
+
+impl SubvolumeTree {
+    /// Build a tree from the subvolumes listed by an iterator builder.
+    pub fn build(builder: SubvolumeIteratorBuilder) -> Result<Self> {
+        builder.iter_with_info()?.collect()
+    }
+
+    /// Build a tree from subvolume paths and information.
+    ///
+    /// Children are kept in the order they are listed in.
+    pub fn from_entries<I: IntoIterator<Item = (PathBuf, SubvolumeInfo)>>(entries: I) -> Self {
+        let mut tree = SubvolumeTree::default();
+        let mut order: Vec<u64> = Vec::new();
+
+        for (path, info) in entries {
+            order.push(info.id);
+            tree.entries.insert(
+                info.id,
+                TreeEntry {
+                    path,
+                    info,
+                    children: Vec::new(),
+                },
+            );
+        }
+
+        for id in order {
+            let parent_id = tree.entries[&id].info.parent_id;
+            match parent_id {
+                Some(parent_id) if parent_id != id && tree.entries.contains_key(&parent_id) => {
+                    if let Some(parent) = tree.entries.get_mut(&parent_id) {
+                        parent.children.push(id);
+                    }
+                }
+                _ => tree.roots.push(id),
+            }
+        }
+
+        tree
+    }
+
+    /// Get the number of subvolumes in this tree.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether this tree contains no subvolumes.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get a subvolume of this tree by id.
+    pub fn get(&self, id: u64) -> Option<SubvolumeNode<'_>> {
+        if self.entries.contains_key(&id) {
+            Some(SubvolumeNode { tree: self, id })
+        } else {
+            None
+        }
+    }
+
+    /// Get the subvolumes whose parent is not part of this tree.
+    pub fn roots(&self) -> impl Iterator<Item = SubvolumeNode<'_>> {
+        self.roots.iter().map(move |id| SubvolumeNode {
+            tree: self,
+            id: *id,
+        })
+    }
+
+    /// Walk this tree depth-first, visiting parents before their children.
+    pub fn depth_first(&self) -> DepthFirst<'_> {
+        DepthFirst {
+            tree: self,
+            stack: self.roots.iter().rev().copied().collect(),
+        }
+    }
+
+    /// Walk this tree breadth-first, level by level.
+    pub fn breadth_first(&self) -> BreadthFirst<'_> {
+        BreadthFirst {
+            tree: self,
+            queue: self.roots.iter().copied().collect(),
+        }
+    }
+}
+
+impl SubvolumeTree {
+    /// Write this tree as a [Graphviz] DOT graph, with an edge from every subvolume to the
+    /// subvolumes it contains.
+    ///
+    /// [Graphviz]: https://graphviz.org/doc/info/lang.html
+    pub fn to_dot<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "digraph subvolumes {{")?;
+        for node in self.depth_first() {
+            write_dot_node(&mut writer, node)?;
+        }
+        for node in self.depth_first() {
+            for child in node.children() {
+                writeln!(writer, "    {} -> {};", node.id(), child.id())?;
+            }
+        }
+        writeln!(writer, "}}")
+    }
+
+    /// Write the snapshot relationships of this tree as a [Graphviz] DOT graph, with a dashed edge
+    /// from every subvolume to the snapshots taken of it.
+    ///
+    /// Snapshots are matched to their source by UUID. Sources which are not part of this tree are
+    /// drawn as dotted nodes labeled with their UUID.
+    ///
+    /// [Graphviz]: https://graphviz.org/doc/info/lang.html
+    pub fn to_snapshot_dot<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let by_uuid: BTreeMap<Uuid, u64> = self
+            .entries
+            .values()
+            .map(|entry| (entry.info.uuid, entry.info.id))
+            .collect();
+
+        writeln!(writer, "digraph snapshots {{")?;
+        for node in self.depth_first() {
+            write_dot_node(&mut writer, node)?;
+        }
+        let mut missing: BTreeSet<Uuid> = BTreeSet::new();
+        for node in self.depth_first() {
+            if let Some(parent_uuid) = node.info().parent_uuid {
+                match by_uuid.get(&parent_uuid) {
+                    Some(source) => {
+                        writeln!(writer, "    {} -> {} [style=dashed];", source, node.id())?
+                    }
+                    None => {
+                        if missing.insert(parent_uuid) {
+                            writeln!(
+                                writer,
+                                "    \"{}\" [label=\"{}\", style=dotted];",
+                                parent_uuid, parent_uuid
+                            )?;
+                        }
+                        writeln!(
+                            writer,
+                            "    \"{}\" -> {} [style=dashed];",
+                            parent_uuid,
+                            node.id()
+                        )?;
+                    }
+                }
+            }
+        }
+        writeln!(writer, "}}")
+    }
+}
+
+/// Write a subvolume as a DOT node labeled with its path and id.
+fn write_dot_node<W: Write>(writer: &mut W, node: SubvolumeNode<'_>) -> io::Result<()> {
+    let path = node
+        .path()
+        .display()
+        .to_string()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"");
+    writeln!(
+        writer,
+        "    {} [label=\"{}\\n(id {})\"];",
+        node.id(),
+        path,
+        node.id()
+    )
+}
+
+impl FromIterator<(PathBuf, SubvolumeInfo)> for SubvolumeTree {
+    fn from_iter<I: IntoIterator<Item = (PathBuf, SubvolumeInfo)>>(iter: I) -> Self {
+        SubvolumeTree::from_entries(iter)
+    }
+}
+
+impl SubvolumeIteratorBuilder {
+    /// List the subvolumes and build a tree out of them.
+    pub fn tree(self) -> Result<SubvolumeTree> {
+        SubvolumeTree::build(self)
+    }
+}
+
+/// A subvolume in a [SubvolumeTree].
+///
+/// [SubvolumeTree]: struct.SubvolumeTree.html
+#[derive(Clone, Copy, Debug)]
+pub struct SubvolumeNode<'a> {
+    tree: &'a SubvolumeTree,
+    id: u64,
+}
+
+impl<'a> SubvolumeNode<'a> {
+    fn entry(&self) -> &'a TreeEntry {
+        &self.tree.entries[&self.id]
+    }
+
+    /// Get the id of this subvolume.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Get the path of this subvolume, as it was listed.
+    pub fn path(&self) -> &'a Path {
+        &self.entry().path
+    }
+
+    /// Get information about this subvolume.
+    pub fn info(&self) -> &'a SubvolumeInfo {
+        &self.entry().info
+    }
+
+    /// Get the parent of this subvolume, if it is part of the tree.
+    pub fn parent(&self) -> Option<SubvolumeNode<'a>> {
+        if self.tree.roots.contains(&self.id) {
+            return None;
+        }
+        self.entry()
+            .info
+            .parent_id
+            .and_then(|parent_id| self.tree.get(parent_id))
+    }
+
+    /// Get the children of this subvolume.
+    pub fn children(&self) -> impl Iterator<Item = SubvolumeNode<'a>> {
+        let tree = self.tree;
+        self.entry()
+            .children
+            .iter()
+            .map(move |id| SubvolumeNode { tree, id: *id })
+    }
+
+    /// Get the depth of this subvolume, roots being at depth zero.
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut node = *self;
+        while let Some(parent) = node.parent() {
+            depth += 1;
+            node = parent;
+        }
+        depth
+    }
+}
+
+/// Depth-first walk over a [SubvolumeTree].
+///
+/// [SubvolumeTree]: struct.SubvolumeTree.html
+pub struct DepthFirst<'a> {
+    tree: &'a SubvolumeTree,
+    stack: Vec<u64>,
+}
+
+impl<'a> Iterator for DepthFirst<'a> {
+    type Item = SubvolumeNode<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = self.stack.pop()?;
+        let node = SubvolumeNode {
+            tree: self.tree,
+            id,
+        };
+        self.stack
+            .extend(node.entry().children.iter().rev().copied());
+        Some(node)
+    }
+}
+
+/// Breadth-first walk over a [SubvolumeTree].
+///
+/// [SubvolumeTree]: struct.SubvolumeTree.html
+pub struct BreadthFirst<'a> {
+    tree: &'a SubvolumeTree,
+    queue: VecDeque<u64>,
+}
+
+impl<'a> Iterator for BreadthFirst<'a> {
+    type Item = SubvolumeNode<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = self.queue.pop_front()?;
+        let node = SubvolumeNode {
+            tree: self.tree,
+            id,
+        };
+        self.queue.extend(node.entry().children.iter().copied());
+        Some(node)
+    }
+}
@@ -0,0 +1,332 @@
+//! Btrfs filesystem synchronization
+
+use crate::bindings;
+use crate::common;
+use crate::error::LibError;
+use crate::error::LibErrorCode;
+use crate::filesystem::Filesystem;
+use crate::ioctl;
+use crate::BtrfsUtilError;
+use crate::Result;
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use bindings::btrfs_util_start_sync;
+use bindings::btrfs_util_start_sync_fd;
+use bindings::btrfs_util_sync;
+use bindings::btrfs_util_sync_fd;
+use bindings::btrfs_util_wait_sync;
+use bindings::btrfs_util_wait_sync_fd;
+
+/// A Btrfs transaction id.
+///
+/// Transaction id zero refers to the current transaction.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Transid(pub u64);
+
+impl fmt::Display for Transid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<u64> for Transid {
+    fn from(transid: u64) -> Self {
+        Self(transid)
+    }
+}
+
+/// Force a sync on the Btrfs filesystem containing a path.
+pub fn sync_filesystem<T: Into<PathBuf>>(path: T) -> Result<()> {
+    let path_cstr = common::into_path_to_cstr(path)?;
+
+    unsafe_wrapper!(errcode, {
+        errcode = btrfs_util_sync(path_cstr.as_ptr());
+    });
+
+    Ok(())
+}
+
+/// Force a sync on the Btrfs filesystem containing an open file descriptor.
+pub fn sync_filesystem_fd(fd: RawFd) -> Result<()> {
+    unsafe_wrapper!(errcode, {
+        errcode = btrfs_util_sync_fd(fd);
+    });
+
+    Ok(())
+}
+
+/// Start a sync on the Btrfs filesystem containing a path, without waiting for it to commit.
+///
+/// Returns the id of the transaction being committed, which can be passed to [wait_sync].
+///
+/// [wait_sync]: fn.wait_sync.html
+pub fn start_sync<T: Into<PathBuf>>(path: T) -> Result<Transid> {
+    let path_cstr = common::into_path_to_cstr(path)?;
+    let mut transid: u64 = 0;
+
+    unsafe_wrapper!(errcode, {
+        errcode = btrfs_util_start_sync(path_cstr.as_ptr(), &mut transid);
+    });
+
+    Ok(Transid(transid))
+}
+
+/// Start a sync on the Btrfs filesystem containing an open file descriptor, without waiting for it
+/// to commit.
+///
+/// Returns the id of the transaction being committed, which can be passed to [wait_sync_fd].
+///
+/// [wait_sync_fd]: fn.wait_sync_fd.html
+pub fn start_sync_fd(fd: RawFd) -> Result<Transid> {
+    let mut transid: u64 = 0;
+
+    unsafe_wrapper!(errcode, {
+        errcode = btrfs_util_start_sync_fd(fd, &mut transid);
+    });
+
+    Ok(Transid(transid))
+}
+
+/// Wait for a transaction to commit on the Btrfs filesystem containing a path.
+///
+/// Waiting for transaction id zero waits for the current transaction.
+pub fn wait_sync<T: Into<PathBuf>>(path: T, transid: Transid) -> Result<()> {
+    let path_cstr = common::into_path_to_cstr(path)?;
+
+    unsafe_wrapper!(errcode, {
+        errcode = btrfs_util_wait_sync(path_cstr.as_ptr(), transid.0);
+    });
+
+    Ok(())
+}
+
+/// Wait for a transaction to commit on the Btrfs filesystem containing an open file descriptor.
+///
+/// Waiting for transaction id zero waits for the current transaction.
+pub fn wait_sync_fd(fd: RawFd, transid: Transid) -> Result<()> {
+    unsafe_wrapper!(errcode, {
+        errcode = btrfs_util_wait_sync_fd(fd, transid.0);
+    });
+
+    Ok(())
+}
+
+/// Wait for a transaction to commit on the Btrfs filesystem containing a path, giving up after a
+/// timeout.
+///
+/// The wait happens on a helper thread. If the timeout expires first, [BtrfsUtilError::TimedOut]
+/// is returned and the helper thread is left behind until the kernel returns, so a hung device does
+/// not block the caller.
+///
+/// [BtrfsUtilError::TimedOut]: ../error/enum.BtrfsUtilError.html#variant.TimedOut
+pub fn wait_sync_timeout<T: Into<PathBuf>>(
+    path: T,
+    transid: Transid,
+    timeout: Duration,
+) -> Result<()> {
+    let path: PathBuf = path.into();
+    let (sender, receiver) = mpsc::channel();
+
+    let handle = thread::spawn(move || {
+        let _ = sender.send(wait_sync(path, transid));
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(BtrfsUtilError::TimedOut),
+        Err(RecvTimeoutError::Disconnected) => match handle.join() {
+            Err(panic) => std::panic::resume_unwind(panic),
+            Ok(()) => unreachable!("the helper thread exited without a result"),
+        },
+    }
+}
+
+/// Default interval between two samples of the filesystem generation by a [TransidWatcher].
+///
+/// [TransidWatcher]: struct.TransidWatcher.html
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Watches the generation of a Btrfs filesystem, to find out when a transaction has been committed
+/// without forcing a commit like [wait_sync] does.
+///
+/// The generation is sampled periodically. Once it has reached the target transaction, the commit
+/// of that transaction is awaited, which does not force a new one.
+///
+/// This requires Linux 5.10 or newer, which reports the generation of the filesystem.
+///
+/// [wait_sync]: fn.wait_sync.html
+#[derive(Debug)]
+pub struct TransidWatcher {
+    fs: Filesystem,
+    interval: Duration,
+}
+
+impl TransidWatcher {
+    /// Create a watcher for the Btrfs filesystem containing a path.
+    pub fn new<T: Into<PathBuf>>(path: T) -> Result<Self> {
+        Ok(Self {
+            fs: Filesystem::open(path)?,
+            interval: DEFAULT_WATCH_INTERVAL,
+        })
+    }
+
+    /// Set the interval between two samples of the filesystem generation.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Get the current generation of the filesystem, which is the id of the running transaction, or
+    /// of the last committed one.
+    ///
+    /// Fails before Linux 5.10, which does not report the generation.
+    pub fn generation(&self) -> Result<Transid> {
+        let info = ioctl::fs_info(self.fs.as_raw_fd(), ioctl::BTRFS_FS_INFO_FLAG_GENERATION)?;
+        // The kernel clears the flags it does not support, and leaves the generation zeroed.
+        if info.flags & ioctl::BTRFS_FS_INFO_FLAG_GENERATION == 0 {
+            return Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP).into());
+        }
+        Ok(Transid(info.generation))
+    }
+
+    /// Block until a transaction has been committed.
+    ///
+    /// Returns the generation of the filesystem which was observed at or past the target.
+    pub fn wait(&self, target: Transid) -> Result<Transid> {
+        wait_for_generation(
+            target,
+            self.interval,
+            || self.generation(),
+            |target| wait_sync_fd(self.fs.as_raw_fd(), target),
+        )
+    }
+
+    /// Call a function on a helper thread once a transaction has been committed.
+    pub fn notify<F>(self, target: Transid, f: F) -> JoinHandle<()>
+    where
+        F: FnOnce(Result<Transid>) + Send + 'static,
+    {
+        thread::spawn(move || f(self.wait(target)))
+    }
+
+    /// Get a channel receiving the result once a transaction has been committed.
+    pub fn watch(self, target: Transid) -> mpsc::Receiver<Result<Transid>> {
+        let (sender, receiver) = mpsc::channel();
+        self.notify(target, move |result| {
+            let _ = sender.send(result);
+        });
+        receiver
+    }
+}
+
+/// Sample the generation until it reaches the target, then wait for the target to commit.
+///
+/// An idle filesystem stays at the generation of its last commit, so reaching the target is enough.
+fn wait_for_generation<G, W>(
+    target: Transid,
+    interval: Duration,
+    mut generation: G,
+    wait: W,
+) -> Result<Transid>
+where
+    G: FnMut() -> Result<Transid>,
+    W: FnOnce(Transid) -> Result<()>,
+{
+    loop {
+        let generation = generation()?;
+        if generation >= target {
+            wait(target)?;
+            return Ok(generation);
+        }
+        thread::sleep(interval);
+    }
+}
+
+/// Sync the Btrfs filesystem containing a path, without blocking the async runtime.
+///
+/// Runs [sync_filesystem] on a blocking thread pool.
+///
+/// [sync_filesystem]: fn.sync_filesystem.html
+#[cfg(feature = "async")]
+pub async fn sync_filesystem_async<T: Into<PathBuf>>(path: T) -> Result<()> {
+    let path: PathBuf = path.into();
+    common::spawn_blocking(move || sync_filesystem(path)).await
+}
+
+/// Start a sync on the Btrfs filesystem containing a path and wait for it to commit, without
+/// blocking the async runtime.
+///
+/// Both steps run on a blocking thread pool. Resolves to the id of the committed transaction.
+#[cfg(feature = "async")]
+pub async fn commit_async<T: Into<PathBuf>>(path: T) -> Result<Transid> {
+    let path: PathBuf = path.into();
+    common::spawn_blocking(move || {
+        let transid = start_sync(path.clone())?;
+        wait_sync(path, transid)?;
+        Ok(transid)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wait_on_idle_filesystem() {
+        let mut waited = None;
+        let generation = wait_for_generation(
+            Transid(7),
+            Duration::from_secs(3600),
+            || Ok(Transid(7)),
+            |target| {
+                waited = Some(target);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(generation, Transid(7));
+        assert_eq!(waited, Some(Transid(7)));
+    }
+
+    #[test]
+    fn wait_until_generation_reaches_target() {
+        let mut samples = vec![Transid(9), Transid(6), Transid(5)];
+        let mut waited = None;
+        let generation = wait_for_generation(
+            Transid(7),
+            Duration::ZERO,
+            || Ok(samples.pop().unwrap()),
+            |target| {
+                waited = Some(target);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(generation, Transid(9));
+        assert_eq!(waited, Some(Transid(7)));
+        assert!(samples.is_empty());
+    }
+
+    #[test]
+    fn wait_fails_with_generation() {
+        let result = wait_for_generation(
+            Transid(7),
+            Duration::ZERO,
+            || Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP).into()),
+            |_| panic!("waited without a generation"),
+        );
+        assert!(matches!(result, Err(BtrfsUtilError::Io(_))));
+    }
+}
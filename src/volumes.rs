@@ -0,0 +1,103 @@
+//! Writable volumes for container runtimes
+//!
+//! Volumes are snapshots of a base image, created next to it, like the Docker btrfs graph driver
+//! does. Their size can be limited through the level 0 qgroup of the volume, which requires
+//! quotas to be enabled.
+
+use crate::filesystem::Filesystem;
+use crate::qgroup::Limit;
+use crate::qgroup::Qgroup;
+use crate::qgroup::QgroupId;
+use crate::subvolume::wait_cleaned;
+use crate::subvolume::DeleteOptions;
+use crate::subvolume::SnapshotOptions;
+use crate::subvolume::Subvolume;
+use crate::Result;
+
+use std::io;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// A writable volume created by [provision_volume].
+///
+/// [provision_volume]: fn.provision_volume.html
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Volume {
+    /// Path of the volume.
+    pub path: PathBuf,
+    /// Id of the volume.
+    pub id: u64,
+    /// Limit of the space referenced by the volume, in bytes.
+    pub quota: Option<u64>,
+}
+
+/// Create a writable volume from a base image subvolume.
+///
+/// The volume is a snapshot of the base image, named `name`, in the directory containing the
+/// base image. If a quota is given, the space the volume references is limited to it, in bytes.
+/// If the limit cannot be set, the volume is deleted again. This requires elevated
+/// privileges(CAP_SYS_ADMIN).
+pub fn provision_volume<T: AsRef<Path>>(
+    base_image_subvol: T,
+    name: &str,
+    quota: Option<u64>,
+) -> Result<Volume> {
+    let base_path = base_image_subvol.as_ref();
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => {}
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid volume name: {}", name),
+            )
+            .into())
+        }
+    }
+    let path = base_path
+        .parent()
+        .unwrap_or_else(|| Path::new("/"))
+        .join(name);
+
+    let base = Subvolume::get(base_path)?;
+    let volume = base.snapshot_with(path.as_path(), SnapshotOptions::new())?;
+    if let Some(bytes) = quota {
+        let limited = Filesystem::open(path.as_path()).and_then(|fs| {
+            Qgroup::set_limit(
+                &fs,
+                QgroupId::for_subvolume(&volume),
+                Limit {
+                    referenced: Some(bytes),
+                    exclusive: None,
+                },
+            )
+        });
+        if let Err(e) = limited {
+            let _ = volume.delete_with(DeleteOptions::default());
+            return Err(e);
+        }
+    }
+    Ok(Volume {
+        path,
+        id: volume.id(),
+        quota,
+    })
+}
+
+/// Delete a volume and wait until the kernel has cleaned it up.
+///
+/// Subvolumes created inside the volume, e.g. by a container, are deleted as well. Once the
+/// volume is cleaned up, its level 0 qgroup is destroyed if it was left behind, ignoring errors
+/// since kernels which remove it themselves or disabled quotas make that fail. This requires
+/// elevated privileges(CAP_SYS_ADMIN).
+pub fn destroy_volume<T: AsRef<Path>>(volume: T) -> Result<()> {
+    let path = volume.as_ref();
+    let fs = Filesystem::open(path.parent().unwrap_or_else(|| Path::new("/")))?;
+    let subvol = Subvolume::get(path)?;
+    let id = subvol.id();
+    subvol.delete_with(DeleteOptions::new().recursive(true))?;
+    wait_cleaned(&fs, id)?;
+    let _ = Qgroup::destroy(&fs, QgroupId::new(0, id));
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use crate::subvolume::Subvolume;
+use crate::watch::WatchEvent;
+use crate::watch::WatchHandle;
+use crate::Result;
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+
+/// Watches directories for new subvolumes and snapshots through inotify.
+///
+/// Unlike a [Watcher], new subvolumes are noticed as soon as they are created, but only when
+/// they are created or moved directly into a watched directory, and changes and deletions are
+/// not reported. Every new directory is checked to be a subvolume root before it is reported.
+///
+/// [Watcher]: struct.Watcher.html
+#[derive(Debug)]
+pub struct InotifyWatcher {
+    inotify: File,
+    dirs: HashMap<libc::c_int, PathBuf>,
+}
+
+impl InotifyWatcher {
+    /// Create a watcher without directories.
+    pub fn new() -> Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(Self {
+            inotify: unsafe { File::from_raw_fd(fd) },
+            dirs: HashMap::new(),
+        })
+    }
+
+    /// Watch a directory, e.g. one snapshots are taken into.
+    pub fn add_dir<T: AsRef<Path>>(&mut self, dir: T) -> Result<()> {
+        let dir = dir.as_ref();
+        let dir_cstr = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let wd = unsafe {
+            libc::inotify_add_watch(
+                self.inotify.as_raw_fd(),
+                dir_cstr.as_ptr(),
+                libc::IN_CREATE | libc::IN_MOVED_TO | libc::IN_ONLYDIR,
+            )
+        };
+        if wd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        self.dirs.insert(wd, dir.to_path_buf());
+        Ok(())
+    }
+
+    /// Block until subvolumes are created in the watched directories, returning them.
+    pub fn wait(&mut self) -> Result<Vec<WatchEvent>> {
+        Ok(self.wait_or_stop(None)?.unwrap_or_default())
+    }
+
+    /// Block until subvolumes are created in the watched directories, or until an optional
+    /// eventfd is signalled, returning `None` then.
+    fn wait_or_stop(&mut self, stop: Option<&File>) -> Result<Option<Vec<WatchEvent>>> {
+        let mut events = Vec::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        while events.is_empty() {
+            if let Some(stop) = stop {
+                let mut fds = [
+                    libc::pollfd {
+                        fd: self.inotify.as_raw_fd(),
+                        events: libc::POLLIN,
+                        revents: 0,
+                    },
+                    libc::pollfd {
+                        fd: stop.as_raw_fd(),
+                        events: libc::POLLIN,
+                        revents: 0,
+                    },
+                ];
+                if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+                    let e = io::Error::last_os_error();
+                    if e.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return Err(e.into());
+                }
+                if fds[1].revents != 0 {
+                    return Ok(None);
+                }
+            }
+            let len = match self.inotify.read(&mut buf) {
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let mut offset = 0;
+            while offset + mem::size_of::<libc::inotify_event>() <= len {
+                let event: libc::inotify_event =
+                    unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr() as *const _) };
+                let name_start = offset + mem::size_of::<libc::inotify_event>();
+                let name_end = name_start + event.len as usize;
+                offset = name_end;
+                if event.mask & libc::IN_ISDIR == 0 || name_end > len {
+                    continue;
+                }
+                let name = &buf[name_start..name_end];
+                let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+                let dir = match self.dirs.get(&event.wd) {
+                    Some(dir) => dir,
+                    None => continue,
+                };
+                if let Some(event) = created(&dir.join(OsStr::from_bytes(name))) {
+                    events.push(event);
+                }
+            }
+        }
+        Ok(Some(events))
+    }
+
+    /// Wait for new subvolumes on a helper thread, sending the events over a channel.
+    ///
+    /// The helper thread stops after sending the first error, or right away when the returned
+    /// handle is dropped, which wakes it up from waiting for inotify events.
+    pub fn watch(mut self) -> Result<WatchHandle> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let mut stop = unsafe { File::from_raw_fd(fd) };
+        let stop_wait = stop.try_clone()?;
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::spawn(move || loop {
+            match self.wait_or_stop(Some(&stop_wait)) {
+                Ok(None) => return,
+                Ok(Some(events)) => {
+                    for event in events {
+                        if sender.send(Ok(event)).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    return;
+                }
+            }
+        });
+        Ok(WatchHandle::new(receiver, thread, move || {
+            let _ = stop.write_all(&1u64.to_ne_bytes());
+        }))
+    }
+}
+
+/// Describe a new directory, if it is a subvolume root which still exists.
+fn created(path: &Path) -> Option<WatchEvent> {
+    Subvolume::is_subvolume(path).ok()?;
+    let info = Subvolume::get(path).and_then(|subvol| subvol.info()).ok()?;
+    Some(match info.parent_uuid {
+        Some(parent_uuid) => WatchEvent::SnapshotCreated {
+            id: info.id,
+            path: path.to_path_buf(),
+            parent_uuid,
+        },
+        None => WatchEvent::SubvolumeCreated {
+            id: info.id,
+            path: path.to_path_buf(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    #[test]
+    fn stop_waiting_thread() {
+        let dir = std::env::temp_dir().join(format!("btrfsutil-inotify-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut watcher = InotifyWatcher::new().unwrap();
+        watcher.add_dir(&dir).unwrap();
+        let handle = watcher.watch().unwrap();
+        // Directories which are not subvolumes are not reported.
+        fs::create_dir(dir.join("plain")).unwrap();
+        assert!(handle
+            .events()
+            .recv_timeout(Duration::from_millis(100))
+            .is_err());
+
+        let start = Instant::now();
+        handle.stop();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
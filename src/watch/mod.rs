@@ -0,0 +1,9 @@
+//! Change notification for subvolumes
+
+#[cfg(feature = "inotify")]
+mod inotify;
+mod poll;
+
+#[cfg(feature = "inotify")]
+pub use inotify::*;
+pub use poll::*;
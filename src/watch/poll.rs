@@ -0,0 +1,310 @@
+use crate::send::subvolume_root;
+use crate::subvolume::Subvolume;
+use crate::subvolume::SubvolumeInfo;
+use crate::subvolume::SubvolumeIterator;
+use crate::sync::Transid;
+use crate::sync::TransidWatcher;
+use crate::sync::DEFAULT_WATCH_INTERVAL;
+use crate::Result;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+/// A change to a subvolume, detected by a [Watcher].
+///
+/// [Watcher]: struct.Watcher.html
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum WatchEvent {
+    /// A subvolume which is not a snapshot was created.
+    SubvolumeCreated {
+        /// Id of the subvolume.
+        id: u64,
+        /// Path of the subvolume.
+        path: PathBuf,
+    },
+    /// A snapshot was created.
+    SnapshotCreated {
+        /// Id of the snapshot.
+        id: u64,
+        /// Path of the snapshot.
+        path: PathBuf,
+        /// UUID of the subvolume the snapshot was taken of.
+        parent_uuid: Uuid,
+    },
+    /// The contents of a subvolume changed.
+    SubvolumeChanged {
+        /// Id of the subvolume.
+        id: u64,
+        /// Path of the subvolume.
+        path: PathBuf,
+        /// Generation of the subvolume root after the change.
+        generation: u64,
+    },
+    /// A subvolume was deleted.
+    SubvolumeDeleted {
+        /// Id of the subvolume.
+        id: u64,
+        /// Path the subvolume had.
+        path: PathBuf,
+    },
+}
+
+/// Events of a watcher running on a helper thread, returned by [Watcher::watch].
+///
+/// The helper thread stops after sending the first error, or when this handle is dropped or
+/// [stopped](#method.stop). Also an [Iterator] over the events, which blocks until the next one
+/// and ends when the helper thread stopped.
+///
+/// [Watcher::watch]: struct.Watcher.html#method.watch
+/// [Iterator]: https://doc.rust-lang.org/std/iter/trait.Iterator.html
+pub struct WatchHandle {
+    receiver: mpsc::Receiver<Result<WatchEvent>>,
+    stop: Option<Box<dyn FnOnce() + Send>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WatchHandle {
+    /// Create a handle of a helper thread, given how to make it stop.
+    pub(crate) fn new<F: FnOnce() + Send + 'static>(
+        receiver: mpsc::Receiver<Result<WatchEvent>>,
+        thread: JoinHandle<()>,
+        stop: F,
+    ) -> Self {
+        Self {
+            receiver,
+            stop: Some(Box::new(stop)),
+            thread: Some(thread),
+        }
+    }
+
+    /// Get the receiving end of the channel the events are sent over, e.g. to wait for them with
+    /// a timeout.
+    pub fn events(&self) -> &mpsc::Receiver<Result<WatchEvent>> {
+        &self.receiver
+    }
+
+    /// Stop the helper thread and wait for it to exit.
+    pub fn stop(mut self) {
+        self.signal_stop();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    fn signal_stop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            stop();
+        }
+    }
+}
+
+impl Iterator for WatchHandle {
+    type Item = Result<WatchEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        self.signal_stop();
+    }
+}
+
+impl fmt::Debug for WatchHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WatchHandle")
+            .field("stopped", &self.stop.is_none())
+            .finish()
+    }
+}
+
+/// A subvolume as last seen by a [Watcher].
+///
+/// [Watcher]: struct.Watcher.html
+#[derive(Clone, Debug)]
+struct Seen {
+    path: PathBuf,
+    generation: u64,
+    parent_uuid: Option<Uuid>,
+}
+
+/// Whether the subvolumes must be listed again, which is always the case when the generation of the
+/// filesystem is unknown.
+fn generation_advanced(previous: Option<Transid>, current: Option<Transid>) -> bool {
+    current.is_none() || current != previous
+}
+
+/// Watches the subvolumes beneath a directory for changes.
+///
+/// Btrfs has no event interface for subvolumes, so the generation of the filesystem is polled,
+/// and whenever it advanced, the subvolumes are listed and compared with the previous listing.
+/// Only changes committed to disk are seen, so events may lag behind by up to the commit interval
+/// of the filesystem.
+///
+/// On Linux 4.18 and newer, this does not require elevated privileges. Only subvolumes which are
+/// accessible to the caller are watched.
+#[derive(Debug)]
+pub struct Watcher {
+    dir: PathBuf,
+    transid: TransidWatcher,
+    interval: Duration,
+    generation: Option<Transid>,
+    seen: BTreeMap<u64, Seen>,
+}
+
+impl Watcher {
+    /// Create a watcher for the subvolumes beneath a directory, including the subvolume containing
+    /// it. Existing subvolumes are listed right away, and do not cause events.
+    pub fn new<T: Into<PathBuf>>(dir: T) -> Result<Self> {
+        let dir = dir.into().canonicalize()?;
+        let mut watcher = Self {
+            transid: TransidWatcher::new(dir.as_path())?,
+            dir,
+            interval: DEFAULT_WATCH_INTERVAL,
+            generation: None,
+            seen: BTreeMap::new(),
+        };
+        watcher.generation = watcher.transid.generation().ok();
+        watcher.seen = watcher.list()?;
+        Ok(watcher)
+    }
+
+    /// Set the interval between two samples of the filesystem generation.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// List the watched subvolumes.
+    fn list(&self) -> Result<BTreeMap<u64, Seen>> {
+        let root = subvolume_root(&self.dir)?;
+        let mut seen = BTreeMap::new();
+        let top = Subvolume::get(root.as_path())?.info()?;
+        seen.insert(
+            top.id,
+            Seen {
+                path: root.clone(),
+                generation: top.generation,
+                parent_uuid: top.parent_uuid,
+            },
+        );
+        for entry in SubvolumeIterator::builder_for_path(root.clone()).iter_with_info()? {
+            let (path, info): (PathBuf, SubvolumeInfo) = entry?;
+            let path = root.join(path);
+            if path.starts_with(&self.dir) {
+                seen.insert(
+                    info.id,
+                    Seen {
+                        path,
+                        generation: info.generation,
+                        parent_uuid: info.parent_uuid,
+                    },
+                );
+            }
+        }
+        Ok(seen)
+    }
+
+    /// Check for changes since the previous poll, or since the watcher was created.
+    ///
+    /// Subvolumes are only listed again if the generation of the filesystem advanced, or if the
+    /// kernel does not report it (before Linux 5.10).
+    pub fn poll(&mut self) -> Result<Vec<WatchEvent>> {
+        // Fails when the kernel does not report the generation, which forces a new listing.
+        let generation = self.transid.generation().ok();
+        if !generation_advanced(self.generation, generation) {
+            return Ok(Vec::new());
+        }
+        let seen = self.list()?;
+        let mut events = Vec::new();
+
+        for (id, old) in &self.seen {
+            if !seen.contains_key(id) {
+                events.push(WatchEvent::SubvolumeDeleted {
+                    id: *id,
+                    path: old.path.clone(),
+                });
+            }
+        }
+        for (id, new) in &seen {
+            match self.seen.get(id) {
+                None => events.push(match new.parent_uuid {
+                    Some(parent_uuid) => WatchEvent::SnapshotCreated {
+                        id: *id,
+                        path: new.path.clone(),
+                        parent_uuid,
+                    },
+                    None => WatchEvent::SubvolumeCreated {
+                        id: *id,
+                        path: new.path.clone(),
+                    },
+                }),
+                Some(old) if old.generation != new.generation => {
+                    events.push(WatchEvent::SubvolumeChanged {
+                        id: *id,
+                        path: new.path.clone(),
+                        generation: new.generation,
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+
+        self.generation = generation;
+        self.seen = seen;
+        Ok(events)
+    }
+
+    /// Poll for changes on a helper thread, sending the events over a channel.
+    ///
+    /// The helper thread stops after sending the first error, or right away when the returned
+    /// handle is dropped.
+    pub fn watch(mut self) -> WatchHandle {
+        let (sender, receiver) = mpsc::channel();
+        // Never sent to, so that waiting on it is interrupted when the handle drops the sender.
+        let (stop_sender, stop_receiver) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || loop {
+            if stop_receiver.recv_timeout(self.interval) != Err(RecvTimeoutError::Timeout) {
+                return;
+            }
+            match self.poll() {
+                Ok(events) => {
+                    for event in events {
+                        if sender.send(Ok(event)).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    return;
+                }
+            }
+        });
+        WatchHandle::new(receiver, thread, move || drop(stop_sender))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_again_without_generation() {
+        assert!(generation_advanced(None, None));
+        assert!(generation_advanced(Some(Transid(5)), None));
+        assert!(generation_advanced(None, Some(Transid(5))));
+        assert!(generation_advanced(Some(Transid(5)), Some(Transid(6))));
+        assert!(!generation_advanced(Some(Transid(5)), Some(Transid(5))));
+    }
+}